@@ -0,0 +1,56 @@
+//! A demo of the get-work / submit-work protocol. The node runs on the main thread and
+//! hands out work packages. The miner runs on its own thread and only ever sees the
+//! packages, never the blocks.
+//!
+//! Run it with `cargo run --bin external_miner`.
+
+use std::thread;
+
+use diy_blockchain::c1_state_machine::LightSwitch;
+use diy_blockchain::c3_consensus::Pow;
+use diy_blockchain::c4_client::{work_channel, FullClient};
+use diy_blockchain::hex::Hex;
+
+const BLOCKS: u64 = 5;
+
+fn main() {
+    let mut client = FullClient::<Pow, LightSwitch, (), ()>::new(
+        Pow::with_difficulty(100_000),
+        LightSwitch,
+        (),
+        (),
+        false,
+    );
+    let (mut server, miner) = work_channel();
+
+    let miner = thread::spawn(move || {
+        while let Some(package) = miner.get_work() {
+            let nonce = package
+                .solve()
+                .expect("a nonce exists for any reasonable difficulty");
+            println!("miner: solved package {} with nonce {}", package.id, nonce);
+            miner.submit_seal(package.id, nonce);
+        }
+        println!("miner: node hung up, shutting down");
+    });
+
+    let mut best = client.genesis_hash();
+    for _ in 0..BLOCKS {
+        let id = client
+            .publish_work(&mut server, best, vec![()])
+            .expect("the miner is running");
+        println!("node: published package {id} on top of {}", Hex(best));
+
+        let seal = server.recv_seal().expect("the miner is running");
+        match client.import_seal(&mut server, seal) {
+            Some(hash) => {
+                println!("node: imported block {}", Hex(hash));
+                best = hash;
+            }
+            None => println!("node: rejected seal for package {}", seal.package_id),
+        }
+    }
+
+    drop(server);
+    miner.join().expect("the miner thread does not panic");
+}
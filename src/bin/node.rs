@@ -0,0 +1,197 @@
+//! A toy node built from the client, storage, and consensus modules. It keeps its chain in a
+//! sled database, so every command picks up where the last one left off.
+//!
+//! Run it with `cargo run --features sled,json,chain-file --bin node -- <command>`. The
+//! commands are:
+//!
+//! - `run [blocks]` authors and imports the given number of blocks, ten by default.
+//! - `export <file>` writes the best chain to a chain file, in the binary format of the client
+//!   module, with a checksum for every block.
+//! - `import <file>` imports the blocks in a file written by `export`, after checking it.
+//! - `inspect <hash|height>` prints a block's header and post-state. Hashes start with `0x`.
+//! - `net [blocks]` joins the other nodes on the local network and follows the chain with
+//!   them, authoring the given number of blocks along the way, none by default. It runs until
+//!   interrupted with Ctrl-C, and needs the `p2p` feature.
+//!
+//! Every command accepts `--data <dir>` to choose where the chain is kept, and `--spec <file>`
+//! to boot from a chain spec instead of the built-in development chain.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::process::ExitCode;
+
+use diy_blockchain::c1_state_machine::{Balances, Currency, User};
+use diy_blockchain::c2_blockchain::BlockId;
+use diy_blockchain::c3_consensus::{ConsensusAuthority, Pow};
+use diy_blockchain::c4_client::{
+    read_chain_file, write_chain_file, Block, BlockStore, ChainSpec, ConsensusParams, FullClient,
+    ImportBlock, ImportError, LongestChain, SimplePool, SledStore, TieBreak,
+};
+use diy_blockchain::hex::Hex;
+
+type Node = FullClient<Pow, Currency, LongestChain, SimplePool<Currency>, SledStore<Pow, Currency>>;
+
+const DEFAULT_DATA_DIR: &str = "node-data";
+const DEFAULT_BLOCKS: u64 = 10;
+/// How long a node on the network waits between authoring blocks.
+#[cfg(feature = "p2p")]
+const AUTHORING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+const USAGE: &str = "usage: node [--data <dir>] [--spec <file>] <run [blocks] | export <file> \
+                     | import <file> | inspect <hash|height> | net [blocks]>";
+
+/// The chain that the node follows when it is not given a spec.
+fn development_spec() -> ChainSpec<Balances> {
+    ChainSpec {
+        chain_id: "dev".into(),
+        genesis: Balances::from([(User::Alice, 1_000)]),
+        consensus: ConsensusParams {
+            threshold: u64::MAX / 1_000,
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+        forks: BTreeMap::new(),
+    }
+}
+
+fn open(data_dir: &str, spec: Option<&str>) -> Result<Node, String> {
+    let spec = match spec {
+        Some(path) => ChainSpec::from_json(path).map_err(|e| format!("bad spec {path}: {e:?}"))?,
+        None => development_spec(),
+    };
+    let store = SledStore::open(data_dir).map_err(|e| format!("bad database: {e:?}"))?;
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::from_chain_spec_with_store(
+        Currency,
+        fork_choice,
+        SimplePool::default(),
+        store,
+        &spec,
+    )
+    .map_err(|e| format!("bad spec: {e:?}"))
+}
+
+fn run(node: &mut Node, blocks: u64) -> Result<(), String> {
+    for _ in 0..blocks {
+        let hash = node
+            .author_block(vec![])
+            .ok_or("the consensus engine could not seal a block")?;
+        println!("authored block {}", Hex(hash));
+    }
+    Ok(())
+}
+
+fn export(node: &Node, path: &str) -> Result<(), String> {
+    let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+    let blocks: Vec<_> = (1..)
+        .map_while(|height| node.resolve(BlockId::Number(height)))
+        .filter_map(|hash| node.get_block(hash))
+        .collect();
+    write_chain_file(&mut file, &blocks).map_err(|e| format!("{path}: {e:?}"))?;
+    println!("exported {} blocks to {path}", blocks.len());
+    Ok(())
+}
+
+fn import(node: &mut Node, path: &str) -> Result<(), String> {
+    let mut file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let blocks: Vec<Block<Pow, Currency>> =
+        read_chain_file(&mut file).map_err(|e| format!("{path}: {e:?}"))?;
+    let (mut imported, mut known) = (0, 0);
+    for (record, block) in blocks.into_iter().enumerate() {
+        let hash = Hex(block.hash());
+        match node.try_import_block(block) {
+            Ok(_) => imported += 1,
+            Err(ImportError::AlreadyKnown) => known += 1,
+            Err(error) => return Err(format!("record {record}: block {hash}: {error:?}")),
+        }
+    }
+    println!("imported {imported} blocks, {known} already known");
+    Ok(())
+}
+
+fn inspect(node: &Node, block: &str) -> Result<(), String> {
+    let id = if block.starts_with("0x") {
+        BlockId::Hash(block.parse::<Hex>().map_err(|e| e.to_string())?.0)
+    } else {
+        BlockId::Number(block.parse().map_err(|_| USAGE.to_string())?)
+    };
+    let unknown = || format!("unknown block {block}");
+    let hash = node.resolve(id).ok_or_else(unknown)?;
+    let header = node.store().header(id).ok_or_else(unknown)?;
+    let state = node.state_at(id).map_err(|e| format!("{e:?}"))?;
+    println!("hash: {}", Hex(hash));
+    println!("{header}");
+    println!("state: {state:?}");
+    Ok(())
+}
+
+#[cfg(feature = "p2p")]
+fn net(node: Node, blocks: u64) -> Result<(), String> {
+    use diy_blockchain::c4_client::ImportResult;
+    use diy_blockchain::c5_network::P2pNode;
+
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+    runtime.block_on(async {
+        let mut node = P2pNode::new(node).map_err(|e| e.to_string())?;
+        let address = "/ip4/0.0.0.0/tcp/0".parse().expect("the address is valid");
+        node.listen(address).map_err(|e| e.to_string())?;
+        println!("joined the network as {}", node.peer_id());
+
+        let mut authoring = tokio::time::interval(AUTHORING_INTERVAL);
+        let mut remaining = blocks;
+        loop {
+            tokio::select! {
+                _ = authoring.tick(), if remaining > 0 => {
+                    remaining -= 1;
+                    if let Some(hash) = node.author_block(vec![]) {
+                        println!("authored block {}", Hex(hash));
+                    }
+                }
+                results = node.step() => {
+                    for (hash, result) in results {
+                        if result == ImportResult::Imported {
+                            println!("imported block {}", Hex(hash));
+                        }
+                    }
+                }
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    })
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (mut data_dir, mut spec, mut command) = (DEFAULT_DATA_DIR.to_string(), None, Vec::new());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data" => data_dir = args.next().unwrap_or_default(),
+            "--spec" => spec = args.next(),
+            _ => command.push(arg),
+        }
+    }
+
+    let result = open(&data_dir, spec.as_deref()).and_then(|mut node| {
+        let command: Vec<_> = command.iter().map(String::as_str).collect();
+        match command[..] {
+            ["run"] => run(&mut node, DEFAULT_BLOCKS),
+            ["run", blocks] => run(&mut node, blocks.parse().map_err(|_| USAGE.to_string())?),
+            ["export", path] => export(&node, path),
+            ["import", path] => import(&mut node, path),
+            ["inspect", block] => inspect(&node, block),
+            #[cfg(feature = "p2p")]
+            ["net"] => net(node, 0),
+            #[cfg(feature = "p2p")]
+            ["net", blocks] => net(node, blocks.parse().map_err(|_| USAGE.to_string())?),
+            _ => Err(USAGE.to_string()),
+        }
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -7,6 +7,90 @@ mod p3_atm;
 mod p4_accounted_currency;
 mod p5_digital_cash;
 mod p6_open_ended;
+mod p7_staking;
+mod p8_balances;
+mod p9_assets;
+mod p10_governance;
+mod p11_auction;
+mod p12_htlc;
+mod p13_utxo;
+mod p14_composition;
+mod p15_nft;
+mod p16_rock_paper_scissors;
+mod p17_signed_extrinsics;
+mod p18_fees;
+mod p19_issuance;
+mod p20_vm;
+#[cfg(feature = "wasm")]
+mod p21_wasm_runtime;
+mod p22_runtime;
+
+// Re-export some individual state machines so they can be re-used in the Client chapter.
+use crate::c3_consensus::ConsensusAuthority;
+
+pub use p1_switches::LightSwitch;
+pub use p7_staking::{Staking, StakingLedger, StakingTransition, Stakes, UnlockChunk};
+pub use p8_balances::{AccountId, Balances, Currency, CurrencyTransaction};
+pub use p9_assets::{AssetId, AssetTransaction, Assets, MultiAsset};
+pub use p10_governance::{
+    ChainParameters, Governance, GovernanceState, GovernanceTransition, ParameterChange, Proposal,
+    ProposalId,
+};
+pub use p11_auction::{
+    bid_commitment, AuctionPhase, AuctionState, AuctionTransition, SealedBidAuction,
+};
+pub use p12_htlc::{hash_lock, Escrow, EscrowState, EscrowTransition, HashTimeLock, LockId};
+pub use p13_utxo::{OutPoint, Output, Utxo, UtxoSet, UtxoTransaction};
+pub use p14_composition::{OneOf2, OneOf3, OneOf4, OneOf5};
+pub use p15_nft::{Nft, NftRegistry, NftTransition, Token, TokenId};
+pub use p16_rock_paper_scissors::{
+    move_commitment, GameState, GameTransition, Move, Outcome, Player, RockPaperScissors,
+    MOVE_TIMEOUT,
+};
+pub use p17_signed_extrinsics::{
+    Encode, Sender, Signed, SignedCurrency, SignedExtrinsic, SignedState,
+};
+pub use p18_fees::{ChargesFee, Nonced, Paid, PaidCurrency, PaidState, PaysFee};
+pub use p19_issuance::{Ledger, TrackedCurrency};
+pub use p20_vm::{run, Instruction, Program, Storage, Vm, MAX_GAS};
+#[cfg(feature = "wasm")]
+pub use p21_wasm_runtime::{
+    WasmRuntime, WasmState, WasmTransition, FUEL, MAX_MEMORY, MAX_TABLE_ELEMENTS,
+};
+pub use p22_runtime::{Call, Runtime, RuntimeState, SystemCall};
+
+/// What a state machine may know about the block that its transitions are executed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockContext {
+    /// The height of the block.
+    pub height: u64,
+    /// The time at which the block claims to have been authored, in milliseconds since the
+    /// Unix epoch.
+    pub timestamp: u64,
+    /// The author that the block declares. `None` if it does not declare one.
+    pub author: Option<ConsensusAuthority>,
+}
+
+/// A state that knows the height of the block it is in, for machines with deadlines.
+///
+/// Chains that execute transitions with a block context keep the height up to date through
+/// `caught_up`. Otherwise, the chain is expected to begin every block with a transition that
+/// moves the height on by one, such as `AuctionTransition::NewBlock`.
+pub trait TracksHeight: Clone {
+    /// The height of the current block.
+    fn height_mut(&mut self) -> &mut u64;
+
+    /// This state, caught up to the height of the block described by the given context. The
+    /// height never goes backwards. Machines call this at the start of `next_state_in_block`,
+    /// so chains that pass a block context do not need to submit `NewBlock` transitions at all.
+    fn caught_up(&self, context: &BlockContext) -> Self {
+        let mut state = self.clone();
+        let height = state.height_mut();
+        *height = (*height).max(context.height);
+        state
+    }
+}
 
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
@@ -19,6 +103,43 @@ pub trait StateMachine {
     /// Calculate the resulting state when this state undergoes the given transition
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
 
+    /// Calculate the resulting state when this state undergoes the given transition as part
+    /// of the block described by the given context. This is what chains call.
+    ///
+    /// Most machines do not care which block they are in, so by default the context is
+    /// ignored. Machines with deadlines, vesting schedules, or rewards for the block author
+    /// override this instead of asking users to tell them the time.
+    fn next_state_in_block(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+        _context: &BlockContext,
+    ) -> Self::State {
+        Self::next_state(starting_state, t)
+    }
+
+    /// Calculate the resulting state when this state undergoes all of the given transitions,
+    /// in order. Blocks apply their extrinsics like this.
+    ///
+    /// By default the transitions are applied one at a time, which makes a new state for
+    /// every one of them. Machines that can apply a whole batch more cheaply override this,
+    /// and must end up in the same state.
+    fn apply_all(starting_state: &Self::State, ts: &[Self::Transition]) -> Self::State
+    where
+        Self::State: Clone,
+    {
+        ts.iter()
+            .fold(starting_state.clone(), |state, t| Self::next_state(&state, t))
+    }
+
+    /// Whether the given state satisfies every invariant that this machine promises to keep.
+    /// Clients check this after every block when built with the `strict-invariants` feature.
+    ///
+    /// Most machines promise nothing beyond what their types already guarantee, so by default
+    /// every state is fine.
+    fn invariants_hold(_state: &Self::State) -> bool {
+        true
+    }
+
     /// A human-readable name for this state machine. This may be used in user-facing
     /// programs such as the repl described below. This is not in any way related to
     /// the correctness of the state machine.
@@ -27,8 +148,64 @@ pub trait StateMachine {
     }
 }
 
+/// The reasons a transition may not be allowed from a particular state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransitionError {
+    /// Someone tried to spend more than they have.
+    InsufficientFunds,
+    /// A balance or counter would overflow.
+    Overflow,
+    /// The transition refers to something that does not exist.
+    NotFound,
+    /// The caller is not allowed to make this transition.
+    NotPermitted,
+    /// The transition ran out of gas before it finished.
+    OutOfGas,
+    /// The transition is not allowed for any other reason.
+    Invalid,
+}
+
+/// A state machine whose transitions may fail.
+///
+/// So far our state machines have handled invalid transitions by leaving the state unchanged.
+/// That works, but nobody can tell a rejected transition apart from one that simply had no
+/// effect. Machines that implement this trait report why a transition was rejected instead.
+///
+/// Their `next_state` should still leave the state unchanged when `try_next_state` fails, so
+/// that they can be used anywhere an ordinary state machine can.
+pub trait TryStateMachine: StateMachine {
+    /// Calculate the resulting state when this state undergoes the given transition, or
+    /// the reason that the transition is not allowed from this state.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, TransitionError>;
+}
+
+/// A state machine that can take back the transitions it has applied.
+///
+/// Applying a transition in place hands back an undo token. The token holds just enough
+/// information to put the state back the way it was, which is usually far less than a
+/// complete copy of the state. Clients keep these tokens so that when the best chain
+/// switches branches, they can roll the state back to the common ancestor cheaply.
+pub trait UndoableStateMachine: StateMachine {
+    /// Everything needed to take back a single applied transition.
+    type Undo;
+
+    /// Apply the given transition to the state in place, as part of the block described by the
+    /// given context, and return the token that undoes it. The resulting state must be the
+    /// same as the one `next_state_in_block` returns.
+    fn apply(state: &mut Self::State, t: &Self::Transition, context: &BlockContext) -> Self::Undo;
+
+    /// Take back an applied transition using its undo token. Tokens must be used in the
+    /// reverse of the order in which their transitions were applied.
+    fn undo(state: &mut Self::State, undo: Self::Undo);
+}
+
 /// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum User {
     Alice,
     Bob,
@@ -0,0 +1,378 @@
+//! Blockchains need a way to change their own rules. Rather than have a central party decide,
+//! many chains let their token holders vote on changes on chain, through referenda.
+//!
+//! In this module, any token holder may propose a change to one of the chain's parameters,
+//! such as the Proof of Work threshold. Token holders then vote on it, with weight according
+//! to their balance, until a voting window measured in block heights closes. A proposal that
+//! passes is not enacted right away. It is scheduled for a later height, which gives everyone
+//! time to prepare for the change. When that height arrives, the change is applied
+//! automatically, without anyone having to submit it.
+//!
+//! The machine can only know the block height if the chain tells it. So the chain is expected
+//! to begin every block with a `NewBlock` transition, much like Substrate's `on_initialize`.
+//!
+//! The token balances are fixed here. A real chain would read them from its currency, and
+//! lock the tokens of voters until the vote is over so that they can not be counted twice.
+
+use std::collections::BTreeMap;
+
+use super::{AccountId, Balances, StateMachine};
+
+/// The number of blocks that a proposal stays open for voting.
+pub const VOTING_PERIOD: u64 = 10;
+
+/// The number of blocks between a proposal passing and it being enacted.
+pub const ENACTMENT_DELAY: u64 = 5;
+
+/// Identifies a single proposal.
+pub type ProposalId = u64;
+
+/// The chain parameters that governance can change.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainParameters {
+    /// The largest work hash that a Proof of Work seal may have.
+    pub pow_threshold: u64,
+    /// The largest number of extrinsics that may be included in one block.
+    pub max_extrinsics: u64,
+}
+
+/// A change to one of the chain parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParameterChange {
+    SetPowThreshold(u64),
+    SetMaxExtrinsics(u64),
+}
+
+impl ParameterChange {
+    /// Apply this change to the given parameters.
+    fn apply(&self, parameters: &mut ChainParameters) {
+        match self {
+            ParameterChange::SetPowThreshold(threshold) => parameters.pow_threshold = *threshold,
+            ParameterChange::SetMaxExtrinsics(max) => parameters.max_extrinsics = *max,
+        }
+    }
+}
+
+/// A proposal that is open for voting.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Proposal {
+    /// The change that will be made if the proposal passes.
+    pub change: ParameterChange,
+    /// The height at which voting closes and the votes are counted.
+    pub voting_ends: u64,
+    /// The vote of every account that has voted, where `true` is in favor.
+    pub votes: BTreeMap<AccountId, bool>,
+}
+
+/// The state of the governance system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GovernanceState {
+    /// The height of the current block.
+    pub height: u64,
+    /// The token balance of every holder. This is each holder's voting weight.
+    pub holders: Balances,
+    /// The current chain parameters.
+    pub parameters: ChainParameters,
+    /// The id that the next proposal will get.
+    pub next_proposal_id: ProposalId,
+    /// The proposals that are open for voting.
+    pub proposals: BTreeMap<ProposalId, Proposal>,
+    /// Changes that have passed, keyed by the height at which they will be enacted.
+    pub scheduled: BTreeMap<u64, Vec<ParameterChange>>,
+}
+
+impl GovernanceState {
+    /// A governance system at height zero with the given holders and parameters, and no
+    /// proposals.
+    pub fn new(holders: Balances, parameters: ChainParameters) -> Self {
+        Self {
+            height: 0,
+            holders,
+            parameters,
+            next_proposal_id: 0,
+            proposals: BTreeMap::new(),
+            scheduled: BTreeMap::new(),
+        }
+    }
+
+    /// The total weight of the votes for and against the given proposal, in that order.
+    pub fn tally(&self, id: ProposalId) -> Option<(u64, u64)> {
+        let proposal = self.proposals.get(&id)?;
+        let mut ayes = 0u64;
+        let mut nays = 0u64;
+        for (who, aye) in &proposal.votes {
+            let weight = self.holders.get(who).copied().unwrap_or(0);
+            if *aye {
+                ayes = ayes.saturating_add(weight);
+            } else {
+                nays = nays.saturating_add(weight);
+            }
+        }
+        Some((ayes, nays))
+    }
+
+    fn is_holder(&self, who: &AccountId) -> bool {
+        self.holders.get(who).is_some_and(|balance| *balance > 0)
+    }
+}
+
+/// This state machine lets token holders change the chain parameters by referendum.
+pub struct Governance;
+
+/// The transitions that can be made in the governance system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GovernanceTransition {
+    /// Propose a parameter change. Rejected unless the proposer holds tokens.
+    Propose {
+        who: AccountId,
+        change: ParameterChange,
+    },
+    /// Vote for or against an open proposal. Voting again replaces the earlier vote.
+    /// Rejected unless the voter holds tokens and the proposal is open.
+    Vote {
+        who: AccountId,
+        proposal: ProposalId,
+        aye: bool,
+    },
+    /// Begin a new block. This closes voting on proposals whose window has ended, and
+    /// enacts any changes that are scheduled for the new height.
+    NewBlock,
+}
+
+impl StateMachine for Governance {
+    type State = GovernanceState;
+    type Transition = GovernanceTransition;
+
+    /// Rejected transitions leave the state unchanged.
+    fn next_state(starting_state: &GovernanceState, t: &GovernanceTransition) -> GovernanceState {
+        let mut state = starting_state.clone();
+        match t {
+            GovernanceTransition::Propose { who, change } => {
+                if !state.is_holder(who) {
+                    return state;
+                }
+                let proposal = Proposal {
+                    change: *change,
+                    voting_ends: state.height.saturating_add(VOTING_PERIOD),
+                    votes: BTreeMap::new(),
+                };
+                state.proposals.insert(state.next_proposal_id, proposal);
+                state.next_proposal_id += 1;
+            }
+            GovernanceTransition::Vote { who, proposal, aye } => {
+                if !state.is_holder(who) {
+                    return state;
+                }
+                if let Some(proposal) = state.proposals.get_mut(proposal) {
+                    proposal.votes.insert(*who, *aye);
+                }
+            }
+            GovernanceTransition::NewBlock => {
+                state.height = state.height.saturating_add(1);
+
+                // Count the votes on every proposal whose window has closed. A proposal passes
+                // with a simple majority of the votes cast. Ties fail.
+                let closed: Vec<ProposalId> = state
+                    .proposals
+                    .iter()
+                    .filter(|(_, proposal)| proposal.voting_ends <= state.height)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in closed {
+                    let (ayes, nays) = state.tally(id).expect("id was just read from proposals");
+                    let proposal = state.proposals.remove(&id).expect("just checked");
+                    if ayes > nays {
+                        let enact_at = state.height.saturating_add(ENACTMENT_DELAY);
+                        state
+                            .scheduled
+                            .entry(enact_at)
+                            .or_default()
+                            .push(proposal.change);
+                    }
+                }
+
+                // Enact everything that is due, in the order it was scheduled.
+                if let Some(changes) = state.scheduled.remove(&state.height) {
+                    for change in changes {
+                        change.apply(&mut state.parameters);
+                    }
+                }
+            }
+        }
+        state
+    }
+
+    fn human_name() -> String {
+        "Governance".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[cfg(test)]
+fn start() -> GovernanceState {
+    GovernanceState::new(
+        Balances::from([(Alice, 60), (Bob, 30), (Charlie, 20)]),
+        ChainParameters {
+            pow_threshold: 1000,
+            max_extrinsics: 100,
+        },
+    )
+}
+
+#[cfg(test)]
+fn new_blocks(state: &GovernanceState, n: u64) -> GovernanceState {
+    let blocks: Vec<_> = (0..n).map(|_| GovernanceTransition::NewBlock).collect();
+    Governance::apply_all(state, &blocks)
+}
+
+#[test]
+fn sm_10_passed_proposal_is_enacted_at_scheduled_height() {
+    let state = Governance::apply_all(
+        &start(),
+        &[
+            GovernanceTransition::Propose {
+                who: Bob,
+                change: ParameterChange::SetPowThreshold(500),
+            },
+            GovernanceTransition::Vote {
+                who: Bob,
+                proposal: 0,
+                aye: true,
+            },
+            GovernanceTransition::Vote {
+                who: Charlie,
+                proposal: 0,
+                aye: true,
+            },
+        ],
+    );
+    assert_eq!(state.tally(0), Some((50, 0)));
+
+    // Voting closes after the voting period, and the change is scheduled.
+    let state = new_blocks(&state, VOTING_PERIOD);
+    assert!(state.proposals.is_empty());
+    let enact_at = VOTING_PERIOD + ENACTMENT_DELAY;
+    assert_eq!(
+        state.scheduled,
+        BTreeMap::from([(enact_at, vec![ParameterChange::SetPowThreshold(500)])])
+    );
+
+    // Nothing changes until the scheduled height.
+    let state = new_blocks(&state, ENACTMENT_DELAY - 1);
+    assert_eq!(state.parameters.pow_threshold, 1000);
+
+    let state = new_blocks(&state, 1);
+    assert_eq!(state.height, enact_at);
+    assert_eq!(state.parameters.pow_threshold, 500);
+    assert_eq!(state.parameters.max_extrinsics, 100);
+    assert!(state.scheduled.is_empty());
+}
+
+#[test]
+fn sm_10_rejected_proposal_is_never_enacted() {
+    let state = Governance::apply_all(
+        &start(),
+        &[
+            GovernanceTransition::Propose {
+                who: Bob,
+                change: ParameterChange::SetMaxExtrinsics(1),
+            },
+            GovernanceTransition::Vote {
+                who: Bob,
+                proposal: 0,
+                aye: true,
+            },
+            GovernanceTransition::Vote {
+                who: Charlie,
+                proposal: 0,
+                aye: true,
+            },
+            // Alice's balance outweighs the other two together.
+            GovernanceTransition::Vote {
+                who: Alice,
+                proposal: 0,
+                aye: false,
+            },
+        ],
+    );
+    assert_eq!(state.tally(0), Some((50, 60)));
+
+    let state = new_blocks(&state, VOTING_PERIOD + ENACTMENT_DELAY);
+    assert!(state.proposals.is_empty());
+    assert!(state.scheduled.is_empty());
+    assert_eq!(state.parameters, start().parameters);
+}
+
+#[test]
+fn sm_10_voting_again_replaces_earlier_vote() {
+    let state = Governance::apply_all(
+        &start(),
+        &[
+            GovernanceTransition::Propose {
+                who: Alice,
+                change: ParameterChange::SetPowThreshold(2000),
+            },
+            GovernanceTransition::Vote {
+                who: Alice,
+                proposal: 0,
+                aye: false,
+            },
+            GovernanceTransition::Vote {
+                who: Alice,
+                proposal: 0,
+                aye: true,
+            },
+        ],
+    );
+    assert_eq!(state.tally(0), Some((60, 0)));
+}
+
+#[test]
+fn sm_10_votes_after_window_are_rejected() {
+    let state = Governance::next_state(
+        &start(),
+        &GovernanceTransition::Propose {
+            who: Alice,
+            change: ParameterChange::SetPowThreshold(2000),
+        },
+    );
+    let state = new_blocks(&state, VOTING_PERIOD);
+
+    let late_vote = GovernanceTransition::Vote {
+        who: Alice,
+        proposal: 0,
+        aye: true,
+    };
+    assert_eq!(Governance::next_state(&state, &late_vote), state);
+}
+
+#[test]
+fn sm_10_only_holders_may_propose_and_vote() {
+    let start = GovernanceState::new(Balances::from([(Alice, 10)]), start().parameters);
+    let propose = GovernanceTransition::Propose {
+        who: Bob,
+        change: ParameterChange::SetPowThreshold(0),
+    };
+    assert_eq!(Governance::next_state(&start, &propose), start);
+
+    let state = Governance::next_state(
+        &start,
+        &GovernanceTransition::Propose {
+            who: Alice,
+            change: ParameterChange::SetPowThreshold(0),
+        },
+    );
+    let vote = GovernanceTransition::Vote {
+        who: Bob,
+        proposal: 0,
+        aye: true,
+    };
+    assert_eq!(Governance::next_state(&state, &vote), state);
+}
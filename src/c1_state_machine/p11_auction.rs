@@ -0,0 +1,324 @@
+//! Everything on a blockchain is public. If bidders in an auction simply submitted their bids,
+//! the last bidder could see all the others and outbid the best of them by a single unit.
+//!
+//! The commit-reveal pattern solves this. In the commit phase, each bidder submits only a hash
+//! of their bid together with a secret salt. Nobody can learn the bid from the hash, but the
+//! bidder can not change it later either. Once the commit phase is over, the reveal phase
+//! begins, and each bidder reveals their bid and salt. Anyone can check that they hash to the
+//! commitment. Bids that were never committed, or that do not match their commitment, are
+//! simply ignored.
+//!
+//! Just like governance in Part 10, the phases are measured in block heights. Chains that execute
+//! transitions with a block context keep the height up to date automatically. Otherwise, the
+//! chain is expected to begin every block with a `NewBlock` transition.
+
+use std::collections::BTreeMap;
+
+use super::{AccountId, BlockContext, StateMachine, TracksHeight};
+use crate::hash;
+
+type Hash = u64;
+
+/// The commitment to a bid that a bidder submits in the commit phase.
+///
+/// The bidder is part of the commitment, so that nobody can copy someone else's commitment
+/// and then reveal the same bid as their own.
+pub fn bid_commitment(who: AccountId, amount: u64, salt: u64) -> Hash {
+    hash(&(who, amount, salt))
+}
+
+/// The phases of the auction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuctionPhase {
+    /// Bidders may commit to their bids.
+    Commit,
+    /// Bidders may reveal the bids they committed to.
+    Reveal,
+    /// The auction is over, and the winner is known.
+    Ended,
+}
+
+/// The state of a sealed-bid auction.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuctionState {
+    /// The height of the current block.
+    pub height: u64,
+    /// The first height at which commitments are no longer accepted, and reveals are.
+    pub commit_ends: u64,
+    /// The first height at which reveals are no longer accepted.
+    pub reveal_ends: u64,
+    /// The commitment of every bidder.
+    pub commitments: BTreeMap<AccountId, Hash>,
+    /// The bid of every bidder who has made a valid reveal.
+    pub revealed: BTreeMap<AccountId, u64>,
+    /// The highest bid revealed so far. Ties go to whoever revealed first.
+    pub leader: Option<(AccountId, u64)>,
+}
+
+impl AuctionState {
+    /// A new auction at height zero, with the given phase lengths in blocks.
+    pub fn new(commit_blocks: u64, reveal_blocks: u64) -> Self {
+        Self {
+            height: 0,
+            commit_ends: commit_blocks,
+            reveal_ends: commit_blocks.saturating_add(reveal_blocks),
+            commitments: BTreeMap::new(),
+            revealed: BTreeMap::new(),
+            leader: None,
+        }
+    }
+
+    /// The phase that the auction is in at the current height.
+    pub fn phase(&self) -> AuctionPhase {
+        if self.height < self.commit_ends {
+            AuctionPhase::Commit
+        } else if self.height < self.reveal_ends {
+            AuctionPhase::Reveal
+        } else {
+            AuctionPhase::Ended
+        }
+    }
+
+    /// The winning bidder and their bid, once the auction has ended. `None` while the auction
+    /// is still running, or if nobody made a valid reveal.
+    pub fn winner(&self) -> Option<(AccountId, u64)> {
+        match self.phase() {
+            AuctionPhase::Ended => self.leader,
+            _ => None,
+        }
+    }
+}
+
+impl TracksHeight for AuctionState {
+    fn height_mut(&mut self) -> &mut u64 {
+        &mut self.height
+    }
+}
+
+/// This state machine runs a sealed-bid auction with commit and reveal phases.
+pub struct SealedBidAuction;
+
+/// The transitions that can be made in a sealed-bid auction.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuctionTransition {
+    /// Commit to a bid during the commit phase. Committing again replaces the earlier
+    /// commitment.
+    Commit { who: AccountId, commitment: Hash },
+    /// Reveal a committed bid during the reveal phase. Each bidder may reveal once, and only
+    /// a bid that matches their commitment.
+    Reveal {
+        who: AccountId,
+        amount: u64,
+        salt: u64,
+    },
+    /// Begin a new block, which may move the auction on to its next phase.
+    NewBlock,
+}
+
+impl StateMachine for SealedBidAuction {
+    type State = AuctionState;
+    type Transition = AuctionTransition;
+
+    /// Invalid reveals and bids made in the wrong phase leave the state unchanged.
+    fn next_state(starting_state: &AuctionState, t: &AuctionTransition) -> AuctionState {
+        let mut state = starting_state.clone();
+        match t {
+            AuctionTransition::Commit { who, commitment } => {
+                if state.phase() == AuctionPhase::Commit {
+                    state.commitments.insert(*who, *commitment);
+                }
+            }
+            AuctionTransition::Reveal { who, amount, salt } => {
+                if state.phase() != AuctionPhase::Reveal || state.revealed.contains_key(who) {
+                    return state;
+                }
+                if state.commitments.get(who) != Some(&bid_commitment(*who, *amount, *salt)) {
+                    return state;
+                }
+                state.revealed.insert(*who, *amount);
+                if state.leader.is_none_or(|(_, best)| *amount > best) {
+                    state.leader = Some((*who, *amount));
+                }
+            }
+            AuctionTransition::NewBlock => {
+                state.height = state.height.saturating_add(1);
+            }
+        }
+        state
+    }
+
+    fn next_state_in_block(
+        starting_state: &AuctionState,
+        t: &AuctionTransition,
+        context: &BlockContext,
+    ) -> AuctionState {
+        Self::next_state(&starting_state.caught_up(context), t)
+    }
+
+    fn human_name() -> String {
+        "Sealed-Bid Auction".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[cfg(test)]
+fn commit(who: AccountId, amount: u64, salt: u64) -> AuctionTransition {
+    AuctionTransition::Commit {
+        who,
+        commitment: bid_commitment(who, amount, salt),
+    }
+}
+
+#[cfg(test)]
+fn reveal(who: AccountId, amount: u64, salt: u64) -> AuctionTransition {
+    AuctionTransition::Reveal { who, amount, salt }
+}
+
+#[test]
+fn sm_11_highest_revealed_bid_wins() {
+    use AuctionTransition::NewBlock;
+    let end = SealedBidAuction::apply_all(
+        &AuctionState::new(2, 2),
+        &[
+            commit(Alice, 10, 1),
+            commit(Bob, 30, 2),
+            NewBlock,
+            commit(Charlie, 20, 3),
+            NewBlock,
+            reveal(Alice, 10, 1),
+            reveal(Bob, 30, 2),
+            reveal(Charlie, 20, 3),
+        ],
+    );
+    assert_eq!(end.phase(), AuctionPhase::Reveal);
+    assert_eq!(end.winner(), None);
+
+    let end = SealedBidAuction::apply_all(&end, &[NewBlock, NewBlock]);
+    assert_eq!(end.phase(), AuctionPhase::Ended);
+    assert_eq!(end.winner(), Some((Bob, 30)));
+}
+
+#[test]
+fn sm_11_invalid_reveals_are_ignored() {
+    let start = SealedBidAuction::apply_all(
+        &AuctionState::new(1, 1),
+        &[
+            commit(Alice, 10, 1),
+            commit(Bob, 30, 2),
+            AuctionTransition::NewBlock,
+            reveal(Alice, 10, 1),
+        ],
+    );
+
+    // Wrong amount, wrong salt, someone else's commitment, and a bidder who never committed.
+    for invalid in [
+        reveal(Bob, 31, 2),
+        reveal(Bob, 30, 3),
+        reveal(Charlie, 30, 2),
+        reveal(Charlie, 0, 0),
+    ] {
+        assert_eq!(SealedBidAuction::next_state(&start, &invalid), start);
+    }
+
+    // Alice has already revealed, so she can not reveal again.
+    assert_eq!(
+        SealedBidAuction::next_state(&start, &reveal(Alice, 10, 1)),
+        start
+    );
+}
+
+#[test]
+fn sm_11_late_bids_and_reveals_are_ignored() {
+    let reveal_phase = SealedBidAuction::apply_all(
+        &AuctionState::new(1, 1),
+        &[commit(Alice, 10, 1), AuctionTransition::NewBlock],
+    );
+    assert_eq!(
+        SealedBidAuction::next_state(&reveal_phase, &commit(Bob, 30, 2)),
+        reveal_phase
+    );
+
+    // Reveals are not accepted during the commit phase either.
+    let commit_phase =
+        SealedBidAuction::apply_all(&AuctionState::new(1, 1), &[commit(Alice, 10, 1)]);
+    assert_eq!(
+        SealedBidAuction::next_state(&commit_phase, &reveal(Alice, 10, 1)),
+        commit_phase
+    );
+
+    let ended = SealedBidAuction::next_state(&reveal_phase, &AuctionTransition::NewBlock);
+    assert_eq!(
+        SealedBidAuction::next_state(&ended, &reveal(Alice, 10, 1)),
+        ended
+    );
+    assert_eq!(ended.winner(), None);
+}
+
+#[test]
+fn sm_11_ties_go_to_first_reveal() {
+    let end = SealedBidAuction::apply_all(
+        &AuctionState::new(1, 1),
+        &[
+            commit(Alice, 10, 1),
+            commit(Bob, 10, 2),
+            AuctionTransition::NewBlock,
+            reveal(Bob, 10, 2),
+            reveal(Alice, 10, 1),
+            AuctionTransition::NewBlock,
+        ],
+    );
+    assert_eq!(end.winner(), Some((Bob, 10)));
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+#[cfg(test)]
+proptest! {
+    /// Every bidder commits to a bid, and then either reveals it honestly, reveals the wrong
+    /// amount, reveals too late, or does not reveal at all. Whatever happens, the highest bid
+    /// that was revealed honestly and in time wins.
+    #[test]
+    fn sm_11_highest_valid_revealed_bid_always_wins(
+        bids in proptest::collection::vec((0..1000u64, any::<u64>(), 0..4u8), 3),
+        reveal_order in Just([Alice, Bob, Charlie]).prop_shuffle(),
+    ) {
+        let bidders = [Alice, Bob, Charlie];
+        let mut transitions: Vec<_> = bidders
+            .iter()
+            .zip(&bids)
+            .map(|(who, (amount, salt, _))| commit(*who, *amount, *salt))
+            .collect();
+        transitions.push(AuctionTransition::NewBlock);
+
+        let mut valid = Vec::new();
+        let mut late = Vec::new();
+        for who in reveal_order {
+            let i = bidders.iter().position(|b| *b == who).unwrap();
+            let (amount, salt, behavior) = bids[i];
+            match behavior {
+                0 => {
+                    transitions.push(reveal(who, amount, salt));
+                    valid.push(amount);
+                }
+                1 => transitions.push(reveal(who, amount + 1, salt)),
+                2 => late.push(reveal(who, amount, salt)),
+                _ => {}
+            }
+        }
+        transitions.push(AuctionTransition::NewBlock);
+        transitions.extend(late);
+
+        let end = SealedBidAuction::apply_all(&AuctionState::new(1, 1), &transitions);
+        let winning_bid = end.winner().map(|(_, amount)| amount);
+        prop_assert_eq!(winning_bid, valid.iter().copied().max());
+        if let Some((winner, amount)) = end.winner() {
+            prop_assert_eq!(end.revealed.get(&winner), Some(&amount));
+        }
+    }
+}
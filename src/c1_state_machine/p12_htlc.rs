@@ -0,0 +1,328 @@
+//! A hash-time-locked contract, or HTLC, is an escrow with two ways out. The funds are locked
+//! against a hash. Whoever reveals the preimage of that hash before a deadline releases the
+//! funds to the recipient. If nobody does, the sender may take the funds back once the
+//! deadline has passed.
+//!
+//! HTLCs are the building block of atomic swaps between chains. Alice picks a secret and locks
+//! her coins on one chain against its hash. Bob sees the hash, and locks his coins on the other
+//! chain against the same hash, with an earlier deadline. To claim Bob's coins, Alice must
+//! reveal the secret on the other chain. Once she has, Bob can use it to claim her coins too.
+//! Either both transfers happen, or both are refunded.
+//!
+//! Like the auction in Part 11, deadlines are measured in block heights, which come from the
+//! block context, or from `NewBlock` transitions on chains without one.
+
+use std::collections::BTreeMap;
+
+use super::{AccountId, Balances, BlockContext, StateMachine, TracksHeight};
+use crate::hash;
+
+type Hash = u64;
+
+/// The hash that funds are locked against, for the given secret preimage.
+pub fn hash_lock(preimage: u64) -> Hash {
+    hash(&preimage)
+}
+
+/// Identifies a single lock.
+pub type LockId = u64;
+
+/// Funds that are held in escrow.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HashTimeLock {
+    /// The account that locked the funds, and that gets them back on a refund.
+    pub sender: AccountId,
+    /// The account that gets the funds when the preimage is revealed.
+    pub recipient: AccountId,
+    /// The amount of funds that are locked.
+    pub amount: u64,
+    /// The hash whose preimage releases the funds.
+    pub hash_lock: Hash,
+    /// The first height at which the funds can no longer be claimed, and can be refunded.
+    pub deadline: u64,
+}
+
+/// The state of the escrow system.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EscrowState {
+    /// The height of the current block.
+    pub height: u64,
+    /// The free balance of every account. Locked funds are not included.
+    pub balances: Balances,
+    /// The id that the next lock will get.
+    pub next_lock_id: LockId,
+    /// The funds that are currently held in escrow.
+    pub locks: BTreeMap<LockId, HashTimeLock>,
+    /// The preimages that have been revealed by claims, so that the other party of a swap can
+    /// read them from the chain.
+    pub revealed: BTreeMap<Hash, u64>,
+}
+
+impl EscrowState {
+    /// A system at height zero with the given free balances and nothing locked.
+    pub fn new(balances: Balances) -> Self {
+        Self {
+            balances,
+            ..Default::default()
+        }
+    }
+
+    /// The free balance of the given account.
+    pub fn balance(&self, who: AccountId) -> u64 {
+        self.balances.get(&who).copied().unwrap_or(0)
+    }
+
+    /// Add funds to the given account. Returns `false`, without changing anything, if the
+    /// balance would overflow.
+    fn credit(&mut self, who: AccountId, amount: u64) -> bool {
+        let Some(balance) = self.balance(who).checked_add(amount) else {
+            return false;
+        };
+        if balance > 0 {
+            self.balances.insert(who, balance);
+        }
+        true
+    }
+}
+
+impl TracksHeight for EscrowState {
+    fn height_mut(&mut self) -> &mut u64 {
+        &mut self.height
+    }
+}
+
+/// This state machine holds funds in hash-time-locked escrow.
+pub struct Escrow;
+
+/// The transitions that can be made in the escrow system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EscrowTransition {
+    /// Move funds from the sender's free balance into a new lock. Rejected if the sender can
+    /// not afford it, or if the deadline has already passed.
+    Lock {
+        sender: AccountId,
+        recipient: AccountId,
+        amount: u64,
+        hash_lock: Hash,
+        deadline: u64,
+    },
+    /// Release the funds of a lock to its recipient by revealing the preimage. Anyone may do
+    /// this, but only before the deadline.
+    Claim { lock: LockId, preimage: u64 },
+    /// Return the funds of a lock to its sender. Only allowed once the deadline has passed.
+    Refund { lock: LockId },
+    /// Begin a new block.
+    NewBlock,
+}
+
+impl StateMachine for Escrow {
+    type State = EscrowState;
+    type Transition = EscrowTransition;
+
+    /// Rejected transitions leave the state unchanged.
+    fn next_state(starting_state: &EscrowState, t: &EscrowTransition) -> EscrowState {
+        let mut state = starting_state.clone();
+        match t {
+            EscrowTransition::Lock {
+                sender,
+                recipient,
+                amount,
+                hash_lock,
+                deadline,
+            } => {
+                if *deadline <= state.height {
+                    return state;
+                }
+                let Some(remaining) = state.balance(*sender).checked_sub(*amount) else {
+                    return state;
+                };
+                if remaining == 0 {
+                    state.balances.remove(sender);
+                } else {
+                    state.balances.insert(*sender, remaining);
+                }
+                let lock = HashTimeLock {
+                    sender: *sender,
+                    recipient: *recipient,
+                    amount: *amount,
+                    hash_lock: *hash_lock,
+                    deadline: *deadline,
+                };
+                state.locks.insert(state.next_lock_id, lock);
+                state.next_lock_id += 1;
+            }
+            EscrowTransition::Claim { lock, preimage } => {
+                let Some(htlc) = state.locks.get(lock).cloned() else {
+                    return state;
+                };
+                if state.height >= htlc.deadline || hash_lock(*preimage) != htlc.hash_lock {
+                    return state;
+                }
+                if !state.credit(htlc.recipient, htlc.amount) {
+                    return state;
+                }
+                state.locks.remove(lock);
+                state.revealed.insert(htlc.hash_lock, *preimage);
+            }
+            EscrowTransition::Refund { lock } => {
+                let Some(htlc) = state.locks.get(lock).cloned() else {
+                    return state;
+                };
+                if state.height < htlc.deadline || !state.credit(htlc.sender, htlc.amount) {
+                    return state;
+                }
+                state.locks.remove(lock);
+            }
+            EscrowTransition::NewBlock => {
+                state.height = state.height.saturating_add(1);
+            }
+        }
+        state
+    }
+
+    fn next_state_in_block(
+        starting_state: &EscrowState,
+        t: &EscrowTransition,
+        context: &BlockContext,
+    ) -> EscrowState {
+        Self::next_state(&starting_state.caught_up(context), t)
+    }
+
+    fn human_name() -> String {
+        "Hash-Time-Locked Escrow".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[cfg(test)]
+const SECRET: u64 = 0xdead_beef;
+
+/// Alice has locked 40 of her 100 for Bob against the hash of `SECRET`, until height 5.
+#[cfg(test)]
+fn locked() -> EscrowState {
+    Escrow::next_state(
+        &EscrowState::new(Balances::from([(Alice, 100)])),
+        &EscrowTransition::Lock {
+            sender: Alice,
+            recipient: Bob,
+            amount: 40,
+            hash_lock: hash_lock(SECRET),
+            deadline: 5,
+        },
+    )
+}
+
+#[cfg(test)]
+fn new_blocks(state: &EscrowState, n: u64) -> EscrowState {
+    (0..n).fold(state.clone(), |state, _| {
+        Escrow::next_state(&state, &EscrowTransition::NewBlock)
+    })
+}
+
+#[test]
+fn sm_12_lock_moves_funds_into_escrow() {
+    let state = locked();
+    assert_eq!(state.balances, Balances::from([(Alice, 60)]));
+    assert_eq!(state.locks[&0].amount, 40);
+
+    // Alice can not lock more than she has left.
+    let too_much = EscrowTransition::Lock {
+        sender: Alice,
+        recipient: Bob,
+        amount: 61,
+        hash_lock: hash_lock(SECRET),
+        deadline: 5,
+    };
+    assert_eq!(Escrow::next_state(&state, &too_much), state);
+}
+
+#[test]
+fn sm_12_claim_with_preimage_before_deadline() {
+    let state = new_blocks(&locked(), 4);
+    let end = Escrow::next_state(
+        &state,
+        &EscrowTransition::Claim {
+            lock: 0,
+            preimage: SECRET,
+        },
+    );
+    assert_eq!(end.balances, Balances::from([(Alice, 60), (Bob, 40)]));
+    assert!(end.locks.is_empty());
+    assert_eq!(end.revealed, BTreeMap::from([(hash_lock(SECRET), SECRET)]));
+
+    // The funds can not be claimed or refunded a second time.
+    let end = new_blocks(&end, 1);
+    assert_eq!(
+        Escrow::next_state(&end, &EscrowTransition::Refund { lock: 0 }),
+        end
+    );
+}
+
+#[test]
+fn sm_12_claim_with_wrong_preimage_is_rejected() {
+    let state = locked();
+    let claim = EscrowTransition::Claim {
+        lock: 0,
+        preimage: SECRET + 1,
+    };
+    assert_eq!(Escrow::next_state(&state, &claim), state);
+}
+
+#[test]
+fn sm_12_refund_only_after_deadline() {
+    let state = new_blocks(&locked(), 4);
+    let refund = EscrowTransition::Refund { lock: 0 };
+    assert_eq!(Escrow::next_state(&state, &refund), state);
+
+    // Once the deadline is reached, the preimage is no good, but the refund is.
+    let state = new_blocks(&state, 1);
+    let late_claim = EscrowTransition::Claim {
+        lock: 0,
+        preimage: SECRET,
+    };
+    assert_eq!(Escrow::next_state(&state, &late_claim), state);
+
+    let end = Escrow::next_state(&state, &refund);
+    assert_eq!(end.balances, Balances::from([(Alice, 100)]));
+    assert!(end.locks.is_empty());
+}
+
+#[test]
+fn sm_12_atomic_swap() {
+    // Alice and Bob swap coins on two chains. Alice locks hers first, with a late deadline,
+    // and Bob locks his against the same hash with an earlier one.
+    let alices_chain = locked();
+    let bobs_chain = Escrow::next_state(
+        &EscrowState::new(Balances::from([(Bob, 10)])),
+        &EscrowTransition::Lock {
+            sender: Bob,
+            recipient: Alice,
+            amount: 10,
+            hash_lock: alices_chain.locks[&0].hash_lock,
+            deadline: 3,
+        },
+    );
+
+    // Alice claims Bob's coins, which reveals the secret on Bob's chain.
+    let bobs_chain = Escrow::next_state(
+        &bobs_chain,
+        &EscrowTransition::Claim {
+            lock: 0,
+            preimage: SECRET,
+        },
+    );
+    assert_eq!(bobs_chain.balance(Alice), 10);
+
+    // Bob reads the secret, and uses it to claim Alice's coins.
+    let preimage = bobs_chain.revealed[&alices_chain.locks[&0].hash_lock];
+    let alices_chain = Escrow::next_state(
+        &alices_chain,
+        &EscrowTransition::Claim { lock: 0, preimage },
+    );
+    assert_eq!(alices_chain.balance(Bob), 40);
+}
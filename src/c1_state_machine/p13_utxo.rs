@@ -0,0 +1,410 @@
+//! There are two popular ways to track who owns what on a blockchain. The account model, used
+//! by Ethereum and Polkadot and by our `Currency` from Part 8, stores a balance for every
+//! account. The UTXO model, used by Bitcoin, stores a set of unspent transaction outputs
+//! instead. Each output is a coin of some value with an owner. A transaction consumes some
+//! outputs entirely, and creates new ones in their place. The digital cash system from Part 5
+//! is an early version of this idea.
+//!
+//! An output is identified by the hash of the transaction that created it, together with its
+//! position in that transaction's outputs. Spending an output removes it from the set, so
+//! trying to spend it again is simply spending an output that does not exist. This is how the
+//! UTXO model rejects double spends without keeping any nonces.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{AccountId, Balances, StateMachine};
+use crate::hash;
+
+type Hash = u64;
+
+/// Identifies an output by the transaction that created it and its position in that
+/// transaction's outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutPoint {
+    /// The hash of the transaction that created the output.
+    pub tx: Hash,
+    /// The position of the output in the transaction's outputs.
+    pub index: u32,
+}
+
+/// A coin of some value, owned by some account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Output {
+    pub owner: AccountId,
+    pub amount: u64,
+}
+
+/// The state of the UTXO ledger.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UtxoSet {
+    /// Every output that has been created and not yet spent.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pairs"))]
+    pub unspent: BTreeMap<OutPoint, Output>,
+}
+
+impl UtxoSet {
+    /// A UTXO set that gives every account a single output worth its balance.
+    pub fn from_balances(balances: &Balances) -> Self {
+        balances
+            .iter()
+            .fold(UtxoSet::default(), |set, (to, amount)| {
+                Utxo::next_state(
+                    &set,
+                    &UtxoTransaction::Mint {
+                        to: *to,
+                        amount: *amount,
+                    },
+                )
+            })
+    }
+
+    /// The balance of every account, found by adding up the outputs it owns. This is the
+    /// account model's view of the same ledger.
+    pub fn balances(&self) -> Balances {
+        let mut balances = Balances::new();
+        for output in self.unspent.values() {
+            let balance = balances.entry(output.owner).or_default();
+            *balance = balance.saturating_add(output.amount);
+        }
+        balances
+    }
+
+    /// The unspent outputs owned by the given account.
+    pub fn outputs_of(&self, who: AccountId) -> Vec<(OutPoint, Output)> {
+        self.unspent
+            .iter()
+            .filter(|(_, output)| output.owner == who)
+            .map(|(outpoint, output)| (*outpoint, *output))
+            .collect()
+    }
+}
+
+/// This state machine tracks ownership as a set of unspent transaction outputs.
+pub struct Utxo;
+
+/// The transactions that can be made in the UTXO ledger.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UtxoTransaction {
+    /// Create a single new output out of thin air.
+    Mint { to: AccountId, amount: u64 },
+    /// Consume the given outputs, all of which must belong to the owner, and create new ones.
+    /// The outputs may not be worth more than the inputs. Anything left over is destroyed, so
+    /// a sender who wants change must pay it back to themselves.
+    Transfer {
+        owner: AccountId,
+        inputs: Vec<OutPoint>,
+        outputs: Vec<Output>,
+    },
+}
+
+impl UtxoTransaction {
+    /// The outpoints at which this transaction's outputs will be stored.
+    ///
+    /// Two identical transactions would create outputs at the same outpoints. For transfers
+    /// this can not happen, because the second one's inputs are already spent. Two identical
+    /// mints are a real problem though. Bitcoin had exactly this bug, and fixed it by
+    /// rejecting any transaction that would overwrite an unspent output. We do the same.
+    pub fn outpoints(&self) -> Vec<OutPoint> {
+        let tx = hash(self);
+        let count = match self {
+            UtxoTransaction::Mint { .. } => 1,
+            UtxoTransaction::Transfer { outputs, .. } => outputs.len(),
+        };
+        (0..count as u32)
+            .map(|index| OutPoint { tx, index })
+            .collect()
+    }
+}
+
+impl StateMachine for Utxo {
+    type State = UtxoSet;
+    type Transition = UtxoTransaction;
+
+    /// Invalid transactions, including double spends, leave the set unchanged.
+    fn next_state(starting_state: &UtxoSet, t: &UtxoTransaction) -> UtxoSet {
+        let mut state = starting_state.clone();
+        let new_outputs = match t {
+            UtxoTransaction::Mint { to, amount } => vec![Output {
+                owner: *to,
+                amount: *amount,
+            }],
+            UtxoTransaction::Transfer {
+                owner,
+                inputs,
+                outputs,
+            } => {
+                // Every input must exist, belong to the owner, and be spent only once.
+                let distinct: BTreeSet<_> = inputs.iter().collect();
+                if inputs.is_empty() || distinct.len() != inputs.len() {
+                    return state;
+                }
+                let mut total_in = 0u64;
+                for input in inputs {
+                    match state.unspent.get(input) {
+                        Some(output) if output.owner == *owner => {
+                            let Some(total) = total_in.checked_add(output.amount) else {
+                                return state;
+                            };
+                            total_in = total;
+                        }
+                        _ => return state,
+                    }
+                }
+                let total_out = outputs
+                    .iter()
+                    .try_fold(0u64, |total, output| total.checked_add(output.amount));
+                if total_out.is_none_or(|total_out| total_out > total_in) {
+                    return state;
+                }
+                for input in inputs {
+                    state.unspent.remove(input);
+                }
+                outputs.clone()
+            }
+        };
+
+        if new_outputs.iter().any(|output| output.amount == 0) {
+            return starting_state.clone();
+        }
+        let outpoints = t.outpoints();
+        if outpoints.iter().any(|o| state.unspent.contains_key(o)) {
+            return starting_state.clone();
+        }
+        state.unspent.extend(outpoints.into_iter().zip(new_outputs));
+        state
+    }
+
+    fn human_name() -> String {
+        "UTXO Ledger".into()
+    }
+}
+
+#[cfg(test)]
+use super::{Currency, CurrencyTransaction, User::*};
+
+/// The outpoint of Alice's first coin.
+#[cfg(test)]
+fn alices_coin(set: &UtxoSet) -> OutPoint {
+    set.outputs_of(Alice)[0].0
+}
+
+#[test]
+fn sm_13_transfer_with_change() {
+    let start = UtxoSet::from_balances(&Balances::from([(Alice, 100)]));
+    let transfer = UtxoTransaction::Transfer {
+        owner: Alice,
+        inputs: vec![alices_coin(&start)],
+        outputs: vec![
+            Output {
+                owner: Bob,
+                amount: 30,
+            },
+            Output {
+                owner: Alice,
+                amount: 70,
+            },
+        ],
+    };
+    let end = Utxo::next_state(&start, &transfer);
+
+    assert_eq!(end.unspent.len(), 2);
+    assert!(!end.unspent.contains_key(&alices_coin(&start)));
+    assert_eq!(end.balances(), Balances::from([(Alice, 70), (Bob, 30)]));
+    assert_eq!(end.unspent.keys().copied().collect::<Vec<_>>(), {
+        let mut outpoints = transfer.outpoints();
+        outpoints.sort();
+        outpoints
+    });
+}
+
+#[test]
+fn sm_13_double_spend_is_rejected() {
+    let start = UtxoSet::from_balances(&Balances::from([(Alice, 100)]));
+    let coin = alices_coin(&start);
+    let pay = |to| UtxoTransaction::Transfer {
+        owner: Alice,
+        inputs: vec![coin],
+        outputs: vec![Output {
+            owner: to,
+            amount: 100,
+        }],
+    };
+    let spent = Utxo::next_state(&start, &pay(Bob));
+    assert_eq!(spent.balances(), Balances::from([(Bob, 100)]));
+
+    // The coin is gone, so it can not be spent again, whether to the same recipient or not.
+    assert_eq!(Utxo::next_state(&spent, &pay(Bob)), spent);
+    assert_eq!(Utxo::next_state(&spent, &pay(Charlie)), spent);
+
+    // Nor can it be spent twice within one transaction.
+    let twice = UtxoTransaction::Transfer {
+        owner: Alice,
+        inputs: vec![coin, coin],
+        outputs: vec![Output {
+            owner: Bob,
+            amount: 200,
+        }],
+    };
+    assert_eq!(Utxo::next_state(&start, &twice), start);
+}
+
+#[test]
+fn sm_13_invalid_transfers_are_rejected() {
+    let start = UtxoSet::from_balances(&Balances::from([(Alice, 100), (Bob, 5)]));
+    let coin = alices_coin(&start);
+    let invalid = [
+        // Bob does not own Alice's coin.
+        UtxoTransaction::Transfer {
+            owner: Bob,
+            inputs: vec![coin],
+            outputs: vec![Output {
+                owner: Bob,
+                amount: 100,
+            }],
+        },
+        // The outputs are worth more than the inputs.
+        UtxoTransaction::Transfer {
+            owner: Alice,
+            inputs: vec![coin],
+            outputs: vec![Output {
+                owner: Bob,
+                amount: 101,
+            }],
+        },
+        // An output worth nothing.
+        UtxoTransaction::Transfer {
+            owner: Alice,
+            inputs: vec![coin],
+            outputs: vec![Output {
+                owner: Bob,
+                amount: 0,
+            }],
+        },
+        // An input that never existed.
+        UtxoTransaction::Transfer {
+            owner: Alice,
+            inputs: vec![OutPoint { tx: 0, index: 0 }],
+            outputs: vec![],
+        },
+    ];
+    for t in invalid {
+        assert_eq!(Utxo::next_state(&start, &t), start);
+    }
+}
+
+#[test]
+fn sm_13_identical_mints_can_not_overwrite_outputs() {
+    let mint = UtxoTransaction::Mint {
+        to: Alice,
+        amount: 10,
+    };
+    let once = Utxo::next_state(&UtxoSet::default(), &mint);
+    assert_eq!(Utxo::next_state(&once, &mint), once);
+}
+
+#[test]
+fn sm_13_utxo_and_account_models_agree_on_balances() {
+    let genesis = Balances::from([(Alice, 100), (Bob, 50)]);
+    let utxos = UtxoSet::from_balances(&genesis);
+    assert_eq!(utxos.balances(), genesis);
+
+    // Alice pays Bob 30. The account model just moves the balance.
+    let accounts = Currency::next_state(
+        &genesis,
+        &CurrencyTransaction::Transfer {
+            from: Alice,
+            to: Bob,
+            amount: 30,
+        },
+    );
+
+    // The UTXO model spends Alice's whole coin, and pays her the change.
+    let utxos = Utxo::next_state(
+        &utxos,
+        &UtxoTransaction::Transfer {
+            owner: Alice,
+            inputs: vec![alices_coin(&utxos)],
+            outputs: vec![
+                Output {
+                    owner: Bob,
+                    amount: 30,
+                },
+                Output {
+                    owner: Alice,
+                    amount: 70,
+                },
+            ],
+        },
+    );
+    assert_eq!(utxos.balances(), accounts);
+
+    // Bob now owns two separate coins, where the account model only has one balance.
+    assert_eq!(utxos.outputs_of(Bob).len(), 2);
+    assert_eq!(accounts[&Bob], 80);
+
+    // Converting back and forth merges coins, but keeps the balances.
+    let merged = UtxoSet::from_balances(&utxos.balances());
+    assert_eq!(merged.outputs_of(Bob).len(), 1);
+    assert_eq!(merged.balances(), accounts);
+}
+
+#[test]
+fn sm_13_replayed_transfer_differs_between_models() {
+    let genesis = Balances::from([(Alice, 100)]);
+    let pay = CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 10,
+    };
+    // Without nonces, the account model happily applies the same transfer twice.
+    let accounts = Currency::next_state(&Currency::next_state(&genesis, &pay), &pay);
+    assert_eq!(accounts, Balances::from([(Alice, 80), (Bob, 20)]));
+
+    // The same transfer in the UTXO model can only ever be applied once.
+    let utxos = UtxoSet::from_balances(&genesis);
+    let pay = UtxoTransaction::Transfer {
+        owner: Alice,
+        inputs: vec![alices_coin(&utxos)],
+        outputs: vec![
+            Output {
+                owner: Bob,
+                amount: 10,
+            },
+            Output {
+                owner: Alice,
+                amount: 90,
+            },
+        ],
+    };
+    let once = Utxo::next_state(&utxos, &pay);
+    assert_eq!(Utxo::next_state(&once, &pay), once);
+    assert_eq!(once.balances(), Balances::from([(Alice, 90), (Bob, 10)]));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn sm_13_utxo_set_survives_json() {
+    // JSON objects can only have strings as keys, so the outputs are stored as a list.
+    let utxos = UtxoSet::from_balances(&Balances::from([(Alice, 100), (Bob, 50)]));
+    let json = serde_json::to_string(&utxos).unwrap();
+    assert!(json.starts_with(r#"{"unspent":[["#));
+    assert_eq!(serde_json::from_str::<UtxoSet>(&json).unwrap(), utxos);
+
+    let spend = UtxoTransaction::Transfer {
+        owner: Alice,
+        inputs: vec![alices_coin(&utxos)],
+        outputs: vec![Output {
+            owner: Bob,
+            amount: 100,
+        }],
+    };
+    let json = serde_json::to_string(&spend).unwrap();
+    assert_eq!(
+        serde_json::from_str::<UtxoTransaction>(&json).unwrap(),
+        spend
+    );
+}
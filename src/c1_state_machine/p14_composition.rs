@@ -0,0 +1,174 @@
+//! Real chains do many things at once. A single chain may have balances, staking, and
+//! governance all running side by side. Rather than writing one enormous state machine that
+//! does everything, we can compose small ones.
+//!
+//! A tuple of state machines is itself a state machine. Its state is the tuple of the
+//! components' states, and each of its transitions is a transition of exactly one of the
+//! components, which is dispatched to that component alone. This is the same idea that
+//! Substrate's runtime uses to combine its pallets, where the combined transition type is
+//! the outer `Call` enum.
+
+use super::{BlockContext, StateMachine};
+
+/// Implement `StateMachine` for a tuple of state machines, together with the enum of
+/// transitions that dispatches to the components.
+macro_rules! impl_composite_state_machine {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident($sm:ident, $index:tt)),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name<$($sm),+> {
+            $($variant($sm)),+
+        }
+
+        impl<$($sm: StateMachine),+> StateMachine for ($($sm,)+)
+        where
+            $($sm::State: Clone),+
+        {
+            type State = ($($sm::State,)+);
+            type Transition = $name<$($sm::Transition),+>;
+
+            /// Only the component that the transition belongs to changes. The others are
+            /// left exactly as they were.
+            fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+                let mut state = starting_state.clone();
+                match t {
+                    $($name::$variant(t) => {
+                        state.$index = $sm::next_state(&starting_state.$index, t);
+                    })+
+                }
+                state
+            }
+
+            /// Every component sees the same block context.
+            fn next_state_in_block(
+                starting_state: &Self::State,
+                t: &Self::Transition,
+                context: &BlockContext,
+            ) -> Self::State {
+                let mut state = starting_state.clone();
+                match t {
+                    $($name::$variant(t) => {
+                        state.$index =
+                            $sm::next_state_in_block(&starting_state.$index, t, context);
+                    })+
+                }
+                state
+            }
+
+            fn human_name() -> String {
+                [$($sm::human_name()),+].join(" + ")
+            }
+        }
+    };
+}
+
+impl_composite_state_machine! {
+    /// A transition of a pair of state machines.
+    OneOf2 { First(A, 0), Second(B, 1) }
+}
+
+impl_composite_state_machine! {
+    /// A transition of a triple of state machines.
+    OneOf3 { First(A, 0), Second(B, 1), Third(C, 2) }
+}
+
+impl_composite_state_machine! {
+    /// A transition of a tuple of four state machines.
+    OneOf4 { First(A, 0), Second(B, 1), Third(C, 2), Fourth(D, 3) }
+}
+
+impl_composite_state_machine! {
+    /// A transition of a tuple of five state machines.
+    OneOf5 { First(A, 0), Second(B, 1), Third(C, 2), Fourth(D, 3), Fifth(E, 4) }
+}
+
+#[cfg(test)]
+use super::{
+    Balances, ChainParameters, Currency, CurrencyTransaction, Governance, GovernanceState,
+    GovernanceTransition, ParameterChange, Stakes, Staking, StakingLedger, StakingTransition,
+    User::*,
+};
+#[cfg(test)]
+use crate::c3_consensus::ConsensusAuthority;
+
+/// Balances, staking, and governance, all running as one machine.
+#[cfg(test)]
+type Runtime = (Currency, Staking, Governance);
+
+#[cfg(test)]
+fn genesis() -> <Runtime as StateMachine>::State {
+    (
+        Balances::from([(Alice, 100)]),
+        StakingLedger::new(Stakes::from([(ConsensusAuthority::Alice, 50)])),
+        GovernanceState::new(
+            Balances::from([(Alice, 10)]),
+            ChainParameters {
+                pow_threshold: 1000,
+                max_extrinsics: 100,
+            },
+        ),
+    )
+}
+
+#[test]
+fn sm_14_transitions_only_touch_their_component() {
+    let start = genesis();
+
+    let pay = OneOf3::First(CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 40,
+    });
+    let end = Runtime::next_state(&start, &pay);
+    assert_eq!(end.0, Balances::from([(Alice, 60), (Bob, 40)]));
+    assert_eq!(end.1, start.1);
+    assert_eq!(end.2, start.2);
+
+    let bond = OneOf3::Second(StakingTransition::Bond {
+        who: ConsensusAuthority::Bob,
+        amount: 5,
+    });
+    let end = Runtime::next_state(&end, &bond);
+    assert_eq!(end.0, Balances::from([(Alice, 60), (Bob, 40)]));
+    assert_eq!(end.1.active_stake(ConsensusAuthority::Bob), 5);
+
+    let propose = OneOf3::Third(GovernanceTransition::Propose {
+        who: Alice,
+        change: ParameterChange::SetMaxExtrinsics(10),
+    });
+    let end = Runtime::next_state(&end, &propose);
+    assert_eq!(end.2.proposals.len(), 1);
+    assert_eq!(end.1.active_stake(ConsensusAuthority::Bob), 5);
+}
+
+#[test]
+fn sm_14_composite_matches_components() {
+    let start = genesis();
+    let transitions = [
+        CurrencyTransaction::Mint {
+            to: Charlie,
+            amount: 3,
+        },
+        CurrencyTransaction::Burn {
+            from: Alice,
+            amount: 1,
+        },
+    ];
+
+    let composed = transitions.iter().fold(start.clone(), |state, t| {
+        Runtime::next_state(&state, &OneOf3::First(t.clone()))
+    });
+    let alone = transitions
+        .iter()
+        .fold(start.0.clone(), |state, t| Currency::next_state(&state, t));
+    assert_eq!(composed, (alone, start.1, start.2));
+}
+
+#[test]
+fn sm_14_human_name() {
+    assert_eq!(
+        <(Currency, Staking) as StateMachine>::human_name(),
+        "Currency + Staking"
+    );
+}
@@ -0,0 +1,266 @@
+//! Fungible tokens, like the currency in Part 8, are interchangeable. One unit is as good as
+//! any other, so all we need to store is how many units each account has. Non-fungible tokens
+//! are not interchangeable. Each one is a distinct item with its own id and its own data, and
+//! each one has exactly one owner.
+//!
+//! So the state is keyed by the item rather than by the account. Every transition that touches
+//! an item must check that it is made by that item's owner. Answering "what does Alice own?"
+//! from that state would mean scanning every item, so we also keep an index from each owner to
+//! their items. The index must be updated together with the items, or the two will disagree.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{AccountId, StateMachine};
+
+/// Identifies a single token.
+pub type TokenId = u64;
+
+/// A single non-fungible token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token {
+    /// The account that owns the token.
+    pub owner: AccountId,
+    /// The data that makes this token what it is. Typically the hash of some off-chain content.
+    pub metadata: u64,
+}
+
+/// The state of the NFT registry.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NftRegistry {
+    /// Every token that currently exists.
+    pub tokens: BTreeMap<TokenId, Token>,
+    /// The tokens owned by every account. Accounts that own nothing are not stored at all.
+    pub owned: BTreeMap<AccountId, BTreeSet<TokenId>>,
+}
+
+impl NftRegistry {
+    /// The owner of the given token, if it exists.
+    pub fn owner_of(&self, id: TokenId) -> Option<AccountId> {
+        self.tokens.get(&id).map(|token| token.owner)
+    }
+
+    /// The tokens owned by the given account, found through the owner index.
+    pub fn tokens_of(&self, who: AccountId) -> Vec<TokenId> {
+        self.owned
+            .get(&who)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Add the given token to its owner's index entry.
+    fn index(&mut self, owner: AccountId, id: TokenId) {
+        self.owned.entry(owner).or_default().insert(id);
+    }
+
+    /// Remove the given token from its owner's index entry, and the entry itself once empty.
+    fn unindex(&mut self, owner: AccountId, id: TokenId) {
+        if let Some(ids) = self.owned.get_mut(&owner) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.owned.remove(&owner);
+            }
+        }
+    }
+}
+
+/// This state machine tracks the ownership of non-fungible tokens.
+pub struct Nft;
+
+/// The transitions that can be made in the NFT registry.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NftTransition {
+    /// Create a new token with the given id, owned by the account that creates it. Rejected if
+    /// a token with that id already exists.
+    Mint {
+        who: AccountId,
+        id: TokenId,
+        metadata: u64,
+    },
+    /// Give a token to someone else. Rejected unless it is done by the token's owner.
+    Transfer {
+        from: AccountId,
+        to: AccountId,
+        id: TokenId,
+    },
+    /// Destroy a token. Rejected unless it is done by the token's owner.
+    Burn { who: AccountId, id: TokenId },
+}
+
+impl StateMachine for Nft {
+    type State = NftRegistry;
+    type Transition = NftTransition;
+
+    /// Rejected transitions leave the state unchanged.
+    fn next_state(starting_state: &NftRegistry, t: &NftTransition) -> NftRegistry {
+        let mut state = starting_state.clone();
+        match t {
+            NftTransition::Mint { who, id, metadata } => {
+                if state.tokens.contains_key(id) {
+                    return state;
+                }
+                let token = Token {
+                    owner: *who,
+                    metadata: *metadata,
+                };
+                state.tokens.insert(*id, token);
+                state.index(*who, *id);
+            }
+            NftTransition::Transfer { from, to, id } => {
+                let Some(token) = state.tokens.get_mut(id) else {
+                    return state;
+                };
+                if token.owner != *from {
+                    return state;
+                }
+                token.owner = *to;
+                state.unindex(*from, *id);
+                state.index(*to, *id);
+            }
+            NftTransition::Burn { who, id } => {
+                if state.owner_of(*id) != Some(*who) {
+                    return state;
+                }
+                state.tokens.remove(id);
+                state.unindex(*who, *id);
+            }
+        }
+        state
+    }
+
+    fn human_name() -> String {
+        "NFT Registry".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+/// Alice owns tokens 1 and 2, and Bob owns token 3.
+#[cfg(test)]
+fn minted() -> NftRegistry {
+    [(Alice, 1), (Alice, 2), (Bob, 3)].into_iter().fold(
+        NftRegistry::default(),
+        |state, (who, id)| {
+            Nft::next_state(
+                &state,
+                &NftTransition::Mint {
+                    who,
+                    id,
+                    metadata: id * 100,
+                },
+            )
+        },
+    )
+}
+
+/// Check that the owner index agrees with the tokens themselves.
+#[cfg(test)]
+fn assert_index_consistent(state: &NftRegistry) {
+    let mut expected: BTreeMap<AccountId, BTreeSet<TokenId>> = BTreeMap::new();
+    for (id, token) in &state.tokens {
+        expected.entry(token.owner).or_default().insert(*id);
+    }
+    assert_eq!(state.owned, expected);
+}
+
+#[test]
+fn sm_15_mint_requires_unique_id() {
+    let state = minted();
+    assert_eq!(state.tokens_of(Alice), vec![1, 2]);
+    assert_eq!(state.tokens_of(Bob), vec![3]);
+    assert_eq!(state.tokens[&2].metadata, 200);
+    assert_index_consistent(&state);
+
+    // Nobody can mint over an existing token, not even its owner.
+    for who in [Alice, Charlie] {
+        let mint = NftTransition::Mint {
+            who,
+            id: 1,
+            metadata: 0,
+        };
+        assert_eq!(Nft::next_state(&state, &mint), state);
+    }
+}
+
+#[test]
+fn sm_15_transfer_moves_token_between_owners() {
+    let end = Nft::next_state(
+        &minted(),
+        &NftTransition::Transfer {
+            from: Alice,
+            to: Charlie,
+            id: 2,
+        },
+    );
+    assert_eq!(end.owner_of(2), Some(Charlie));
+    assert_eq!(end.tokens_of(Alice), vec![1]);
+    assert_eq!(end.tokens_of(Charlie), vec![2]);
+    assert_eq!(end.tokens[&2].metadata, 200);
+    assert_index_consistent(&end);
+
+    // Once Bob gives away his only token, he has no index entry at all.
+    let end = Nft::next_state(
+        &end,
+        &NftTransition::Transfer {
+            from: Bob,
+            to: Alice,
+            id: 3,
+        },
+    );
+    assert_eq!(end.tokens_of(Alice), vec![1, 3]);
+    assert!(!end.owned.contains_key(&Bob));
+    assert_index_consistent(&end);
+}
+
+#[test]
+fn sm_15_only_owner_can_transfer_or_burn() {
+    let state = minted();
+    let invalid = [
+        NftTransition::Transfer {
+            from: Bob,
+            to: Bob,
+            id: 1,
+        },
+        NftTransition::Transfer {
+            from: Alice,
+            to: Bob,
+            id: 4,
+        },
+        NftTransition::Burn {
+            who: Charlie,
+            id: 3,
+        },
+        NftTransition::Burn { who: Alice, id: 4 },
+    ];
+    for t in invalid {
+        assert_eq!(Nft::next_state(&state, &t), state);
+    }
+}
+
+#[test]
+fn sm_15_burn_destroys_token() {
+    let end = Nft::next_state(&minted(), &NftTransition::Burn { who: Bob, id: 3 });
+    assert_eq!(end.owner_of(3), None);
+    assert!(end.tokens_of(Bob).is_empty());
+    assert_index_consistent(&end);
+
+    // A burned token can not be transferred, but its id is free to be minted again.
+    let transfer = NftTransition::Transfer {
+        from: Bob,
+        to: Alice,
+        id: 3,
+    };
+    assert_eq!(Nft::next_state(&end, &transfer), end);
+    let end = Nft::next_state(
+        &end,
+        &NftTransition::Mint {
+            who: Charlie,
+            id: 3,
+            metadata: 7,
+        },
+    );
+    assert_eq!(end.owner_of(3), Some(Charlie));
+}
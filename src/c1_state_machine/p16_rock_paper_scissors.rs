@@ -0,0 +1,392 @@
+//! Rock-paper-scissors is a game of simultaneous moves. On a blockchain there is no such thing.
+//! Transactions land one after another, and whoever moves second can read the first move from
+//! the chain and pick the move that beats it.
+//!
+//! So we use the same commit-reveal pattern as the auction in Part 11. Each player first
+//! commits to the hash of their move and a secret salt. Once both have committed, each reveals
+//! their move and salt, and the chain checks them against the commitments. When both moves are
+//! revealed, the winner is known.
+//!
+//! A player who is about to lose can simply refuse to reveal. To stop the game from stalling
+//! forever, every phase has a deadline measured in block heights. Once it passes, anyone may
+//! end the game. A player who revealed wins against one who did not, and a game where nobody
+//! did their part is abandoned. Like the escrow in Part 12, the height comes from the block
+//! context, or from `NewBlock` transitions on chains without one.
+
+use super::{AccountId, BlockContext, StateMachine, TracksHeight};
+use crate::hash;
+
+type Hash = u64;
+
+/// The number of blocks each phase of the game lasts before it can be timed out.
+pub const MOVE_TIMEOUT: u64 = 10;
+
+/// The moves a player can make.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Move {
+    Rock,
+    Paper,
+    Scissors,
+}
+
+impl Move {
+    /// Whether this move beats the other one.
+    pub fn beats(&self, other: &Move) -> bool {
+        matches!(
+            (self, other),
+            (Move::Rock, Move::Scissors)
+                | (Move::Paper, Move::Rock)
+                | (Move::Scissors, Move::Paper)
+        )
+    }
+}
+
+/// The commitment to a move that a player submits before revealing it.
+///
+/// The player is part of the commitment. Otherwise the second player could copy the first
+/// player's commitment, wait for them to reveal, and reveal the same move to force a draw.
+pub fn move_commitment(who: AccountId, m: Move, salt: u64) -> Hash {
+    hash(&(who, m, salt))
+}
+
+/// How a finished game ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    /// The given player won, either by making the better move or because the other player
+    /// failed to reveal theirs in time.
+    Win(AccountId),
+    /// Both players made the same move.
+    Draw,
+    /// The deadline passed before anyone could win. Either the second player never committed,
+    /// or neither player revealed.
+    Abandoned,
+}
+
+/// One player's part in the game.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Player {
+    pub who: AccountId,
+    pub commitment: Option<Hash>,
+    pub revealed: Option<Move>,
+}
+
+/// The state of a single game of rock-paper-scissors.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameState {
+    /// The height of the current block.
+    pub height: u64,
+    /// The two players.
+    pub players: [Player; 2],
+    /// The first height at which the current phase can be timed out. `None` until the first
+    /// commitment arrives.
+    pub deadline: Option<u64>,
+    /// How the game ended, once it has.
+    pub outcome: Option<Outcome>,
+}
+
+impl GameState {
+    /// A new game at height zero between the given players.
+    pub fn new(first: AccountId, second: AccountId) -> Self {
+        let player = |who| Player {
+            who,
+            commitment: None,
+            revealed: None,
+        };
+        Self {
+            height: 0,
+            players: [player(first), player(second)],
+            deadline: None,
+            outcome: None,
+        }
+    }
+
+    /// The position of the given account among the players, if it is playing at all.
+    fn seat(&self, who: AccountId) -> Option<usize> {
+        self.players.iter().position(|p| p.who == who)
+    }
+
+    /// Whether both players have committed to their moves.
+    pub fn both_committed(&self) -> bool {
+        self.players.iter().all(|p| p.commitment.is_some())
+    }
+}
+
+impl TracksHeight for GameState {
+    fn height_mut(&mut self) -> &mut u64 {
+        &mut self.height
+    }
+}
+
+/// This state machine plays a single game of rock-paper-scissors with hidden moves.
+pub struct RockPaperScissors;
+
+/// The transitions that can be made in a game of rock-paper-scissors.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GameTransition {
+    /// Commit to a move. Each player may commit once.
+    Commit { who: AccountId, commitment: Hash },
+    /// Reveal a committed move. Only allowed once both players have committed, and only a
+    /// move that matches the commitment.
+    Reveal { who: AccountId, m: Move, salt: u64 },
+    /// End a game whose deadline has passed. Anyone may do this.
+    ClaimTimeout,
+    /// Begin a new block.
+    NewBlock,
+}
+
+impl StateMachine for RockPaperScissors {
+    type State = GameState;
+    type Transition = GameTransition;
+
+    /// Moves by outsiders, moves made out of turn, and moves made after the game is over leave
+    /// the state unchanged.
+    fn next_state(starting_state: &GameState, t: &GameTransition) -> GameState {
+        let mut state = starting_state.clone();
+        if state.outcome.is_some() && *t != GameTransition::NewBlock {
+            return state;
+        }
+        let timed_out = state
+            .deadline
+            .is_some_and(|deadline| state.height >= deadline);
+        match t {
+            GameTransition::Commit { who, commitment } => {
+                let Some(seat) = state.seat(*who) else {
+                    return state;
+                };
+                if timed_out || state.players[seat].commitment.is_some() {
+                    return state;
+                }
+                state.players[seat].commitment = Some(*commitment);
+                // The first commitment starts the clock for the second, and the second starts
+                // it again for the reveals.
+                state.deadline = Some(state.height.saturating_add(MOVE_TIMEOUT));
+            }
+            GameTransition::Reveal { who, m, salt } => {
+                let Some(seat) = state.seat(*who) else {
+                    return state;
+                };
+                let player = &state.players[seat];
+                if timed_out || !state.both_committed() || player.revealed.is_some() {
+                    return state;
+                }
+                if player.commitment != Some(move_commitment(*who, *m, *salt)) {
+                    return state;
+                }
+                state.players[seat].revealed = Some(*m);
+
+                let [first, second] = state.players;
+                if let (Some(a), Some(b)) = (first.revealed, second.revealed) {
+                    state.outcome = Some(if a.beats(&b) {
+                        Outcome::Win(first.who)
+                    } else if b.beats(&a) {
+                        Outcome::Win(second.who)
+                    } else {
+                        Outcome::Draw
+                    });
+                }
+            }
+            GameTransition::ClaimTimeout => {
+                if !timed_out {
+                    return state;
+                }
+                let revealed: Vec<_> = state
+                    .players
+                    .iter()
+                    .filter(|p| p.revealed.is_some())
+                    .collect();
+                state.outcome = Some(match revealed.as_slice() {
+                    [only] => Outcome::Win(only.who),
+                    _ => Outcome::Abandoned,
+                });
+            }
+            GameTransition::NewBlock => {
+                state.height = state.height.saturating_add(1);
+            }
+        }
+        state
+    }
+
+    fn next_state_in_block(
+        starting_state: &GameState,
+        t: &GameTransition,
+        context: &BlockContext,
+    ) -> GameState {
+        Self::next_state(&starting_state.caught_up(context), t)
+    }
+
+    fn human_name() -> String {
+        "Rock-Paper-Scissors".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[cfg(test)]
+fn play(start: &GameState, transitions: &[GameTransition]) -> GameState {
+    transitions.iter().fold(start.clone(), |state, t| {
+        RockPaperScissors::next_state(&state, t)
+    })
+}
+
+#[cfg(test)]
+fn commit(who: AccountId, m: Move, salt: u64) -> GameTransition {
+    GameTransition::Commit {
+        who,
+        commitment: move_commitment(who, m, salt),
+    }
+}
+
+#[cfg(test)]
+fn reveal(who: AccountId, m: Move, salt: u64) -> GameTransition {
+    GameTransition::Reveal { who, m, salt }
+}
+
+#[cfg(test)]
+fn new_blocks(n: u64) -> Vec<GameTransition> {
+    vec![GameTransition::NewBlock; n as usize]
+}
+
+#[test]
+fn sm_16_moves_beat_each_other_in_a_circle() {
+    use Move::*;
+    for (winner, loser) in [(Rock, Scissors), (Paper, Rock), (Scissors, Paper)] {
+        assert!(winner.beats(&loser));
+        assert!(!loser.beats(&winner));
+        assert!(!winner.beats(&winner));
+    }
+}
+
+#[test]
+fn sm_16_better_move_wins() {
+    let committed = play(
+        &GameState::new(Alice, Bob),
+        &[commit(Alice, Move::Rock, 1), commit(Bob, Move::Paper, 2)],
+    );
+    assert!(committed.both_committed());
+
+    // Alice reveals first. Bob can see her move now, but he is already committed to his.
+    let end = play(
+        &committed,
+        &[reveal(Alice, Move::Rock, 1), reveal(Bob, Move::Paper, 2)],
+    );
+    assert_eq!(end.outcome, Some(Outcome::Win(Bob)));
+
+    let end = play(
+        &GameState::new(Alice, Bob),
+        &[
+            commit(Alice, Move::Scissors, 1),
+            commit(Bob, Move::Scissors, 2),
+            reveal(Bob, Move::Scissors, 2),
+            reveal(Alice, Move::Scissors, 1),
+        ],
+    );
+    assert_eq!(end.outcome, Some(Outcome::Draw));
+}
+
+#[test]
+fn sm_16_invalid_moves_are_ignored() {
+    let start = play(&GameState::new(Alice, Bob), &[commit(Alice, Move::Rock, 1)]);
+
+    // Outsiders can not play, players can not commit twice, and nobody can reveal before both
+    // players have committed.
+    for invalid in [
+        commit(Charlie, Move::Rock, 3),
+        commit(Alice, Move::Paper, 1),
+        reveal(Alice, Move::Rock, 1),
+    ] {
+        assert_eq!(RockPaperScissors::next_state(&start, &invalid), start);
+    }
+
+    // Bob copies Alice's commitment, hoping to force a draw. He can not reveal it as his own.
+    let copied = GameTransition::Commit {
+        who: Bob,
+        commitment: start.players[0].commitment.unwrap(),
+    };
+    let state = play(&start, &[copied, reveal(Alice, Move::Rock, 1)]);
+    for invalid in [reveal(Bob, Move::Rock, 1), reveal(Bob, Move::Paper, 1)] {
+        assert_eq!(RockPaperScissors::next_state(&state, &invalid), state);
+    }
+
+    // Once the game is over, nothing changes it.
+    let over = play(&state, &new_blocks(MOVE_TIMEOUT));
+    let over = RockPaperScissors::next_state(&over, &GameTransition::ClaimTimeout);
+    assert_eq!(over.outcome, Some(Outcome::Win(Alice)));
+    assert_eq!(
+        RockPaperScissors::next_state(&over, &GameTransition::ClaimTimeout),
+        over
+    );
+}
+
+#[test]
+fn sm_16_player_who_does_not_reveal_forfeits() {
+    let state = play(
+        &GameState::new(Alice, Bob),
+        &[
+            commit(Alice, Move::Rock, 1),
+            commit(Bob, Move::Scissors, 2),
+            reveal(Alice, Move::Rock, 1),
+        ],
+    );
+
+    // Bob sees that he has lost, and stalls. The game can not be ended early.
+    let waiting = play(&state, &new_blocks(MOVE_TIMEOUT - 1));
+    assert_eq!(
+        RockPaperScissors::next_state(&waiting, &GameTransition::ClaimTimeout),
+        waiting
+    );
+
+    // Once the deadline has passed, Bob can no longer reveal, and Alice wins.
+    let late = play(&waiting, &new_blocks(1));
+    let late_reveal = reveal(Bob, Move::Scissors, 2);
+    assert_eq!(RockPaperScissors::next_state(&late, &late_reveal), late);
+    let end = RockPaperScissors::next_state(&late, &GameTransition::ClaimTimeout);
+    assert_eq!(end.outcome, Some(Outcome::Win(Alice)));
+}
+
+#[test]
+fn sm_16_game_is_abandoned_when_nobody_plays_their_part() {
+    // Bob never commits.
+    let mut transitions = vec![commit(Alice, Move::Paper, 1)];
+    transitions.extend(new_blocks(MOVE_TIMEOUT));
+    transitions.extend([commit(Bob, Move::Rock, 2), GameTransition::ClaimTimeout]);
+    let end = play(&GameState::new(Alice, Bob), &transitions);
+    assert_eq!(end.players[1].commitment, None);
+    assert_eq!(end.outcome, Some(Outcome::Abandoned));
+
+    // Both commit, but neither reveals.
+    let mut transitions = vec![commit(Alice, Move::Paper, 1), commit(Bob, Move::Rock, 2)];
+    transitions.extend(new_blocks(MOVE_TIMEOUT));
+    transitions.push(GameTransition::ClaimTimeout);
+    let end = play(&GameState::new(Alice, Bob), &transitions);
+    assert_eq!(end.outcome, Some(Outcome::Abandoned));
+}
+
+#[test]
+fn sm_16_deadlines_follow_block_context() {
+    let at = |height| BlockContext {
+        height,
+        ..Default::default()
+    };
+    let state = RockPaperScissors::next_state_in_block(
+        &GameState::new(Alice, Bob),
+        &commit(Alice, Move::Rock, 1),
+        &at(3),
+    );
+    assert_eq!(state.deadline, Some(3 + MOVE_TIMEOUT));
+
+    // Bob's commitment lands after the deadline, without any `NewBlock` in between.
+    let late = RockPaperScissors::next_state_in_block(
+        &state,
+        &commit(Bob, Move::Paper, 2),
+        &at(3 + MOVE_TIMEOUT),
+    );
+    assert_eq!(late.players[1].commitment, None);
+    let end = RockPaperScissors::next_state_in_block(&late, &GameTransition::ClaimTimeout, &at(14));
+    assert_eq!(end.outcome, Some(Outcome::Abandoned));
+}
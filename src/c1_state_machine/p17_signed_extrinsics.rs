@@ -0,0 +1,452 @@
+//! Our currency trusts every transaction to be honest about who sent it. Anyone can write
+//! `Transfer { from: Alice, .. }` and spend Alice's money. Real chains stop this with
+//! signatures: every extrinsic carries its sender's public key and a signature over the
+//! payload, and the chain refuses extrinsics whose signature does not check out, or that were
+//! signed by someone other than the account they spend from.
+//!
+//! A signature alone does not stop anyone from including the same signed transfer again and
+//! again, so every extrinsic also carries a nonce, which counts its signer's extrinsics. The
+//! chain remembers the next nonce of every signer, and refuses extrinsics with any other nonce.
+//! Some transitions, like minting, belong to no account at all. Those must be signed by the
+//! chain's authority instead.
+//!
+//! Here we wrap any state machine whose transitions have a sender, so that its transitions must
+//! be signed with ed25519, the same scheme that our signed consensus engines use.
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use super::{
+    AccountId, BlockContext, Currency, CurrencyTransaction, StateMachine, TransitionError,
+    TryStateMachine, User,
+};
+/// A transition together with its signer's nonce and signature.
+///
+/// The key and signature are stored as raw bytes, like in `SignatureDigest`, because that is
+/// how they would travel over the network, and because extrinsics must be hashable.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedExtrinsic<T> {
+    /// The transition itself.
+    pub payload: T,
+    /// Which of its signer's extrinsics this is, counting from zero.
+    pub nonce: u64,
+    /// The public key of whoever signed it.
+    pub signer: [u8; 32],
+    /// The ed25519 signature over the encoding of the payload and the nonce.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::byte_array"))]
+    pub signature: [u8; 64],
+}
+
+/// A canonical byte encoding, which is what signers sign.
+///
+/// Signing the `hash` of a payload would be simpler, but `hash` is a 64 bit `DefaultHasher`
+/// digest. It may change from one Rust version to the next, which would break every old
+/// signature, and collisions can be found, so a signature over one payload could be replayed for
+/// another. An encoding is fixed, and no two values of a type share one.
+pub trait Encode {
+    /// Append this value's encoding to the given bytes.
+    fn encode_to(&self, bytes: &mut Vec<u8>);
+}
+
+impl Encode for u64 {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+/// Encoded as a `u64`, so that the encoding is the same on every platform.
+impl Encode for usize {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        (*self as u64).encode_to(bytes);
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        match self {
+            None => bytes.push(0),
+            Some(value) => {
+                bytes.push(1);
+                value.encode_to(bytes);
+            }
+        }
+    }
+}
+
+impl Encode for User {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.push(*self as u8);
+    }
+}
+
+impl Encode for CurrencyTransaction {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        match self {
+            CurrencyTransaction::Mint { to, amount } => {
+                bytes.push(0);
+                to.encode_to(bytes);
+                amount.encode_to(bytes);
+            }
+            CurrencyTransaction::Burn { from, amount } => {
+                bytes.push(1);
+                from.encode_to(bytes);
+                amount.encode_to(bytes);
+            }
+            CurrencyTransaction::Transfer { from, to, amount } => {
+                bytes.push(2);
+                from.encode_to(bytes);
+                to.encode_to(bytes);
+                amount.encode_to(bytes);
+            }
+        }
+    }
+}
+
+/// The message that signers sign: the encoding of the payload and the nonce.
+fn signing_payload<T: Encode>(payload: &T, nonce: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    payload.encode_to(&mut bytes);
+    nonce.encode_to(&mut bytes);
+    bytes
+}
+
+impl<T: Encode> SignedExtrinsic<T> {
+    /// Sign the given payload, with the given nonce, with the given key.
+    pub fn sign(payload: T, nonce: u64, signing_key: &SigningKey) -> Self {
+        SignedExtrinsic {
+            signer: signing_key.verifying_key().to_bytes(),
+            signature: signing_key
+                .sign(&signing_payload(&payload, nonce))
+                .to_bytes(),
+            payload,
+            nonce,
+        }
+    }
+
+    /// Whether the signature is a valid signature over the payload and nonce by the given key.
+    pub fn is_signed_by(&self, key: &VerifyingKey) -> bool {
+        self.signer == key.to_bytes()
+            && key
+                .verify(
+                    &signing_payload(&self.payload, self.nonce),
+                    &Signature::from_bytes(&self.signature),
+                )
+                .is_ok()
+    }
+}
+
+impl<T: Encode + Sender> SignedExtrinsic<T> {
+    /// Whether the payload was signed by the account that sends it.
+    pub fn is_signed_by_sender(&self) -> bool {
+        self.is_signed_by(&self.payload.sender().public_key())
+    }
+}
+
+/// Transitions that act on behalf of one particular account, which must sign them.
+pub trait Sender {
+    /// The account that must sign this transition.
+    fn sender(&self) -> AccountId;
+
+    /// Whether this transition must be signed by the chain's authority rather than by its
+    /// sender, because it does something that no account may do on its own.
+    fn needs_authority(&self) -> bool {
+        false
+    }
+}
+
+/// Money always comes out of, or goes into, the account that is named first. Minting creates
+/// money, so the account it goes into can not be the one that permits it.
+impl Sender for CurrencyTransaction {
+    fn sender(&self) -> AccountId {
+        match self {
+            CurrencyTransaction::Mint { to, .. } => *to,
+            CurrencyTransaction::Burn { from, .. } => *from,
+            CurrencyTransaction::Transfer { from, .. } => *from,
+        }
+    }
+
+    fn needs_authority(&self) -> bool {
+        matches!(self, CurrencyTransaction::Mint { .. })
+    }
+}
+
+impl User {
+    /// This user's key pair. Our play users have well-known keys, derived from their names, so
+    /// that tests and examples can sign for any of them. Never derive real keys like this!
+    pub fn signing_key(self) -> SigningKey {
+        // Offset from the small seeds that authorities' test keys use, so no user shares a key
+        // with an authority.
+        SigningKey::from_bytes(&[0x80 + self as u8; 32])
+    }
+
+    /// This user's public key.
+    pub fn public_key(self) -> VerifyingKey {
+        self.signing_key().verifying_key()
+    }
+}
+
+/// The state of a signed machine: the wrapped machine's state, along with what signatures are
+/// checked against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedState<S> {
+    /// The state of the wrapped machine.
+    pub inner: S,
+    /// The nonce that each key must sign its next extrinsic with. Keys that have never signed
+    /// anything are missing, and start at zero.
+    pub nonces: BTreeMap<[u8; 32], u64>,
+    /// The public key that signs the transitions that need the chain's authority.
+    pub authority: [u8; 32],
+}
+
+impl<S> SignedState<S> {
+    /// The given state, with no extrinsics signed yet, under the given authority.
+    pub fn new(inner: S, authority: &VerifyingKey) -> Self {
+        SignedState {
+            inner,
+            nonces: BTreeMap::new(),
+            authority: authority.to_bytes(),
+        }
+    }
+
+    /// The nonce that the given key must sign its next extrinsic with.
+    pub fn next_nonce(&self, signer: &[u8; 32]) -> u64 {
+        self.nonces.get(signer).copied().unwrap_or(0)
+    }
+
+    /// Whether the given extrinsic is signed by the key it needs: the authority's, for
+    /// transitions that need it, and its sender's, for everything else.
+    pub fn is_correctly_signed<T: Encode + Sender>(&self, t: &SignedExtrinsic<T>) -> bool {
+        if !t.payload.needs_authority() {
+            return t.is_signed_by_sender();
+        }
+        VerifyingKey::from_bytes(&self.authority).is_ok_and(|authority| t.is_signed_by(&authority))
+    }
+
+    /// Check the given extrinsic's signature and nonce, and return the nonces with its one used
+    /// up. Fails with `NotPermitted` if it is badly signed, and with `Invalid` if it does not
+    /// carry its signer's next nonce.
+    fn use_nonce<T: Encode + Sender>(
+        &self,
+        t: &SignedExtrinsic<T>,
+    ) -> Result<BTreeMap<[u8; 32], u64>, TransitionError> {
+        if !self.is_correctly_signed(t) {
+            return Err(TransitionError::NotPermitted);
+        }
+        let nonce = self.next_nonce(&t.signer);
+        if t.nonce != nonce {
+            return Err(TransitionError::Invalid);
+        }
+        let mut nonces = self.nonces.clone();
+        nonces.insert(
+            t.signer,
+            nonce.checked_add(1).ok_or(TransitionError::Overflow)?,
+        );
+        Ok(nonces)
+    }
+}
+
+/// The given state machine, but every transition must be signed, with its signer's next nonce,
+/// by its sender or, if it needs one, by the authority. Transitions that are badly signed, or
+/// carry the wrong nonce, fail and leave the state unchanged.
+pub struct Signed<SM>(PhantomData<SM>);
+
+/// The currency, with signed transactions.
+pub type SignedCurrency = Signed<Currency>;
+
+impl<SM> StateMachine for Signed<SM>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: Encode + Sender,
+{
+    type State = SignedState<SM::State>;
+    type Transition = SignedExtrinsic<SM::Transition>;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        Self::next_state_in_block(starting_state, t, &BlockContext::default())
+    }
+
+    /// A correctly signed transition uses up its nonce even if the wrapped machine rejects it,
+    /// so that it can never be applied later.
+    fn next_state_in_block(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+        context: &BlockContext,
+    ) -> Self::State {
+        match starting_state.use_nonce(t) {
+            Ok(nonces) => SignedState {
+                inner: SM::next_state_in_block(&starting_state.inner, &t.payload, context),
+                nonces,
+                authority: starting_state.authority,
+            },
+            Err(_) => starting_state.clone(),
+        }
+    }
+
+    fn human_name() -> String {
+        format!("Signed {}", SM::human_name())
+    }
+}
+
+impl<SM> TryStateMachine for Signed<SM>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: Encode + Sender,
+{
+    /// A transition that is not signed by the key it needs is not permitted, and one that does
+    /// not carry its signer's next nonce is invalid.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, TransitionError> {
+        let nonces = starting_state.use_nonce(t)?;
+        Ok(SignedState {
+            inner: SM::try_next_state(&starting_state.inner, &t.payload)?,
+            nonces,
+            authority: starting_state.authority,
+        })
+    }
+}
+
+#[cfg(test)]
+use super::Balances;
+
+#[cfg(test)]
+fn transfer(amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: User::Alice,
+        to: User::Bob,
+        amount,
+    }
+}
+
+/// Alice holds 100, and Charlie is the authority.
+#[cfg(test)]
+fn start() -> SignedState<Balances> {
+    SignedState::new(
+        Balances::from([(User::Alice, 100)]),
+        &User::Charlie.public_key(),
+    )
+}
+
+#[test]
+fn sm_17_signed_transfer_goes_through() {
+    let start = start();
+    let signed = SignedExtrinsic::sign(transfer(30), 0, &User::Alice.signing_key());
+    assert!(signed.is_signed_by_sender());
+
+    let end = SignedCurrency::try_next_state(&start, &signed).unwrap();
+    assert_eq!(
+        end.inner,
+        Balances::from([(User::Alice, 70), (User::Bob, 30)])
+    );
+    assert_eq!(end.next_nonce(&signed.signer), 1);
+}
+
+#[test]
+fn sm_17_forged_transfers_are_refused() {
+    let start = start();
+
+    // Bob signs a transfer out of Alice's account.
+    let forged = SignedExtrinsic::sign(transfer(30), 0, &User::Bob.signing_key());
+    assert!(forged.is_signed_by(&User::Bob.public_key()));
+    assert!(!forged.is_signed_by_sender());
+    let end = SignedCurrency::try_next_state(&start, &forged);
+    assert_eq!(end, Err(TransitionError::NotPermitted));
+    assert_eq!(SignedCurrency::next_state(&start, &forged), start);
+
+    // Alice signs a small transfer, and someone raises the amount, or changes the nonce.
+    let mut tampered = SignedExtrinsic::sign(transfer(1), 0, &User::Alice.signing_key());
+    tampered.payload = transfer(100);
+    let end = SignedCurrency::try_next_state(&start, &tampered);
+    assert_eq!(end, Err(TransitionError::NotPermitted));
+    let mut renumbered = SignedExtrinsic::sign(transfer(1), 0, &User::Alice.signing_key());
+    renumbered.nonce = 1;
+    let end = SignedCurrency::try_next_state(&start, &renumbered);
+    assert_eq!(end, Err(TransitionError::NotPermitted));
+}
+
+#[test]
+fn sm_17_signed_transfers_can_not_be_replayed() {
+    let alice = User::Alice.signing_key();
+    let first = SignedExtrinsic::sign(transfer(30), 0, &alice);
+    let once = SignedCurrency::try_next_state(&start(), &first).unwrap();
+    assert_eq!(
+        SignedCurrency::try_next_state(&once, &first),
+        Err(TransitionError::Invalid)
+    );
+    assert_eq!(SignedCurrency::next_state(&once, &first), once);
+
+    // Nonces can not be skipped either.
+    let skipping = SignedExtrinsic::sign(transfer(30), 2, &alice);
+    assert_eq!(
+        SignedCurrency::try_next_state(&once, &skipping),
+        Err(TransitionError::Invalid)
+    );
+    let second = SignedExtrinsic::sign(transfer(30), 1, &alice);
+    let twice = SignedCurrency::try_next_state(&once, &second).unwrap();
+    assert_eq!(
+        twice.inner,
+        Balances::from([(User::Alice, 40), (User::Bob, 60)])
+    );
+
+    // A transfer that Alice can not afford still uses up its nonce inside a block, so it can
+    // not be applied later, once she can.
+    let greedy = SignedExtrinsic::sign(transfer(1_000), 2, &alice);
+    let failed = SignedCurrency::next_state(&twice, &greedy);
+    assert_eq!(failed.inner, twice.inner);
+    assert_eq!(failed.next_nonce(&greedy.signer), 3);
+}
+
+#[test]
+fn sm_17_only_the_authority_mints() {
+    let mint = CurrencyTransaction::Mint {
+        to: User::Bob,
+        amount: 1_000,
+    };
+    let selfish = SignedExtrinsic::sign(mint.clone(), 0, &User::Bob.signing_key());
+    assert!(selfish.is_signed_by_sender());
+    assert_eq!(
+        SignedCurrency::try_next_state(&start(), &selfish),
+        Err(TransitionError::NotPermitted)
+    );
+
+    let minted = SignedExtrinsic::sign(mint, 0, &User::Charlie.signing_key());
+    let end = SignedCurrency::try_next_state(&start(), &minted).unwrap();
+    assert_eq!(
+        end.inner,
+        Balances::from([(User::Alice, 100), (User::Bob, 1_000)])
+    );
+}
+
+#[test]
+fn sm_17_users_have_distinct_keys() {
+    let keys = [User::Alice, User::Bob, User::Charlie].map(User::public_key);
+    assert_ne!(keys[0], keys[1]);
+    assert_ne!(keys[1], keys[2]);
+    assert_eq!(User::Alice.public_key(), User::Alice.public_key());
+}
+
+#[test]
+fn sm_17_signers_sign_a_fixed_encoding() {
+    let mut expected = vec![2, User::Alice as u8, User::Bob as u8];
+    expected.extend_from_slice(&30u64.to_le_bytes());
+    expected.extend_from_slice(&7u64.to_le_bytes());
+    assert_eq!(signing_payload(&transfer(30), 7), expected);
+
+    // Payloads that differ in any field are encoded differently.
+    let mint = CurrencyTransaction::Mint {
+        to: User::Alice,
+        amount: 30,
+    };
+    assert_ne!(signing_payload(&mint, 7), signing_payload(&transfer(30), 7));
+    assert_ne!(
+        signing_payload(&transfer(30), 8),
+        signing_payload(&transfer(30), 7)
+    );
+}
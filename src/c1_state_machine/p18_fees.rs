@@ -0,0 +1,319 @@
+//! Block space is scarce, and every transaction costs the nodes that execute it. So real chains
+//! make senders pay a fee for each transaction. The fee goes to whoever authors the block that
+//! includes it, which gives authors a reason to include transactions at all, and lets senders
+//! outbid one another when blocks are full.
+//!
+//! Here the currency takes the fee out of the sender's account before it dispatches a
+//! transaction. A transaction that fails once dispatched has still cost the nodes that
+//! executed it, so its sender still pays the fee. Otherwise anyone could fill blocks with
+//! failing transactions for free. Only a transaction whose sender can not pay the fee at all
+//! pays nothing, and a block gains nothing by including it. The state machine does not know
+//! who authored the block, so it only takes the fee. The chain adds up the fees of every
+//! transaction in a block, and credits them to the author, as it does with the block reward.
+//!
+//! A sender in a hurry can add a tip on top of the fee, which the author also keeps. Authors
+//! include the transactions with the highest tips first. Each transaction also carries a nonce,
+//! which numbers its sender's transactions. The currency remembers the next nonce of every
+//! account, and refuses transactions with any other nonce, so the same transaction can not be
+//! applied twice. A transaction that is still waiting in the pool can be replaced by another
+//! one from the same sender with the same nonce, as long as the new one pays a higher tip. This
+//! is how a sender gets a stuck transaction moving again.
+
+use std::collections::BTreeMap;
+
+use super::p8_balances::{balance_of, set_balance};
+use super::{
+    AccountId, Balances, Currency, CurrencyTransaction, Sender, SignedExtrinsic, StateMachine,
+    TransitionError, TryStateMachine,
+};
+
+/// A transition together with the fee that its sender pays for it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Paid<T> {
+    /// The transition itself.
+    pub call: T,
+    /// The fee that the sender pays.
+    pub fee: u64,
+    /// What the sender pays on top of the fee to be included sooner, if anything.
+    pub tip: Option<u64>,
+    /// Which of its sender's transactions this is, counting from zero. It must be the sender's
+    /// next nonce when the transaction is applied.
+    pub nonce: u64,
+}
+
+/// Transitions that pay a fee to be applied.
+pub trait PaysFee {
+    /// The fee that this transition pays.
+    fn fee(&self) -> u64;
+
+    /// The tip that this transition pays on top of its fee.
+    fn tip(&self) -> u64 {
+        0
+    }
+}
+
+impl<T> PaysFee for Paid<T> {
+    fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    fn tip(&self) -> u64 {
+        self.tip.unwrap_or(0)
+    }
+}
+
+/// State machines that take a transition's fee before they dispatch it.
+pub trait ChargesFee: TryStateMachine
+where
+    Self::Transition: PaysFee,
+{
+    /// Take the transition's fee and tip from its sender, without dispatching it. Fails if they
+    /// can not be taken, in which case nothing is paid.
+    fn charge_fee(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, TransitionError>;
+
+    /// Dispatch a transition whose fee has already been taken.
+    fn dispatch(
+        charged_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, TransitionError>;
+}
+
+/// Transitions that their sender numbers, so that a later one can take the place of an earlier
+/// one that has not been included yet.
+pub trait Nonced: Sender {
+    /// The number of this transition among its sender's transitions.
+    fn nonce(&self) -> u64;
+}
+
+impl<T: Sender> Nonced for Paid<T> {
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+/// The fee comes from the same account that signs the transition.
+impl<T: Sender> Sender for Paid<T> {
+    fn sender(&self) -> AccountId {
+        self.call.sender()
+    }
+}
+
+/// Signing a transition does not change its fee.
+impl<T: PaysFee> PaysFee for SignedExtrinsic<T> {
+    fn fee(&self) -> u64 {
+        self.payload.fee()
+    }
+
+    fn tip(&self) -> u64 {
+        self.payload.tip()
+    }
+}
+
+/// The balances, along with the nonce that each account's next transaction must carry.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PaidState {
+    /// The balance of every account.
+    pub balances: Balances,
+    /// The next nonce of every account. Accounts that have never sent anything are missing, and
+    /// start at zero.
+    pub nonces: BTreeMap<AccountId, u64>,
+}
+
+impl PaidState {
+    /// The given balances, with no transactions sent yet.
+    pub fn new(balances: Balances) -> Self {
+        PaidState {
+            balances,
+            nonces: BTreeMap::new(),
+        }
+    }
+
+    /// The nonce that the given account's next transaction must carry.
+    pub fn next_nonce(&self, account: &AccountId) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+}
+
+/// The currency, but every transaction pays a fee.
+pub struct PaidCurrency;
+
+impl StateMachine for PaidCurrency {
+    type State = PaidState;
+    type Transition = Paid<CurrencyTransaction>;
+
+    /// A transaction whose fee can be taken pays it, and uses up its nonce, even if it then
+    /// fails. Any other transaction leaves the state unchanged.
+    fn next_state(starting_state: &PaidState, t: &Paid<CurrencyTransaction>) -> PaidState {
+        match Self::charge_fee(starting_state, t) {
+            Ok(charged) => Self::dispatch(&charged, t).unwrap_or(charged),
+            Err(_) => starting_state.clone(),
+        }
+    }
+
+    fn human_name() -> String {
+        "Currency with fees".into()
+    }
+}
+
+impl TryStateMachine for PaidCurrency {
+    /// The fee and the tip are taken before the transaction is dispatched, so the sender must
+    /// be able to afford all three. Here a transaction that fails pays no fee, so that failing
+    /// leaves the state unchanged, as it does for every `TryStateMachine`. Pools use this to
+    /// decide what is worth including.
+    fn try_next_state(
+        starting_state: &PaidState,
+        t: &Paid<CurrencyTransaction>,
+    ) -> Result<PaidState, TransitionError> {
+        Self::dispatch(&Self::charge_fee(starting_state, t)?, t)
+    }
+}
+
+impl ChargesFee for PaidCurrency {
+    /// Fails with `Invalid` unless the transaction carries its sender's next nonce, and with
+    /// `InsufficientFunds` if the sender can not afford the fee and the tip.
+    fn charge_fee(
+        starting_state: &PaidState,
+        t: &Paid<CurrencyTransaction>,
+    ) -> Result<PaidState, TransitionError> {
+        let sender = t.call.sender();
+        if t.nonce != starting_state.next_nonce(&sender) {
+            return Err(TransitionError::Invalid);
+        }
+        let charge = t
+            .fee
+            .checked_add(t.tip())
+            .ok_or(TransitionError::Overflow)?;
+        let balance = balance_of(&starting_state.balances, &sender)
+            .checked_sub(charge)
+            .ok_or(TransitionError::InsufficientFunds)?;
+        let mut balances = starting_state.balances.clone();
+        set_balance(&mut balances, sender, balance);
+        let mut nonces = starting_state.nonces.clone();
+        nonces.insert(
+            sender,
+            t.nonce.checked_add(1).ok_or(TransitionError::Overflow)?,
+        );
+        Ok(PaidState { balances, nonces })
+    }
+
+    fn dispatch(
+        charged_state: &PaidState,
+        t: &Paid<CurrencyTransaction>,
+    ) -> Result<PaidState, TransitionError> {
+        Ok(PaidState {
+            balances: Currency::try_next_state(&charged_state.balances, &t.call)?,
+            nonces: charged_state.nonces.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[cfg(test)]
+fn transfer(amount: u64, fee: u64) -> Paid<CurrencyTransaction> {
+    Paid {
+        call: CurrencyTransaction::Transfer {
+            from: Alice,
+            to: Bob,
+            amount,
+        },
+        fee,
+        tip: None,
+        nonce: 0,
+    }
+}
+
+#[cfg(test)]
+fn start() -> PaidState {
+    PaidState::new(Balances::from([(Alice, 100)]))
+}
+
+#[test]
+fn sm_18_fees_are_taken_from_the_sender() {
+    let end = PaidCurrency::try_next_state(&start(), &transfer(60, 5)).unwrap();
+    assert_eq!(end.balances, Balances::from([(Alice, 35), (Bob, 60)]));
+    assert_eq!(end.next_nonce(&Alice), 1);
+    assert_eq!(transfer(60, 5).fee(), 5);
+    assert_eq!(transfer(60, 5).sender(), Alice);
+}
+
+#[test]
+fn sm_18_failed_transactions_still_pay_their_fee() {
+    let start = start();
+
+    // Alice can not afford the transfer and the fee together, nor a fee bigger than her balance.
+    let end = PaidCurrency::try_next_state(&start, &transfer(100, 1));
+    assert_eq!(end, Err(TransitionError::InsufficientFunds));
+    let end = PaidCurrency::try_next_state(&start, &transfer(1, 101));
+    assert_eq!(end, Err(TransitionError::InsufficientFunds));
+
+    // In a block she pays the fee for the transfer she can not afford, but nothing for the fee.
+    let end = PaidCurrency::next_state(&start, &transfer(100, 1));
+    assert_eq!(end.balances, Balances::from([(Alice, 99)]));
+    assert_eq!(end.next_nonce(&Alice), 1);
+    assert_eq!(PaidCurrency::next_state(&start, &transfer(1, 101)), start);
+}
+
+#[test]
+fn sm_18_tips_are_taken_with_the_fee() {
+    let tipped = Paid {
+        tip: Some(10),
+        ..transfer(60, 5)
+    };
+    assert_eq!(tipped.tip(), 10);
+    assert_eq!(transfer(60, 5).tip(), 0);
+
+    let start = start();
+    let end = PaidCurrency::try_next_state(&start, &tipped).unwrap();
+    assert_eq!(end.balances, Balances::from([(Alice, 25), (Bob, 60)]));
+    let greedy = Paid {
+        tip: Some(u64::MAX),
+        ..transfer(60, 5)
+    };
+    let end = PaidCurrency::try_next_state(&start, &greedy);
+    assert_eq!(end, Err(TransitionError::Overflow));
+}
+
+#[test]
+fn sm_18_nonces_must_come_in_order() {
+    let once = PaidCurrency::try_next_state(&start(), &transfer(10, 1)).unwrap();
+    assert_eq!(
+        PaidCurrency::try_next_state(&once, &transfer(10, 1)),
+        Err(TransitionError::Invalid)
+    );
+    let skipping = Paid {
+        nonce: 2,
+        ..transfer(10, 1)
+    };
+    assert_eq!(
+        PaidCurrency::try_next_state(&once, &skipping),
+        Err(TransitionError::Invalid)
+    );
+    let next = Paid {
+        nonce: 1,
+        ..transfer(10, 1)
+    };
+    let twice = PaidCurrency::try_next_state(&once, &next).unwrap();
+    assert_eq!(twice.balances, Balances::from([(Alice, 78), (Bob, 20)]));
+
+    // Nonces are counted per sender, and a failed transaction does not use one up.
+    let from_bob = Paid {
+        call: CurrencyTransaction::Transfer {
+            from: Bob,
+            to: Alice,
+            amount: 100,
+        },
+        ..transfer(0, 1)
+    };
+    assert_eq!(
+        PaidCurrency::try_next_state(&twice, &from_bob),
+        Err(TransitionError::InsufficientFunds)
+    );
+    assert_eq!(twice.next_nonce(&Bob), 0);
+}
@@ -0,0 +1,147 @@
+//! Money can only be created by minting it, and only destroyed by burning it. Everything else
+//! just moves it around. So a currency can keep a running total of all the money there is,
+//! called the total issuance, without ever adding up the balances.
+//!
+//! The total issuance is handy on its own, for example to work out inflation. It also gives us
+//! an invariant: the balances must always add up to the total issuance. A bug that creates or
+//! destroys money by accident breaks the invariant, and checking it after every block catches
+//! such bugs before they do much harm. Adding up every balance is slow on a real chain, so
+//! clients only check this when built with the `strict-invariants` feature, and in debug builds.
+
+use super::p8_balances::balance_of;
+use super::{
+    Balances, Currency, CurrencyTransaction, StateMachine, TransitionError, TryStateMachine,
+};
+
+/// The balances, along with the total issuance.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ledger {
+    /// The balance of every account.
+    pub balances: Balances,
+    /// All the money there is. Mints that would take it past `u64::MAX` are refused, so the
+    /// balances can always be added up without overflowing.
+    pub issuance: u64,
+}
+
+impl Ledger {
+    /// A ledger holding the given balances, whose issuance is their total. Returns None if the
+    /// total does not fit in a `u64`.
+    pub fn new(balances: Balances) -> Option<Self> {
+        let issuance = balances
+            .values()
+            .try_fold(0u64, |total, balance| total.checked_add(*balance))?;
+        Some(Ledger { balances, issuance })
+    }
+
+    /// Whether the balances add up to the total issuance.
+    pub fn is_consistent(&self) -> bool {
+        let total: u128 = self.balances.values().map(|&balance| balance as u128).sum();
+        total == self.issuance as u128
+    }
+}
+
+/// The currency, keeping track of its total issuance.
+pub struct TrackedCurrency;
+
+impl StateMachine for TrackedCurrency {
+    type State = Ledger;
+    type Transition = CurrencyTransaction;
+
+    /// Rejected transactions leave the ledger unchanged.
+    fn next_state(starting_state: &Ledger, t: &CurrencyTransaction) -> Ledger {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn invariants_hold(state: &Ledger) -> bool {
+        state.is_consistent()
+    }
+
+    fn human_name() -> String {
+        "Currency with issuance".into()
+    }
+}
+
+impl TryStateMachine for TrackedCurrency {
+    fn try_next_state(
+        starting_state: &Ledger,
+        t: &CurrencyTransaction,
+    ) -> Result<Ledger, TransitionError> {
+        let balances = Currency::try_next_state(&starting_state.balances, t)?;
+        let issuance = match t {
+            CurrencyTransaction::Mint { amount, .. } => starting_state
+                .issuance
+                .checked_add(*amount)
+                .ok_or(TransitionError::Overflow)?,
+            // A burn may destroy less than it asks to, so look at what it actually destroyed.
+            CurrencyTransaction::Burn { from, .. } => {
+                let burned =
+                    balance_of(&starting_state.balances, from) - balance_of(&balances, from);
+                starting_state.issuance - burned
+            }
+            CurrencyTransaction::Transfer { .. } => starting_state.issuance,
+        };
+        Ok(Ledger { balances, issuance })
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+#[test]
+fn sm_19_issuance_follows_mints_and_burns() {
+    use CurrencyTransaction::*;
+
+    let start = Ledger::new(Balances::from([(Alice, 100), (Bob, 20)])).unwrap();
+    assert_eq!(start.issuance, 120);
+    let transactions = [
+        Mint {
+            to: Charlie,
+            amount: 30,
+        },
+        Transfer {
+            from: Alice,
+            to: Bob,
+            amount: 60,
+        },
+        Burn {
+            from: Bob,
+            amount: 1_000,
+        },
+        // Rejected, because Alice can not afford it.
+        Transfer {
+            from: Alice,
+            to: Charlie,
+            amount: 41,
+        },
+    ];
+    let mut ledger = start;
+    for t in &transactions {
+        ledger = TrackedCurrency::next_state(&ledger, t);
+        assert!(TrackedCurrency::invariants_hold(&ledger));
+    }
+    assert_eq!(ledger.issuance, 70);
+    assert_eq!(
+        ledger.balances,
+        Balances::from([(Alice, 40), (Charlie, 30)])
+    );
+}
+
+#[test]
+fn sm_19_issuance_never_overflows() {
+    assert_eq!(
+        Ledger::new(Balances::from([(Alice, u64::MAX), (Bob, 1)])),
+        None
+    );
+
+    let start = Ledger::new(Balances::from([(Alice, u64::MAX)])).unwrap();
+    let mint = CurrencyTransaction::Mint { to: Bob, amount: 1 };
+    let end = TrackedCurrency::try_next_state(&start, &mint);
+    assert_eq!(end, Err(TransitionError::Overflow));
+
+    let broken = Ledger {
+        issuance: 0,
+        ..start
+    };
+    assert!(!TrackedCurrency::invariants_hold(&broken));
+}
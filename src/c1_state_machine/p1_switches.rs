@@ -2,7 +2,7 @@
 //! In these examples, we use actually switch boards as the state machine. The state is,
 //! well, just the state of the switches.
 
-use super::StateMachine;
+use super::{BlockContext, StateMachine, UndoableStateMachine};
 
 /// This state machine models a single light switch.
 /// The internal state, a bool, represents whether the switch is on or not.
@@ -20,18 +20,33 @@ impl StateMachine for LightSwitch {
     }
 }
 
+/// Toggling is its own inverse, so there is nothing to remember in order to undo it.
+impl UndoableStateMachine for LightSwitch {
+    type Undo = ();
+
+    fn apply(state: &mut bool, _: &(), _: &BlockContext) {
+        *state = !*state;
+    }
+
+    fn undo(state: &mut bool, _: ()) {
+        *state = !*state;
+    }
+}
+
 /// This second  state machine models two light switches with one weird property.
 /// Whenever switch one is turned off, switch two also goes off.
 pub struct WeirdSwitchMachine;
 
 /// The state is now two switches instead of one so we use a struct.
 #[derive(PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TwoSwitches {
     first_switch: bool,
     second_switch: bool,
 }
 
 /// Now there are two switches so we need a proper type for the transition.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Toggle {
     FirstSwitch,
     SecondSwitch,
@@ -0,0 +1,240 @@
+//! So far every state machine has had a fixed set of transitions, chosen by whoever wrote it.
+//! Adding a new kind of transition means changing the machine. Smart contract platforms take a
+//! different approach: a transition carries a small program, and the machine runs it.
+//!
+//! Here we build a tiny virtual machine. Its state is a key-value store, and a program is a list
+//! of instructions that work on a stack of numbers. Anyone can write a program, so the machine
+//! must be careful about three things.
+//!
+//! * Programs must be deterministic. Every node re-executes them when it verifies a block, and
+//!   every node must reach the same post-state.
+//! * Programs must terminate. A program can jump backwards, so it can loop forever. So every
+//!   program carries a gas limit, every instruction costs some gas, and a program that runs out
+//!   is stopped.
+//! * Programs that fail must not leave a half-finished mess behind. A program that fails for
+//!   any reason, including running out of gas, does not change the store at all.
+
+use std::collections::BTreeMap;
+
+use super::{StateMachine, TransitionError, TryStateMachine};
+
+/// The key-value store that programs work on. Keys that were never written hold zero.
+pub type Storage = BTreeMap<u64, u64>;
+
+/// The most gas that any program may ask for.
+pub const MAX_GAS: u64 = 10_000;
+
+/// A single step of a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    /// Push the given number on top of the stack.
+    Push(u64),
+    /// Pop two numbers and push their sum, wrapping around on overflow as the EVM does.
+    Add,
+    /// Pop a value, then a key, and write the value at the key in the store.
+    Store,
+    /// Pop a key, and push the value at that key in the store.
+    Load,
+    /// Pop a number, and continue at the given instruction if it is not zero. Jumping just past
+    /// the last instruction ends the program.
+    JumpIf(usize),
+}
+
+impl Instruction {
+    /// The gas that this instruction costs. Writing to the store costs more than anything else,
+    /// because every node has to keep what was written.
+    pub fn gas(&self) -> u64 {
+        match self {
+            Instruction::Store => 10,
+            _ => 1,
+        }
+    }
+}
+
+/// A program, along with the most gas it may use.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    /// The instructions, run in order from the first one, except where they jump.
+    pub code: Vec<Instruction>,
+    /// The most gas the program may use. It may not be more than `MAX_GAS`.
+    pub gas_limit: u64,
+}
+
+/// Run a program against the given store. Returns the store that the program leaves behind,
+/// along with the gas it used.
+///
+/// Fails with `OutOfGas` if the program needs more gas than its limit, and with `Invalid` if the
+/// program asks for more than `MAX_GAS`, pops from an empty stack, or jumps outside of its code.
+pub fn run(storage: &Storage, program: &Program) -> Result<(Storage, u64), TransitionError> {
+    if program.gas_limit > MAX_GAS {
+        return Err(TransitionError::Invalid);
+    }
+    let mut storage = storage.clone();
+    let mut stack: Vec<u64> = Vec::new();
+    let mut gas_used: u64 = 0;
+    let mut next = 0;
+    while let Some(instruction) = program.code.get(next) {
+        gas_used += instruction.gas();
+        if gas_used > program.gas_limit {
+            return Err(TransitionError::OutOfGas);
+        }
+        next += 1;
+        let mut pop = || stack.pop().ok_or(TransitionError::Invalid);
+        match *instruction {
+            Instruction::Push(value) => stack.push(value),
+            Instruction::Add => {
+                let sum = pop()?.wrapping_add(pop()?);
+                stack.push(sum);
+            }
+            Instruction::Store => {
+                let value = pop()?;
+                storage.insert(pop()?, value);
+            }
+            Instruction::Load => {
+                let key = pop()?;
+                stack.push(storage.get(&key).copied().unwrap_or(0));
+            }
+            Instruction::JumpIf(target) => {
+                if pop()? != 0 {
+                    if target > program.code.len() {
+                        return Err(TransitionError::Invalid);
+                    }
+                    next = target;
+                }
+            }
+        }
+    }
+    Ok((storage, gas_used))
+}
+
+/// A machine whose transitions are programs.
+pub struct Vm;
+
+impl StateMachine for Vm {
+    type State = Storage;
+    type Transition = Program;
+
+    /// Programs that fail leave the store unchanged.
+    fn next_state(starting_state: &Storage, t: &Program) -> Storage {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn human_name() -> String {
+        "Virtual machine".into()
+    }
+}
+
+impl TryStateMachine for Vm {
+    fn try_next_state(starting_state: &Storage, t: &Program) -> Result<Storage, TransitionError> {
+        run(starting_state, t).map(|(storage, _)| storage)
+    }
+}
+
+#[cfg(test)]
+use Instruction::*;
+
+/// Count down from the value at key 0 to zero, adding each number to the value at key 1.
+#[cfg(test)]
+fn sum_down(gas_limit: u64) -> Program {
+    let code = vec![
+        // Stop once the counter reaches zero.
+        Push(0),
+        Load,
+        JumpIf(5),
+        Push(1),
+        JumpIf(20),
+        // Add the counter to the total.
+        Push(1),
+        Push(1),
+        Load,
+        Push(0),
+        Load,
+        Add,
+        Store,
+        // Decrement the counter, by adding `u64::MAX` and letting it wrap around, then loop.
+        Push(0),
+        Push(0),
+        Load,
+        Push(u64::MAX),
+        Add,
+        Store,
+        Push(1),
+        JumpIf(0),
+    ];
+    Program { code, gas_limit }
+}
+
+#[test]
+fn sm_20_programs_change_the_store() {
+    let program = Program {
+        code: vec![
+            Push(7),
+            Push(2),
+            Push(3),
+            Add,
+            Store,
+            Push(7),
+            Load,
+            JumpIf(9),
+            Push(1),
+        ],
+        gas_limit: 100,
+    };
+    let (end, gas_used) = run(&Storage::new(), &program).unwrap();
+    assert_eq!(end, Storage::from([(7, 5)]));
+    assert_eq!(gas_used, 17);
+    assert_eq!(Vm::next_state(&Storage::new(), &program), end);
+}
+
+#[test]
+fn sm_20_failed_programs_change_nothing() {
+    let start = Storage::from([(0, 1)]);
+    let program = |code| Program {
+        code,
+        gas_limit: 100,
+    };
+
+    // Stores the new value, then fails.
+    let underflow = program(vec![Push(0), Push(2), Store, Add]);
+    assert_eq!(
+        Vm::try_next_state(&start, &underflow),
+        Err(TransitionError::Invalid)
+    );
+    assert_eq!(Vm::next_state(&start, &underflow), start);
+
+    let wild_jump = program(vec![Push(1), JumpIf(3)]);
+    assert_eq!(
+        Vm::try_next_state(&start, &wild_jump),
+        Err(TransitionError::Invalid)
+    );
+    let greedy = Program {
+        code: vec![],
+        gas_limit: MAX_GAS + 1,
+    };
+    assert_eq!(
+        Vm::try_next_state(&start, &greedy),
+        Err(TransitionError::Invalid)
+    );
+}
+
+#[test]
+fn sm_20_loops_run_out_of_gas() {
+    let start = Storage::from([(0, 3)]);
+    let (end, gas_used) = run(&start, &sum_down(1_000)).unwrap();
+    assert_eq!(end, Storage::from([(0, 0), (1, 6)]));
+    assert_eq!(
+        run(&start, &sum_down(gas_used - 1)),
+        Err(TransitionError::OutOfGas)
+    );
+
+    let forever = Program {
+        code: vec![Push(1), JumpIf(0)],
+        gas_limit: MAX_GAS,
+    };
+    assert_eq!(
+        Vm::try_next_state(&start, &forever),
+        Err(TransitionError::OutOfGas)
+    );
+}
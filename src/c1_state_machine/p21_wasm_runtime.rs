@@ -0,0 +1,258 @@
+//! Every node on a chain runs the same state transition function, compiled into its binary. So
+//! changing the rules means getting every node operator to install a new binary at the same
+//! time, and anyone who does not is left on a fork of their own.
+//!
+//! Substrate avoids this by keeping the state transition function in the state itself, compiled
+//! to WebAssembly. Nodes read the code from the state and run it in an interpreter. Changing the
+//! rules is then just another transition, which replaces the code. Every node applies it at the
+//! same block, without installing anything, which is why these are called forkless upgrades.
+//!
+//! Here the code works on the same key-value store as the virtual machine from the previous
+//! part. It must export a function `apply(arg: i64)`, which is called once for every `Call`
+//! transition. It may import `get(key: i64) -> i64` and `set(key: i64, value: i64)` from the
+//! `env` module, to read and write the store. Numbers are passed as `i64`, because WebAssembly
+//! has no unsigned types. Running the code burns fuel, so that it can not loop forever, and it
+//! may only allocate so much memory, so that it can not exhaust the node's.
+
+use std::sync::OnceLock;
+
+use wasmi::core::TrapCode;
+use wasmi::{
+    Caller, Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use super::{StateMachine, Storage, TransitionError, TryStateMachine};
+
+/// The fuel that a single call may burn.
+pub const FUEL: u64 = 10_000;
+
+/// The most linear memory that the code may use, in bytes. That is 16 WebAssembly pages.
+pub const MAX_MEMORY: usize = 1 << 20;
+
+/// The most elements that the code's table may hold.
+pub const MAX_TABLE_ELEMENTS: u32 = 1_000;
+
+/// The runtime's code, along with the store it works on.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WasmState {
+    /// The state transition function, as a WebAssembly module.
+    pub code: Vec<u8>,
+    /// The store that the code reads and writes.
+    pub storage: Storage,
+}
+
+/// The transitions of a chain whose rules live in its state.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WasmTransition {
+    /// Call the code's `apply` function with the given argument.
+    Call(u64),
+    /// Replace the code. The new code must be a valid runtime. On a real chain only governance
+    /// may do this, but here anyone can.
+    SetCode(Vec<u8>),
+}
+
+/// What running code can reach: the store it works on, and the limits on what it allocates.
+struct Host {
+    storage: Storage,
+    limits: StoreLimits,
+}
+
+/// The engine that runs every call. One engine can run any number of modules, so it is built
+/// once rather than for every call.
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    })
+}
+
+/// Load the given code, ready to work on the given store.
+fn instantiate(
+    code: &[u8],
+    storage: Storage,
+) -> Result<(Store<Host>, TypedFunc<i64, ()>), wasmi::Error> {
+    let engine = engine();
+    let module = Module::new(engine, code)?;
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY)
+        .table_elements(MAX_TABLE_ELEMENTS)
+        .instances(1)
+        .memories(1)
+        .tables(1)
+        .build();
+    let mut store = Store::new(engine, Host { storage, limits });
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(FUEL)?;
+
+    let mut linker = Linker::<Host>::new(engine);
+    linker.func_wrap("env", "get", |caller: Caller<'_, Host>, key: i64| {
+        caller
+            .data()
+            .storage
+            .get(&(key as u64))
+            .copied()
+            .unwrap_or(0) as i64
+    })?;
+    linker.func_wrap(
+        "env",
+        "set",
+        |mut caller: Caller<'_, Host>, key: i64, value: i64| {
+            caller.data_mut().storage.insert(key as u64, value as u64);
+        },
+    )?;
+    let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+    let apply = instance.get_typed_func::<i64, ()>(&store, "apply")?;
+    Ok((store, apply))
+}
+
+/// A state machine that runs whatever code its state holds.
+pub struct WasmRuntime;
+
+impl StateMachine for WasmRuntime {
+    type State = WasmState;
+    type Transition = WasmTransition;
+
+    /// Calls that fail, and upgrades to invalid code, leave the state unchanged.
+    fn next_state(starting_state: &WasmState, t: &WasmTransition) -> WasmState {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn human_name() -> String {
+        "Wasm runtime".into()
+    }
+}
+
+impl TryStateMachine for WasmRuntime {
+    /// A call fails with `OutOfGas` if it burns all of its fuel, and with `Invalid` if the code
+    /// can not be loaded or traps. An upgrade fails with `Invalid` if the new code can not be
+    /// loaded, which includes code that asks for more than `MAX_MEMORY` up front.
+    fn try_next_state(
+        starting_state: &WasmState,
+        t: &WasmTransition,
+    ) -> Result<WasmState, TransitionError> {
+        match t {
+            WasmTransition::Call(arg) => {
+                let storage = starting_state.storage.clone();
+                let (mut store, apply) = instantiate(&starting_state.code, storage)
+                    .map_err(|_| TransitionError::Invalid)?;
+                apply.call(&mut store, *arg as i64).map_err(|error| {
+                    match error.as_trap_code() {
+                        Some(TrapCode::OutOfFuel) => TransitionError::OutOfGas,
+                        _ => TransitionError::Invalid,
+                    }
+                })?;
+                Ok(WasmState {
+                    code: starting_state.code.clone(),
+                    storage: store.into_data().storage,
+                })
+            }
+            WasmTransition::SetCode(code) => {
+                instantiate(code, Storage::new()).map_err(|_| TransitionError::Invalid)?;
+                Ok(WasmState {
+                    code: code.clone(),
+                    storage: starting_state.storage.clone(),
+                })
+            }
+        }
+    }
+}
+
+/// A runtime that adds `factor` times the argument to the value at key 0.
+#[cfg(test)]
+fn adder(factor: u64) -> Vec<u8> {
+    wat::parse_str(format!(
+        r#"(module
+            (import "env" "get" (func $get (param i64) (result i64)))
+            (import "env" "set" (func $set (param i64 i64)))
+            (func (export "apply") (param $arg i64)
+                (call $set
+                    (i64.const 0)
+                    (i64.add
+                        (call $get (i64.const 0))
+                        (i64.mul (local.get $arg) (i64.const {factor}))))))"#
+    ))
+    .unwrap()
+}
+
+#[test]
+fn sm_21_upgrades_change_the_rules() {
+    use WasmTransition::*;
+
+    let start = WasmState {
+        code: adder(1),
+        storage: Storage::new(),
+    };
+    let transitions = [Call(5), SetCode(adder(2)), Call(5)];
+    let end = WasmRuntime::apply_all(&start, &transitions);
+    assert_eq!(end.storage, Storage::from([(0, 15)]));
+    assert_eq!(end.code, adder(2));
+}
+
+#[test]
+fn sm_21_bad_code_and_endless_loops_are_refused() {
+    use WasmTransition::*;
+
+    let start = WasmState {
+        code: adder(1),
+        storage: Storage::from([(0, 1)]),
+    };
+    let garbage = SetCode(vec![0, 1, 2, 3]);
+    assert_eq!(
+        WasmRuntime::try_next_state(&start, &garbage),
+        Err(TransitionError::Invalid)
+    );
+    let no_apply = SetCode(wat::parse_str("(module)").unwrap());
+    assert_eq!(
+        WasmRuntime::try_next_state(&start, &no_apply),
+        Err(TransitionError::Invalid)
+    );
+
+    let endless = r#"(module (func (export "apply") (param i64) (loop (br 0))))"#;
+    let looping = WasmState {
+        code: wat::parse_str(endless).unwrap(),
+        ..start
+    };
+    assert_eq!(
+        WasmRuntime::try_next_state(&looping, &Call(1)),
+        Err(TransitionError::OutOfGas)
+    );
+    assert_eq!(WasmRuntime::next_state(&looping, &Call(1)), looping);
+}
+
+#[test]
+fn sm_21_memory_is_limited() {
+    use WasmTransition::*;
+
+    let start = WasmState {
+        code: adder(1),
+        storage: Storage::new(),
+    };
+    let greedy = SetCode(
+        wat::parse_str(r#"(module (memory 17) (func (export "apply") (param i64)))"#).unwrap(),
+    );
+    assert_eq!(
+        WasmRuntime::try_next_state(&start, &greedy),
+        Err(TransitionError::Invalid)
+    );
+
+    // Growing memory past the limit fails, which the code sees as `memory.grow` returning -1.
+    let growing = r#"(module
+        (import "env" "set" (func $set (param i64 i64)))
+        (memory 1)
+        (func (export "apply") (param $pages i64)
+            (call $set
+                (i64.const 0)
+                (i64.extend_i32_s (memory.grow (i32.wrap_i64 (local.get $pages)))))))"#;
+    let growing = WasmState {
+        code: wat::parse_str(growing).unwrap(),
+        ..start
+    };
+    let end = WasmRuntime::try_next_state(&growing, &Call(1)).unwrap();
+    assert_eq!(end.storage, Storage::from([(0, 1)]));
+    let end = WasmRuntime::try_next_state(&growing, &Call(16)).unwrap();
+    assert_eq!(end.storage, Storage::from([(0, u64::MAX)]));
+}
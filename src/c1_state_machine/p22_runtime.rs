@@ -0,0 +1,235 @@
+//! The tuples from part 14 can compose any state machines, but their transitions are only told
+//! apart by position: `OneOf3::Second` says nothing about what it does. Substrate instead gives
+//! each pallet in the runtime a name, and the outer `Call` enum has one named variant per
+//! pallet. Every extrinsic is a `Call`, and the runtime dispatches it to the pallet it names.
+//!
+//! Here we do the same for the machines we already have. The runtime has balances, staking,
+//! and governance, plus a system pallet whose only call is a remark. Remarks do not change the
+//! state at all. They are just a way to put some bytes on chain, which is handy for
+//! timestamping a document, or for testing.
+//!
+//! On their own, the pallets keep separate ledgers, so staking would happily bond funds that
+//! nobody owns. In the runtime, the two share their money. Bonding takes the stake out of the
+//! authority's account, and withdrawing puts it back. Slashed stake is simply gone.
+
+use super::p8_balances::{balance_of, set_balance};
+use super::{
+    AccountId, Balances, BlockContext, Currency, CurrencyTransaction, Governance, GovernanceState,
+    GovernanceTransition, Staking, StakingLedger, StakingTransition, StateMachine, User,
+};
+use crate::c3_consensus::ConsensusAuthority;
+
+/// The calls of the system pallet.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SystemCall {
+    /// Put the given bytes on chain, without changing the state.
+    Remark(Vec<u8>),
+}
+
+/// Every extrinsic of the runtime, named after the pallet that handles it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Call {
+    Balances(CurrencyTransaction),
+    Staking(StakingTransition),
+    Governance(GovernanceTransition),
+    System(SystemCall),
+}
+
+/// The state of every pallet in the runtime. The system pallet has no state.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimeState {
+    pub balances: Balances,
+    pub staking: StakingLedger,
+    pub governance: GovernanceState,
+}
+
+/// Balances, staking, governance and system, running as one machine.
+pub struct Runtime;
+
+/// The account that holds the given authority's funds, or None if it is no authority at all.
+fn account_of(who: ConsensusAuthority) -> Option<AccountId> {
+    match who {
+        ConsensusAuthority::Alice => Some(User::Alice),
+        ConsensusAuthority::Bob => Some(User::Bob),
+        ConsensusAuthority::Charlie => Some(User::Charlie),
+        ConsensusAuthority::Unsealed => None,
+    }
+}
+
+/// Apply a staking call, moving bonded funds out of the authority's account and withdrawn
+/// funds back into it. Returns None if the call is rejected, including a bond that the
+/// authority can not afford.
+fn stake(
+    starting_state: &RuntimeState,
+    t: &StakingTransition,
+    context: &BlockContext,
+) -> Option<RuntimeState> {
+    let staking = Staking::next_state_in_block(&starting_state.staking, t, context);
+    let mut balances = starting_state.balances.clone();
+    match *t {
+        StakingTransition::Bond { who, amount } => {
+            if staking.active_stake(who) == starting_state.staking.active_stake(who) {
+                return None;
+            }
+            let account = account_of(who)?;
+            let balance = balance_of(&balances, &account).checked_sub(amount)?;
+            set_balance(&mut balances, account, balance);
+        }
+        StakingTransition::Withdraw { who } => {
+            let released =
+                starting_state.staking.unlocking_stake(who) - staking.unlocking_stake(who);
+            let account = account_of(who)?;
+            let balance = balance_of(&balances, &account).checked_add(released)?;
+            set_balance(&mut balances, account, balance);
+        }
+        _ => {}
+    }
+    Some(RuntimeState {
+        balances,
+        staking,
+        ..starting_state.clone()
+    })
+}
+
+impl StateMachine for Runtime {
+    type State = RuntimeState;
+    type Transition = Call;
+
+    /// Only the pallet that the call names changes, except that bonding and withdrawing stake
+    /// also move funds between staking and balances. Rejected calls change nothing.
+    fn next_state(starting_state: &RuntimeState, t: &Call) -> RuntimeState {
+        Self::next_state_in_block(starting_state, t, &BlockContext::default())
+    }
+
+    /// Every pallet sees the same block context.
+    fn next_state_in_block(
+        starting_state: &RuntimeState,
+        t: &Call,
+        context: &BlockContext,
+    ) -> RuntimeState {
+        let mut state = starting_state.clone();
+        match t {
+            Call::Balances(t) => {
+                state.balances =
+                    Currency::next_state_in_block(&starting_state.balances, t, context);
+            }
+            Call::Staking(t) => {
+                if let Some(post_state) = stake(starting_state, t, context) {
+                    state = post_state;
+                }
+            }
+            Call::Governance(t) => {
+                state.governance =
+                    Governance::next_state_in_block(&starting_state.governance, t, context);
+            }
+            Call::System(SystemCall::Remark(_)) => {}
+        }
+        state
+    }
+
+    fn human_name() -> String {
+        "Runtime".into()
+    }
+}
+
+#[cfg(test)]
+use super::p7_staking::UNBONDING_DELAY;
+#[cfg(test)]
+use super::{ChainParameters, ParameterChange, Stakes, User::*};
+
+#[cfg(test)]
+fn genesis() -> RuntimeState {
+    RuntimeState {
+        balances: Balances::from([(Alice, 100)]),
+        staking: StakingLedger::new(Stakes::from([(ConsensusAuthority::Alice, 50)])),
+        governance: GovernanceState::new(
+            Balances::from([(Alice, 10)]),
+            ChainParameters {
+                pow_threshold: 1000,
+                max_extrinsics: 100,
+            },
+        ),
+    }
+}
+
+#[test]
+fn sm_22_calls_are_dispatched_to_their_pallet() {
+    let start = genesis();
+    let calls = [
+        Call::Balances(CurrencyTransaction::Transfer {
+            from: Alice,
+            to: Bob,
+            amount: 40,
+        }),
+        Call::Staking(StakingTransition::Bond {
+            who: ConsensusAuthority::Bob,
+            amount: 5,
+        }),
+        Call::Governance(GovernanceTransition::Propose {
+            who: Alice,
+            change: ParameterChange::SetMaxExtrinsics(10),
+        }),
+        Call::Governance(GovernanceTransition::Vote {
+            who: Alice,
+            proposal: 0,
+            aye: true,
+        }),
+    ];
+    let end = Runtime::apply_all(&start, &calls);
+
+    assert_eq!(end.balances, Balances::from([(Alice, 60), (Bob, 35)]));
+    assert_eq!(end.staking.active_stake(ConsensusAuthority::Bob), 5);
+    assert_eq!(end.governance.proposals.len(), 1);
+    let alone = Governance::apply_all(
+        &start.governance,
+        &[
+            GovernanceTransition::Propose {
+                who: Alice,
+                change: ParameterChange::SetMaxExtrinsics(10),
+            },
+            GovernanceTransition::Vote {
+                who: Alice,
+                proposal: 0,
+                aye: true,
+            },
+        ],
+    );
+    assert_eq!(end.governance, alone);
+}
+
+#[test]
+fn sm_22_stake_comes_out_of_balances() {
+    let start = genesis();
+    let bond = |amount| {
+        Call::Staking(StakingTransition::Bond {
+            who: ConsensusAuthority::Alice,
+            amount,
+        })
+    };
+    assert_eq!(Runtime::next_state(&start, &bond(101)), start);
+
+    let mut calls = vec![
+        bond(100),
+        Call::Staking(StakingTransition::Unbond {
+            who: ConsensusAuthority::Alice,
+            amount: 30,
+        }),
+    ];
+    calls.extend((0..UNBONDING_DELAY).map(|_| Call::Staking(StakingTransition::NewEra)));
+    calls.push(Call::Staking(StakingTransition::Withdraw {
+        who: ConsensusAuthority::Alice,
+    }));
+    let end = Runtime::apply_all(&start, &calls);
+    assert_eq!(end.balances, Balances::from([(Alice, 30)]));
+    assert_eq!(end.staking.active_stake(ConsensusAuthority::Alice), 120);
+}
+
+#[test]
+fn sm_22_remarks_change_nothing() {
+    let start = genesis();
+    let remark = Call::System(SystemCall::Remark(b"hello, chain".to_vec()));
+    assert_eq!(Runtime::next_state(&start, &remark), start);
+}
@@ -24,6 +24,7 @@ pub struct AccountedCurrency;
 type Balances = HashMap<User, u64>;
 
 /// The state transitions that users can make in an accounted currency system
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AccountingTransaction {
     /// Create some new money for the given minter in the given amount
     Mint { minter: User, amount: u64 },
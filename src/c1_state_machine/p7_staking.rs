@@ -0,0 +1,405 @@
+//! In Proof of Stake, authorities put tokens at stake to earn the right to author blocks.
+//! The stake is what keeps them honest. If they misbehave, part of it is taken away, or slashed.
+//!
+//! In this module we design the state machine that tracks how much each authority has staked,
+//! and that applies slashes when misbehavior is reported. Authorities may also unbond their
+//! stake, but it stays locked, and slashable, for a while before it can be withdrawn.
+
+use std::collections::BTreeMap;
+
+use super::StateMachine;
+use crate::c3_consensus::{ConsensusAuthority, EquivocationProof};
+
+/// The percentage of an authority's stake that is slashed for equivocating.
+pub const EQUIVOCATION_SLASH_PERCENT: u64 = 10;
+
+/// The number of eras that unbonded stake stays locked before it can be withdrawn.
+///
+/// During this time the stake no longer counts towards authoring blocks, but it can still be
+/// slashed. Without the delay, an authority could misbehave and then unbond before anyone
+/// had a chance to report it.
+pub const UNBONDING_DELAY: u64 = 3;
+
+/// This state machine tracks the stake of each authority.
+pub struct Staking;
+
+/// The amount each authority has staked. Authorities with no stake are not stored at all.
+///
+/// This is a `BTreeMap` rather than a `HashMap` so that the state can be hashed, and so
+/// that it can be used as the state of a blockchain.
+pub type Stakes = BTreeMap<ConsensusAuthority, u64>;
+
+/// Stake that has been unbonded, and is waiting for the unbonding delay to pass.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnlockChunk {
+    /// The amount of stake that is unlocking.
+    pub amount: u64,
+    /// The first era in which this stake may be withdrawn.
+    pub unlock_era: u64,
+}
+
+/// The state of the staking system.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StakingLedger {
+    /// The current era. Unbonding delays are measured in eras.
+    pub era: u64,
+    /// The stake that is actively bonded, and counts towards authoring blocks.
+    pub active: Stakes,
+    /// The stake that each authority has unbonded but not yet withdrawn, oldest first.
+    /// Authorities with nothing unlocking are not stored at all.
+    pub unlocking: BTreeMap<ConsensusAuthority, Vec<UnlockChunk>>,
+}
+
+impl StakingLedger {
+    /// A ledger in the first era with the given active stakes and nothing unlocking.
+    pub fn new(active: Stakes) -> Self {
+        Self {
+            active,
+            ..Default::default()
+        }
+    }
+
+    /// The stake that the given authority has actively bonded.
+    pub fn active_stake(&self, who: ConsensusAuthority) -> u64 {
+        self.active.get(&who).copied().unwrap_or(0)
+    }
+
+    /// The stake that the given authority has unbonded but not yet withdrawn, whether or not
+    /// the unbonding delay has passed.
+    pub fn unlocking_stake(&self, who: ConsensusAuthority) -> u64 {
+        self.unlocking
+            .get(&who)
+            .map_or(0, |chunks| chunks.iter().map(|chunk| chunk.amount).sum())
+    }
+
+    /// The active stake of every authority with any, in the order of the authorities.
+    ///
+    /// This is exactly the stake table that the Proof of Stake engine from the consensus
+    /// chapter, `SimplePos`, selects its authors from.
+    pub fn authority_stakes(&self) -> Vec<(ConsensusAuthority, u64)> {
+        self.active
+            .iter()
+            .map(|(who, stake)| (*who, *stake))
+            .collect()
+    }
+
+    /// Slash the given amount from an authority. Active stake is taken first, then unlocking
+    /// stake, newest chunk first.
+    fn slash(&mut self, who: ConsensusAuthority, mut amount: u64) {
+        if let Some(stake) = self.active.get_mut(&who) {
+            let taken = amount.min(*stake);
+            *stake -= taken;
+            amount -= taken;
+            if *stake == 0 {
+                self.active.remove(&who);
+            }
+        }
+        if let Some(chunks) = self.unlocking.get_mut(&who) {
+            while amount > 0 {
+                let Some(chunk) = chunks.last_mut() else {
+                    break;
+                };
+                let taken = amount.min(chunk.amount);
+                chunk.amount -= taken;
+                amount -= taken;
+                if chunk.amount == 0 {
+                    chunks.pop();
+                }
+            }
+            if chunks.is_empty() {
+                self.unlocking.remove(&who);
+            }
+        }
+    }
+}
+
+/// The transitions that can be made in the staking system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StakingTransition {
+    /// Lock up the given amount of additional stake for the given authority.
+    Bond {
+        who: ConsensusAuthority,
+        amount: u64,
+    },
+    /// Stop the given amount of active stake from counting towards authoring blocks. It can
+    /// be withdrawn once `UNBONDING_DELAY` eras have passed. Rejected if the authority does
+    /// not have that much active stake.
+    Unbond {
+        who: ConsensusAuthority,
+        amount: u64,
+    },
+    /// Release all of the given authority's unlocking stake whose delay has passed.
+    Withdraw { who: ConsensusAuthority },
+    /// Move on to the next era.
+    NewEra,
+    /// Punish the given authority for equivocating by slashing part of their stake.
+    SlashEquivocation { offender: ConsensusAuthority },
+}
+
+/// Any equivocation proof can be turned into a slash. The proof itself should be verified
+/// by the consensus engine before the slash is applied.
+impl<Digest> From<&EquivocationProof<Digest>> for StakingTransition {
+    fn from(proof: &EquivocationProof<Digest>) -> Self {
+        StakingTransition::SlashEquivocation {
+            offender: proof.offender,
+        }
+    }
+}
+
+impl StateMachine for Staking {
+    type State = StakingLedger;
+    type Transition = StakingTransition;
+
+    /// Rejected transitions leave the ledger unchanged.
+    fn next_state(starting_state: &StakingLedger, t: &StakingTransition) -> StakingLedger {
+        let mut ledger = starting_state.clone();
+        match t {
+            StakingTransition::Bond { who, amount } => {
+                if *amount > 0 {
+                    if let Some(stake) = ledger.active_stake(*who).checked_add(*amount) {
+                        ledger.active.insert(*who, stake);
+                    }
+                }
+            }
+            StakingTransition::Unbond { who, amount } => {
+                let Some(remaining) = ledger.active_stake(*who).checked_sub(*amount) else {
+                    return ledger;
+                };
+                if *amount == 0 {
+                    return ledger;
+                }
+                if remaining == 0 {
+                    ledger.active.remove(who);
+                } else {
+                    ledger.active.insert(*who, remaining);
+                }
+                ledger.unlocking.entry(*who).or_default().push(UnlockChunk {
+                    amount: *amount,
+                    unlock_era: ledger.era.saturating_add(UNBONDING_DELAY),
+                });
+            }
+            StakingTransition::Withdraw { who } => {
+                let era = ledger.era;
+                if let Some(chunks) = ledger.unlocking.get_mut(who) {
+                    chunks.retain(|chunk| chunk.unlock_era > era);
+                    if chunks.is_empty() {
+                        ledger.unlocking.remove(who);
+                    }
+                }
+            }
+            StakingTransition::NewEra => {
+                ledger.era = ledger.era.saturating_add(1);
+            }
+            StakingTransition::SlashEquivocation { offender } => {
+                let total = ledger
+                    .active_stake(*offender)
+                    .saturating_add(ledger.unlocking_stake(*offender));
+                if total > 0 {
+                    // Always slash at least one unit, so that small stakes are punished too.
+                    let slash = (total as u128 * EQUIVOCATION_SLASH_PERCENT as u128 / 100) as u64;
+                    ledger.slash(*offender, slash.max(1));
+                }
+            }
+        }
+        ledger
+    }
+
+    fn human_name() -> String {
+        "Staking".into()
+    }
+}
+
+#[test]
+fn sm_7_bond_adds_stake() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100)]));
+    let end = Staking::next_state(
+        &start,
+        &StakingTransition::Bond {
+            who: Alice,
+            amount: 50,
+        },
+    );
+    let end = Staking::next_state(
+        &end,
+        &StakingTransition::Bond {
+            who: Bob,
+            amount: 20,
+        },
+    );
+
+    assert_eq!(end.active, Stakes::from([(Alice, 150), (Bob, 20)]));
+}
+
+#[test]
+fn sm_7_empty_bond() {
+    use ConsensusAuthority::*;
+    let end = Staking::next_state(
+        &StakingLedger::default(),
+        &StakingTransition::Bond {
+            who: Alice,
+            amount: 0,
+        },
+    );
+
+    assert_eq!(end.active, Stakes::new());
+}
+
+#[test]
+fn sm_7_slash_equivocation() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100), (Bob, 100)]));
+    let end = Staking::next_state(
+        &start,
+        &StakingTransition::SlashEquivocation { offender: Alice },
+    );
+
+    assert_eq!(end.active, Stakes::from([(Alice, 90), (Bob, 100)]));
+}
+
+#[test]
+fn sm_7_slash_removes_exhausted_stake() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 1), (Bob, 100)]));
+    let end = Staking::next_state(
+        &start,
+        &StakingTransition::SlashEquivocation { offender: Alice },
+    );
+    let end = Staking::next_state(
+        &end,
+        &StakingTransition::SlashEquivocation { offender: Charlie },
+    );
+
+    assert_eq!(end.active, Stakes::from([(Bob, 100)]));
+}
+
+#[test]
+fn sm_7_unbond_leaves_active_stake() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100), (Bob, 50)]));
+    let end = Staking::apply_all(
+        &start,
+        &[
+            StakingTransition::Unbond {
+                who: Alice,
+                amount: 30,
+            },
+            StakingTransition::Unbond {
+                who: Bob,
+                amount: 50,
+            },
+        ],
+    );
+
+    assert_eq!(end.active, Stakes::from([(Alice, 70)]));
+    assert_eq!(end.unlocking_stake(Alice), 30);
+    assert_eq!(end.unlocking_stake(Bob), 50);
+    assert_eq!(
+        end.unlocking[&Alice],
+        vec![UnlockChunk {
+            amount: 30,
+            unlock_era: UNBONDING_DELAY,
+        }]
+    );
+}
+
+#[test]
+fn sm_7_unbond_more_than_active_is_rejected() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100)]));
+    let too_much = StakingTransition::Unbond {
+        who: Alice,
+        amount: 101,
+    };
+    let nothing = StakingTransition::Unbond {
+        who: Alice,
+        amount: 0,
+    };
+    let not_staked = StakingTransition::Unbond {
+        who: Bob,
+        amount: 1,
+    };
+
+    assert_eq!(Staking::next_state(&start, &too_much), start);
+    assert_eq!(Staking::next_state(&start, &nothing), start);
+    assert_eq!(Staking::next_state(&start, &not_staked), start);
+}
+
+#[test]
+fn sm_7_withdraw_waits_for_unbonding_delay() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100)]));
+    let unbond = |amount| StakingTransition::Unbond { who: Alice, amount };
+    let withdraw = StakingTransition::Withdraw { who: Alice };
+
+    // Unbond twice, one era apart.
+    let ledger = Staking::apply_all(&start, &[unbond(10), StakingTransition::NewEra, unbond(20)]);
+
+    // Nothing can be withdrawn until the first chunk has waited out the delay.
+    let mut ledger = ledger;
+    assert_eq!(ledger.era, 1);
+    for _ in 2..UNBONDING_DELAY {
+        ledger = Staking::apply_all(&ledger, &[StakingTransition::NewEra, withdraw.clone()]);
+        assert_eq!(ledger.unlocking_stake(Alice), 30);
+    }
+
+    // Then the first chunk is released, but the second is still locked.
+    let ledger = Staking::apply_all(&ledger, &[StakingTransition::NewEra, withdraw.clone()]);
+    assert_eq!(ledger.era, UNBONDING_DELAY);
+    assert_eq!(ledger.unlocking_stake(Alice), 20);
+
+    let ledger = Staking::apply_all(&ledger, &[StakingTransition::NewEra, withdraw]);
+    assert_eq!(ledger.unlocking_stake(Alice), 0);
+    assert!(ledger.unlocking.is_empty());
+    assert_eq!(ledger.active, Stakes::from([(Alice, 70)]));
+}
+
+#[test]
+fn sm_7_unlocking_stake_can_still_be_slashed() {
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100), (Bob, 100)]));
+    // Alice tries to escape a slash by unbonding nearly everything right away.
+    let end = Staking::apply_all(
+        &start,
+        &[
+            StakingTransition::Unbond {
+                who: Alice,
+                amount: 95,
+            },
+            StakingTransition::SlashEquivocation { offender: Alice },
+        ],
+    );
+
+    // The slash is 10% of everything Alice has, taken from the active stake first.
+    assert_eq!(end.active_stake(Alice), 0);
+    assert_eq!(end.unlocking_stake(Alice), 90);
+    assert_eq!(end.active, Stakes::from([(Bob, 100)]));
+}
+
+#[test]
+fn sm_7_active_stake_feeds_pos_authority_selection() {
+    use crate::c3_consensus::SimplePos;
+    use ConsensusAuthority::*;
+    let start = StakingLedger::new(Stakes::from([(Alice, 100), (Bob, 100)]));
+    let end = Staking::next_state(
+        &start,
+        &StakingTransition::Unbond {
+            who: Bob,
+            amount: 100,
+        },
+    );
+
+    assert_eq!(end.authority_stakes(), vec![(Alice, 100)]);
+
+    // Bob's unlocking stake does not count, so only Alice may author blocks.
+    let pos = SimplePos {
+        stakes: end.authority_stakes(),
+    };
+    assert_eq!(pos.total_stake(), 100);
+    for parent_hash in 0..20 {
+        assert_eq!(pos.slot_author(parent_hash), Some(Alice));
+    }
+}
@@ -0,0 +1,375 @@
+//! The accounted currency from Part 4 keeps its balances in a `HashMap`. That is fine for a
+//! stand-alone state machine, but a `HashMap` can not be hashed, so it can not be committed to
+//! by a block's state root. Its iteration order is also different on every node.
+//!
+//! Here we build the same kind of currency on a `BTreeMap` instead, so that it can serve as the
+//! state of a real blockchain. This time transfers that the sender can not afford are rejected
+//! outright, and no balance is ever allowed to overflow.
+
+use std::collections::BTreeMap;
+
+use super::{
+    BlockContext, StateMachine, TransitionError, TryStateMachine, UndoableStateMachine, User,
+};
+
+/// The accounts that hold balances. Our play users are enough for now.
+pub type AccountId = User;
+
+/// The balance of every account. Accounts with a zero balance are not stored at all.
+pub type Balances = BTreeMap<AccountId, u64>;
+
+/// This state machine tracks the balance of each account, and lets accounts pay one another.
+pub struct Currency;
+
+/// The transitions that can be made in the currency system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CurrencyTransaction {
+    /// Create the given amount of new money in the given account.
+    Mint { to: AccountId, amount: u64 },
+    /// Destroy the given amount of money from the given account. If the account does not
+    /// have that much, its entire balance is destroyed.
+    Burn { from: AccountId, amount: u64 },
+    /// Move the given amount of money from one account to another. The transfer is rejected
+    /// if the sender can not afford it.
+    Transfer {
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+    },
+}
+
+/// Set the balance of the given account, removing it when the balance is zero.
+pub(super) fn set_balance(balances: &mut Balances, who: AccountId, balance: u64) {
+    if balance == 0 {
+        balances.remove(&who);
+    } else {
+        balances.insert(who, balance);
+    }
+}
+
+/// The balance of the given account, which is zero for accounts that are not stored.
+pub(super) fn balance_of(balances: &Balances, who: &AccountId) -> u64 {
+    balances.get(who).copied().unwrap_or(0)
+}
+
+/// Apply the given transaction to the balances in place. Every check is made before any
+/// balance changes, so a rejected transaction leaves the balances as they were.
+fn try_apply(balances: &mut Balances, t: &CurrencyTransaction) -> Result<(), TransitionError> {
+    match t {
+        CurrencyTransaction::Mint { to, amount } => {
+            let balance = balance_of(balances, to)
+                .checked_add(*amount)
+                .ok_or(TransitionError::Overflow)?;
+            set_balance(balances, *to, balance);
+        }
+        CurrencyTransaction::Burn { from, amount } => {
+            let balance = balance_of(balances, from).saturating_sub(*amount);
+            set_balance(balances, *from, balance);
+        }
+        CurrencyTransaction::Transfer { from, to, amount } => {
+            let sender_balance = balance_of(balances, from)
+                .checked_sub(*amount)
+                .ok_or(TransitionError::InsufficientFunds)?;
+            if from == to {
+                return Err(TransitionError::Invalid);
+            }
+            let receiver_balance = balance_of(balances, to)
+                .checked_add(*amount)
+                .ok_or(TransitionError::Overflow)?;
+            set_balance(balances, *from, sender_balance);
+            set_balance(balances, *to, receiver_balance);
+        }
+    }
+    Ok(())
+}
+
+impl StateMachine for Currency {
+    type State = Balances;
+    type Transition = CurrencyTransaction;
+
+    /// Rejected transactions leave the balances unchanged.
+    fn next_state(starting_state: &Balances, t: &CurrencyTransaction) -> Balances {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    /// The balances are copied once for the whole batch, rather than once per transaction.
+    fn apply_all(starting_state: &Balances, ts: &[CurrencyTransaction]) -> Balances {
+        let mut balances = starting_state.clone();
+        for t in ts {
+            // Rejected transactions leave the balances unchanged, as in `next_state`.
+            let _ = try_apply(&mut balances, t);
+        }
+        balances
+    }
+
+    fn human_name() -> String {
+        "Currency".into()
+    }
+}
+
+impl TryStateMachine for Currency {
+    fn try_next_state(
+        starting_state: &Balances,
+        t: &CurrencyTransaction,
+    ) -> Result<Balances, TransitionError> {
+        let mut balances = starting_state.clone();
+        try_apply(&mut balances, t)?;
+        Ok(balances)
+    }
+}
+
+/// A transaction only ever changes the balances of the accounts it names, so remembering
+/// their old balances is enough to undo it.
+impl UndoableStateMachine for Currency {
+    /// The balance each touched account had before the transaction.
+    type Undo = Vec<(AccountId, u64)>;
+
+    fn apply(state: &mut Balances, t: &CurrencyTransaction, _: &BlockContext) -> Self::Undo {
+        let touched = match t {
+            CurrencyTransaction::Mint { to, .. } => vec![*to],
+            CurrencyTransaction::Burn { from, .. } => vec![*from],
+            CurrencyTransaction::Transfer { from, to, .. } => vec![*from, *to],
+        };
+        let undo = touched
+            .into_iter()
+            .map(|who| (who, balance_of(state, &who)))
+            .collect();
+        // Rejected transactions change nothing, so there is nothing to do.
+        let _ = try_apply(state, t);
+        undo
+    }
+
+    fn undo(state: &mut Balances, undo: Self::Undo) {
+        // Restore in reverse, so that an account named twice ends with its oldest balance.
+        for (who, balance) in undo.into_iter().rev() {
+            set_balance(state, who, balance);
+        }
+    }
+}
+
+#[test]
+fn sm_8_mint_and_burn() {
+    let start = Balances::new();
+    let minted = Currency::next_state(
+        &start,
+        &CurrencyTransaction::Mint {
+            to: User::Alice,
+            amount: 100,
+        },
+    );
+    assert_eq!(minted, Balances::from([(User::Alice, 100)]));
+
+    let burned = Currency::next_state(
+        &minted,
+        &CurrencyTransaction::Burn {
+            from: User::Alice,
+            amount: 30,
+        },
+    );
+    assert_eq!(burned, Balances::from([(User::Alice, 70)]));
+
+    // Burning more than the balance empties the account, which removes it.
+    let emptied = Currency::next_state(
+        &burned,
+        &CurrencyTransaction::Burn {
+            from: User::Alice,
+            amount: 1000,
+        },
+    );
+    assert_eq!(emptied, Balances::new());
+}
+
+#[test]
+fn sm_8_transfer() {
+    let start = Balances::from([(User::Alice, 100), (User::Bob, 5)]);
+    let end = Currency::next_state(
+        &start,
+        &CurrencyTransaction::Transfer {
+            from: User::Alice,
+            to: User::Bob,
+            amount: 100,
+        },
+    );
+    assert_eq!(end, Balances::from([(User::Bob, 105)]));
+}
+
+#[test]
+fn sm_8_transfer_exceeding_balance_is_rejected() {
+    let start = Balances::from([(User::Alice, 100)]);
+    let end = Currency::next_state(
+        &start,
+        &CurrencyTransaction::Transfer {
+            from: User::Alice,
+            to: User::Bob,
+            amount: 101,
+        },
+    );
+    assert_eq!(end, start);
+
+    // Accounts that do not exist can not send anything.
+    let end = Currency::next_state(
+        &start,
+        &CurrencyTransaction::Transfer {
+            from: User::Charlie,
+            to: User::Bob,
+            amount: 1,
+        },
+    );
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_8_balances_never_overflow() {
+    let start = Balances::from([(User::Alice, u64::MAX), (User::Bob, 1)]);
+    let mint = CurrencyTransaction::Mint {
+        to: User::Alice,
+        amount: 1,
+    };
+    assert_eq!(Currency::next_state(&start, &mint), start);
+
+    let transfer = CurrencyTransaction::Transfer {
+        from: User::Bob,
+        to: User::Alice,
+        amount: 1,
+    };
+    assert_eq!(Currency::next_state(&start, &transfer), start);
+}
+
+#[test]
+fn sm_8_try_next_state_reports_errors() {
+    let start = Balances::from([(User::Alice, u64::MAX), (User::Bob, 1)]);
+    let cases = [
+        (
+            CurrencyTransaction::Transfer {
+                from: User::Bob,
+                to: User::Charlie,
+                amount: 2,
+            },
+            TransitionError::InsufficientFunds,
+        ),
+        (
+            CurrencyTransaction::Transfer {
+                from: User::Bob,
+                to: User::Alice,
+                amount: 1,
+            },
+            TransitionError::Overflow,
+        ),
+        (
+            CurrencyTransaction::Transfer {
+                from: User::Bob,
+                to: User::Bob,
+                amount: 1,
+            },
+            TransitionError::Invalid,
+        ),
+    ];
+    for (t, error) in cases {
+        assert_eq!(Currency::try_next_state(&start, &t), Err(error));
+        // The infallible version leaves the state unchanged instead.
+        assert_eq!(Currency::next_state(&start, &t), start);
+    }
+
+    let pay = CurrencyTransaction::Transfer {
+        from: User::Bob,
+        to: User::Charlie,
+        amount: 1,
+    };
+    assert_eq!(
+        Currency::try_next_state(&start, &pay),
+        Ok(Balances::from([
+            (User::Alice, u64::MAX),
+            (User::Charlie, 1)
+        ]))
+    );
+}
+
+#[test]
+fn sm_8_undo_restores_balances() {
+    let start = Balances::from([(User::Alice, 100), (User::Bob, 5)]);
+    let transactions = [
+        CurrencyTransaction::Transfer {
+            from: User::Alice,
+            to: User::Charlie,
+            amount: 100,
+        },
+        CurrencyTransaction::Burn {
+            from: User::Bob,
+            amount: 10,
+        },
+        // Rejected, but it still gets a token.
+        CurrencyTransaction::Transfer {
+            from: User::Bob,
+            to: User::Alice,
+            amount: 1,
+        },
+        CurrencyTransaction::Mint {
+            to: User::Alice,
+            amount: 7,
+        },
+    ];
+
+    let mut state = start.clone();
+    let mut undos = Vec::new();
+    for t in &transactions {
+        let expected = Currency::next_state(&state, t);
+        undos.push(Currency::apply(&mut state, t, &BlockContext::default()));
+        assert_eq!(state, expected);
+    }
+    assert_eq!(
+        state,
+        Balances::from([(User::Alice, 7), (User::Charlie, 100)])
+    );
+
+    for undo in undos.into_iter().rev() {
+        Currency::undo(&mut state, undo);
+    }
+    assert_eq!(state, start);
+}
+
+#[test]
+fn sm_8_apply_all_matches_one_at_a_time() {
+    use CurrencyTransaction::*;
+    use User::*;
+
+    let start = Balances::from([(Alice, 100), (Bob, u64::MAX)]);
+    let batch = [
+        Transfer {
+            from: Alice,
+            to: Charlie,
+            amount: 30,
+        },
+        // Rejected: Charlie can not afford it, Bob's balance would overflow, and nobody can pay
+        // themselves.
+        Transfer {
+            from: Charlie,
+            to: Alice,
+            amount: 31,
+        },
+        Mint { to: Bob, amount: 1 },
+        Transfer {
+            from: Alice,
+            to: Alice,
+            amount: 1,
+        },
+        Burn {
+            from: Alice,
+            amount: 500,
+        },
+        Transfer {
+            from: Bob,
+            to: Alice,
+            amount: 5,
+        },
+    ];
+    let one_at_a_time = batch
+        .iter()
+        .fold(start.clone(), |state, t| Currency::next_state(&state, t));
+    assert_eq!(Currency::apply_all(&start, &batch), one_at_a_time);
+    assert_eq!(
+        one_at_a_time,
+        Balances::from([(Alice, 5), (Bob, u64::MAX - 5), (Charlie, 30)])
+    );
+    assert_eq!(Currency::apply_all(&start, &[]), start);
+}
@@ -0,0 +1,240 @@
+//! Many chains track more than one kind of token. Rather than writing a new currency state
+//! machine for each token, we can track them all in one machine whose balances are keyed by
+//! both the asset and the account.
+//!
+//! This kind of composite keyed state is very common in real blockchains. It is exactly what
+//! key-value storage, such as a Merkle trie, is good at. Each `(asset, account)` pair becomes
+//! a single key, and each balance can be read or proven on its own.
+
+use std::collections::BTreeMap;
+
+use super::{AccountId, StateMachine};
+
+/// Identifies a single asset.
+pub type AssetId = u32;
+
+/// The state of the multi-asset system.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Assets {
+    /// The admin of every asset that has been created. Only the admin may mint new units.
+    pub admins: BTreeMap<AssetId, AccountId>,
+    /// The balance of every account in every asset. Zero balances are not stored at all.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::pairs"))]
+    pub balances: BTreeMap<(AssetId, AccountId), u64>,
+}
+
+impl Assets {
+    /// The balance of the given account in the given asset.
+    pub fn balance(&self, asset: AssetId, who: AccountId) -> u64 {
+        self.balances.get(&(asset, who)).copied().unwrap_or(0)
+    }
+
+    /// The total amount of the given asset held by all accounts. Returns None if it does not
+    /// fit in a `u64`.
+    pub fn total_issuance(&self, asset: AssetId) -> Option<u64> {
+        self.balances
+            .iter()
+            .filter(|((a, _), _)| *a == asset)
+            .try_fold(0u64, |total, (_, balance)| total.checked_add(*balance))
+    }
+
+    /// Set the balance of the given account, removing it when the balance is zero.
+    fn set_balance(&mut self, asset: AssetId, who: AccountId, balance: u64) {
+        if balance == 0 {
+            self.balances.remove(&(asset, who));
+        } else {
+            self.balances.insert((asset, who), balance);
+        }
+    }
+}
+
+/// This state machine tracks the balances of many fungible assets at once.
+pub struct MultiAsset;
+
+/// The transitions that can be made in the multi-asset system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AssetTransaction {
+    /// Create a new asset with the given id and admin. Rejected if the id is already taken.
+    CreateAsset { asset: AssetId, admin: AccountId },
+    /// Create new units of an asset. Rejected unless it is done by the asset's admin.
+    Mint {
+        asset: AssetId,
+        admin: AccountId,
+        to: AccountId,
+        amount: u64,
+    },
+    /// Move units of an asset from one account to another. Rejected if the sender can not
+    /// afford it.
+    Transfer {
+        asset: AssetId,
+        from: AccountId,
+        to: AccountId,
+        amount: u64,
+    },
+}
+
+impl StateMachine for MultiAsset {
+    type State = Assets;
+    type Transition = AssetTransaction;
+
+    /// Rejected transactions leave the state unchanged.
+    fn next_state(starting_state: &Assets, t: &AssetTransaction) -> Assets {
+        let mut state = starting_state.clone();
+        match t {
+            AssetTransaction::CreateAsset { asset, admin } => {
+                state.admins.entry(*asset).or_insert(*admin);
+            }
+            AssetTransaction::Mint {
+                asset,
+                admin,
+                to,
+                amount,
+            } => {
+                if state.admins.get(asset) != Some(admin) {
+                    return state;
+                }
+                if let Some(balance) = state.balance(*asset, *to).checked_add(*amount) {
+                    state.set_balance(*asset, *to, balance);
+                }
+            }
+            AssetTransaction::Transfer {
+                asset,
+                from,
+                to,
+                amount,
+            } => {
+                let Some(sender_balance) = state.balance(*asset, *from).checked_sub(*amount) else {
+                    return state;
+                };
+                if from == to {
+                    return state;
+                }
+                let Some(receiver_balance) = state.balance(*asset, *to).checked_add(*amount) else {
+                    return state;
+                };
+                state.set_balance(*asset, *from, sender_balance);
+                state.set_balance(*asset, *to, receiver_balance);
+            }
+        }
+        state
+    }
+
+    fn human_name() -> String {
+        "Multi-Asset".into()
+    }
+}
+
+#[cfg(test)]
+use super::User::*;
+
+/// Two assets, 1 administered by Alice and 2 by Bob, with some balances in each.
+#[cfg(test)]
+fn two_assets() -> Assets {
+    Assets {
+        admins: BTreeMap::from([(1, Alice), (2, Bob)]),
+        balances: BTreeMap::from([((1, Alice), 50), ((2, Alice), 5), ((2, Bob), 10)]),
+    }
+}
+
+#[test]
+fn sm_9_create_asset() {
+    let end = MultiAsset::next_state(
+        &Assets::default(),
+        &AssetTransaction::CreateAsset {
+            asset: 7,
+            admin: Charlie,
+        },
+    );
+    assert_eq!(end.admins, BTreeMap::from([(7, Charlie)]));
+    assert!(end.balances.is_empty());
+}
+
+#[test]
+fn sm_9_create_existing_asset_is_rejected() {
+    let start = two_assets();
+    let end = MultiAsset::next_state(
+        &start,
+        &AssetTransaction::CreateAsset {
+            asset: 1,
+            admin: Charlie,
+        },
+    );
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_only_admin_can_mint() {
+    let start = two_assets();
+    let by_admin = MultiAsset::next_state(
+        &start,
+        &AssetTransaction::Mint {
+            asset: 1,
+            admin: Alice,
+            to: Charlie,
+            amount: 20,
+        },
+    );
+    assert_eq!(by_admin.balance(1, Charlie), 20);
+    assert_eq!(by_admin.total_issuance(1), Some(70));
+
+    let by_someone_else = AssetTransaction::Mint {
+        asset: 1,
+        admin: Bob,
+        to: Bob,
+        amount: 20,
+    };
+    assert_eq!(MultiAsset::next_state(&start, &by_someone_else), start);
+
+    // Assets that do not exist have no admin at all.
+    let unknown_asset = AssetTransaction::Mint {
+        asset: 3,
+        admin: Alice,
+        to: Alice,
+        amount: 20,
+    };
+    assert_eq!(MultiAsset::next_state(&start, &unknown_asset), start);
+}
+
+#[test]
+fn sm_9_transfer_only_moves_one_asset() {
+    let end = MultiAsset::next_state(
+        &two_assets(),
+        &AssetTransaction::Transfer {
+            asset: 2,
+            from: Alice,
+            to: Bob,
+            amount: 5,
+        },
+    );
+    assert_eq!(end.balance(2, Alice), 0);
+    assert_eq!(end.balance(2, Bob), 15);
+    assert_eq!(end.balance(1, Alice), 50);
+    assert_eq!(end.total_issuance(2), Some(15));
+    assert!(!end.balances.contains_key(&(2, Alice)));
+}
+
+#[test]
+fn sm_9_transfer_exceeding_balance_is_rejected() {
+    let start = two_assets();
+    // Alice has plenty of asset 1, but not enough of asset 2.
+    let end = MultiAsset::next_state(
+        &start,
+        &AssetTransaction::Transfer {
+            asset: 2,
+            from: Alice,
+            to: Bob,
+            amount: 6,
+        },
+    );
+    assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_total_issuance_does_not_overflow() {
+    let mut assets = two_assets();
+    assets.balances.insert((1, Bob), u64::MAX);
+    assert_eq!(assets.total_issuance(1), None);
+    assert_eq!(assets.total_issuance(2), Some(15));
+}
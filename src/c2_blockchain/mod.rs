@@ -4,11 +4,64 @@
 
 // We make the complete Block and Header types publicly visible so that we can continue developing
 // against them in future chapters. The prior iterations are not available outside this chapter.
-pub use p6_rich_state::{Block, Header};
+pub use p6_rich_state::{
+    Block, CurrencyBlock, ExtrinsicPolicy, GenericBlock, Header, RewardAuthor, RuntimeBlock,
+    SignedCurrencyBlock, SumAndProduct, Upgradable, UpgradableExtrinsic, UpgradableState,
+    ValidityRules, BLOCK_REWARD,
+};
+
+use std::collections::BTreeMap;
+
+/// A way to refer to a block. Either directly by its hash, or by its number (height).
+///
+/// A number only identifies a block unambiguously within a single chain. When there are
+/// forks, several blocks may share the same number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockId {
+    Hash(u64),
+    Number(u64),
+}
+
+/// Trusted (height, hash) pairs, typically shipped with the client software.
+///
+/// A checkpoint is a promise from the client developers that the block with the given hash is
+/// part of the real chain. Any chain that has a different block at a checkpointed height is
+/// rejected outright. Because the blocks below a matching checkpoint are already trusted, their
+/// proof of work does not need to be checked again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoints {
+    points: BTreeMap<u64, u64>,
+}
+
+impl Checkpoints {
+    /// Create a set of checkpoints from (height, hash) pairs.
+    pub fn new(points: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        Checkpoints {
+            points: points.into_iter().collect(),
+        }
+    }
+
+    /// Whether a block with the given height and hash conflicts with a checkpoint.
+    pub fn contradicts(&self, height: u64, hash: u64) -> bool {
+        self.points.get(&height).is_some_and(|expected| *expected != hash)
+    }
+
+    /// Whether the block with the given height and hash is exactly a checkpoint.
+    pub fn matches(&self, height: u64, hash: u64) -> bool {
+        self.points.get(&height) == Some(&hash)
+    }
+
+    /// The height of the highest checkpoint, if there are any.
+    pub fn latest_height(&self) -> Option<u64> {
+        self.points.keys().next_back().copied()
+    }
+}
 
 mod p1_header_chain;
 mod p2_extrinsic_state;
-mod p3_consensus;
+pub mod p3_consensus;
 pub mod p4_batched_extrinsics;
 mod p5_fork_choice;
 mod p6_rich_state;
@@ -4,6 +4,10 @@
 //! 1. Rules to throttle authoring. In this case we will use a simple PoW.
 //! 2. Arbitrary / Political rules. Here we will implement two alternate validity rules
 
+use std::collections::HashMap;
+
+use super::{BlockId, Checkpoints};
+use crate::c3_consensus::{ConsensusAuthority, Pow};
 use crate::hash;
 
 // We will use Rust's built-in hashing where the output type is u64. I'll make an alias
@@ -13,7 +17,113 @@ type Hash = u64;
 /// In this lesson we are introducing proof of work onto our blocks. We need a hash threshold.
 /// You may change this as you see fit, and I encourage you to experiment. Probably best to start
 /// high so we aren't wasting time mining. I'll start with 1 in 100 blocks being valid.
-const THRESHOLD: u64 = u64::max_value() / 100;
+///
+/// The threshold is given as a difficulty, which is the inverse of the threshold: it is roughly
+/// the number of hashes a miner has to try to find a valid block. See `threshold_for`. Each
+/// header records the difficulty it was mined at, starting from this default at genesis, and
+/// `PowConfig` chooses another one at runtime.
+pub const DIFFICULTY: u64 = 100;
+
+/// The hash threshold that a block mined at the given difficulty must be below.
+pub fn threshold_for(difficulty: u64) -> u64 {
+    u64::MAX / difficulty.max(1)
+}
+
+/// The proof of work threshold that a chain starts out with, chosen at runtime rather than
+/// fixed at compile time by `DIFFICULTY`, so that tests and networks can try other difficulties.
+///
+/// Every header records the difficulty it was mined at, and `child` and `verify_sub_chain` take
+/// each block's difficulty from its parent. So the config only has to be given once, to
+/// `Header::genesis_with`, and it is carried along the whole chain from there.
+///
+/// Headers record a difficulty, not a threshold, so the config keeps the difficulty too. Only
+/// thresholds that some difficulty maps to exactly can be configured, so the threshold that
+/// verifiers enforce is always the one that was asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PowConfig {
+    difficulty: u64,
+}
+
+impl Default for PowConfig {
+    fn default() -> Self {
+        PowConfig {
+            difficulty: DIFFICULTY,
+        }
+    }
+}
+
+impl PowConfig {
+    /// The config in which roughly one in `difficulty` hashes is below the threshold.
+    pub fn with_difficulty(difficulty: u64) -> Self {
+        PowConfig {
+            difficulty: difficulty.max(1),
+        }
+    }
+
+    /// The config with the given threshold, or None if no difficulty maps to exactly that
+    /// threshold. Rounding it to the nearest one instead could make it far easier to meet.
+    pub fn with_threshold(threshold: u64) -> Option<Self> {
+        let difficulty = u64::MAX / threshold.max(1);
+        (threshold_for(difficulty) == threshold).then_some(PowConfig { difficulty })
+    }
+
+    /// The hash threshold that blocks must be below.
+    pub fn threshold(&self) -> u64 {
+        threshold_for(self.difficulty)
+    }
+
+    /// The difficulty that headers record for this threshold.
+    pub fn difficulty(&self) -> u64 {
+        self.difficulty
+    }
+
+    /// A consensus engine from the next chapter that mines and checks work against the same
+    /// threshold.
+    pub fn engine(&self) -> Pow {
+        Pow::with_threshold(self.threshold())
+    }
+}
+
+/// The number of time units (think seconds) we would like to pass between blocks.
+pub const TARGET_BLOCK_TIME: u64 = 10;
+
+/// The difficulty is retargeted once every this many blocks, just like Bitcoin does every
+/// 2016 blocks. We use a much shorter epoch so that it is easy to watch in tests.
+pub const RETARGET_INTERVAL: u64 = 4;
+
+/// A single retarget never changes the difficulty by more than this factor in either direction.
+/// This stops a single epoch with wild timestamps from swinging the difficulty too far.
+pub const MAX_RETARGET_FACTOR: u64 = 4;
+
+/// The expected number of hashes it took to mine the given block. This is exactly the
+/// difficulty the block was mined at.
+pub fn block_work(header: &Header) -> u128 {
+    header.difficulty as u128
+}
+
+/// The total work that went into mining all of the given headers.
+///
+/// Chains that were mined at different difficulties can not be fairly compared by length.
+/// A fork choice rule should compare them by their total work instead.
+pub fn total_work(chain: &[Header]) -> u128 {
+    chain.iter().map(block_work).sum()
+}
+
+/// Compute the difficulty of the next epoch from the difficulty of the last epoch and how long
+/// that epoch actually took to mine.
+///
+/// If blocks came too quickly the difficulty goes up, and if they came too slowly it goes down,
+/// in proportion to how far off the target the epoch was.
+pub fn retarget(difficulty: u64, actual_timespan: u64) -> u64 {
+    let target_timespan = TARGET_BLOCK_TIME * RETARGET_INTERVAL;
+    let actual_timespan = actual_timespan.clamp(
+        target_timespan / MAX_RETARGET_FACTOR,
+        target_timespan * MAX_RETARGET_FACTOR,
+    );
+    let new_difficulty = difficulty as u128 * target_timespan as u128 / actual_timespan as u128;
+    new_difficulty.clamp(1, u64::MAX as u128) as u64
+}
 
 /// In this lesson we introduce the concept of a contentious hard fork. The fork will happen at
 /// this block height.
@@ -24,11 +134,19 @@ const FORK_HEIGHT: u64 = 2;
 /// hash below a certain threshold. Although we could call the field `nonce` we will leave
 /// the more general `digest` term. For PoA we would have a cryptographic signature in this field.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     parent: Hash,
     height: u64,
     extrinsic: u64,
     state: u64,
+    /// The time at which this block was authored.
+    timestamp: u64,
+    /// The difficulty this block was mined at. See `threshold_for`.
+    difficulty: u64,
+    /// The timestamp of the last block of the previous difficulty epoch. Carrying it along
+    /// lets each header be checked against only its parent, without looking up old ancestors.
+    epoch_start: u64,
     consensus_digest: u64,
 }
 
@@ -43,6 +161,9 @@ impl Header {
             height: 0,
             extrinsic: 0,
             state: 0,
+            timestamp: 0,
+            difficulty: DIFFICULTY,
+            epoch_start: 0,
             consensus_digest: 0,
         }
     }
@@ -50,55 +171,302 @@ impl Header {
     /// Create and return a valid child header.
     fn child(&self, extrinsic: u64) -> Self {
         // todo!("Exercise 2")
+        self.child_at(extrinsic, self.timestamp + TARGET_BLOCK_TIME)
+    }
+
+    /// Create and return a valid child header authored at the given time.
+    ///
+    /// Panics if the extrinsic would overflow the state. See `try_child` to choose what
+    /// happens instead.
+    pub fn child_at(&self, extrinsic: u64, timestamp: u64) -> Self {
+        let state = self
+            .state
+            .checked_add(extrinsic)
+            .expect("the state overflowed");
+        self.mine_child(extrinsic, state, timestamp)
+    }
+
+    /// Create and return a valid child header, handling a state that would overflow according
+    /// to the given policy.
+    pub fn try_child(&self, extrinsic: u64, policy: OverflowPolicy) -> Result<Self, VerifyError> {
+        let state = policy
+            .apply(self.state, extrinsic)
+            .ok_or(VerifyError::StateOverflow {
+                height: self.height + 1,
+            })?;
+        Ok(self.mine_child(extrinsic, state, self.timestamp + TARGET_BLOCK_TIME))
+    }
+
+    /// Mine a child header with the given extrinsic, the given state, and timestamp.
+    fn mine_child(&self, extrinsic: u64, state: u64, timestamp: u64) -> Self {
+        let (difficulty, epoch_start) = self.next_difficulty();
         let mut new_block = Header {
             parent: hash(self),
             height: self.height + 1,
             extrinsic: extrinsic,
-            state: self.state + extrinsic,
+            state,
+            timestamp,
+            difficulty,
+            epoch_start,
             consensus_digest: 0,
         };
         let mut nonce = 0;
-        while hash(&new_block) > THRESHOLD {
+        while hash(&new_block) >= threshold_for(new_block.difficulty) {
             nonce += 1;
             new_block.consensus_digest = nonce;
         }
         return new_block;
     }
 
+    /// The difficulty and epoch start that this header's children must have.
+    ///
+    /// Blocks within an epoch keep their parent's difficulty. The first block of each new epoch
+    /// retargets based on how long the previous epoch took.
+    pub fn next_difficulty(&self) -> (u64, u64) {
+        if self.height == 0 || !self.height.is_multiple_of(RETARGET_INTERVAL) {
+            return (self.difficulty, self.epoch_start);
+        }
+        let timespan = self.timestamp.saturating_sub(self.epoch_start);
+        (retarget(self.difficulty, timespan), self.timestamp)
+    }
+
     /// Verify that all the given headers form a valid chain from this header to the tip.
     ///
     /// In addition to all the rules we had before, we now need to check that the block hash
-    /// is below a specific threshold.
+    /// is below a specific threshold, and that the threshold follows the retargeting schedule.
     fn verify_sub_chain(&self, chain: &[Header]) -> bool {
         // todo!("Exercise 3")
-        let mut verifiable = true;
-        let mut current_height = self.height;
-        let mut current_state = self.state;
-        for (block_idx, header) in chain.iter().enumerate() {
-            if hash(header) >= THRESHOLD {
-                verifiable =  false;
+        self.verify_sub_chain_detailed(chain).is_ok()
+    }
+
+    /// Verify the given headers like `verify_sub_chain`, but say which rule the chain broke,
+    /// and where, when it is not valid. This is the strict check, see `verify_sub_chain_strict`.
+    pub fn verify_sub_chain_detailed(&self, chain: &[Header]) -> Result<(), VerifyError> {
+        self.verify_sub_chain_strict(chain)
+    }
+
+    /// Verify that the given header is a valid child of this one, according to every rule.
+    ///
+    /// This is all a client needs to import blocks one at a time, as they arrive, without
+    /// gathering them into a slice first.
+    pub fn verify_child(&self, child: &Header) -> Result<(), VerifyError> {
+        self.verify_child_with_policy(child, OverflowPolicy::default())
+    }
+
+    /// Verify the given child like `verify_child`, under the given overflow policy.
+    pub fn verify_child_with_policy(
+        &self,
+        child: &Header,
+        policy: OverflowPolicy,
+    ) -> Result<(), VerifyError> {
+        self.verify_child_checking_work(child, policy, true)
+    }
+
+    /// Verify the given child like `verify_child_with_policy`, but only check its proof of work
+    /// if asked to. Every other rule is always checked.
+    fn verify_child_checking_work(
+        &self,
+        child: &Header,
+        policy: OverflowPolicy,
+        check_work: bool,
+    ) -> Result<(), VerifyError> {
+        let height = self.height + 1;
+        if child.timestamp < self.timestamp {
+            return Err(VerifyError::TimestampBeforeParent { height });
+        }
+        if (child.difficulty, child.epoch_start) != self.next_difficulty() {
+            return Err(VerifyError::WrongDifficulty { height });
+        }
+        if child.height != height {
+            return Err(VerifyError::BadHeight {
+                expected: height,
+                found: child.height,
+            });
+        }
+        if check_work {
+            verify_work(child)?;
+        }
+        verify_transition(hash(self), self.state, child, policy)?;
+        Ok(())
+    }
+
+    /// Verify the given headers, applying every rule in the same way to every block, the tip
+    /// included.
+    ///
+    /// Earlier versions of this check only tied each block to the next one while walking the
+    /// chain, and forgot to do so for the first and last blocks, so a tampered tip slipped
+    /// through. Here each block is simply checked against its own parent with `verify_child`.
+    ///
+    /// Heights in the error are the heights that the blocks should have, counting on from this
+    /// header, so they point at the right block even when the block's own height is wrong.
+    pub fn verify_sub_chain_strict(&self, chain: &[Header]) -> Result<(), VerifyError> {
+        self.verify_sub_chain_with_policy(chain, OverflowPolicy::default())
+    }
+
+    /// Verify the given headers like `verify_sub_chain_strict`, under the given overflow
+    /// policy.
+    pub fn verify_sub_chain_with_policy(
+        &self,
+        chain: &[Header],
+        policy: OverflowPolicy,
+    ) -> Result<(), VerifyError> {
+        chain
+            .iter()
+            .try_fold(self, |parent, header| {
+                parent.verify_child_with_policy(header, policy)?;
+                Ok(header)
+            })
+            .map(|_| ())
+    }
+
+    /// Verify the headers that the given iterator yields, like `verify_sub_chain_strict`, but
+    /// without holding them all in memory. Each header only needs its parent, so only the
+    /// latest one is kept, and chains of any length can be verified in constant memory as they
+    /// are read from a file or the network.
+    ///
+    /// Returns the tip, so that verification can carry on from it when more headers arrive.
+    /// The tip is this header when the iterator yields nothing.
+    pub fn verify_stream(
+        &self,
+        headers: impl IntoIterator<Item = Header>,
+    ) -> Result<Header, VerifyError> {
+        self.verify_stream_with_policy(headers, OverflowPolicy::default())
+    }
+
+    /// Verify the given headers like `verify_stream`, under the given overflow policy.
+    pub fn verify_stream_with_policy(
+        &self,
+        headers: impl IntoIterator<Item = Header>,
+        policy: OverflowPolicy,
+    ) -> Result<Header, VerifyError> {
+        headers
+            .into_iter()
+            .try_fold(self.clone(), |parent, header| {
+                parent.verify_child_with_policy(&header, policy)?;
+                Ok(header)
+            })
+    }
+
+    /// Check the given headers against every rule, and report every rule that every block
+    /// broke, rather than stopping at the first problem. This is what you want when working out
+    /// what went wrong with a chain, rather than just whether to accept it.
+    ///
+    /// Each block's height and state are checked against what they should be counting on from
+    /// this header, so a single tampered block does not make all the blocks after it look wrong.
+    /// Its hash does change though, so the next block no longer points to it.
+    pub fn verify_report(&self, chain: &[Header]) -> VerificationReport {
+        self.verify_report_with_policy(chain, OverflowPolicy::default())
+    }
+
+    /// Check the given headers like `verify_report`, under the given overflow policy.
+    pub fn verify_report_with_policy(
+        &self,
+        chain: &[Header],
+        policy: OverflowPolicy,
+    ) -> VerificationReport {
+        let mut report = VerificationReport::default();
+        let mut expected_state = self.state;
+        let mut parent = self;
+        for (index, header) in chain.iter().enumerate() {
+            let height = self.height + index as u64 + 1;
+            let mut violated = |error| report.violations.push(Violation { index, error });
+            if hash(header) >= threshold_for(header.difficulty) {
+                violated(VerifyError::InsufficientWork { height });
             }
-            if header.height != current_height + 1 {
-                verifiable =  false;
+            if header.timestamp < parent.timestamp {
+                violated(VerifyError::TimestampBeforeParent { height });
             }
-            if header.extrinsic + current_state !=  header.state {
-                verifiable =  false;
+            if (header.difficulty, header.epoch_start) != parent.next_difficulty() {
+                violated(VerifyError::WrongDifficulty { height });
             }
-            if block_idx == 0 {
-                if hash(self) != header.parent {
-                    verifiable =  false;
+            if header.height != height {
+                violated(VerifyError::BadHeight {
+                    expected: height,
+                    found: header.height,
+                });
+            }
+            match policy.apply(expected_state, header.extrinsic) {
+                Some(state) => {
+                    expected_state = state;
+                    if header.state != expected_state {
+                        violated(VerifyError::StateMismatch { height });
+                    }
                 }
-                current_height += 1;
-                current_state += header.extrinsic;
-            } else if block_idx != chain.len() - 1 {
-                if hash(header) != chain[block_idx + 1].parent {
-                    verifiable =  false;
+                None => {
+                    violated(VerifyError::StateOverflow { height });
+                    expected_state = header.state;
                 }
-                current_height += 1;
-                current_state += header.extrinsic;
+            }
+            if header.parent != hash(parent) {
+                violated(VerifyError::WrongParent { height });
+            }
+            parent = header;
+        }
+        report
+    }
+
+    /// Check the given headers like `verify_report`, and also report every block whose state
+    /// the given rule does not accept at the height the block should have.
+    pub fn verify_report_with<R: StateValidityRule>(
+        &self,
+        chain: &[Header],
+        rule: &R,
+    ) -> VerificationReport {
+        let mut report = self.verify_report(chain);
+        for (index, header) in chain.iter().enumerate() {
+            let height = self.height + index as u64 + 1;
+            if !rule.is_valid_state(header.state, height) {
+                let error = VerifyError::StateRejected { height };
+                report.violations.push(Violation { index, error });
             }
         }
-        verifiable
+        report.violations.sort_by_key(|violation| violation.index);
+        report
+    }
+
+    /// Verify the given headers like `verify_sub_chain`, but also against a set of checkpoints.
+    ///
+    /// A chain with a different block at any checkpointed height is invalid. Blocks below the
+    /// highest checkpoint that the chain actually matches are trusted, so their proof of work is
+    /// not checked. All the other rules still are, which is what links them to the checkpoint.
+    pub fn verify_sub_chain_with_checkpoints(
+        &self,
+        chain: &[Header],
+        checkpoints: &Checkpoints,
+    ) -> bool {
+        self.verify_sub_chain_with_checkpoints_and_policy(
+            chain,
+            checkpoints,
+            OverflowPolicy::default(),
+        )
+    }
+
+    /// Verify the given headers like `verify_sub_chain_with_checkpoints`, under the given
+    /// overflow policy.
+    pub fn verify_sub_chain_with_checkpoints_and_policy(
+        &self,
+        chain: &[Header],
+        checkpoints: &Checkpoints,
+        policy: OverflowPolicy,
+    ) -> bool {
+        let all_headers = || std::iter::once(self).chain(chain);
+        if all_headers().any(|h| checkpoints.contradicts(h.height, hash(h))) {
+            return false;
+        }
+        let trusted_below = all_headers()
+            .filter(|h| checkpoints.matches(h.height, hash(h)))
+            .map(|h| h.height)
+            .max()
+            .unwrap_or(0);
+
+        chain
+            .iter()
+            .try_fold(self, |parent, header| {
+                let check_work = header.height >= trusted_below;
+                parent.verify_child_checking_work(header, policy, check_work)?;
+                Ok::<_, VerifyError>(header)
+            })
+            .is_ok()
     }
 
     // After the blockchain ran for a while, a political rift formed in the community.
@@ -111,90 +479,689 @@ impl Header {
     /// In this case "valid" means that the STATE MUST BE EVEN.
     fn verify_sub_chain_even(&self, chain: &[Header]) -> bool {
         // todo!("Exercise 4")
-        let mut verifiable = true;
-        let mut current_height = self.height;
-        let mut current_state = self.state;
-        if current_state % 2 == 1 && self.height > FORK_HEIGHT {
-            println!("1");
-            verifiable = false;
-        }
-        for (block_idx, header) in chain.iter().enumerate() {
-            if hash(header) >= THRESHOLD {
-                println!("2");
-                verifiable =  false;
-            }
-            if header.height != current_height + 1 {
-                println!("3");
-                verifiable =  false;
-            }
-            if header.extrinsic + current_state !=  header.state {
-                println!("4");
-                verifiable =  false;
-            }
-            if block_idx == 0 {
-                if hash(self) != header.parent {
-                    println!("5");
-                    verifiable =  false;
-                }
-            } else if block_idx != chain.len() - 1 {
-                if hash(header) != chain[block_idx + 1].parent {
-                    println!("6");
-                    verifiable =  false;
-                }
-            }
-            current_height += 1;
-            current_state += header.extrinsic;
-            if current_state % 2 == 1 && current_height > FORK_HEIGHT {
-                println!("7");
-                verifiable =  false;
+        self.verify_sub_chain_with(chain, &EvenOnlyAfterFork::default())
+    }
+
+    /// verify that the given headers form a valid chain.
+    /// In this case "valid" means that the STATE MUST BE ODD.
+    fn verify_sub_chain_odd(&self, chain: &[Header]) -> bool {
+        // todo!("Exercise 5")
+        self.verify_sub_chain_with(chain, &OddOnlyAfterFork::default())
+    }
+
+    /// Verify that the given headers form a valid chain according to all the original rules,
+    /// and additionally that every state in it, including this header's, satisfies the given rule.
+    pub fn verify_sub_chain_with<R: StateValidityRule>(&self, chain: &[Header], rule: &R) -> bool {
+        self.verify_sub_chain_with_detailed(chain, rule).is_ok()
+    }
+
+    /// Verify the given headers like `verify_sub_chain_with`, but say which rule the chain
+    /// broke, and where, when it is not valid.
+    pub fn verify_sub_chain_with_detailed<R: StateValidityRule>(
+        &self,
+        chain: &[Header],
+        rule: &R,
+    ) -> Result<(), VerifyError> {
+        self.verify_sub_chain_detailed(chain)?;
+        match std::iter::once(self)
+            .chain(chain)
+            .find(|header| !rule.is_valid_state(header.state, header.height))
+        {
+            Some(header) => Err(VerifyError::StateRejected {
+                height: header.height,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Verify the given block knowing only its parent's hash and the state after its parent,
+/// rather than the parent header or any other ancestors, and return the state after the block.
+///
+/// This checks the proof of work, the link to the parent, and the state transition. That is
+/// enough for a light client that trusts a parent hash and state from elsewhere, or to check a
+/// fraud proof, which claims that one block's transition was wrong. The height, timestamp, and
+/// difficulty schedule can only be checked against the parent header, with `verify_child`.
+pub fn verify_block_stateless(
+    parent_hash: Hash,
+    pre_state: u64,
+    block: &Header,
+) -> Result<u64, VerifyError> {
+    verify_block_stateless_with_policy(parent_hash, pre_state, block, OverflowPolicy::default())
+}
+
+/// Verify the given block like `verify_block_stateless`, under the given overflow policy.
+pub fn verify_block_stateless_with_policy(
+    parent_hash: Hash,
+    pre_state: u64,
+    block: &Header,
+    policy: OverflowPolicy,
+) -> Result<u64, VerifyError> {
+    verify_work(block)?;
+    verify_transition(parent_hash, pre_state, block, policy)
+}
+
+/// Check that the given block's hash is below the threshold for its difficulty.
+fn verify_work(block: &Header) -> Result<(), VerifyError> {
+    if hash(block) >= threshold_for(block.difficulty) {
+        return Err(VerifyError::InsufficientWork {
+            height: block.height,
+        });
+    }
+    Ok(())
+}
+
+/// Check that the given block follows the given parent hash, and that its state is the given
+/// pre-state plus its extrinsic, and return that state. The proof of work is not checked.
+fn verify_transition(
+    parent_hash: Hash,
+    pre_state: u64,
+    block: &Header,
+    policy: OverflowPolicy,
+) -> Result<u64, VerifyError> {
+    let height = block.height;
+    if block.parent != parent_hash {
+        return Err(VerifyError::WrongParent { height });
+    }
+    let post_state = policy
+        .apply(pre_state, block.extrinsic)
+        .ok_or(VerifyError::StateOverflow { height })?;
+    if block.state != post_state {
+        return Err(VerifyError::StateMismatch { height });
+    }
+    Ok(post_state)
+}
+
+/// The reasons that a chain of headers can be invalid. Each one names the height of the first
+/// block that broke the rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The block does not point to the hash of the block before it.
+    WrongParent { height: u64 },
+    /// The block's height is not one more than its parent's.
+    BadHeight { expected: u64, found: u64 },
+    /// The block's state is not its parent's state plus its extrinsic.
+    StateMismatch { height: u64 },
+    /// The block's hash is not below the threshold for its difficulty.
+    InsufficientWork { height: u64 },
+    /// The block's difficulty or epoch start does not follow the retargeting schedule.
+    WrongDifficulty { height: u64 },
+    /// The block claims to be older than its parent.
+    TimestampBeforeParent { height: u64 },
+    /// The block's state is not acceptable under the chosen `StateValidityRule`.
+    StateRejected { height: u64 },
+    /// Adding the block's extrinsic to its parent's state overflows, and the chain's
+    /// `OverflowPolicy` rejects that.
+    StateOverflow { height: u64 },
+}
+
+/// What a chain does when adding an extrinsic to the state would overflow a `u64`.
+///
+/// Left to itself, Rust panics on overflow in debug builds and wraps around in release builds,
+/// so nodes built differently would disagree about which blocks are valid. A chain has to pick
+/// one behaviour, and every node has to follow it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowPolicy {
+    /// A block whose extrinsic overflows the state is invalid.
+    #[default]
+    Reject,
+    /// The state stops at `u64::MAX`, and stays there.
+    Saturate,
+}
+
+impl OverflowPolicy {
+    /// The state after adding the given extrinsic, or None if this policy rejects it.
+    pub fn apply(self, state: u64, extrinsic: u64) -> Option<u64> {
+        match self {
+            OverflowPolicy::Reject => state.checked_add(extrinsic),
+            OverflowPolicy::Saturate => Some(state.saturating_add(extrinsic)),
+        }
+    }
+}
+
+/// A rule that one block in a chain broke.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// The index of the block in the chain that was checked.
+    pub index: usize,
+    /// The rule that it broke.
+    pub error: VerifyError,
+}
+
+/// Every rule that a chain broke, in the order of the blocks that broke them. See
+/// `Header::verify_report`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub violations: Vec<Violation>,
+}
+
+impl VerificationReport {
+    /// Whether the chain broke no rules at all.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// The indices of the blocks that broke at least one rule, without repeats.
+    pub fn invalid_blocks(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = self.violations.iter().map(|v| v.index).collect();
+        indices.dedup();
+        indices
+    }
+}
+
+/// An arbitrary, "political" rule about which states are acceptable.
+///
+/// Rather than writing a whole new verification function for each side of a political
+/// debate, each side just describes the states it is willing to accept.
+pub trait StateValidityRule {
+    /// Whether the given state is acceptable in a block at the given height.
+    fn is_valid_state(&self, state: u64, height: u64) -> bool;
+}
+
+/// The original rule set, which accepts every state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AlwaysValid;
+
+impl StateValidityRule for AlwaysValid {
+    fn is_valid_state(&self, _: u64, _: u64) -> bool {
+        true
+    }
+}
+
+/// The even side of the fork: after the fork height, the state must be even.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvenOnlyAfterFork {
+    pub fork_height: u64,
+}
+
+impl Default for EvenOnlyAfterFork {
+    fn default() -> Self {
+        EvenOnlyAfterFork {
+            fork_height: FORK_HEIGHT,
+        }
+    }
+}
+
+impl StateValidityRule for EvenOnlyAfterFork {
+    fn is_valid_state(&self, state: u64, height: u64) -> bool {
+        height <= self.fork_height || state.is_multiple_of(2)
+    }
+}
+
+/// The odd side of the fork: after the fork height, the state must be odd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OddOnlyAfterFork {
+    pub fork_height: u64,
+}
+
+impl Default for OddOnlyAfterFork {
+    fn default() -> Self {
+        OddOnlyAfterFork {
+            fork_height: FORK_HEIGHT,
+        }
+    }
+}
+
+impl StateValidityRule for OddOnlyAfterFork {
+    fn is_valid_state(&self, state: u64, height: u64) -> bool {
+        height <= self.fork_height || state % 2 == 1
+    }
+}
+
+/// Everything needed to boot a new network from its own genesis block.
+///
+/// Different test networks can start from different initial states and difficulties. They
+/// are also told apart by their chain id and their initial authority set, so that blocks
+/// from one network are never mistaken for blocks from another.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenesisConfig {
+    /// The state before any extrinsics have been applied.
+    pub initial_state: u64,
+    /// A number that uniquely identifies this network.
+    pub chain_id: u64,
+    /// The difficulty that blocks must be mined at, starting with block 1.
+    pub initial_difficulty: u64,
+    /// The authorities that are trusted at genesis. Proof of work does not use them itself,
+    /// but identity-based consensus engines and finality gadgets will.
+    pub authorities: Vec<ConsensusAuthority>,
+    /// Whether the genesis block must carry a valid proof of work, like every other block.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mined: bool,
+    /// What happens when an extrinsic would overflow the state.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig {
+            initial_state: 0,
+            chain_id: 0,
+            initial_difficulty: DIFFICULTY,
+            authorities: Vec::new(),
+            mined: false,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl Header {
+    /// Returns the genesis header described by the given config.
+    ///
+    /// The genesis block is never sealed, so its consensus digest is free to commit to the
+    /// parts of the config that do not have a header field of their own: the chain id and
+    /// the authorities. This gives every network a distinct genesis hash.
+    ///
+    /// When the config says genesis must be mined, the digest is then mined like a nonce,
+    /// starting from that commitment.
+    pub fn genesis_from(config: &GenesisConfig) -> Self {
+        let mut genesis = Header {
+            parent: 0,
+            height: 0,
+            extrinsic: 0,
+            state: config.initial_state,
+            timestamp: 0,
+            difficulty: config.initial_difficulty,
+            epoch_start: 0,
+            consensus_digest: hash(&(config.chain_id, &config.authorities)),
+        };
+        if config.mined {
+            // Mining starts from the commitment, so the nonce still tells networks apart.
+            while hash(&genesis) >= threshold_for(genesis.difficulty) {
+                genesis.consensus_digest = genesis.consensus_digest.wrapping_add(1);
             }
         }
-        verifiable
+        genesis
+    }
+
+    /// Returns a genesis header like `genesis`, for a chain whose blocks are mined to the given
+    /// proof of work config instead of the default one.
+    pub fn genesis_with(pow: &PowConfig) -> Self {
+        Header {
+            difficulty: pow.difficulty(),
+            ..Self::genesis()
+        }
+    }
+
+    /// Returns a genesis header like `genesis`, but with a nonce that gives it a valid proof
+    /// of work, so that it meets the same threshold as every other block.
+    pub fn genesis_mined() -> Self {
+        HeaderBuilder::from_header(&Self::genesis()).seal_pow()
+    }
+
+    /// Verify that this header is a genesis header, and that it carries a valid proof of work
+    /// when the given config says genesis must be mined.
+    pub fn verify_genesis(&self, config: &GenesisConfig) -> Result<(), VerifyError> {
+        if self.height != 0 {
+            return Err(VerifyError::BadHeight {
+                expected: 0,
+                found: self.height,
+            });
+        }
+        if self.parent != 0 {
+            return Err(VerifyError::WrongParent { height: 0 });
+        }
+        if config.mined && hash(self) >= threshold_for(self.difficulty) {
+            return Err(VerifyError::InsufficientWork { height: 0 });
+        }
+        Ok(())
+    }
+}
+
+/// A single valid chain of headers, indexed by both hash and number.
+///
+/// This saves us from juggling raw vectors of headers and keeping track of which
+/// index holds which block. Headers can only be added by extending the tip, and every
+/// new header is verified first, so a `Chain` is always valid.
+#[derive(Clone, Debug)]
+pub struct Chain {
+    /// The headers in order. A header's index is also its number.
+    headers: Vec<Header>,
+    /// Maps each header's hash to its number.
+    numbers: HashMap<Hash, u64>,
+    /// What happens when an extrinsic would overflow the state.
+    overflow: OverflowPolicy,
+}
+
+impl Chain {
+    /// Start a new chain from the given genesis header.
+    pub fn new(genesis: Header) -> Self {
+        Chain {
+            numbers: HashMap::from([(hash(&genesis), 0)]),
+            headers: vec![genesis],
+            overflow: OverflowPolicy::default(),
+        }
+    }
+
+    /// Start a new chain from the genesis header that the given config describes, following
+    /// the config's overflow policy.
+    pub fn from_config(config: &GenesisConfig) -> Self {
+        Chain {
+            overflow: config.overflow,
+            ..Chain::new(Header::genesis_from(config))
+        }
+    }
+
+    /// Append a header to the tip of the chain. Returns whether the header
+    /// was valid and therefore appended.
+    pub fn push(&mut self, header: Header) -> bool {
+        let verified = self
+            .best_header()
+            .verify_child_with_policy(&header, self.overflow);
+        if verified.is_err() {
+            return false;
+        }
+        self.numbers.insert(hash(&header), self.headers.len() as u64);
+        self.headers.push(header);
+        true
+    }
+
+    /// Mine a new block with the given extrinsic on top of the tip, append it, and return it.
+    ///
+    /// Panics if the chain's overflow policy rejects the extrinsic.
+    pub fn extend(&mut self, extrinsic: u64) -> &Header {
+        let child = self
+            .best_header()
+            .try_child(extrinsic, self.overflow)
+            .expect("the state overflowed");
+        self.numbers.insert(hash(&child), self.headers.len() as u64);
+        self.headers.push(child);
+        self.best_header()
+    }
+
+    /// Look up a header in this chain.
+    pub fn get_header(&self, id: BlockId) -> Option<&Header> {
+        let number = match id {
+            BlockId::Hash(h) => *self.numbers.get(&h)?,
+            BlockId::Number(n) => n,
+        };
+        self.headers.get(number as usize)
+    }
+
+    /// The header at the tip of the chain.
+    pub fn best_header(&self) -> &Header {
+        self.headers.last().expect("a chain always contains at least its genesis header")
+    }
+
+    /// All of the headers in the chain, starting from genesis.
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+}
+
+/// The reasons that a chain can not be imported from JSON.
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub enum ChainJsonError {
+    /// The JSON is not a list of headers.
+    Parse(serde_json::Error),
+    /// The list is empty, so there is no header to verify the others from.
+    Empty,
+    /// The headers do not form a valid chain according to the original rules.
+    Invalid,
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for ChainJsonError {
+    fn from(error: serde_json::Error) -> Self {
+        ChainJsonError::Parse(error)
+    }
+}
+
+/// Write the given headers as JSON, for example to share a contentious fork with others who
+/// want to replay it.
+#[cfg(feature = "json")]
+pub fn export_chain_json(chain: &[Header]) -> String {
+    serde_json::to_string_pretty(chain).expect("headers only contain numbers")
+}
+
+/// Read headers written by `export_chain_json`.
+///
+/// The JSON may have been edited by hand or come from anywhere, so it is not trusted. The first
+/// header is taken as given, and every header after it must validly extend the one before it.
+/// This only checks the original rules. Which side of a contentious fork the chain is on is for
+/// the reader to decide, with `verify_sub_chain_with`.
+#[cfg(feature = "json")]
+pub fn import_chain_json(json: &str) -> Result<Vec<Header>, ChainJsonError> {
+    let chain: Vec<Header> = serde_json::from_str(json)?;
+    let (first, rest) = chain.split_first().ok_or(ChainJsonError::Empty)?;
+    if !first.verify_sub_chain(rest) {
+        return Err(ChainJsonError::Invalid);
+    }
+    Ok(chain)
+}
+
+/// Constructs arbitrary headers field by field.
+///
+/// The header's fields are private so that the only way to create headers from outside
+/// this module is through `genesis()` and `child()`, which always produce valid blocks.
+/// When exploring validity rules it is useful to create invalid headers on purpose, so
+/// this builder lets you set every field directly. Nothing is checked along the way.
+#[derive(Clone, Debug)]
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    /// Start building from an all-zero header, which happens to be the genesis header.
+    pub fn new() -> Self {
+        HeaderBuilder {
+            header: Header::genesis(),
+        }
+    }
+
+    /// Start building from a copy of an existing header.
+    pub fn from_header(header: &Header) -> Self {
+        HeaderBuilder {
+            header: header.clone(),
+        }
+    }
+
+    /// Start building from a header that correctly extends the given parent with an
+    /// empty extrinsic, authored right on schedule. The consensus digest is left at zero,
+    /// so it is not sealed yet.
+    pub fn child_of(parent: &Header) -> Self {
+        let (difficulty, epoch_start) = parent.next_difficulty();
+        HeaderBuilder::new()
+            .parent(hash(parent))
+            .height(parent.height + 1)
+            .state(parent.state)
+            .timestamp(parent.timestamp + TARGET_BLOCK_TIME)
+            .difficulty(difficulty)
+            .epoch_start(epoch_start)
+    }
+
+    pub fn parent(mut self, parent: Hash) -> Self {
+        self.header.parent = parent;
+        self
+    }
+
+    pub fn height(mut self, height: u64) -> Self {
+        self.header.height = height;
+        self
+    }
+
+    pub fn extrinsic(mut self, extrinsic: u64) -> Self {
+        self.header.extrinsic = extrinsic;
+        self
+    }
+
+    pub fn state(mut self, state: u64) -> Self {
+        self.header.state = state;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: u64) -> Self {
+        self.header.difficulty = difficulty;
+        self
+    }
+
+    pub fn epoch_start(mut self, epoch_start: u64) -> Self {
+        self.header.epoch_start = epoch_start;
+        self
+    }
+
+    pub fn consensus_digest(mut self, consensus_digest: u64) -> Self {
+        self.header.consensus_digest = consensus_digest;
+        self
+    }
+
+    /// Return the header exactly as it was built.
+    pub fn build(self) -> Header {
+        self.header
+    }
+
+    /// Mine a nonce that puts the header's hash below the threshold for its difficulty
+    /// and return the sealed header.
+    ///
+    /// Only the consensus digest is changed, so the result is not necessarily a valid block.
+    /// It just has valid proof of work.
+    pub fn seal_pow(mut self) -> Header {
+        self.header.consensus_digest = 0;
+        while hash(&self.header) >= threshold_for(self.header.difficulty) {
+            self.header.consensus_digest += 1;
+        }
+        self.header
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Bitcoin, the original proof of work chain, packs its headers into exactly 80 bytes:
+//
+// | bytes  | field       | our field                           |
+// |--------|-------------|-------------------------------------|
+// | 0..4   | version     | always `BITCOIN_HEADER_VERSION`     |
+// | 4..36  | prev hash   | `parent`                            |
+// | 36..68 | merkle root | `extrinsic`, standing in for a root |
+// | 68..72 | time        | `timestamp`                         |
+// | 72..76 | bits        | the threshold, in compact form      |
+// | 76..80 | nonce       | `consensus_digest`                  |
+//
+// Every number is little-endian. The height, state, and epoch start are not there at all. A
+// Bitcoin node works them out from the parent, and so do we. Our hashes and thresholds only
+// have 64 bits, so they fill the low 8 of the 32 bytes that Bitcoin gives its hashes.
+
+/// The version that headers are encoded with.
+pub const BITCOIN_HEADER_VERSION: u32 = 1;
+
+/// The size of an encoded header.
+pub const BITCOIN_HEADER_SIZE: usize = 80;
+
+/// Encode a threshold, or target as Bitcoin calls it, in the compact form that headers carry
+/// it in: the length in bytes in the top byte, followed by the three most significant bytes.
+/// Precision is lost beyond those three bytes.
+pub fn target_to_bits(target: u64) -> u32 {
+    let mut size = (u64::BITS - target.leading_zeros()).div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        (target << (8 * (3 - size))) as u32
+    } else {
+        (target >> (8 * (size - 3))) as u32
+    };
+    // The top bit of the mantissa is a sign bit, and targets are never negative.
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
     }
+    (size << 24) | mantissa
+}
 
-    /// verify that the given headers form a valid chain.
-    /// In this case "valid" means that the STATE MUST BE ODD.
-    fn verify_sub_chain_odd(&self, chain: &[Header]) -> bool {
-        // todo!("Exercise 5")
-        let mut verifiable = true;
-        let mut current_height = self.height;
-        let mut current_state = self.state;
-        if current_state % 2 == 0 && self.height > FORK_HEIGHT {
-            println!("1");
-            verifiable = false;
-        }
-        for (block_idx, header) in chain.iter().enumerate() {
-            if hash(header) >= THRESHOLD {
-                println!("3");
-                verifiable =  false;
-            }
-            if header.height != current_height + 1 {
-                println!("4");
-                verifiable =  false;
-            }
-            if header.extrinsic + current_state !=  header.state {
-                println!("5");
-                verifiable =  false;
-            }
-            if block_idx == 0 {
-                if hash(self) != header.parent {
-                    println!("6");
-                    verifiable =  false;
-                }
-            } else if block_idx != chain.len() - 1 {
-                if hash(header) != chain[block_idx + 1].parent {
-                    println!("7");
-                    verifiable =  false;
-                }
-            }
-            current_height += 1;
-            current_state += header.extrinsic;
-            if current_state % 2 == 0 && current_height > FORK_HEIGHT {
-                println!("8");
-                verifiable = false;
-            }
+/// Decode a compact target. Returns None if it is negative, or does not fit in 64 bits.
+pub fn bits_to_target(bits: u32) -> Option<u64> {
+    let size = bits >> 24;
+    let mantissa = u64::from(bits & 0x007f_ffff);
+    if bits & 0x0080_0000 != 0 && mantissa != 0 {
+        return None;
+    }
+    if size <= 3 {
+        return Some(mantissa >> (8 * (3 - size)));
+    }
+    let shift = 8 * (size - 3);
+    mantissa
+        .checked_shl(shift)
+        .filter(|target| target >> shift == mantissa)
+}
+
+/// The reasons that a header can not be encoded in, or decoded from, Bitcoin's format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitcoinHeaderError {
+    /// The timestamp or nonce does not fit in the 32 bits that Bitcoin gives it, or the
+    /// extrinsic does not fit in the 64 bits that we give it.
+    FieldTooLarge,
+    /// The header was encoded with a version we do not know.
+    UnknownVersion,
+    /// The header's previous hash is not the hash of the given parent.
+    WrongParent,
+    /// The header's bits are not the threshold that the retargeting schedule requires.
+    WrongDifficulty,
+    /// The header's extrinsic overflows the parent's state.
+    StateOverflow,
+}
+
+impl Header {
+    /// Encode this header in Bitcoin's 80 byte format.
+    pub fn to_bitcoin_bytes(&self) -> Result<[u8; BITCOIN_HEADER_SIZE], BitcoinHeaderError> {
+        let narrow =
+            |field: u64| u32::try_from(field).map_err(|_| BitcoinHeaderError::FieldTooLarge);
+        let mut bytes = [0; BITCOIN_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&BITCOIN_HEADER_VERSION.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.parent.to_le_bytes());
+        bytes[36..44].copy_from_slice(&self.extrinsic.to_le_bytes());
+        bytes[68..72].copy_from_slice(&narrow(self.timestamp)?.to_le_bytes());
+        let bits = target_to_bits(threshold_for(self.difficulty));
+        bytes[72..76].copy_from_slice(&bits.to_le_bytes());
+        bytes[76..80].copy_from_slice(&narrow(self.consensus_digest)?.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Decode a header in Bitcoin's 80 byte format, working out the fields it leaves out from
+    /// the given parent. The proof of work is not checked, only that the header really is a
+    /// child of the parent, and was mined at the difficulty the schedule requires.
+    pub fn from_bitcoin_bytes(
+        bytes: &[u8; BITCOIN_HEADER_SIZE],
+        parent: &Header,
+    ) -> Result<Header, BitcoinHeaderError> {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        if u32_at(0) != BITCOIN_HEADER_VERSION {
+            return Err(BitcoinHeaderError::UnknownVersion);
+        }
+        if u64_at(4) != hash(parent) || bytes[12..36].iter().any(|&byte| byte != 0) {
+            return Err(BitcoinHeaderError::WrongParent);
         }
-        verifiable
+        let (difficulty, epoch_start) = parent.next_difficulty();
+        if u32_at(72) != target_to_bits(threshold_for(difficulty)) {
+            return Err(BitcoinHeaderError::WrongDifficulty);
+        }
+        if bytes[44..68].iter().any(|&byte| byte != 0) {
+            return Err(BitcoinHeaderError::FieldTooLarge);
+        }
+        let extrinsic = u64_at(36);
+        let state = parent
+            .state
+            .checked_add(extrinsic)
+            .ok_or(BitcoinHeaderError::StateOverflow)?;
+        Ok(Header {
+            parent: hash(parent),
+            height: parent.height + 1,
+            extrinsic,
+            state,
+            timestamp: u32_at(68).into(),
+            difficulty,
+            epoch_start,
+            consensus_digest: u32_at(76).into(),
+        })
     }
 }
 
@@ -216,57 +1183,26 @@ impl Header {
 ///            \-- 3'-- 4'
 fn build_contentious_forked_chain() -> (Vec<Header>, Vec<Header>, Vec<Header>) {
     // todo!("Exercise 6")
-    let mut blockchain_0:Vec<Header> = Vec::new();
-    let mut blockchain_1:Vec<Header> = Vec::new();
-    let mut blockchain_2:Vec<Header> = Vec::new();
-    // genesis block
-    let genesis = Header::genesis();
-    let genesis_child = genesis.child(1);
-    blockchain_0.push(genesis.clone());
-    blockchain_0.push(genesis_child.clone());
-
-    for i in 1..5 {
-
-        let mut odd_number: u64  = i.clone();
-        if blockchain_1.len() == 0 {
-            while (blockchain_0[1].state + (odd_number.clone() as u64) ) % 2 != 1 {
-                if (blockchain_0[1].state + (odd_number.clone() as u64) )% 2 != 1 {
-                    odd_number += 1;
-                }
-            }
-            let new_block = blockchain_0[1].child( odd_number);
-            blockchain_1.push(new_block.clone());
-        } else {
-            while (blockchain_1[blockchain_1.len() - 1].state + (odd_number.clone() as u64) ) % 2 != 1 {
-                if (blockchain_1[blockchain_1.len() - 1].state + (odd_number.clone() as u64) )% 2 != 1 {
-                    odd_number += 1;
-                }
-            }
-            let new_block = blockchain_1[blockchain_1.len() - 1].child( odd_number);
-            blockchain_1.push(new_block.clone());
-        }
-
-        let mut even_number: u64 = i.clone();
-        if blockchain_2.len() == 0 {
-            while (blockchain_0[1].state + even_number) % 2 != 0 {
-                if (blockchain_0[1].state + even_number) % 2 != 0 {
-                    even_number += 1;
-                }
-            }
-            let new_block_2 = blockchain_0[1].child(even_number);
-            blockchain_2.push(new_block_2.clone());
-        } else {
-            while (blockchain_2[blockchain_2.len() - 1].state + even_number) % 2 != 0 {
-                if (blockchain_2[blockchain_2.len() - 1].state + even_number) % 2 != 0 {
-                    even_number += 1;
-                }
-            }
-            let new_block_2 = blockchain_2[blockchain_2.len() - 1].child(even_number);
-            blockchain_2.push(new_block_2.clone());
-        }
+    let mut prefix = Chain::new(Header::genesis());
+    prefix.extend(1);
 
+    // From the common state of 1, one side adds 1 to become even and the other adds 2
+    // to stay odd. After that, both sides keep their parity by only adding even numbers.
+    let mut even = prefix.clone();
+    let mut odd = prefix.clone();
+    even.extend(1);
+    odd.extend(2);
+    for _ in 0..3 {
+        even.extend(2);
+        odd.extend(2);
     }
-    return (blockchain_0, blockchain_2, blockchain_1);
+
+    let fork_point = prefix.headers().len();
+    (
+        prefix.headers().to_vec(),
+        even.headers()[fork_point..].to_vec(),
+        odd.headers()[fork_point..].to_vec(),
+    )
 }
 
 // To run these tests: `cargo test bc_3`
@@ -300,6 +1236,7 @@ fn bc_3_genesis_block_state() {
 fn bc_3_genesis_consensus_digest() {
     // We could require that the genesis block have a valid proof of work as well.
     // But instead I've chosen the simpler path of defining the nonce = 0 in genesis.
+    // See `genesis_mined` for the other path.
     let g = Header::genesis();
     assert!(g.consensus_digest == 0);
 }
@@ -336,7 +1273,7 @@ fn bc_3_child_block_state() {
 fn bc_3_child_block_consensus_digest() {
     let g = Header::genesis();
     let b1 = g.child(7);
-    assert!(hash(&b1) < THRESHOLD);
+    assert!(hash(&b1) < threshold_for(DIFFICULTY));
 }
 
 #[test]
@@ -484,3 +1421,769 @@ fn bc_3_verify_forked_chain() {
     assert!(!g.verify_sub_chain_odd(&full_even_chain[..]));
     assert!(g.verify_sub_chain_odd(&full_odd_chain[..]));
 }
+
+#[test]
+fn bc_3_builder_child_of_matches_child() {
+    let g = Header::genesis();
+    let built = HeaderBuilder::child_of(&g).extrinsic(5).state(5).seal_pow();
+
+    assert_eq!(built, g.child(5));
+    assert!(g.verify_sub_chain(&[built]));
+}
+
+#[test]
+fn bc_3_builder_sealed_header_with_bad_state_does_not_check() {
+    let g = Header::genesis();
+    let b1 = HeaderBuilder::child_of(&g).extrinsic(5).state(6).seal_pow();
+
+    // The work is valid, but the state transition is not.
+    assert!(hash(&b1) < threshold_for(DIFFICULTY));
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_builder_unsealed_header_does_not_check() {
+    let g = Header::genesis();
+    let mut nonce = 0;
+    let b1 = loop {
+        let candidate = HeaderBuilder::child_of(&g).consensus_digest(nonce).build();
+        if hash(&candidate) >= threshold_for(DIFFICULTY) {
+            break candidate;
+        }
+        nonce += 1;
+    };
+
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_genesis_from_config() {
+    let config = GenesisConfig {
+        initial_state: 10,
+        chain_id: 42,
+        initial_difficulty: 200,
+        authorities: vec![ConsensusAuthority::Alice],
+        mined: false,
+        overflow: OverflowPolicy::Reject,
+    };
+    let g = Header::genesis_from(&config);
+
+    assert_eq!(g.height, 0);
+    assert_eq!(g.parent, 0);
+    assert_eq!(g.state, 10);
+    assert_eq!(g.difficulty, 200);
+
+    let b1 = g.child(5);
+    assert_eq!(b1.state, 15);
+    assert!(hash(&b1) < threshold_for(200));
+    assert!(g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_genesis_from_distinguishes_networks() {
+    let config = GenesisConfig::default();
+    let other_chain = GenesisConfig {
+        chain_id: 1,
+        ..GenesisConfig::default()
+    };
+    let other_authorities = GenesisConfig {
+        authorities: vec![ConsensusAuthority::Bob],
+        ..GenesisConfig::default()
+    };
+
+    let g = Header::genesis_from(&config);
+    assert_ne!(g, Header::genesis_from(&other_chain));
+    assert_ne!(g, Header::genesis_from(&other_authorities));
+    assert_eq!(g, Header::genesis_from(&config.clone()));
+}
+
+#[test]
+fn bc_3_cant_verify_changed_difficulty() {
+    let g = Header::genesis();
+    let b1 = HeaderBuilder::child_of(&g)
+        .extrinsic(5)
+        .state(5)
+        .difficulty(DIFFICULTY / 2)
+        .seal_pow();
+
+    assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_chain_lookup_by_hash_and_number() {
+    let mut chain = Chain::new(Header::genesis());
+    let b1 = chain.extend(5).clone();
+    let b2 = chain.extend(6).clone();
+
+    assert_eq!(chain.best_header(), &b2);
+    assert_eq!(chain.get_header(BlockId::Number(1)), Some(&b1));
+    assert_eq!(chain.get_header(BlockId::Hash(hash(&b2))), Some(&b2));
+    assert_eq!(chain.get_header(BlockId::Number(3)), None);
+    assert_eq!(chain.get_header(BlockId::Hash(0)), None);
+    assert!(chain.headers()[0].verify_sub_chain(&chain.headers()[1..]));
+}
+
+#[test]
+fn bc_3_chain_rejects_invalid_header() {
+    let mut chain = Chain::new(Header::genesis());
+    chain.extend(5);
+    let bad = HeaderBuilder::child_of(chain.best_header()).extrinsic(1).state(7).seal_pow();
+    let good = HeaderBuilder::child_of(chain.best_header()).extrinsic(1).state(6).seal_pow();
+
+    assert!(!chain.push(bad));
+    assert!(chain.push(good.clone()));
+    assert_eq!(chain.best_header(), &good);
+    assert_eq!(chain.get_header(BlockId::Number(2)), Some(&good));
+}
+
+#[cfg(test)]
+fn chain_with_block_time(blocks: u64, block_time: u64) -> Vec<Header> {
+    let mut headers = vec![Header::genesis()];
+    for _ in 0..blocks {
+        let parent = headers.last().unwrap();
+        headers.push(parent.child_at(1, parent.timestamp + block_time));
+    }
+    headers
+}
+
+#[test]
+fn bc_3_retarget_on_schedule_keeps_difficulty() {
+    let headers = chain_with_block_time(2 * RETARGET_INTERVAL + 1, TARGET_BLOCK_TIME);
+
+    assert!(headers.iter().all(|h| h.difficulty == DIFFICULTY));
+    assert!(headers[0].verify_sub_chain(&headers[1..]));
+}
+
+#[test]
+fn bc_3_retarget_fast_blocks_raise_difficulty() {
+    let headers = chain_with_block_time(RETARGET_INTERVAL + 1, TARGET_BLOCK_TIME / 2);
+
+    // The whole first epoch keeps the genesis difficulty, then it doubles.
+    let first_epoch = &headers[..=RETARGET_INTERVAL as usize];
+    assert!(first_epoch.iter().all(|h| h.difficulty == DIFFICULTY));
+    assert_eq!(headers.last().unwrap().difficulty, 2 * DIFFICULTY);
+    assert!(headers[0].verify_sub_chain(&headers[1..]));
+}
+
+#[test]
+fn bc_3_retarget_slow_blocks_lower_difficulty_within_bounds() {
+    let slow = chain_with_block_time(RETARGET_INTERVAL + 1, 2 * TARGET_BLOCK_TIME);
+    assert_eq!(slow.last().unwrap().difficulty, DIFFICULTY / 2);
+    assert!(slow[0].verify_sub_chain(&slow[1..]));
+
+    // However slow the blocks are, a single retarget is clamped.
+    assert_eq!(retarget(DIFFICULTY, u64::MAX), DIFFICULTY / MAX_RETARGET_FACTOR);
+    assert_eq!(retarget(DIFFICULTY, 0), DIFFICULTY * MAX_RETARGET_FACTOR);
+}
+
+#[test]
+fn bc_3_cant_verify_skipped_retarget() {
+    let headers = chain_with_block_time(RETARGET_INTERVAL, TARGET_BLOCK_TIME / 2);
+    let tip = headers.last().unwrap();
+
+    // The next block should be mined at double difficulty. Keeping the old difficulty is invalid.
+    let lazy = HeaderBuilder::child_of(tip).difficulty(DIFFICULTY).seal_pow();
+    let honest = HeaderBuilder::child_of(tip).seal_pow();
+    assert_eq!(honest.difficulty, 2 * DIFFICULTY);
+
+    assert!(!tip.verify_sub_chain(&[lazy]));
+    assert!(tip.verify_sub_chain(&[honest]));
+}
+
+#[test]
+fn bc_3_cant_verify_timestamp_going_backwards() {
+    let g = Header::genesis();
+    let b1 = g.child_at(1, 50);
+    let b2 = HeaderBuilder::child_of(&b1).timestamp(40).seal_pow();
+
+    assert!(!g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_always_valid_rule_matches_original_rules() {
+    let (prefix, even, odd) = build_contentious_forked_chain();
+    let g = &prefix[0];
+    let full_even_chain = [&prefix[1..], &even].concat();
+    let full_odd_chain = [&prefix[1..], &odd].concat();
+
+    assert!(g.verify_sub_chain_with(&full_even_chain, &AlwaysValid));
+    assert!(g.verify_sub_chain_with(&full_odd_chain, &AlwaysValid));
+
+    let mut bad = full_even_chain;
+    bad[1].state += 1;
+    assert!(!g.verify_sub_chain_with(&bad, &AlwaysValid));
+}
+
+#[test]
+fn bc_3_rule_fork_height_is_configurable() {
+    let g = Header::genesis(); // 0
+    let b1 = g.child(1); // 1
+    let b2 = b1.child(1); // 2
+
+    // With the fork at genesis, block 1's odd state is already too late for the even side.
+    let early_even = EvenOnlyAfterFork { fork_height: 0 };
+    let early_odd = OddOnlyAfterFork { fork_height: 0 };
+    assert!(!g.verify_sub_chain_with(&[b1.clone(), b2.clone()], &early_even));
+    assert!(!g.verify_sub_chain_with(&[b1.clone(), b2.clone()], &early_odd));
+
+    // With the default fork height both chains are still valid.
+    assert!(g.verify_sub_chain_even(&[b1.clone(), b2.clone()]));
+    assert!(g.verify_sub_chain_odd(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_checkpoints_reject_contradicting_chain() {
+    let mut honest = Chain::new(Header::genesis());
+    let mut other = honest.clone();
+    for _ in 0..3 {
+        honest.extend(1);
+        other.extend(2);
+    }
+    let b2 = honest.get_header(BlockId::Number(2)).unwrap();
+    let checkpoints = Checkpoints::new([(2, hash(b2))]);
+
+    let g = Header::genesis();
+    assert!(g.verify_sub_chain_with_checkpoints(&honest.headers()[1..], &checkpoints));
+    // The other chain is valid by itself, but not according to the checkpoint.
+    assert!(g.verify_sub_chain(&other.headers()[1..]));
+    assert!(!g.verify_sub_chain_with_checkpoints(&other.headers()[1..], &checkpoints));
+}
+
+#[test]
+fn bc_3_checkpoints_skip_work_below_matched_checkpoint() {
+    let g = Header::genesis();
+    let unsealed = |parent: &Header| {
+        let mut nonce = 0;
+        loop {
+            let candidate = HeaderBuilder::child_of(parent).consensus_digest(nonce).build();
+            if hash(&candidate) >= threshold_for(DIFFICULTY) {
+                break candidate;
+            }
+            nonce += 1;
+        }
+    };
+    // Block 1 has no valid work, but it is anchored by the checkpoint at block 2.
+    let b1 = unsealed(&g);
+    let b2 = HeaderBuilder::child_of(&b1).seal_pow();
+    let b3 = unsealed(&b2);
+    let checkpoints = Checkpoints::new([(2, hash(&b2))]);
+
+    assert!(!g.verify_sub_chain(&[b1.clone(), b2.clone()]));
+    assert!(g.verify_sub_chain_with_checkpoints(&[b1.clone(), b2.clone()], &checkpoints));
+
+    // Without reaching the checkpoint, nothing is trusted.
+    assert!(!g.verify_sub_chain_with_checkpoints(std::slice::from_ref(&b1), &checkpoints));
+    // Blocks above the checkpoint still need valid work.
+    assert!(!g.verify_sub_chain_with_checkpoints(&[b1, b2, b3], &checkpoints));
+}
+
+#[test]
+fn bc_3_checkpoints_follow_the_overflow_policy() {
+    let g = Header::genesis_from(&GenesisConfig {
+        initial_state: u64::MAX - 1,
+        ..GenesisConfig::default()
+    });
+    let b1 = g.try_child(5, OverflowPolicy::Saturate).unwrap();
+    let b2 = b1.try_child(1, OverflowPolicy::Saturate).unwrap();
+    let checkpoints = Checkpoints::new([(1, hash(&b1))]);
+    let chain = [b1, b2];
+
+    assert!(g.verify_sub_chain_with_checkpoints_and_policy(
+        &chain,
+        &checkpoints,
+        OverflowPolicy::Saturate
+    ));
+    // Matching a checkpoint only vouches for the work, not for the state.
+    assert!(!g.verify_sub_chain_with_checkpoints(&chain, &checkpoints));
+}
+
+#[test]
+fn bc_3_total_work_sums_difficulty() {
+    let fast = chain_with_block_time(RETARGET_INTERVAL + 2, TARGET_BLOCK_TIME / 2);
+    let steady = chain_with_block_time(RETARGET_INTERVAL + 2, TARGET_BLOCK_TIME);
+
+    // The first epoch is mined at the default difficulty, and the fast chain doubles after it.
+    let epoch_work = RETARGET_INTERVAL as u128 * DIFFICULTY as u128;
+    assert_eq!(total_work(&steady[1..]), epoch_work + 2 * DIFFICULTY as u128);
+    assert_eq!(total_work(&fast[1..]), epoch_work + 4 * DIFFICULTY as u128);
+    assert_eq!(total_work(&[]), 0);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn bc_3_contentious_fork_survives_json() {
+    let (prefix, even, odd) = build_contentious_forked_chain();
+    for suffix in [even, odd] {
+        let chain = [&prefix[..], &suffix].concat();
+        let imported = import_chain_json(&export_chain_json(&chain)).unwrap();
+        assert_eq!(imported, chain);
+    }
+
+    // Each side keeps its political rules after the trip.
+    let (prefix, even, _) = build_contentious_forked_chain();
+    let imported = import_chain_json(&export_chain_json(&even)).unwrap();
+    assert!(prefix.last().unwrap().verify_sub_chain_even(&imported));
+    assert!(!prefix.last().unwrap().verify_sub_chain_odd(&imported));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn bc_3_json_import_rejects_invalid_chains() {
+    let (prefix, even, _) = build_contentious_forked_chain();
+    let mut chain = [&prefix[..], &even].concat();
+    chain[2] = HeaderBuilder::from_header(&chain[2]).state(99).build();
+    let tampered = import_chain_json(&export_chain_json(&chain));
+    assert!(matches!(tampered, Err(ChainJsonError::Invalid)));
+
+    let empty = import_chain_json("[]");
+    assert!(matches!(empty, Err(ChainJsonError::Empty)));
+    let not_headers = import_chain_json("[1, 2]");
+    assert!(matches!(not_headers, Err(ChainJsonError::Parse(_))));
+}
+
+#[test]
+fn bc_3_compact_targets_match_bitcoin() {
+    // Test vectors from Bitcoin Core's `arith_uint256` tests.
+    assert_eq!(bits_to_target(0x0100_3456), Some(0));
+    assert_eq!(bits_to_target(0x0112_3456), Some(0x12));
+    assert_eq!(bits_to_target(0x0212_3456), Some(0x1234));
+    assert_eq!(bits_to_target(0x0312_3456), Some(0x12_3456));
+    assert_eq!(bits_to_target(0x0412_3456), Some(0x1234_5600));
+    assert_eq!(bits_to_target(0x0500_9234), Some(0x9234_0000));
+    assert_eq!(target_to_bits(0x12), 0x0112_0000);
+    assert_eq!(target_to_bits(0x1234), 0x0212_3400);
+    assert_eq!(target_to_bits(0x1234_5600), 0x0412_3456);
+    assert_eq!(target_to_bits(0x9234_0000), 0x0500_9234);
+    assert_eq!(target_to_bits(0x80), 0x0200_8000);
+
+    // Negative targets, and targets beyond 64 bits.
+    assert_eq!(bits_to_target(0x0492_3456), None);
+    assert_eq!(bits_to_target(0x1d00_ffff), None);
+
+    // Only the three most significant bytes survive.
+    let threshold = threshold_for(DIFFICULTY);
+    let rounded = threshold & !0xff_ffff_ffff;
+    assert_eq!(bits_to_target(target_to_bits(threshold)), Some(rounded));
+}
+
+#[test]
+fn bc_3_headers_survive_bitcoin_encoding() {
+    // Long enough to retarget, so the bits change along the way.
+    let chain = chain_with_block_time(2 * RETARGET_INTERVAL + 1, TARGET_BLOCK_TIME / 2);
+    for pair in chain.windows(2) {
+        let bytes = pair[1].to_bitcoin_bytes().unwrap();
+        assert_eq!(bytes[..4], BITCOIN_HEADER_VERSION.to_le_bytes());
+        let decoded = Header::from_bitcoin_bytes(&bytes, &pair[0]);
+        assert_eq!(decoded, Ok(pair[1].clone()));
+    }
+}
+
+#[test]
+fn bc_3_bitcoin_decoding_checks_against_the_parent() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let bytes = b1.child(2).to_bitcoin_bytes().unwrap();
+    let decoded = Header::from_bitcoin_bytes(&bytes, &g);
+    assert_eq!(decoded, Err(BitcoinHeaderError::WrongParent));
+
+    let harder = HeaderBuilder::child_of(&g)
+        .difficulty(2 * DIFFICULTY)
+        .seal_pow();
+    let bytes = harder.to_bitcoin_bytes().unwrap();
+    let decoded = Header::from_bitcoin_bytes(&bytes, &g);
+    assert_eq!(decoded, Err(BitcoinHeaderError::WrongDifficulty));
+
+    let mut bytes = b1.to_bitcoin_bytes().unwrap();
+    bytes[0] = 2;
+    let decoded = Header::from_bitcoin_bytes(&bytes, &g);
+    assert_eq!(decoded, Err(BitcoinHeaderError::UnknownVersion));
+
+    let late = HeaderBuilder::child_of(&g).timestamp(1 << 32).build();
+    let encoded = late.to_bitcoin_bytes();
+    assert_eq!(encoded, Err(BitcoinHeaderError::FieldTooLarge));
+}
+
+#[test]
+fn bc_3_detailed_verification_names_the_broken_rule() {
+    let g = Header::genesis();
+    let b1 = g.child(5);
+    let b2 = b1.child(3);
+    assert_eq!(
+        g.verify_sub_chain_detailed(&[b1.clone(), b2.clone()]),
+        Ok(())
+    );
+
+    // Reseal every tampered header so that its proof of work does not give it away first.
+    let tampered = |header: &Header| HeaderBuilder::from_header(header);
+    let wrong_parent = tampered(&b1).parent(10).seal_pow();
+    let bad_height = tampered(&b1).height(10).seal_pow();
+    let bad_state = tampered(&b1).state(10).seal_pow();
+    let mut no_work = b1.clone();
+    no_work.consensus_digest += 1;
+    while hash(&no_work) < threshold_for(no_work.difficulty) {
+        no_work.consensus_digest += 1;
+    }
+    let too_easy = tampered(&b1).difficulty(DIFFICULTY / 2).seal_pow();
+
+    let verify = |header: Header| g.verify_sub_chain_detailed(&[header]);
+    assert_eq!(
+        verify(wrong_parent),
+        Err(VerifyError::WrongParent { height: 1 })
+    );
+    let bad_height = verify(bad_height);
+    assert_eq!(
+        bad_height,
+        Err(VerifyError::BadHeight {
+            expected: 1,
+            found: 10
+        })
+    );
+    assert_eq!(
+        verify(bad_state),
+        Err(VerifyError::StateMismatch { height: 1 })
+    );
+    assert_eq!(
+        verify(no_work),
+        Err(VerifyError::InsufficientWork { height: 1 })
+    );
+    assert_eq!(
+        verify(too_easy),
+        Err(VerifyError::WrongDifficulty { height: 1 })
+    );
+
+    let early = tampered(&b2).timestamp(0).seal_pow();
+    let early = g.verify_sub_chain_detailed(&[b1, early]);
+    assert_eq!(early, Err(VerifyError::TimestampBeforeParent { height: 2 }));
+}
+
+#[test]
+fn bc_3_detailed_verification_names_the_rejected_state() {
+    let (prefix, even, odd) = build_contentious_forked_chain();
+    let g = &prefix[0];
+    let odd_chain: Vec<Header> = prefix[1..].iter().chain(&odd).cloned().collect();
+    let rule = EvenOnlyAfterFork::default();
+    let rejected = g.verify_sub_chain_with_detailed(&odd_chain, &rule);
+    assert_eq!(rejected, Err(VerifyError::StateRejected { height: 3 }));
+
+    let even_chain: Vec<Header> = prefix[1..].iter().chain(&even).cloned().collect();
+    assert_eq!(g.verify_sub_chain_with_detailed(&even_chain, &rule), Ok(()));
+}
+
+#[test]
+fn bc_3_report_lists_every_violation() {
+    let g = Header::genesis();
+    let mut chain = vec![g.child(1)];
+    for extrinsic in 2..=4 {
+        chain.push(chain.last().unwrap().child(extrinsic));
+    }
+    assert!(g.verify_report(&chain).is_valid());
+
+    chain[0] = HeaderBuilder::from_header(&chain[0]).state(10).seal_pow();
+    chain[2] = HeaderBuilder::from_header(&chain[2]).height(7).seal_pow();
+    let report = g.verify_report(&chain);
+    let violations: Vec<(usize, VerifyError)> = report
+        .violations
+        .iter()
+        .map(|violation| (violation.index, violation.error))
+        .collect();
+    assert_eq!(
+        violations,
+        vec![
+            (0, VerifyError::StateMismatch { height: 1 }),
+            (1, VerifyError::WrongParent { height: 2 }),
+            (
+                2,
+                VerifyError::BadHeight {
+                    expected: 3,
+                    found: 7
+                }
+            ),
+            (3, VerifyError::WrongParent { height: 4 }),
+        ]
+    );
+    assert_eq!(report.invalid_blocks(), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn bc_3_report_lists_every_rejected_state() {
+    let (prefix, _, odd) = build_contentious_forked_chain();
+    let odd_chain: Vec<Header> = prefix[1..].iter().chain(&odd).cloned().collect();
+    let report = prefix[0].verify_report_with(&odd_chain, &EvenOnlyAfterFork::default());
+    assert!(report
+        .violations
+        .iter()
+        .all(|violation| matches!(violation.error, VerifyError::StateRejected { .. })));
+    // Every block above the fork height has an odd state.
+    assert_eq!(report.invalid_blocks(), vec![2, 3, 4]);
+    assert!(!prefix[0].verify_sub_chain_even(&odd_chain));
+
+    // A block that lies about its height is still named by the height it should have.
+    let mut lying = odd_chain.clone();
+    lying[4] = HeaderBuilder::from_header(&lying[4]).height(9).seal_pow();
+    let report = prefix[0].verify_report_with(&lying, &EvenOnlyAfterFork::default());
+    let rejected = Violation {
+        index: 4,
+        error: VerifyError::StateRejected { height: 5 },
+    };
+    assert!(report.violations.contains(&rejected));
+}
+
+#[test]
+fn bc_3_cant_verify_tampered_tip() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let b2 = b1.child(2);
+
+    // Point the tip somewhere else, and reseal it so that its work is still valid.
+    let tampered = HeaderBuilder::from_header(&b2).parent(10).seal_pow();
+    let chain = [b1.clone(), tampered];
+    assert!(!g.verify_sub_chain(&chain));
+    let error = g.verify_sub_chain_strict(&chain);
+    assert_eq!(error, Err(VerifyError::WrongParent { height: 2 }));
+
+    // The same goes for a tip at the wrong height, on top of a longer chain.
+    let b3 = b2.child(3);
+    let tampered = HeaderBuilder::from_header(&b3).height(2).seal_pow();
+    assert!(!g.verify_sub_chain(&[b1, b2, tampered]));
+}
+
+#[test]
+fn bc_3_cant_verify_tampered_link_after_first_block() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let b2 = HeaderBuilder::from_header(&b1.child(2))
+        .parent(10)
+        .seal_pow();
+    let b3 = b2.child(3);
+
+    let chain = [b1, b2, b3];
+    assert!(!g.verify_sub_chain(&chain));
+    assert!(!g.verify_report(&chain).is_valid());
+}
+
+#[test]
+fn bc_3_verify_child_one_block_at_a_time() {
+    let g = Header::genesis();
+    let b1 = g.child(4);
+    assert_eq!(g.verify_child(&b1), Ok(()));
+    assert_eq!(b1.verify_child(&b1.child(2)), Ok(()));
+
+    let orphan = HeaderBuilder::from_header(&b1.child(2))
+        .parent(10)
+        .seal_pow();
+    assert_eq!(
+        b1.verify_child(&orphan),
+        Err(VerifyError::WrongParent { height: 2 })
+    );
+    // A valid block is not a valid child of any header but its parent.
+    assert_eq!(
+        g.verify_child(&b1.child(2)),
+        Err(VerifyError::BadHeight {
+            expected: 1,
+            found: 2
+        })
+    );
+}
+
+#[test]
+fn bc_3_mined_genesis_meets_the_threshold() {
+    let g = Header::genesis_mined();
+    assert!(hash(&g) < threshold_for(g.difficulty));
+    // Only the nonce differs from the unmined genesis.
+    let nonce_cleared = HeaderBuilder::from_header(&g).consensus_digest(0).build();
+    assert_eq!(nonce_cleared, Header::genesis());
+    assert!(g.verify_sub_chain(&[g.child(1)]));
+
+    let mined = GenesisConfig {
+        mined: true,
+        ..GenesisConfig::default()
+    };
+    assert_eq!(g.verify_genesis(&mined), Ok(()));
+    let unmined = Header::genesis();
+    assert_eq!(unmined.verify_genesis(&GenesisConfig::default()), Ok(()));
+    // The unmined genesis may meet the threshold by luck, so pick a nonce that does not.
+    let unlucky = (0..)
+        .map(|nonce| {
+            HeaderBuilder::from_header(&unmined)
+                .consensus_digest(nonce)
+                .build()
+        })
+        .find(|header| hash(header) >= threshold_for(DIFFICULTY))
+        .unwrap();
+    assert_eq!(unlucky.verify_genesis(&GenesisConfig::default()), Ok(()));
+    let error = unlucky.verify_genesis(&mined);
+    assert_eq!(error, Err(VerifyError::InsufficientWork { height: 0 }));
+
+    let from_config = Header::genesis_from(&mined);
+    assert_eq!(from_config.verify_genesis(&mined), Ok(()));
+    let other_chain = GenesisConfig {
+        chain_id: 1,
+        ..mined.clone()
+    };
+    assert_ne!(from_config, Header::genesis_from(&other_chain));
+}
+
+#[test]
+fn bc_3_pow_config_sets_the_threshold_at_runtime() {
+    assert_eq!(PowConfig::default().threshold(), threshold_for(DIFFICULTY));
+    assert_eq!(PowConfig::default().difficulty(), DIFFICULTY);
+    assert_eq!(
+        Header::genesis_with(&PowConfig::default()),
+        Header::genesis()
+    );
+
+    for pow in [
+        PowConfig::with_difficulty(2),
+        PowConfig::with_difficulty(1_000),
+    ] {
+        let g = Header::genesis_with(&pow);
+        let b1 = g.child(1);
+        let b2 = b1.child(2);
+        assert!(hash(&b1) < pow.threshold() && hash(&b2) < pow.threshold());
+        assert!(g.verify_sub_chain(&[b1, b2]));
+        assert_eq!(pow.engine().threshold(), pow.threshold());
+    }
+}
+
+#[test]
+fn bc_3_pow_config_enforces_the_exact_threshold() {
+    // This threshold would round to difficulty 1, which accepts every hash.
+    assert_eq!(PowConfig::with_threshold(u64::MAX / 2 + 1000), None);
+    let pow = PowConfig::with_threshold(threshold_for(7)).unwrap();
+    assert_eq!(pow.difficulty(), 7);
+    assert_eq!(PowConfig::with_threshold(pow.threshold()), Some(pow));
+
+    // A block whose hash is between the configured threshold and difficulty 1's is refused.
+    let pow = PowConfig::with_difficulty(2);
+    let g = Header::genesis_with(&pow);
+    let b1 = g.child(1);
+    let too_easy = (0..)
+        .map(|nonce| HeaderBuilder::from_header(&b1).consensus_digest(nonce).build())
+        .find(|header| hash(header) >= pow.threshold())
+        .unwrap();
+    assert!(!g.verify_sub_chain(&[too_easy]));
+}
+
+#[test]
+fn bc_3_verify_block_stateless() {
+    let g = Header::genesis();
+    let b1 = g.child(4);
+    let b2 = b1.child(3);
+
+    // Knowing b1's hash and state is enough to check b2, without b1 or genesis.
+    let parent_hash = hash(&b1);
+    assert_eq!(verify_block_stateless(parent_hash, 4, &b2), Ok(7));
+
+    let wrong_state = verify_block_stateless(parent_hash, 5, &b2);
+    assert_eq!(wrong_state, Err(VerifyError::StateMismatch { height: 2 }));
+    let wrong_parent = verify_block_stateless(hash(&g), 4, &b2);
+    assert_eq!(wrong_parent, Err(VerifyError::WrongParent { height: 2 }));
+}
+
+#[test]
+fn bc_3_overflow_policy_decides_the_state() {
+    assert_eq!(
+        OverflowPolicy::Reject.apply(u64::MAX - 1, 1),
+        Some(u64::MAX)
+    );
+    assert_eq!(OverflowPolicy::Reject.apply(u64::MAX, 1), None);
+    assert_eq!(
+        OverflowPolicy::Saturate.apply(u64::MAX - 1, 5),
+        Some(u64::MAX)
+    );
+
+    let g = Header::genesis_from(&GenesisConfig {
+        initial_state: u64::MAX - 1,
+        ..GenesisConfig::default()
+    });
+    let overflow = VerifyError::StateOverflow { height: 1 };
+    assert_eq!(g.try_child(5, OverflowPolicy::Reject), Err(overflow));
+
+    let saturated = g.try_child(5, OverflowPolicy::Saturate).unwrap();
+    assert_eq!(saturated.state, u64::MAX);
+    let chain = [saturated];
+    assert_eq!(
+        g.verify_sub_chain_with_policy(&chain, OverflowPolicy::Saturate),
+        Ok(())
+    );
+    // Chains that reject overflows reject the saturated block too.
+    assert_eq!(
+        g.verify_sub_chain_with_policy(&chain, OverflowPolicy::Reject),
+        Err(overflow)
+    );
+    assert!(!g.verify_sub_chain(&chain));
+    assert!(!g.verify_report(&chain).is_valid());
+}
+
+#[test]
+fn bc_3_report_follows_the_overflow_policy() {
+    let g = Header::genesis_from(&GenesisConfig {
+        initial_state: u64::MAX - 1,
+        ..GenesisConfig::default()
+    });
+    let b1 = g.try_child(5, OverflowPolicy::Saturate).unwrap();
+    let b2 = b1.try_child(1, OverflowPolicy::Saturate).unwrap();
+    let chain = [b1, b2];
+
+    assert!(g
+        .verify_report_with_policy(&chain, OverflowPolicy::Saturate)
+        .is_valid());
+
+    let rejecting = g.verify_report_with_policy(&chain, OverflowPolicy::Reject);
+    let errors: Vec<VerifyError> = rejecting.violations.iter().map(|v| v.error).collect();
+    assert_eq!(
+        errors,
+        vec![
+            VerifyError::StateOverflow { height: 1 },
+            VerifyError::StateOverflow { height: 2 },
+        ]
+    );
+}
+
+#[test]
+fn bc_3_chain_follows_the_configured_overflow_policy() {
+    let config = GenesisConfig {
+        initial_state: u64::MAX - 1,
+        overflow: OverflowPolicy::Saturate,
+        ..GenesisConfig::default()
+    };
+    let mut saturating = Chain::from_config(&config);
+    assert_eq!(saturating.extend(5).state, u64::MAX);
+    let tip = saturating.best_header().clone();
+
+    let mut rejecting = Chain::new(Header::genesis_from(&config));
+    assert!(!rejecting.push(tip));
+    assert_eq!(rejecting.headers().len(), 1);
+}
+
+#[test]
+fn bc_3_verify_stream_of_any_length() {
+    let g = Header::genesis();
+    // The headers are mined one at a time as they are verified, and never collected.
+    let stream = std::iter::successors(Some(g.child(1)), |parent| Some(parent.child(1)));
+    let tip = g.verify_stream(stream.take(1_000)).unwrap();
+    assert_eq!(tip.height, 1_000);
+    assert_eq!(tip.state, 1_000);
+
+    // Verification carries on from the tip.
+    let more = [tip.child(2), tip.child(2).child(3)];
+    assert_eq!(tip.verify_stream(more.clone()).unwrap(), more[1]);
+    assert_eq!(g.verify_stream([]), Ok(g.clone()));
+}
+
+#[test]
+fn bc_3_verify_stream_agrees_with_slices() {
+    let g = Header::genesis();
+    let b1 = g.child(1);
+    let b2 = b1.child(2);
+    let mut b3 = b2.child(3);
+    b3.state = 7;
+    let chain = vec![b1, b2, b3];
+
+    assert_eq!(g.verify_stream(chain[..2].to_vec()), Ok(chain[1].clone()));
+    assert_eq!(
+        g.verify_stream(chain.clone()).map(|_| ()),
+        g.verify_sub_chain_strict(&chain)
+    );
+    assert!(g.verify_stream(chain).is_err());
+}
@@ -9,6 +9,7 @@ type Hash = u64;
 /// the block body. We are still storing the state in the header for now. This will change in an upcoming
 /// lesson as well.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     parent: Hash,
     height: u64,
@@ -87,8 +88,88 @@ impl Header {
     }
 }
 
+/// The default maximum total weight of all the extrinsics in a single block.
+pub const MAX_BLOCK_WEIGHT: u64 = 1_000;
+
+/// Every extrinsic has at least this weight, no matter how simple it is.
+pub const BASE_EXTRINSIC_WEIGHT: u64 = 1;
+
+/// The default maximum number of extrinsics in a single block.
+pub const MAX_EXTRINSICS_PER_BLOCK: usize = 500;
+
+/// The default maximum number of blocks that a verifier accepts in a single sub-chain.
+pub const MAX_CHAIN_LENGTH: usize = 10_000;
+
+/// The default maximum height that a verifier believes. At one block every ten seconds, it
+/// would take hundreds of thousands of years to get this far.
+pub const MAX_HEIGHT: u64 = 1 << 40;
+
+/// The weight of a single extrinsic.
+///
+/// Weight models the resources, mostly execution time, that it takes to apply an extrinsic.
+/// Blocks limit the total weight they may contain so that every node can execute them in
+/// a reasonable amount of time. In our adder, bigger numbers are modeled as being more
+/// expensive to add, so an extrinsic weighs the base weight plus one unit per significant bit.
+pub fn extrinsic_weight(extrinsic: &u64) -> u64 {
+    BASE_EXTRINSIC_WEIGHT + (u64::BITS - extrinsic.leading_zeros()) as u64
+}
+
+/// The total weight of a batch of extrinsics.
+pub fn total_weight(extrinsics: &[u64]) -> u64 {
+    extrinsics.iter().map(extrinsic_weight).sum()
+}
+
+/// Resource limits that every block must respect. Different chains may choose different
+/// limits, so they are passed around as a parameter rather than hard-coded.
+///
+/// The chain length and height limits protect verifiers rather than blocks. Whoever sends us a
+/// chain to verify may be an attacker, and should not be able to keep us busy for as long as
+/// they like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockLimits {
+    /// The maximum total weight of all the extrinsics in a single block.
+    pub max_block_weight: u64,
+    /// The maximum number of extrinsics in a single block.
+    pub max_extrinsics: usize,
+    /// The maximum number of blocks in a sub-chain that is verified in one go.
+    pub max_chain_length: usize,
+    /// The maximum height of any block.
+    pub max_height: u64,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        BlockLimits {
+            max_block_weight: MAX_BLOCK_WEIGHT,
+            max_extrinsics: MAX_EXTRINSICS_PER_BLOCK,
+            max_chain_length: MAX_CHAIN_LENGTH,
+            max_height: MAX_HEIGHT,
+        }
+    }
+}
+
+/// The reasons that a chain of blocks can be invalid. Each one that concerns a single block
+/// names the height that block should have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// The sub-chain has more blocks than the limits allow.
+    ChainTooLong { length: usize, max: usize },
+    /// The block claims a height beyond the limits.
+    HeightTooLarge { height: u64 },
+    /// The block has more extrinsics than the limits allow.
+    TooManyExtrinsics { height: u64, count: usize },
+    /// The block's extrinsics weigh more than the limits allow.
+    TooHeavy { height: u64 },
+    /// The block's header is not a valid child of its parent's header.
+    InvalidHeader { height: u64 },
+    /// The block's extrinsics root does not match its extrinsics.
+    WrongExtrinsicsRoot { height: u64 },
+}
+
 /// A complete Block is a header and the extrinsics.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block {
     pub(crate) header: Header,
     pub(crate) body: Vec<u64>,
@@ -109,40 +190,96 @@ impl Block {
 
     /// Create and return a valid child block.
     /// The extrinsics are batched now, so we need to execute each of them.
+    ///
+    /// The default block limits apply. See `child_with_limits` for what happens
+    /// when the batch is too heavy.
     pub fn child(&self, extrinsics: Vec<u64>) -> Self {
         // todo!("Exercise 6")
-        let mut new_extrinsics = extrinsics.clone();
-        println!("new_extrinsics : {:?}", new_extrinsics);
-        let mut length = extrinsics.len();
-        let mut new_header: Header;
-        
-        if length > 0 {
-            new_header = self.header.child(hash(&new_extrinsics), 0);
-        } else {
-            new_header = self.header.child(hash(&Vec::<u64>::new()), 0);
-        }
-
-        println!("new_extrinsics : {:?}", new_extrinsics);
+        self.child_with_limits(extrinsics, &BlockLimits::default())
+    }
 
-        Block { header: new_header, body: new_extrinsics }
+    /// Create and return a valid child block that respects the given limits.
+    ///
+    /// If the batch is too heavy to fit in a single block, it is truncated to the
+    /// longest prefix that fits. The extrinsics that were left out are not lost
+    /// forever. The author may include them in a later block.
+    pub fn child_with_limits(&self, mut extrinsics: Vec<u64>, limits: &BlockLimits) -> Self {
+        let mut weight = 0;
+        let fits = extrinsics
+            .iter()
+            .take(limits.max_extrinsics)
+            .take_while(|extrinsic| {
+                weight += extrinsic_weight(extrinsic);
+                weight <= limits.max_block_weight
+            })
+            .count();
+        extrinsics.truncate(fits);
+
+        let new_header = self.header.child(hash(&extrinsics), 0);
+        Block { header: new_header, body: extrinsics }
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     ///
     /// We need to verify the headers as well as execute all transactions and check the final state.
+    /// The default block limits apply.
     pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
         // todo!("Exercise 7");
+        self.verify_sub_chain_with_limits(chain, &BlockLimits::default())
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip,
+    /// and that none of them exceed the given limits.
+    pub fn verify_sub_chain_with_limits(&self, chain: &[Block], limits: &BlockLimits) -> bool {
+        self.verify_sub_chain_detailed(chain, limits).is_ok()
+    }
+
+    /// Verify the given blocks like `verify_sub_chain_with_limits`, but say which rule the
+    /// chain broke, and where, when it is not valid.
+    ///
+    /// The cheap limits are checked before any hashing, so an oversized chain or block is
+    /// refused without doing work in proportion to its size. A tip that claims an absurd height
+    /// refuses the whole chain before the first block is even looked at.
+    pub fn verify_sub_chain_detailed(
+        &self,
+        chain: &[Block],
+        limits: &BlockLimits,
+    ) -> Result<(), BlockError> {
+        if chain.len() > limits.max_chain_length {
+            return Err(BlockError::ChainTooLong {
+                length: chain.len(),
+                max: limits.max_chain_length,
+            });
+        }
+        if let Some(tip) = chain.last() {
+            if tip.header.height > limits.max_height {
+                let height = self.header.height + chain.len() as u64;
+                return Err(BlockError::HeightTooLarge { height });
+            }
+        }
+
         let mut parent = self;
         for block in chain {
+            let height = parent.header.height + 1;
+            if block.header.height > limits.max_height {
+                return Err(BlockError::HeightTooLarge { height });
+            }
+            if block.body.len() > limits.max_extrinsics {
+                let count = block.body.len();
+                return Err(BlockError::TooManyExtrinsics { height, count });
+            }
             if !parent.header.verify_child(&block.header) {
-                return false;
+                return Err(BlockError::InvalidHeader { height });
             }
-            if block.header.extrinsics_root != hash(&parent.body) {
-                return false;
+            if block.header.extrinsics_root != hash(&block.body) {
+                return Err(BlockError::WrongExtrinsicsRoot { height });
+            }
+            if total_weight(&block.body) > limits.max_block_weight {
+                return Err(BlockError::TooHeavy { height });
             }
             parent = block;
         }
-        true
+        Ok(())
     }
 }
 
@@ -282,3 +419,103 @@ fn bc_4_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&[b1]));
 }
+
+#[test]
+fn bc_4_extrinsic_weight() {
+    assert_eq!(extrinsic_weight(&0), BASE_EXTRINSIC_WEIGHT);
+    assert_eq!(extrinsic_weight(&1), BASE_EXTRINSIC_WEIGHT + 1);
+    assert_eq!(extrinsic_weight(&u64::MAX), BASE_EXTRINSIC_WEIGHT + 64);
+    assert_eq!(total_weight(&[0, 1, 2]), 3 * BASE_EXTRINSIC_WEIGHT + 3);
+}
+
+#[test]
+fn bc_4_child_truncates_overweight_batch() {
+    let limits = BlockLimits {
+        max_block_weight: 3 * extrinsic_weight(&7),
+        ..BlockLimits::default()
+    };
+    let g = Block::genesis();
+    let b1 = g.child_with_limits(vec![7, 7, 7, 7, 7], &limits);
+
+    assert_eq!(b1.body, vec![7, 7, 7]);
+    assert_eq!(b1.header.extrinsics_root, hash(&vec![7u64, 7, 7]));
+    assert!(g.verify_sub_chain_with_limits(&[b1], &limits));
+}
+
+#[test]
+fn bc_4_overweight_block_does_not_check() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![u64::MAX; 10]);
+    assert_eq!(b1.body.len(), 10);
+
+    let limits = BlockLimits {
+        max_block_weight: total_weight(&b1.body) - 1,
+        ..BlockLimits::default()
+    };
+    assert!(g.verify_sub_chain(std::slice::from_ref(&b1)));
+    assert!(!g.verify_sub_chain_with_limits(&[b1], &limits));
+}
+
+#[test]
+fn bc_4_limits_are_reported() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![1, 2, 3]);
+    let b2 = b1.child(vec![4]);
+    let chain = [b1, b2];
+    let limits = BlockLimits::default();
+    assert_eq!(g.verify_sub_chain_detailed(&chain, &limits), Ok(()));
+
+    let short = BlockLimits {
+        max_chain_length: 1,
+        ..limits
+    };
+    assert_eq!(
+        g.verify_sub_chain_detailed(&chain, &short),
+        Err(BlockError::ChainTooLong { length: 2, max: 1 })
+    );
+
+    let few = BlockLimits {
+        max_extrinsics: 2,
+        ..limits
+    };
+    assert_eq!(
+        g.verify_sub_chain_detailed(&chain, &few),
+        Err(BlockError::TooManyExtrinsics {
+            height: 1,
+            count: 3
+        })
+    );
+    // Authors leave out the extrinsics that do not fit.
+    assert_eq!(g.child_with_limits(vec![1, 2, 3], &few).body, vec![1, 2]);
+}
+
+#[test]
+fn bc_4_absurd_heights_are_refused_early() {
+    let g = Block::genesis();
+    let b1 = g.child(vec![1]);
+    let mut b2 = b1.child(vec![2]);
+    b2.header.height = u64::MAX;
+
+    // The tip's height is checked before anything else, so the verifier does not even look at
+    // the first block.
+    let mut bad_root = b1.clone();
+    bad_root.body = vec![];
+    let limits = BlockLimits::default();
+    assert_eq!(
+        g.verify_sub_chain_detailed(&[bad_root, b2.clone()], &limits),
+        Err(BlockError::HeightTooLarge { height: 2 })
+    );
+    assert_eq!(
+        g.verify_sub_chain_detailed(&[b1.clone(), b2], &limits),
+        Err(BlockError::HeightTooLarge { height: 2 })
+    );
+
+    let low = BlockLimits {
+        max_height: 0,
+        ..limits
+    };
+    assert_eq!(
+        g.verify_sub_chain_detailed(&[b1], &low),
+        Err(BlockError::HeightTooLarge { height: 1 })
+    );
+}
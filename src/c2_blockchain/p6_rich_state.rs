@@ -10,23 +10,72 @@
 //!
 //! This notion of state may sound familiar from our previous work on state machines. Indeed this
 //! naming coincidence foreshadows a key abstraction that we will make in a coming chapter.
+//! In fact, we already make it here. The blocks are generic over any state machine, and the
+//! sum and product tracker is just one of them.
 
 type Hash = u64;
+use std::marker::PhantomData;
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use super::p4_batched_extrinsics::MAX_EXTRINSICS_PER_BLOCK;
+use crate::c1_state_machine::{
+    AccountId, ChargesFee, Currency, CurrencyTransaction, Encode, PaidCurrency, PaidState, PaysFee,
+    Runtime, Sender, Signed, SignedCurrency, SignedExtrinsic, SignedState, StateMachine,
+    TransitionError, TryStateMachine,
+};
 use crate::hash;
 
 /// In this section we will use sum and product together to be our state. While this is only a doubling of state size
 /// remember that in real world blockchains, the state is often really really large.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct State {
     sum: u64,
     product: u64,
 }
 
+/// The state machine behind this section's blockchain. It is the adder from the previous sections,
+/// extended to also track the product.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SumAndProduct;
+
+impl StateMachine for SumAndProduct {
+    type State = State;
+    type Transition = u64;
+
+    fn next_state(starting_state: &State, t: &u64) -> State {
+        State {
+            sum: starting_state.sum + t,
+            product: starting_state.product * t,
+        }
+    }
+
+    /// The sum and product of a whole batch can be calculated directly, without making a new
+    /// state for every transition. The product is multiplied out starting from the current
+    /// one, just as it is one at a time. Multiplying the batch on its own first could overflow
+    /// even when the product is already zero, and stays zero.
+    fn apply_all(starting_state: &State, ts: &[u64]) -> State {
+        State {
+            sum: starting_state.sum + ts.iter().sum::<u64>(),
+            product: ts
+                .iter()
+                .fold(starting_state.product, |product, t| product * t),
+        }
+    }
+
+    fn human_name() -> String {
+        "Sum and Product".into()
+    }
+}
+
 /// The header no longer contains the state directly, but rather, it contains a hash of
 /// the complete state. This hash will allow block verifiers to cryptographically confirm
 /// that they got the same state as the author without having a complete copy of the
 /// author's state
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     parent: Hash,
     height: u64,
@@ -34,6 +83,11 @@ pub struct Header {
     /// Stores a cryptographic commitment, like a Merkle root or a hash to the complete
     /// post state.
     state_root: Hash,
+    /// The account that authored this block, and is paid the block reward. Blocks that do not
+    /// claim a reward, such as the genesis block, have no author.
+    author: Option<AccountId>,
+    /// The total fees and tips that the block's extrinsics paid, which the author claims.
+    fees: u64,
     consensus_digest: u64,
 }
 
@@ -46,7 +100,16 @@ pub struct Header {
 impl Header {
     /// Returns a new valid genesis header.
     fn genesis(genesis_state_root: Hash) -> Self {
-        todo!("Exercise 1")
+        // todo!("Exercise 1")
+        Header {
+            parent: 0,
+            height: 0,
+            extrinsics_root: hash(&Vec::<u64>::new()),
+            state_root: genesis_state_root,
+            author: None,
+            fees: 0,
+            consensus_digest: 0,
+        }
     }
 
     /// Create and return a valid child header.
@@ -54,25 +117,125 @@ impl Header {
     /// The state root is passed in similarly to how the complete state
     /// was in the previous section.
     fn child(&self, extrinsics_root: Hash, state_root: Hash) -> Self {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        Header {
+            parent: hash(self),
+            height: self.height + 1,
+            extrinsics_root,
+            state_root,
+            author: None,
+            fees: 0,
+            consensus_digest: 0,
+        }
     }
 
     /// Verify a single child header.
     fn verify_child(&self, child: &Header) -> bool {
-        todo!("Exercise 3")
+        // todo!("Exercise 3")
+        child.parent == hash(self) && child.height == self.height + 1
     }
 
     /// Verify that all the given headers form a valid chain from this header to the tip.
     fn verify_sub_chain(&self, chain: &[Header]) -> bool {
-        todo!("Exercise 4")
+        // todo!("Exercise 4")
+        let mut parent = self;
+        for child in chain {
+            if !parent.verify_child(child) {
+                return false;
+            }
+            parent = child;
+        }
+        true
     }
 }
 
 /// A complete Block is a header and the extrinsics.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct Block {
+///
+/// The extrinsics are the transitions of whatever state machine the chain runs.
+// Serde would otherwise require the state machine itself to be serializable, so we only bound
+// the extrinsics.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "SM::Transition: serde::Serialize",
+        deserialize = "SM::Transition: serde::de::DeserializeOwned"
+    ))
+)]
+pub struct GenericBlock<SM: StateMachine> {
     pub(crate) header: Header,
-    pub(crate) body: Vec<u64>,
+    pub(crate) body: Vec<SM::Transition>,
+}
+
+// Deriving these traits would require the state machine itself to implement them,
+// so we write them by hand instead.
+impl<SM: StateMachine> Clone for GenericBlock<SM>
+where
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        GenericBlock {
+            header: self.header.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+impl<SM: StateMachine> PartialEq for GenericBlock<SM>
+where
+    SM::Transition: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.body == other.body
+    }
+}
+
+impl<SM: StateMachine> Eq for GenericBlock<SM> where SM::Transition: Eq {}
+
+impl<SM: StateMachine> std::hash::Hash for GenericBlock<SM>
+where
+    SM::Transition: std::hash::Hash,
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.header.hash(state);
+        self.body.hash(state);
+    }
+}
+
+impl<SM: StateMachine> std::fmt::Debug for GenericBlock<SM>
+where
+    SM::Transition: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Block")
+            .field("header", &self.header)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+/// The blocks of this section's blockchain, which runs the sum and product state machine.
+pub type Block = GenericBlock<SumAndProduct>;
+
+/// The blocks of a blockchain that runs a currency instead of the adder.
+pub type CurrencyBlock = GenericBlock<Currency>;
+
+/// The blocks of a blockchain that runs a currency whose transactions must be signed.
+pub type SignedCurrencyBlock = GenericBlock<SignedCurrency>;
+
+/// A block whose extrinsics are calls to the runtime's pallets, as on a real chain. It sits
+/// next to `Block`, rather than replacing it, because the exercises in this section are built
+/// on the sum and product machine and its raw `u64` extrinsics.
+pub type RuntimeBlock = GenericBlock<Runtime>;
+
+/// Apply all of the given extrinsics, in order, to the given pre-state. Authors and verifiers
+/// both come through here, so both get the state machine's batch fast path.
+fn execute<SM>(pre_state: &SM::State, extrinsics: &[SM::Transition]) -> SM::State
+where
+    SM: StateMachine,
+    SM::State: Clone,
+{
+    SM::apply_all(pre_state, extrinsics)
 }
 
 /// Methods for creating and verifying blocks.
@@ -85,15 +248,29 @@ pub struct Block {
 ///
 /// These methods also differ from last time because you will need to
 /// calculate state roots to pass to the header-level methods.
-impl Block {
+impl<SM> GenericBlock<SM>
+where
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash,
+{
     /// Returns a new valid genesis block. By convention this block has no extrinsics.
-    pub fn genesis(genesis_state: &State) -> Self {
-        todo!("Exercise 5")
+    pub fn genesis(genesis_state: &SM::State) -> Self {
+        // todo!("Exercise 5")
+        GenericBlock {
+            header: Header::genesis(hash(genesis_state)),
+            body: Vec::new(),
+        }
     }
 
     /// Create and return a valid child block.
-    pub fn child(&self, pre_state: &State, extrinsics: Vec<u64>) -> Self {
-        todo!("Exercise 6")
+    pub fn child(&self, pre_state: &SM::State, extrinsics: Vec<SM::Transition>) -> Self {
+        // todo!("Exercise 6")
+        let post_state = execute::<SM>(pre_state, &extrinsics);
+        GenericBlock {
+            header: self.header.child(hash(&extrinsics), hash(&post_state)),
+            body: extrinsics,
+        }
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
@@ -101,8 +278,551 @@ impl Block {
     /// This time we need to validate the initial block itself by confirming that we
     /// have been given a valid pre-state. And we still need to verify the headers,
     /// execute all transactions, and check the final state.
-    pub fn verify_sub_chain(&self, pre_state: &State, chain: &[Block]) -> bool {
-        todo!("Exercise 7")
+    pub fn verify_sub_chain(&self, pre_state: &SM::State, chain: &[Self]) -> bool {
+        // todo!("Exercise 7")
+        self.verify_sub_chain_with(pre_state, chain, |state, block| {
+            Ok(execute::<SM>(state, &block.body))
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip, where
+    /// each block's post-state is whatever the given function makes of its pre-state. Blocks
+    /// for which the function fails are invalid. Every kind of chain checks its headers and
+    /// state roots the same way, and only executes its blocks differently.
+    fn verify_sub_chain_with<F>(
+        &self,
+        pre_state: &SM::State,
+        chain: &[Self],
+        mut execute_block: F,
+    ) -> bool
+    where
+        F: FnMut(&SM::State, &Self) -> Result<SM::State, TransitionError>,
+    {
+        if hash(pre_state) != self.header.state_root {
+            return false;
+        }
+
+        let mut parent = self;
+        let mut state = pre_state.clone();
+        for block in chain {
+            if !parent.header.verify_child(&block.header)
+                || block.header.extrinsics_root != hash(&block.body)
+            {
+                return false;
+            }
+            state = match execute_block(&state, block) {
+                Ok(post_state) => post_state,
+                Err(_) => return false,
+            };
+            if hash(&state) != block.header.state_root {
+                return false;
+            }
+            parent = block;
+        }
+        true
+    }
+}
+
+/// What a chain does with an extrinsic whose transition fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExtrinsicPolicy {
+    /// The failed extrinsic stays in the block, but does not change the state. This is what
+    /// Substrate and Ethereum do, so that the sender can still be charged a fee for it.
+    #[default]
+    Skip,
+    /// A block that contains a failing extrinsic is invalid. This is what Bitcoin does.
+    InvalidateBlock,
+}
+
+/// Apply all of the given extrinsics, in order, to the given pre-state, handling failed
+/// extrinsics according to the given policy.
+fn try_execute<SM>(
+    pre_state: &SM::State,
+    extrinsics: &[SM::Transition],
+    policy: ExtrinsicPolicy,
+) -> Result<SM::State, TransitionError>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+{
+    let mut state = pre_state.clone();
+    for t in extrinsics {
+        match (SM::try_next_state(&state, t), policy) {
+            (Ok(post_state), _) => state = post_state,
+            (Err(_), ExtrinsicPolicy::Skip) => {}
+            (Err(e), ExtrinsicPolicy::InvalidateBlock) => return Err(e),
+        }
+    }
+    Ok(state)
+}
+
+/// Methods for chains whose state machine reports failed transitions. These let the chain
+/// choose what a failed extrinsic means for the block that contains it.
+impl<SM> GenericBlock<SM>
+where
+    SM: TryStateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash,
+{
+    /// Create and return a valid child block under the given policy. Under
+    /// `ExtrinsicPolicy::InvalidateBlock`, no valid block contains a failing extrinsic, so
+    /// this returns the error of the first one instead.
+    pub fn try_child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        policy: ExtrinsicPolicy,
+    ) -> Result<Self, TransitionError> {
+        let post_state = try_execute::<SM>(pre_state, &extrinsics, policy)?;
+        Ok(GenericBlock {
+            header: self.header.child(hash(&extrinsics), hash(&post_state)),
+            body: extrinsics,
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip under
+    /// the given policy.
+    pub fn verify_sub_chain_with_policy(
+        &self,
+        pre_state: &SM::State,
+        chain: &[Self],
+        policy: ExtrinsicPolicy,
+    ) -> bool {
+        self.verify_sub_chain_with(pre_state, chain, |state, block| {
+            try_execute::<SM>(state, &block.body, policy)
+        })
+    }
+}
+
+/// Methods for chains whose extrinsics must be signed by their senders, or by the authority.
+///
+/// A badly signed extrinsic is not a transaction that happened to fail, like spending more
+/// than you have. Nobody can be charged a fee for it, because nobody signed it. So a block
+/// that contains one is invalid whatever the chain's `ExtrinsicPolicy` says.
+impl<SM> GenericBlock<Signed<SM>>
+where
+    SM: TryStateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash + Encode + Sender,
+{
+    /// Sign the given transitions with the given key, and return a valid child block that
+    /// contains them. Each one is signed with the key's next nonce at that point in the block.
+    /// A transition that fails does not use up its nonce, so the one after it gets the same
+    /// nonce. Fails with `TransitionError::NotPermitted` if the key is not the one that every
+    /// transition needs.
+    pub fn signed_child(
+        &self,
+        pre_state: &SignedState<SM::State>,
+        payloads: Vec<SM::Transition>,
+        signing_key: &SigningKey,
+        policy: ExtrinsicPolicy,
+    ) -> Result<Self, TransitionError> {
+        let signer = signing_key.verifying_key().to_bytes();
+        let mut state = pre_state.clone();
+        let mut extrinsics = Vec::new();
+        for payload in payloads {
+            let extrinsic = SignedExtrinsic::sign(payload, state.next_nonce(&signer), signing_key);
+            if !state.is_correctly_signed(&extrinsic) {
+                return Err(TransitionError::NotPermitted);
+            }
+            if let Ok(post_state) = Signed::<SM>::try_next_state(&state, &extrinsic) {
+                state = post_state;
+            }
+            extrinsics.push(extrinsic);
+        }
+        self.try_child(pre_state, extrinsics, policy)
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip under
+    /// the given policy, and that every extrinsic in them is signed by the key it needs. No
+    /// extrinsic changes the authority, so the pre-state's authority holds for every block.
+    pub fn verify_signed_sub_chain(
+        &self,
+        pre_state: &SignedState<SM::State>,
+        chain: &[Self],
+        policy: ExtrinsicPolicy,
+    ) -> bool {
+        chain
+            .iter()
+            .all(|block| block.body.iter().all(|t| pre_state.is_correctly_signed(t)))
+            && self.verify_sub_chain_with_policy(pre_state, chain, policy)
+    }
+}
+
+/// The default reward paid to the author of each block.
+pub const BLOCK_REWARD: u64 = 50;
+
+/// State machines with accounts that block authors can be paid into.
+pub trait RewardAuthor: StateMachine {
+    /// Credit the given reward to the given author's account.
+    fn reward_author(
+        state: &Self::State,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self::State, TransitionError>;
+}
+
+/// The reward is new money, much like the coinbase transaction of a Bitcoin block.
+impl RewardAuthor for Currency {
+    fn reward_author(
+        state: &Self::State,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self::State, TransitionError> {
+        let mint = CurrencyTransaction::Mint {
+            to: author,
+            amount: reward,
+        };
+        Currency::try_next_state(state, &mint)
+    }
+}
+
+impl RewardAuthor for PaidCurrency {
+    fn reward_author(
+        state: &Self::State,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self::State, TransitionError> {
+        Ok(PaidState {
+            balances: Currency::reward_author(&state.balances, author, reward)?,
+            nonces: state.nonces.clone(),
+        })
+    }
+}
+
+/// Nobody signs the reward, and it uses up no nonce. The chain pays it.
+impl<SM> RewardAuthor for Signed<SM>
+where
+    SM: RewardAuthor + TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: std::hash::Hash + Encode + Sender,
+{
+    fn reward_author(
+        state: &Self::State,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self::State, TransitionError> {
+        Ok(SignedState {
+            inner: SM::reward_author(&state.inner, author, reward)?,
+            ..state.clone()
+        })
+    }
+}
+
+/// Methods for chains that pay a reward to the author of every block.
+///
+/// The reward is credited after the block's extrinsics have been applied, so it is part of the
+/// state that the block's state root commits to. A block that pays its author the wrong amount,
+/// or pays the wrong account, has the wrong state root, and is invalid.
+impl<SM> GenericBlock<SM>
+where
+    SM: RewardAuthor,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash,
+{
+    /// Create and return a valid child block authored by the given account, which is paid the
+    /// given reward. Fails if the reward can not be paid, for example because the author's
+    /// balance would overflow.
+    pub fn rewarded_child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self, TransitionError> {
+        let post_state = SM::reward_author(&execute::<SM>(pre_state, &extrinsics), author, reward)?;
+        Ok(GenericBlock {
+            header: Header {
+                author: Some(author),
+                ..self.header.child(hash(&extrinsics), hash(&post_state))
+            },
+            body: extrinsics,
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip, and
+    /// that every block names an author and pays them exactly the given reward.
+    pub fn verify_sub_chain_with_reward(
+        &self,
+        pre_state: &SM::State,
+        chain: &[Self],
+        reward: u64,
+    ) -> bool {
+        self.verify_sub_chain_with(pre_state, chain, |state, block| {
+            let author = block.header.author.ok_or(TransitionError::Invalid)?;
+            SM::reward_author(&execute::<SM>(state, &block.body), author, reward)
+        })
+    }
+}
+
+/// Apply all of the given extrinsics, in order, to the given pre-state. Each one pays its fee
+/// before it is dispatched, and keeps paying it if it then fails. Those whose fee can not be
+/// paid are skipped. Returns the post-state, along with the total fees and tips that were paid.
+fn execute_paying<SM>(
+    pre_state: &SM::State,
+    extrinsics: &[SM::Transition],
+) -> Result<(SM::State, u64), TransitionError>
+where
+    SM: ChargesFee,
+    SM::State: Clone,
+    SM::Transition: PaysFee,
+{
+    let mut state = pre_state.clone();
+    let mut fees: u64 = 0;
+    for t in extrinsics {
+        let Ok(charged) = SM::charge_fee(&state, t) else {
+            continue;
+        };
+        fees = fees
+            .checked_add(t.fee())
+            .and_then(|fees| fees.checked_add(t.tip()))
+            .ok_or(TransitionError::Overflow)?;
+        state = SM::dispatch(&charged, t).unwrap_or(charged);
+    }
+    Ok((state, fees))
+}
+
+/// Methods for chains whose extrinsics pay fees.
+///
+/// The author claims the fees in the block's header, and is paid them along with the block
+/// reward. Only extrinsics whose senders could pay pay their fee, so verifiers execute the
+/// block to find out what was really paid, and reject blocks that claim anything else.
+impl<SM> GenericBlock<SM>
+where
+    SM: RewardAuthor + ChargesFee,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash + PaysFee,
+{
+    /// Create and return a valid child block authored by the given account, which is paid the
+    /// given reward and every fee that the extrinsics paid.
+    pub fn fee_paying_child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        author: AccountId,
+        reward: u64,
+    ) -> Result<Self, TransitionError> {
+        let (state, fees) = execute_paying::<SM>(pre_state, &extrinsics)?;
+        let payout = reward.checked_add(fees).ok_or(TransitionError::Overflow)?;
+        let post_state = SM::reward_author(&state, author, payout)?;
+        Ok(GenericBlock {
+            header: Header {
+                author: Some(author),
+                fees,
+                ..self.header.child(hash(&extrinsics), hash(&post_state))
+            },
+            body: extrinsics,
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip, that
+    /// every block claims exactly the fees its extrinsics paid, and that every block's author
+    /// is paid those fees and the given reward.
+    pub fn verify_sub_chain_with_fees(
+        &self,
+        pre_state: &SM::State,
+        chain: &[Self],
+        reward: u64,
+    ) -> bool {
+        self.verify_sub_chain_with(pre_state, chain, |state, block| {
+            let author = block.header.author.ok_or(TransitionError::Invalid)?;
+            let (post_state, fees) = execute_paying::<SM>(state, &block.body)?;
+            if fees != block.header.fees {
+                return Err(TransitionError::Invalid);
+            }
+            let payout = reward.checked_add(fees).ok_or(TransitionError::Overflow)?;
+            SM::reward_author(&post_state, author, payout)
+        })
+    }
+}
+
+/// The rules that a chain's blocks are checked against, beyond executing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidityRules {
+    /// What a failed extrinsic means for the block that contains it.
+    pub policy: ExtrinsicPolicy,
+    /// The most extrinsics that a block may contain.
+    pub max_extrinsics: usize,
+}
+
+impl Default for ValidityRules {
+    fn default() -> Self {
+        ValidityRules {
+            policy: ExtrinsicPolicy::default(),
+            max_extrinsics: MAX_EXTRINSICS_PER_BLOCK,
+        }
+    }
+}
+
+impl Encode for ValidityRules {
+    fn encode_to(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.policy as u8);
+        self.max_extrinsics.encode_to(bytes);
+    }
+}
+
+/// A state, along with the rules that blocks built on top of it must follow.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UpgradableState<S> {
+    /// The state of the wrapped machine.
+    pub inner: S,
+    /// The rules that the next block must follow.
+    pub rules: ValidityRules,
+    /// Rules that were set during the current block, and take over once it ends.
+    pub scheduled: Option<ValidityRules>,
+    /// How many upgrades have been applied, which is the index of the next one.
+    pub upgrades: u64,
+    /// The public key of whoever may change the rules.
+    pub authority: [u8; 32],
+}
+
+impl<S> UpgradableState<S> {
+    /// The given state, under the given rules, which the given key may change.
+    pub fn new(inner: S, rules: ValidityRules, authority: &VerifyingKey) -> Self {
+        UpgradableState {
+            inner,
+            rules,
+            scheduled: None,
+            upgrades: 0,
+            authority: authority.to_bytes(),
+        }
+    }
+}
+
+/// The extrinsics of a chain that can change its own rules.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpgradableExtrinsic<T> {
+    /// An ordinary transition of the wrapped machine.
+    Call(T),
+    /// Change the rules from the next block onward. Only valid when signed by the authority.
+    /// The signature's nonce counts upgrades, and must match the state's count, so a signed
+    /// upgrade is applied at most once, and an old one can not be replayed later to bring back
+    /// the rules it set.
+    SetValidityRule(SignedExtrinsic<ValidityRules>),
+}
+
+/// The given state machine, on a chain that keeps its validity rules in its state.
+///
+/// Nodes read the rules from the state, rather than having them built in. So the rules can be
+/// changed by an extrinsic, and every node starts following the new rules at the same block,
+/// without upgrading anything. Changing the rules in the middle of a block would mean that one
+/// block follows two sets of rules, so new rules only take over once the block that sets them
+/// ends.
+pub struct Upgradable<SM>(PhantomData<SM>);
+
+impl<SM> StateMachine for Upgradable<SM>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+{
+    type State = UpgradableState<SM::State>;
+    type Transition = UpgradableExtrinsic<SM::Transition>;
+
+    /// Transitions that fail leave the state unchanged.
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn human_name() -> String {
+        format!("Upgradable {}", SM::human_name())
+    }
+}
+
+impl<SM> TryStateMachine for Upgradable<SM>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+{
+    /// Setting the rules fails with `NotPermitted` unless the authority signed the new rules,
+    /// and with `Invalid` unless they are the next upgrade.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, TransitionError> {
+        match t {
+            UpgradableExtrinsic::Call(call) => Ok(UpgradableState {
+                inner: SM::try_next_state(&starting_state.inner, call)?,
+                ..starting_state.clone()
+            }),
+            UpgradableExtrinsic::SetValidityRule(rules) => {
+                let authority = VerifyingKey::from_bytes(&starting_state.authority)
+                    .map_err(|_| TransitionError::NotPermitted)?;
+                if !rules.is_signed_by(&authority) {
+                    return Err(TransitionError::NotPermitted);
+                }
+                if rules.nonce != starting_state.upgrades {
+                    return Err(TransitionError::Invalid);
+                }
+                Ok(UpgradableState {
+                    scheduled: Some(rules.payload),
+                    upgrades: starting_state.upgrades + 1,
+                    ..starting_state.clone()
+                })
+            }
+        }
+    }
+}
+
+/// Apply all of the given extrinsics under the rules of the given pre-state, then let any rules
+/// that they set take over.
+fn execute_upgradable<SM>(
+    pre_state: &UpgradableState<SM::State>,
+    extrinsics: &[UpgradableExtrinsic<SM::Transition>],
+) -> Result<UpgradableState<SM::State>, TransitionError>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+{
+    let rules = pre_state.rules;
+    if extrinsics.len() > rules.max_extrinsics {
+        return Err(TransitionError::Invalid);
+    }
+    let mut state = try_execute::<Upgradable<SM>>(pre_state, extrinsics, rules.policy)?;
+    if let Some(rules) = state.scheduled.take() {
+        state.rules = rules;
+    }
+    Ok(state)
+}
+
+/// Methods for chains that can change their own rules.
+///
+/// Verifiers check each block against the rules in its pre-state. So the blocks up to and
+/// including the one that changes the rules follow the old rules, and the blocks after it
+/// follow the new ones.
+impl<SM> GenericBlock<Upgradable<SM>>
+where
+    SM: TryStateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: std::hash::Hash,
+{
+    /// Create and return a valid child block. Fails if the extrinsics break the rules of the
+    /// pre-state.
+    pub fn upgradable_child(
+        &self,
+        pre_state: &UpgradableState<SM::State>,
+        extrinsics: Vec<UpgradableExtrinsic<SM::Transition>>,
+    ) -> Result<Self, TransitionError> {
+        let post_state = execute_upgradable::<SM>(pre_state, &extrinsics)?;
+        Ok(GenericBlock {
+            header: self.header.child(hash(&extrinsics), hash(&post_state)),
+            body: extrinsics,
+        })
+    }
+
+    /// Verify that all the given blocks form a valid chain from this block to the tip, and that
+    /// every block follows the rules that were in force when it was built.
+    pub fn verify_upgradable_sub_chain(
+        &self,
+        pre_state: &UpgradableState<SM::State>,
+        chain: &[Self],
+    ) -> bool {
+        self.verify_sub_chain_with(pre_state, chain, |state, block| {
+            execute_upgradable::<SM>(state, &block.body)
+        })
     }
 }
 
@@ -118,7 +838,13 @@ impl Block {
 /// As before, you do not need the entire parent block to do this. You only need the header.
 /// You do, however, now need a pre-state as you have throughout much of this section.
 fn build_invalid_child_block_with_valid_header(parent: &Header, pre_state: &State) -> Block {
-    todo!("Exercise 8")
+    // todo!("Exercise 8")
+    // The extrinsic changes the sum, but the header claims the state did not change.
+    let body = vec![1];
+    Block {
+        header: parent.child(hash(&body), hash(pre_state)),
+        body,
+    }
 }
 
 #[test]
@@ -227,6 +953,8 @@ fn bc_6_invalid_header_doesnt_check() {
         height: 100,
         extrinsics_root: 0,
         state_root: hash(&(State { sum: 0, product: 0 })),
+        author: None,
+        fees: 0,
         consensus_digest: 0,
     };
 
@@ -268,3 +996,647 @@ fn bc_6_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&state, &[b1]));
 }
+
+#[test]
+fn bc_6_blocks_work_with_any_state_machine() {
+    use crate::c1_state_machine::LightSwitch;
+
+    let g = GenericBlock::<LightSwitch>::genesis(&false);
+    let b1 = g.child(&false, vec![(), ()]);
+    let b2 = b1.child(&false, vec![()]);
+    assert_eq!(b2.header.state_root, hash(&true));
+    assert!(g.verify_sub_chain(&false, &[b1.clone(), b2]));
+
+    // A block that was executed from the wrong pre-state does not check.
+    let bad_b2 = b1.child(&true, vec![()]);
+    assert!(!g.verify_sub_chain(&false, &[b1, bad_b2]));
+}
+
+#[test]
+fn bc_6_currency_chain() {
+    use crate::c1_state_machine::{Balances, CurrencyTransaction::*, User::*};
+
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let g = CurrencyBlock::genesis(&genesis_state);
+    let b1 = g.child(
+        &genesis_state,
+        vec![
+            Transfer {
+                from: Alice,
+                to: Bob,
+                amount: 60,
+            },
+            Mint {
+                to: Charlie,
+                amount: 7,
+            },
+        ],
+    );
+    let state_1 = Balances::from([(Alice, 40), (Bob, 60), (Charlie, 7)]);
+    assert_eq!(b1.header.state_root, hash(&state_1));
+
+    // Bob can not afford this, so it has no effect, but the block is still valid.
+    let b2 = b1.child(
+        &state_1,
+        vec![Transfer {
+            from: Bob,
+            to: Alice,
+            amount: 61,
+        }],
+    );
+    assert_eq!(b2.header.state_root, hash(&state_1));
+    assert!(g.verify_sub_chain(&genesis_state, &[b1, b2]));
+}
+
+#[test]
+fn bc_6_composed_chain() {
+    use crate::c1_state_machine::{
+        Balances, Currency, CurrencyTransaction, OneOf2, Staking, StakingLedger, StakingTransition,
+        User::*,
+    };
+    use crate::c3_consensus::ConsensusAuthority;
+
+    let genesis_state = (Balances::from([(Alice, 100)]), StakingLedger::default());
+    let g = GenericBlock::<(Currency, Staking)>::genesis(&genesis_state);
+    let b1 = g.child(
+        &genesis_state,
+        vec![
+            OneOf2::First(CurrencyTransaction::Transfer {
+                from: Alice,
+                to: Bob,
+                amount: 10,
+            }),
+            OneOf2::Second(StakingTransition::Bond {
+                who: ConsensusAuthority::Alice,
+                amount: 50,
+            }),
+        ],
+    );
+
+    let state_1 = (
+        Balances::from([(Alice, 90), (Bob, 10)]),
+        StakingLedger::new([(ConsensusAuthority::Alice, 50)].into()),
+    );
+    assert_eq!(b1.header.state_root, hash(&state_1));
+    assert!(g.verify_sub_chain(&genesis_state, &[b1]));
+}
+
+#[test]
+fn bc_6_failed_extrinsic_policy() {
+    use crate::c1_state_machine::{Balances, CurrencyTransaction::*, User::*};
+
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let g = CurrencyBlock::genesis(&genesis_state);
+    let body = vec![
+        Transfer {
+            from: Alice,
+            to: Bob,
+            amount: 60,
+        },
+        // Bob can not afford this.
+        Transfer {
+            from: Bob,
+            to: Charlie,
+            amount: 61,
+        },
+    ];
+
+    // When failed extrinsics are skipped, the block is valid, and only the first one applies.
+    let b1 = g
+        .try_child(&genesis_state, body.clone(), ExtrinsicPolicy::Skip)
+        .unwrap();
+    assert_eq!(
+        b1.header.state_root,
+        hash(&Balances::from([(Alice, 40), (Bob, 60)]))
+    );
+    assert!(g.verify_sub_chain_with_policy(
+        &genesis_state,
+        std::slice::from_ref(&b1),
+        ExtrinsicPolicy::Skip
+    ));
+
+    // A chain that invalidates such blocks can not build one, and rejects the one built above.
+    assert_eq!(
+        g.try_child(&genesis_state, body, ExtrinsicPolicy::InvalidateBlock),
+        Err(TransitionError::InsufficientFunds)
+    );
+    assert!(!g.verify_sub_chain_with_policy(
+        &genesis_state,
+        &[b1],
+        ExtrinsicPolicy::InvalidateBlock
+    ));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn bc_6_chain_survives_json() {
+    use crate::c1_state_machine::{
+        Balances, Currency, CurrencyTransaction, OneOf2, Staking, StakingLedger, StakingTransition,
+        User::*,
+    };
+    use crate::c3_consensus::ConsensusAuthority;
+
+    let genesis_state = (Balances::from([(Alice, 100)]), StakingLedger::default());
+    let g = GenericBlock::<(Currency, Staking)>::genesis(&genesis_state);
+    let b1 = g.child(
+        &genesis_state,
+        vec![
+            OneOf2::First(CurrencyTransaction::Mint {
+                to: Bob,
+                amount: 10,
+            }),
+            OneOf2::Second(StakingTransition::Bond {
+                who: ConsensusAuthority::Bob,
+                amount: 5,
+            }),
+        ],
+    );
+    let chain = vec![g, b1];
+
+    let json = serde_json::to_string(&chain).unwrap();
+    let reloaded: Vec<GenericBlock<(Currency, Staking)>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(reloaded, chain);
+    assert!(reloaded[0].verify_sub_chain(&genesis_state, &reloaded[1..]));
+
+    let json = serde_json::to_string(&genesis_state).unwrap();
+    let state: (Balances, StakingLedger) = serde_json::from_str(&json).unwrap();
+    assert_eq!(state, genesis_state);
+}
+
+#[test]
+fn bc_6_signed_chain() {
+    use crate::c1_state_machine::{Balances, CurrencyTransaction::*, User::*};
+
+    let genesis_state = SignedState::new(Balances::from([(Alice, 100)]), &Charlie.public_key());
+    let g = SignedCurrencyBlock::genesis(&genesis_state);
+    let transfer = Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 60,
+    };
+    // The second transfer fails, so the third one gets its nonce.
+    let b1 = g
+        .signed_child(
+            &genesis_state,
+            vec![
+                transfer.clone(),
+                transfer.clone(),
+                Burn {
+                    from: Alice,
+                    amount: 40,
+                },
+            ],
+            &Alice.signing_key(),
+            ExtrinsicPolicy::Skip,
+        )
+        .unwrap();
+    assert_eq!(
+        b1.body.iter().map(|t| t.nonce).collect::<Vec<_>>(),
+        vec![0, 1, 1]
+    );
+    let post_state = SignedState {
+        inner: Balances::from([(Bob, 60)]),
+        nonces: [(Alice.public_key().to_bytes(), 2)].into(),
+        ..genesis_state.clone()
+    };
+    assert_eq!(b1.header.state_root, hash(&post_state));
+    assert!(g.verify_signed_sub_chain(
+        &genesis_state,
+        std::slice::from_ref(&b1),
+        ExtrinsicPolicy::Skip
+    ));
+
+    // Including the same signed transfer again does nothing, in this block or a later one. So
+    // the block keeps the same state root.
+    let mut replayed = b1.clone();
+    replayed.body.push(b1.body[0].clone());
+    replayed.header.extrinsics_root = hash(&replayed.body);
+    assert!(g.verify_signed_sub_chain(&genesis_state, &[replayed], ExtrinsicPolicy::Skip));
+    let b2 = b1
+        .try_child(&post_state, vec![b1.body[0].clone()], ExtrinsicPolicy::Skip)
+        .unwrap();
+    assert_eq!(b2.header.state_root, hash(&post_state));
+    assert_eq!(
+        b1.try_child(
+            &post_state,
+            vec![b1.body[0].clone()],
+            ExtrinsicPolicy::InvalidateBlock
+        ),
+        Err(TransitionError::Invalid)
+    );
+
+    // Bob can not spend Alice's money, nor mint his own.
+    let forged = g.signed_child(
+        &genesis_state,
+        vec![transfer.clone()],
+        &Bob.signing_key(),
+        ExtrinsicPolicy::Skip,
+    );
+    assert_eq!(forged, Err(TransitionError::NotPermitted));
+    let mint = Mint { to: Bob, amount: 5 };
+    let forged = g.signed_child(
+        &genesis_state,
+        vec![mint.clone()],
+        &Bob.signing_key(),
+        ExtrinsicPolicy::Skip,
+    );
+    assert_eq!(forged, Err(TransitionError::NotPermitted));
+    let minted = g.signed_child(
+        &genesis_state,
+        vec![mint],
+        &Charlie.signing_key(),
+        ExtrinsicPolicy::Skip,
+    );
+    assert!(minted.is_ok());
+}
+
+#[test]
+fn bc_6_badly_signed_blocks_are_invalid_under_any_policy() {
+    use crate::c1_state_machine::{Balances, CurrencyTransaction::*, User::*};
+
+    let genesis_state = SignedState::new(Balances::from([(Alice, 100)]), &Charlie.public_key());
+    let g = SignedCurrencyBlock::genesis(&genesis_state);
+    let transfer = Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 60,
+    };
+
+    // Skipping the forged extrinsic gives a block with a correct state root, but it is still
+    // rejected.
+    let forged = SignedExtrinsic::sign(transfer, 0, &Bob.signing_key());
+    let b1 = g
+        .try_child(&genesis_state, vec![forged], ExtrinsicPolicy::Skip)
+        .unwrap();
+    assert_eq!(b1.header.state_root, hash(&genesis_state));
+    assert!(g.verify_sub_chain_with_policy(
+        &genesis_state,
+        std::slice::from_ref(&b1),
+        ExtrinsicPolicy::Skip
+    ));
+    for policy in [ExtrinsicPolicy::Skip, ExtrinsicPolicy::InvalidateBlock] {
+        assert!(!g.verify_signed_sub_chain(&genesis_state, std::slice::from_ref(&b1), policy));
+    }
+}
+
+#[test]
+fn bc_6_apply_all_matches_one_at_a_time() {
+    let start = State { sum: 3, product: 2 };
+    let batches: [&[u64]; 4] = [&[], &[5], &[1, 2, 3, 4], &[7, 0, 9]];
+    for batch in batches {
+        let one_at_a_time = batch.iter().fold(start.clone(), |state, t| {
+            SumAndProduct::next_state(&state, t)
+        });
+        assert_eq!(SumAndProduct::apply_all(&start, batch), one_at_a_time);
+    }
+
+    // Once the product is zero, it stays zero, however large the numbers that follow.
+    let big = 1 << 32;
+    let batches: [(u64, &[u64]); 3] = [(0, &[big, big]), (2, &[big, 0, big]), (0, &[0])];
+    for (product, batch) in batches {
+        let start = State { sum: 3, product };
+        let one_at_a_time = batch.iter().fold(start.clone(), |state, t| {
+            SumAndProduct::next_state(&state, t)
+        });
+        assert_eq!(one_at_a_time.product, 0);
+        assert_eq!(SumAndProduct::apply_all(&start, batch), one_at_a_time);
+    }
+
+    // Authoring and verifying both use the fast path, and agree with each other.
+    let genesis_state = State { sum: 0, product: 1 };
+    let g = Block::genesis(&genesis_state);
+    let b1 = g.child(&genesis_state, vec![2, 3, 4]);
+    let state_1 = State {
+        sum: 9,
+        product: 24,
+    };
+    assert_eq!(b1.header.state_root, hash(&state_1));
+    assert!(g.verify_sub_chain(&genesis_state, &[b1]));
+}
+
+#[test]
+fn bc_6_authors_are_rewarded() {
+    use crate::c1_state_machine::{Balances, CurrencyTransaction::*, User::*};
+
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let g = CurrencyBlock::genesis(&genesis_state);
+    let transfer = Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 60,
+    };
+    let b1 = g
+        .rewarded_child(&genesis_state, vec![transfer], Charlie, BLOCK_REWARD)
+        .unwrap();
+    assert_eq!(b1.header.author, Some(Charlie));
+    let state_1 = Balances::from([(Alice, 40), (Bob, 60), (Charlie, BLOCK_REWARD)]);
+    assert_eq!(b1.header.state_root, hash(&state_1));
+
+    let b2 = b1
+        .rewarded_child(&state_1, vec![], Charlie, BLOCK_REWARD)
+        .unwrap();
+    let chain = [b1, b2];
+    assert!(g.verify_sub_chain_with_reward(&genesis_state, &chain, BLOCK_REWARD));
+
+    // Every block must pay exactly the chain's reward.
+    assert!(!g.verify_sub_chain_with_reward(&genesis_state, &chain, BLOCK_REWARD + 1));
+}
+
+#[test]
+fn bc_6_wrong_rewards_are_invalid() {
+    use crate::c1_state_machine::{Balances, User::*};
+
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let g = CurrencyBlock::genesis(&genesis_state);
+
+    // Bob pays himself more than the reward.
+    let greedy = g
+        .rewarded_child(&genesis_state, vec![], Bob, BLOCK_REWARD * 2)
+        .unwrap();
+    let chain = std::slice::from_ref(&greedy);
+    assert!(!g.verify_sub_chain_with_reward(&genesis_state, chain, BLOCK_REWARD));
+
+    // Bob names Alice as the author, but pays himself.
+    let mut stolen = g
+        .rewarded_child(&genesis_state, vec![], Bob, BLOCK_REWARD)
+        .unwrap();
+    stolen.header.author = Some(Alice);
+    let chain = std::slice::from_ref(&stolen);
+    assert!(!g.verify_sub_chain_with_reward(&genesis_state, chain, BLOCK_REWARD));
+
+    // A block without an author pays no reward, so it is invalid on a rewarding chain.
+    let unrewarded = g.child(&genesis_state, vec![]);
+    assert!(!g.verify_sub_chain_with_reward(&genesis_state, &[unrewarded], BLOCK_REWARD));
+
+    // A reward that would overflow the author's balance can not be paid.
+    let rich = Balances::from([(Alice, u64::MAX)]);
+    let g = CurrencyBlock::genesis(&rich);
+    let overflow = g.rewarded_child(&rich, vec![], Alice, BLOCK_REWARD);
+    assert_eq!(overflow, Err(TransitionError::Overflow));
+}
+
+#[cfg(test)]
+fn paid_transfer(
+    from: AccountId,
+    to: AccountId,
+    amount: u64,
+    fee: u64,
+    nonce: u64,
+) -> crate::c1_state_machine::Paid<CurrencyTransaction> {
+    crate::c1_state_machine::Paid {
+        call: CurrencyTransaction::Transfer { from, to, amount },
+        fee,
+        tip: None,
+        nonce,
+    }
+}
+
+#[test]
+fn bc_6_fees_go_to_the_author() {
+    use crate::c1_state_machine::{Balances, User::*};
+    use std::collections::BTreeMap;
+
+    let genesis_state = PaidState::new(Balances::from([(Alice, 100), (Bob, 10)]));
+    let g = GenericBlock::<PaidCurrency>::genesis(&genesis_state);
+    let body = vec![
+        paid_transfer(Alice, Bob, 50, 3, 0),
+        // Bob can not afford the fee on top of the transfer, so the transfer fails, but he
+        // still pays the fee, and uses up his nonce.
+        paid_transfer(Bob, Alice, 60, 1, 0),
+        paid_transfer(Bob, Alice, 5, 2, 1),
+        // Bob can not afford this fee at all, so this pays nothing.
+        paid_transfer(Bob, Alice, 1, 1_000, 2),
+    ];
+    let b1 = g
+        .fee_paying_child(&genesis_state, body, Charlie, BLOCK_REWARD)
+        .unwrap();
+    assert_eq!(b1.header.fees, 6);
+    let state_1 = PaidState {
+        balances: Balances::from([(Alice, 52), (Bob, 52), (Charlie, BLOCK_REWARD + 6)]),
+        nonces: BTreeMap::from([(Alice, 1), (Bob, 2)]),
+    };
+    assert_eq!(b1.header.state_root, hash(&state_1));
+    let chain = std::slice::from_ref(&b1);
+    assert!(g.verify_sub_chain_with_fees(&genesis_state, chain, BLOCK_REWARD));
+}
+
+#[test]
+fn bc_6_blocks_must_claim_the_fees_paid() {
+    use crate::c1_state_machine::{Balances, User::*};
+    use std::collections::BTreeMap;
+
+    let genesis_state = PaidState::new(Balances::from([(Alice, 100)]));
+    let g = GenericBlock::<PaidCurrency>::genesis(&genesis_state);
+    let body = vec![paid_transfer(Alice, Bob, 50, 3, 0)];
+    let b1 = g
+        .fee_paying_child(&genesis_state, body, Charlie, BLOCK_REWARD)
+        .unwrap();
+
+    // Charlie claims more than was paid, and pays himself accordingly.
+    let state_1 = PaidState {
+        balances: Balances::from([(Alice, 47), (Bob, 50), (Charlie, BLOCK_REWARD + 4)]),
+        nonces: BTreeMap::from([(Alice, 1)]),
+    };
+    let mut greedy = b1.clone();
+    greedy.header.fees = 4;
+    greedy.header.state_root = hash(&state_1);
+    let chain = std::slice::from_ref(&greedy);
+    assert!(!g.verify_sub_chain_with_fees(&genesis_state, chain, BLOCK_REWARD));
+
+    // Charlie claims what was paid, but pays himself more.
+    let mut overpaid = b1;
+    overpaid.header.state_root = hash(&state_1);
+    let chain = std::slice::from_ref(&overpaid);
+    assert!(!g.verify_sub_chain_with_fees(&genesis_state, chain, BLOCK_REWARD));
+}
+
+#[test]
+fn bc_6_verifiers_rerun_programs_with_their_gas_limits() {
+    use crate::c1_state_machine::{Instruction::*, Program, Storage, Vm};
+
+    let genesis_state = Storage::new();
+    let g = GenericBlock::<Vm>::genesis(&genesis_state);
+    let store = |gas_limit| Program {
+        code: vec![Push(1), Push(42), Store],
+        gas_limit,
+    };
+    let b1 = g.child(&genesis_state, vec![store(12), store(11)]);
+    assert_eq!(b1.header.state_root, hash(&Storage::from([(1, 42)])));
+    assert!(g.verify_sub_chain(&genesis_state, std::slice::from_ref(&b1)));
+
+    // An author who ignores the gas limit gets a different post-state, which nobody accepts.
+    let mut unmetered = b1;
+    unmetered.body = vec![store(11)];
+    unmetered.header.extrinsics_root = hash(&unmetered.body);
+    assert!(!g.verify_sub_chain(&genesis_state, &[unmetered]));
+}
+
+#[cfg(test)]
+fn set_rules(
+    rules: ValidityRules,
+    index: u64,
+    signer: crate::c1_state_machine::User,
+) -> UpgradableExtrinsic<CurrencyTransaction> {
+    UpgradableExtrinsic::SetValidityRule(SignedExtrinsic::sign(rules, index, &signer.signing_key()))
+}
+
+#[test]
+fn bc_6_new_rules_apply_from_the_next_block() {
+    use crate::c1_state_machine::{Balances, User::*};
+    use UpgradableExtrinsic::Call;
+
+    let genesis_state = UpgradableState::new(
+        Balances::from([(Alice, 100)]),
+        ValidityRules::default(),
+        &Charlie.public_key(),
+    );
+    let g = GenericBlock::<Upgradable<Currency>>::genesis(&genesis_state);
+    let strict = ValidityRules {
+        policy: ExtrinsicPolicy::InvalidateBlock,
+        max_extrinsics: 2,
+    };
+    let overspend = Call(CurrencyTransaction::Transfer {
+        from: Bob,
+        to: Alice,
+        amount: 1,
+    });
+    let transfer = Call(CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount: 10,
+    });
+
+    // The block that changes the rules still follows the old ones, even after the change.
+    let body = vec![
+        overspend.clone(),
+        set_rules(strict, 0, Charlie),
+        overspend.clone(),
+    ];
+    let b1 = g.upgradable_child(&genesis_state, body).unwrap();
+    let state_1 = execute_upgradable::<Currency>(&genesis_state, &b1.body).unwrap();
+    assert_eq!(state_1.rules, strict);
+    assert_eq!(state_1.scheduled, None);
+
+    // The blocks after it follow the new ones.
+    let b2 = b1
+        .upgradable_child(&state_1, vec![transfer.clone()])
+        .unwrap();
+    let chain = [b1.clone(), b2];
+    assert!(g.verify_upgradable_sub_chain(&genesis_state, &chain));
+    let failing = vec![overspend, transfer.clone()];
+    assert_eq!(
+        b1.upgradable_child(&state_1, failing.clone()),
+        Err(TransitionError::InsufficientFunds)
+    );
+    let too_long = vec![transfer.clone(), transfer.clone(), transfer];
+    assert_eq!(
+        b1.upgradable_child(&state_1, too_long),
+        Err(TransitionError::Invalid)
+    );
+
+    // A block built under the old rules is no longer valid.
+    let lax = b1
+        .try_child(&state_1, failing, ExtrinsicPolicy::Skip)
+        .unwrap();
+    assert!(!g.verify_upgradable_sub_chain(&genesis_state, &[b1, lax]));
+}
+
+#[test]
+fn bc_6_only_the_authority_sets_rules() {
+    use crate::c1_state_machine::{Balances, User::*};
+
+    let genesis_state = UpgradableState::new(
+        Balances::new(),
+        ValidityRules::default(),
+        &Charlie.public_key(),
+    );
+    let strict = ValidityRules {
+        policy: ExtrinsicPolicy::InvalidateBlock,
+        ..ValidityRules::default()
+    };
+    let forged = set_rules(strict, 0, Bob);
+    assert_eq!(
+        Upgradable::<Currency>::try_next_state(&genesis_state, &forged),
+        Err(TransitionError::NotPermitted)
+    );
+    let g = GenericBlock::<Upgradable<Currency>>::genesis(&genesis_state);
+    let b1 = g.upgradable_child(&genesis_state, vec![forged]).unwrap();
+    assert_eq!(b1.header.state_root, hash(&genesis_state));
+}
+
+#[test]
+fn bc_6_old_rule_changes_can_not_be_replayed() {
+    use crate::c1_state_machine::{Balances, User::*};
+
+    let genesis_state = UpgradableState::new(
+        Balances::new(),
+        ValidityRules::default(),
+        &Charlie.public_key(),
+    );
+    let strict = ValidityRules {
+        policy: ExtrinsicPolicy::InvalidateBlock,
+        ..ValidityRules::default()
+    };
+    let first = set_rules(strict, 0, Charlie);
+    let second = set_rules(ValidityRules::default(), 1, Charlie);
+    let state = Upgradable::<Currency>::apply_all(&genesis_state, std::slice::from_ref(&first));
+    assert_eq!(state.upgrades, 1);
+    assert_eq!(
+        Upgradable::<Currency>::try_next_state(&state, &first),
+        Err(TransitionError::Invalid)
+    );
+    let skipped = set_rules(strict, 2, Charlie);
+    assert_eq!(
+        Upgradable::<Currency>::try_next_state(&state, &skipped),
+        Err(TransitionError::Invalid)
+    );
+
+    // Once the rules have moved on, replaying the first change can not bring it back.
+    let state = Upgradable::<Currency>::apply_all(&state, &[second]);
+    assert_eq!(state.upgrades, 2);
+    assert_eq!(Upgradable::<Currency>::next_state(&state, &first), state);
+}
+
+#[test]
+fn bc_6_runtime_chain() {
+    use crate::c1_state_machine::{
+        Balances, Call, ChainParameters, GovernanceState, RuntimeState, StakingLedger,
+        StakingTransition, SystemCall, User::*,
+    };
+    use crate::c3_consensus::ConsensusAuthority;
+
+    let genesis_state = RuntimeState {
+        balances: Balances::from([(Alice, 100)]),
+        staking: StakingLedger::default(),
+        governance: GovernanceState::new(
+            Balances::new(),
+            ChainParameters {
+                pow_threshold: 1000,
+                max_extrinsics: 100,
+            },
+        ),
+    };
+    let g = RuntimeBlock::genesis(&genesis_state);
+    let body = vec![
+        Call::System(SystemCall::Remark(b"first!".to_vec())),
+        Call::Balances(CurrencyTransaction::Transfer {
+            from: Alice,
+            to: Bob,
+            amount: 10,
+        }),
+        Call::Staking(StakingTransition::Bond {
+            who: ConsensusAuthority::Alice,
+            amount: 50,
+        }),
+    ];
+    let b1 = g.child(&genesis_state, body);
+
+    // Alice's stake comes out of her balance.
+    let state_1 = RuntimeState {
+        balances: Balances::from([(Alice, 40), (Bob, 10)]),
+        staking: StakingLedger::new([(ConsensusAuthority::Alice, 50)].into()),
+        ..genesis_state.clone()
+    };
+    assert_eq!(b1.header.state_root, hash(&state_1));
+    assert!(g.verify_sub_chain(&genesis_state, &[b1]));
+}
@@ -8,10 +8,33 @@ mod p3_poa; // exercise: dictator is a special case of poa. Create dictator in t
 mod p4_even_only;
 mod p5_interleave;
 mod p6_forking;
+mod p7_signed_poa;
+mod p8_pos;
+mod p9_hybrid;
+mod p10_babe;
+mod p11_equivocation;
+mod p12_authority_changes;
+#[cfg(feature = "substrate")]
+mod p13_substrate;
 
 // Re-export some individual consensus engines so they can be be re-used in the Client chapter.
-pub use p1_pow::Pow;
-pub use p3_poa::SimplePoa;
+pub use p1_pow::{moderate_difficulty_pow, trivial_always_valid_pow, work_hash, Pow};
+pub use p3_poa::{PoaRoundRobinByHeight, SimplePoa};
+pub use p5_interleave::PowOrPoaDigest;
+pub use p6_forking::{pow_to_poa, Forked};
+pub use p7_signed_poa::{SignatureDigest, SignedPoa};
+pub use p8_pos::SimplePos;
+pub use p9_hybrid::{DigestItem, DigestLog, PowAndPoa};
+pub use p10_babe::{BabeDigest, BabeLite};
+pub use p11_equivocation::EquivocationProof;
+pub use p12_authority_changes::AuthoritySetTracker;
+#[cfg(feature = "substrate")]
+pub use p13_substrate::{
+    from_h256, to_h256, ConsensusEngineId, SubstrateDigest, SubstrateDigestItem, SubstrateError,
+    SubstrateHeader, ENGINE_ID, H256,
+};
+
+use crate::hex::Hex;
 
 type Hash = u64;
 
@@ -24,13 +47,72 @@ type Hash = u64;
 /// which means they can operate entirely at the header level. They never need to touch
 /// the complete blocks.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header<Digest> {
-    parent: Hash,
-    height: u64,
-    state_root: Hash,
-    extrinsics_root: Hash,
-    consensus_digest: Digest,
+    pub(crate) parent: Hash,
+    pub(crate) height: u64,
+    pub(crate) state_root: Hash,
+    pub(crate) extrinsics_root: Hash,
+    /// Hashes of recently orphaned sibling blocks that this block acknowledges.
+    /// Consensus engines ignore this field, but GHOST-style fork choice rules use
+    /// it to credit work that did not make it into the main chain.
+    pub(crate) uncles: Vec<Hash>,
+    pub(crate) consensus_digest: Digest,
+}
+impl<Digest> Header<Digest> {
+    /// Strip the seal from this header, leaving the pre-seal header.
+    ///
+    /// The seal can not be part of the data that it seals. So seals, whether they are work
+    /// proofs or signatures, are always made over the pre-seal header. Verifiers must
+    /// strip the seal off again to check it.
+    pub fn pre_sealed(&self) -> Header<()> {
+        Header {
+            parent: self.parent,
+            height: self.height,
+            state_root: self.state_root,
+            extrinsics_root: self.extrinsics_root,
+            uncles: self.uncles.clone(),
+            consensus_digest: (),
+        }
+    }
+
+    /// The hash of the pre-seal header. This is the value that seals commit to.
+    pub fn pre_hash(&self) -> Hash {
+        crate::hash(&self.pre_sealed())
+    }
+}
+
+impl Header<()> {
+    /// Attach the given seal to this pre-seal header, making it a complete header.
+    pub fn seal<Digest>(self, seal: Digest) -> Header<Digest> {
+        Header {
+            parent: self.parent,
+            height: self.height,
+            state_root: self.state_root,
+            extrinsics_root: self.extrinsics_root,
+            uncles: self.uncles,
+            consensus_digest: seal,
+        }
+    }
+}
+
+/// One field per line, with hashes in hex, which is much easier on the eye than `Debug`.
+impl<Digest: core::fmt::Debug> core::fmt::Display for Header<Digest> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "height: {}", self.height)?;
+        writeln!(f, "parent: {}", Hex(self.parent))?;
+        writeln!(f, "state root: {}", Hex(self.state_root))?;
+        writeln!(f, "extrinsics root: {}", Hex(self.extrinsics_root))?;
+        let uncles: Vec<_> = self
+            .uncles
+            .iter()
+            .map(|&uncle| Hex(uncle).to_string())
+            .collect();
+        writeln!(f, "uncles: [{}]", uncles.join(", "))?;
+        write!(f, "digest: {:?}", self.consensus_digest)
+    }
 }
+
 /// A Consensus Engine. Responsible for Sealing blocks and verifying their seals
 ///
 /// Consensus exists independently of execution logic, and therefore operates
@@ -76,7 +158,30 @@ pub trait Consensus {
         parent_digest: &Self::Digest,
         chain: &[Header<Self::Digest>],
     ) -> bool {
-        todo!("Exercise 1")
+        // todo!("Exercise 1")
+        let mut parent_digest = parent_digest;
+        for header in chain {
+            if !self.validate(parent_digest, header) {
+                return false;
+            }
+            parent_digest = &header.consensus_digest;
+        }
+        true
+    }
+
+    /// The authority who authored the given header, for engines in which blocks are
+    /// authored by identifiable authorities. Other engines, such as PoW, return `None`.
+    ///
+    /// This does not check that the header is valid.
+    fn author(&self, _header: &Header<Self::Digest>) -> Option<ConsensusAuthority> {
+        None
+    }
+
+    /// How much work went into authoring the given header. Fork choice rules add this up to
+    /// compare branches. By default every block counts the same, so the heaviest branch is just
+    /// the longest one. Proof of Work engines count the expected number of hashes instead.
+    fn work(&self, _header: &Header<Self::Digest>) -> u128 {
+        1
     }
 
     /// A human-readable name for this engine. This may be used in user-facing
@@ -94,20 +199,36 @@ impl Consensus for () {
 
     /// All blocks are considered valid
     fn validate(&self, _: &Self::Digest, _: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        true
     }
 
     /// No real sealing is required.
     fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 3")
+        // todo!("Exercise 3")
+        Some(partial_header)
     }
 }
 
 /// A set of consensus authority accounts that can be used in
 /// identity-based consensus algorithms.
-#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+///
+/// The default is not an authority at all, but a placeholder digest for headers that nobody
+/// sealed, like genesis, so that a default digest does not read as a real authority's seal.
+#[derive(Hash, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConsensusAuthority {
     Alice,
     Bob,
     Charlie,
+    /// Nobody. Headers that nobody sealed carry this, and it names no author.
+    #[default]
+    Unsealed,
+}
+
+impl ConsensusAuthority {
+    /// This authority, or None if it is the `Unsealed` placeholder.
+    pub fn authority(self) -> Option<Self> {
+        (self != ConsensusAuthority::Unsealed).then_some(self)
+    }
 }
@@ -0,0 +1,281 @@
+//! In slot-based round robin, everyone knows in advance who will author each slot. That makes
+//! the next author an easy target for attackers. BABE, which is used in Polkadot, keeps the
+//! author secret until they reveal themselves by publishing a block.
+//!
+//! In each slot, every authority privately runs a lottery using a Verifiable Random Function
+//! (VRF). A VRF output looks random, but only the key holder can compute it, and anyone can check
+//! it with a proof. If the output is below a threshold, the authority wins the slot and may
+//! author a block. Authorities with more stake get a higher threshold, so they win more often.
+//! Sometimes nobody wins a slot, and sometimes several authorities do.
+//!
+//! We do not implement a real VRF here. Instead we use a well-known stand-in: ed25519
+//! signatures are deterministic, so a signature over the slot number works as a proof, and its
+//! hash works as the output. This is not as secure as a real VRF, but it behaves the same way.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use super::p7_signed_poa::{is_signed_by_authority, sign_header, SignatureDigest};
+use super::{Consensus, Header};
+use crate::hash;
+
+/// Even an authority with all of the stake only wins one slot in this many. Leaving some slots
+/// empty gives blocks time to propagate before the next one is authored.
+pub const SLOT_FILL_DIVISOR: u64 = 2;
+
+/// Authorities never look further ahead than this many slots when trying to seal a block.
+pub const MAX_SLOT_SEARCH: u64 = 1_000;
+
+/// The consensus digest of a BABE-lite block.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BabeDigest {
+    /// The slot that this block was authored in. Slots must always increase, but may be skipped.
+    pub slot: u64,
+    /// The proof that the author won the lottery for this slot.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::byte_array"))]
+    pub vrf_proof: [u8; 64],
+    /// The author's signature over the block itself.
+    pub seal: SignatureDigest,
+}
+
+/// The genesis header is not authored in any slot, so its digest is all zeros.
+impl Default for BabeDigest {
+    fn default() -> Self {
+        BabeDigest {
+            slot: 0,
+            vrf_proof: [0; 64],
+            seal: SignatureDigest::default(),
+        }
+    }
+}
+
+/// The message that is signed to evaluate the VRF.
+fn vrf_input(randomness: u64, slot: u64) -> [u8; 16] {
+    let mut input = [0; 16];
+    input[..8].copy_from_slice(&randomness.to_le_bytes());
+    input[8..].copy_from_slice(&slot.to_le_bytes());
+    input
+}
+
+/// Evaluate the VRF for the given slot. Returns the output along with the proof that allows
+/// others to check it.
+pub fn vrf_evaluate(key: &SigningKey, randomness: u64, slot: u64) -> (u64, [u8; 64]) {
+    let proof = key.sign(&vrf_input(randomness, slot)).to_bytes();
+    (hash(&proof), proof)
+}
+
+/// Check a VRF proof for the given slot. Returns the VRF output if the proof is valid.
+pub fn vrf_verify(key: &VerifyingKey, randomness: u64, slot: u64, proof: &[u8; 64]) -> Option<u64> {
+    key.verify(&vrf_input(randomness, slot), &Signature::from_bytes(proof))
+        .ok()
+        .map(|()| hash(proof))
+}
+
+/// A simplified BABE consensus engine.
+pub struct BabeLite {
+    /// The public key of each authority together with the amount they have staked.
+    pub stakes: Vec<(VerifyingKey, u64)>,
+    /// Public randomness mixed into every lottery, so that nobody could have chosen their key
+    /// to win particular slots. Real BABE refreshes this every epoch.
+    pub randomness: u64,
+    /// This node's own key, if it is an authority.
+    pub signing_key: Option<SigningKey>,
+}
+
+impl BabeLite {
+    /// The VRF output must be below this threshold for an authority with the given stake to
+    /// win a slot. The threshold is proportional to the authority's share of the total stake.
+    pub fn threshold(&self, stake: u64) -> u64 {
+        let total: u128 = self.stakes.iter().map(|(_, stake)| *stake as u128).sum();
+        if total == 0 {
+            return 0;
+        }
+        (u64::MAX as u128 * stake as u128 / total / SLOT_FILL_DIVISOR as u128) as u64
+    }
+
+    /// The stake of the given authority, or `None` if they are not an authority.
+    fn stake_of(&self, key: &VerifyingKey) -> Option<u64> {
+        self.stakes
+            .iter()
+            .find(|(authority, _)| authority == key)
+            .map(|(_, stake)| *stake)
+    }
+
+    /// Run this node's lottery for the given slot. Returns the proof if this node won.
+    pub fn claim_slot(&self, slot: u64) -> Option<[u8; 64]> {
+        let key = self.signing_key.as_ref()?;
+        let stake = self.stake_of(&key.verifying_key())?;
+        let (output, proof) = vrf_evaluate(key, self.randomness, slot);
+        (output < self.threshold(stake)).then_some(proof)
+    }
+}
+
+impl Consensus for BabeLite {
+    type Digest = BabeDigest;
+
+    /// Check that the slot increased, that the author won the lottery for that slot, and that the
+    /// author signed the block.
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        let digest = &header.consensus_digest;
+        if digest.slot <= parent_digest.slot {
+            return false;
+        }
+        let Ok(author) = VerifyingKey::from_bytes(&digest.seal.signer) else {
+            return false;
+        };
+        let Some(stake) = self.stake_of(&author) else {
+            return false;
+        };
+        let Some(output) = vrf_verify(&author, self.randomness, digest.slot, &digest.vrf_proof)
+        else {
+            return false;
+        };
+        output < self.threshold(stake) && is_signed_by_authority(&[author], header, &digest.seal)
+    }
+
+    /// Author the block in the first slot after the parent's that this node wins.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let key = self.signing_key.as_ref()?;
+        let first_slot = parent_digest.slot + 1;
+        let (slot, vrf_proof) = (first_slot..first_slot + MAX_SLOT_SEARCH)
+            .find_map(|slot| Some((slot, self.claim_slot(slot)?)))?;
+        let seal = sign_header(key, &partial_header);
+        Some(partial_header.seal(BabeDigest {
+            slot,
+            vrf_proof,
+            seal,
+        }))
+    }
+
+    fn human_name() -> String {
+        "BABE-lite".into()
+    }
+}
+
+#[cfg(test)]
+use super::p7_signed_poa::test_keypairs;
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: 0,
+        height,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[cfg(test)]
+fn babe(stakes: &[u64], author: Option<usize>) -> BabeLite {
+    let keys = test_keypairs(stakes.len() as u8);
+    BabeLite {
+        stakes: keys
+            .iter()
+            .map(SigningKey::verifying_key)
+            .zip(stakes.iter().copied())
+            .collect(),
+        randomness: 7,
+        signing_key: author.map(|i| keys[i].clone()),
+    }
+}
+
+#[test]
+fn cs_10_vrf_round_trip() {
+    let key = test_keypairs(1).remove(0);
+    let (output, proof) = vrf_evaluate(&key, 7, 3);
+
+    assert_eq!(vrf_verify(&key.verifying_key(), 7, 3, &proof), Some(output));
+    assert_eq!(vrf_verify(&key.verifying_key(), 7, 4, &proof), None);
+    assert_eq!(vrf_verify(&key.verifying_key(), 8, 3, &proof), None);
+}
+
+#[test]
+fn cs_10_sealed_chain_is_valid() {
+    let author = babe(&[10, 10, 10], Some(1));
+    let verifier = babe(&[10, 10, 10], None);
+
+    let genesis = BabeDigest::default();
+    let h1 = author.seal(&genesis, partial_header(1)).unwrap();
+    let h2 = author
+        .seal(&h1.consensus_digest, partial_header(2))
+        .unwrap();
+
+    assert!(h2.consensus_digest.slot > h1.consensus_digest.slot);
+    assert!(verifier.verify_sub_chain(&genesis, &[h1, h2]));
+}
+
+#[test]
+fn cs_10_slot_must_increase() {
+    let author = babe(&[10, 10, 10], Some(0));
+    let h1 = author
+        .seal(&BabeDigest::default(), partial_header(1))
+        .unwrap();
+
+    // Replaying the same slot on top of a block from that slot is not allowed.
+    let parent = h1.consensus_digest;
+    assert!(!author.validate(&parent, &h1));
+}
+
+#[test]
+fn cs_10_forged_proof_is_rejected() {
+    let author = babe(&[10, 10, 10], Some(0));
+    let genesis = BabeDigest::default();
+    let mut header = author.seal(&genesis, partial_header(1)).unwrap();
+
+    // Claiming a different slot with the same proof does not work.
+    header.consensus_digest.slot += 1;
+    assert!(!author.validate(&genesis, &header));
+}
+
+#[test]
+fn cs_10_tampered_header_is_rejected() {
+    let author = babe(&[10, 10, 10], Some(2));
+    let genesis = BabeDigest::default();
+    let mut header = author.seal(&genesis, partial_header(1)).unwrap();
+
+    header.state_root = 99;
+    assert!(!author.validate(&genesis, &header));
+}
+
+#[test]
+fn cs_10_wins_are_proportional_to_stake() {
+    let rich = babe(&[300, 100, 0], Some(0));
+    let poor = babe(&[300, 100, 0], Some(1));
+    let broke = babe(&[300, 100, 0], Some(2));
+
+    let wins = |engine: &BabeLite| {
+        (1..=2000)
+            .filter(|slot| engine.claim_slot(*slot).is_some())
+            .count()
+    };
+
+    // With the fill divisor of 2, expect roughly 750 and 250 wins.
+    assert!((600..900).contains(&wins(&rich)));
+    assert!((150..350).contains(&wins(&poor)));
+    assert_eq!(wins(&broke), 0);
+    assert_eq!(broke.seal(&BabeDigest::default(), partial_header(1)), None);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn cs_10_sealed_header_survives_json() {
+    let author = babe(&[10, 10, 10], Some(2));
+    let verifier = babe(&[10, 10, 10], None);
+    let genesis = BabeDigest::default();
+    let header = author.seal(&genesis, partial_header(1)).unwrap();
+
+    let json = serde_json::to_string(&header).unwrap();
+    let reloaded: Header<BabeDigest> = serde_json::from_str(&json).unwrap();
+    assert_eq!(reloaded, header);
+    assert!(verifier.verify_sub_chain(&genesis, &[reloaded]));
+
+    // A proof of the wrong length is refused rather than padded or cut short.
+    let short = json.replacen("\"vrf_proof\":[", "\"vrf_proof\":[0,", 1);
+    assert!(serde_json::from_str::<Header<BabeDigest>>(&short).is_err());
+}
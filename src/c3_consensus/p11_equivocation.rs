@@ -0,0 +1,122 @@
+//! In identity-based consensus, each authority is expected to author at most one block per
+//! height. An authority that signs two different blocks at the same height is trying to split
+//! the network, and is said to have equivocated.
+//!
+//! Equivocation can not be prevented, but it can be punished. The two conflicting headers are
+//! all the evidence anyone needs. Any node that sees both can package them into a proof, and
+//! the chain can slash (destroy part of) the offender's stake when the proof is included.
+
+use super::{Consensus, ConsensusAuthority, Header};
+
+/// Evidence that an authority authored two different blocks at the same height.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EquivocationProof<Digest> {
+    /// The authority who equivocated.
+    pub offender: ConsensusAuthority,
+    /// One of the conflicting headers.
+    pub first: Header<Digest>,
+    /// The other conflicting header.
+    pub second: Header<Digest>,
+}
+
+impl<Digest: Clone + Default + Eq> EquivocationProof<Digest> {
+    /// Build a proof from two headers, if they really are an equivocation according to the
+    /// given engine.
+    pub fn new<C: Consensus<Digest = Digest>>(
+        engine: &C,
+        first: Header<Digest>,
+        second: Header<Digest>,
+    ) -> Option<Self> {
+        let offender = engine.author(&first)?;
+        let proof = EquivocationProof {
+            offender,
+            first,
+            second,
+        };
+        proof.verify(engine).then_some(proof)
+    }
+
+    /// Check that this proof is convincing according to the given engine. Both headers must be
+    /// at the same height, be different, carry valid seals, and be authored by the offender.
+    ///
+    /// The seals are checked without their parents, so this only works for engines that do not
+    /// use the parent digest, such as the PoA engines.
+    pub fn verify<C: Consensus<Digest = Digest>>(&self, engine: &C) -> bool {
+        let is_valid_by_offender = |header: &Header<Digest>| {
+            engine.author(header) == Some(self.offender)
+                && engine.validate(&Digest::default(), header)
+        };
+        self.first.height == self.second.height
+            && self.first != self.second
+            && is_valid_by_offender(&self.first)
+            && is_valid_by_offender(&self.second)
+    }
+}
+
+#[cfg(test)]
+use super::SimplePoa;
+
+#[cfg(test)]
+fn partial_header(height: u64, state_root: u64) -> Header<()> {
+    Header {
+        parent: 0,
+        height,
+        state_root,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_11_conflicting_headers_prove_equivocation() {
+    use ConsensusAuthority::*;
+    let poa = SimplePoa {
+        authorities: vec![Alice, Bob],
+    };
+    let first = partial_header(3, 1).seal(Alice);
+    let second = partial_header(3, 2).seal(Alice);
+
+    let proof = EquivocationProof::new(&poa, first, second).unwrap();
+    assert_eq!(proof.offender, Alice);
+    assert!(proof.verify(&poa));
+}
+
+#[test]
+fn cs_11_honest_headers_are_not_equivocation() {
+    use ConsensusAuthority::*;
+    let poa = SimplePoa {
+        authorities: vec![Alice, Bob],
+    };
+
+    // The same header twice.
+    let header = partial_header(3, 1).seal(Alice);
+    assert_eq!(EquivocationProof::new(&poa, header.clone(), header), None);
+    // Different heights.
+    let first = partial_header(3, 1).seal(Alice);
+    let second = partial_header(4, 2).seal(Alice);
+    assert_eq!(EquivocationProof::new(&poa, first, second), None);
+    // Different authors.
+    let first = partial_header(3, 1).seal(Alice);
+    let second = partial_header(3, 2).seal(Bob);
+    assert_eq!(EquivocationProof::new(&poa, first, second), None);
+    // Not an authority, so the seals are not valid in the first place.
+    let first = partial_header(3, 1).seal(Charlie);
+    let second = partial_header(3, 2).seal(Charlie);
+    assert_eq!(EquivocationProof::new(&poa, first, second), None);
+}
+
+#[test]
+fn cs_11_tampered_proof_does_not_verify() {
+    use ConsensusAuthority::*;
+    let poa = SimplePoa {
+        authorities: vec![Alice, Bob],
+    };
+    let first = partial_header(3, 1).seal(Alice);
+    let second = partial_header(3, 2).seal(Alice);
+    let mut proof = EquivocationProof::new(&poa, first, second).unwrap();
+
+    proof.offender = Bob;
+    assert!(!proof.verify(&poa));
+}
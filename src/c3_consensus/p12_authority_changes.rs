@@ -0,0 +1,199 @@
+//! Proof of Authority chains rarely keep the same authorities forever. Authorities retire, new
+//! ones are elected, and keys get rotated. The chain itself has to announce these changes so that
+//! every node agrees on which authorities are allowed to seal each block.
+//!
+//! A change is announced with a digest item, and only takes effect a fixed number of blocks
+//! later. The delay gives every node time to see the announcement, and in real chains it gives
+//! the finality gadget time to finalize it, before the new authorities start authoring.
+//!
+//! Unlike the engines we have written so far, checking a header now requires knowing which
+//! authority set was active at its height, which depends on the whole history before it. So
+//! instead of checking headers one at a time, we track the authority sets as we walk the chain.
+
+use super::p9_hybrid::{DigestItem, DigestLog};
+use super::{ConsensusAuthority, Header};
+
+/// An authority set change that has been announced but has not taken effect yet.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingChange {
+    /// The authorities that will take over.
+    pub authorities: Vec<ConsensusAuthority>,
+    /// The first height that must be sealed by the new authorities.
+    pub effective_at: u64,
+}
+
+/// Tracks the active and pending authority sets while walking along a chain of headers.
+///
+/// Each header's digest log must contain exactly one `DigestItem::Authority` seal from the set
+/// that is active at its height. It may also contain one `DigestItem::AuthoritiesChange`, which
+/// takes effect `delay` blocks later. Only one change may be pending at a time.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuthoritySetTracker {
+    /// The authorities who may seal blocks right now.
+    pub active: Vec<ConsensusAuthority>,
+    /// An announced change that has not taken effect yet, if any.
+    pub pending: Option<PendingChange>,
+    /// How many blocks after its announcement a change takes effect.
+    pub delay: u64,
+    /// The height of the last imported header.
+    height: u64,
+}
+
+impl AuthoritySetTracker {
+    /// Start tracking from genesis, with the given initial authorities.
+    pub fn new(genesis_authorities: Vec<ConsensusAuthority>, delay: u64) -> Self {
+        AuthoritySetTracker {
+            active: genesis_authorities,
+            pending: None,
+            delay: delay.max(1),
+            height: 0,
+        }
+    }
+
+    /// The authorities who may seal the block at the given height, which must be the next one.
+    fn authorities_for(&self, height: u64) -> &[ConsensusAuthority] {
+        match &self.pending {
+            Some(change) if height >= change.effective_at => &change.authorities,
+            _ => &self.active,
+        }
+    }
+
+    /// Check the next header in the chain and update the authority sets accordingly.
+    /// Returns whether the header was valid. An invalid header leaves the tracker unchanged.
+    pub fn import_header(&mut self, header: &Header<DigestLog>) -> bool {
+        if header.height != self.height + 1 {
+            return false;
+        }
+
+        let mut seals = Vec::new();
+        let mut changes = Vec::new();
+        for item in &header.consensus_digest {
+            match item {
+                DigestItem::Authority(authority) => seals.push(*authority),
+                DigestItem::AuthoritiesChange(authorities) => changes.push(authorities),
+                DigestItem::Work(_) => return false,
+            }
+        }
+        let [signer] = seals[..] else {
+            return false;
+        };
+        if !self.authorities_for(header.height).contains(&signer) {
+            return false;
+        }
+
+        let announced = match changes[..] {
+            [] => None,
+            [authorities] if !authorities.is_empty() => Some(authorities.clone()),
+            _ => return false,
+        };
+        let enacted = matches!(&self.pending, Some(c) if header.height >= c.effective_at);
+        if announced.is_some() && self.pending.is_some() && !enacted {
+            return false;
+        }
+
+        if enacted {
+            self.active = self.pending.take().expect("enacted changes exist").authorities;
+        }
+        if let Some(authorities) = announced {
+            self.pending = Some(PendingChange {
+                authorities,
+                effective_at: header.height + self.delay,
+            });
+        }
+        self.height = header.height;
+        true
+    }
+
+    /// Verify a whole chain of headers, following on from the headers imported so far.
+    pub fn verify_sub_chain(&mut self, chain: &[Header<DigestLog>]) -> bool {
+        chain.iter().all(|header| self.import_header(header))
+    }
+
+    /// Seal the next header with the first authority allowed to seal it, optionally announcing
+    /// a change to the given authorities. The tracker is not updated; import the result to do so.
+    pub fn seal(
+        &self,
+        partial_header: Header<()>,
+        change: Option<Vec<ConsensusAuthority>>,
+    ) -> Option<Header<DigestLog>> {
+        let signer = *self.authorities_for(partial_header.height).first()?;
+        let mut log = vec![DigestItem::Authority(signer)];
+        log.extend(change.map(DigestItem::AuthoritiesChange));
+        Some(partial_header.seal(log))
+    }
+}
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: 0,
+        height,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_12_change_takes_effect_after_delay() {
+    use ConsensusAuthority::*;
+    let mut tracker = AuthoritySetTracker::new(vec![Alice], 2);
+    let mut author = tracker.clone();
+
+    // Block 1 announces that Bob takes over at block 3.
+    let mut chain = vec![author.seal(partial_header(1), Some(vec![Bob])).unwrap()];
+    assert!(author.import_header(&chain[0]));
+    for height in 2..=4 {
+        let header = author.seal(partial_header(height), None).unwrap();
+        assert!(author.import_header(&header));
+        chain.push(header);
+    }
+
+    assert_eq!(chain[1].consensus_digest, vec![DigestItem::Authority(Alice)]);
+    assert_eq!(chain[2].consensus_digest, vec![DigestItem::Authority(Bob)]);
+    assert_eq!(author.active, vec![Bob]);
+    assert_eq!(author.pending, None);
+    assert!(tracker.verify_sub_chain(&chain));
+}
+
+#[test]
+fn cs_12_wrong_set_is_rejected_around_the_change() {
+    use ConsensusAuthority::*;
+    let h1 = partial_header(1).seal(vec![
+        DigestItem::Authority(Alice),
+        DigestItem::AuthoritiesChange(vec![Bob]),
+    ]);
+
+    // Bob may not seal before the change takes effect.
+    let mut tracker = AuthoritySetTracker::new(vec![Alice], 2);
+    assert!(tracker.import_header(&h1));
+    assert!(!tracker.import_header(&partial_header(2).seal(vec![DigestItem::Authority(Bob)])));
+    assert!(tracker.import_header(&partial_header(2).seal(vec![DigestItem::Authority(Alice)])));
+
+    // Alice may not seal after it does.
+    assert!(!tracker.import_header(&partial_header(3).seal(vec![DigestItem::Authority(Alice)])));
+    assert!(tracker.import_header(&partial_header(3).seal(vec![DigestItem::Authority(Bob)])));
+}
+
+#[test]
+fn cs_12_only_one_change_may_be_pending() {
+    use ConsensusAuthority::*;
+    let change = |height, to| {
+        partial_header(height).seal(vec![
+            DigestItem::Authority(Alice),
+            DigestItem::AuthoritiesChange(to),
+        ])
+    };
+    let mut tracker = AuthoritySetTracker::new(vec![Alice], 3);
+    assert!(tracker.import_header(&change(1, vec![Alice, Bob])));
+
+    let before = tracker.clone();
+    assert!(!tracker.import_header(&change(2, vec![Charlie])));
+    assert_eq!(tracker, before);
+    // Empty authority sets can never be announced.
+    let mut fresh = AuthoritySetTracker::new(vec![Alice], 3);
+    assert!(!fresh.import_header(&change(1, vec![])));
+}
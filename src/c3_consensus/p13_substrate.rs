@@ -0,0 +1,242 @@
+//! Our headers are close cousins of the headers in Substrate, the framework behind Polkadot.
+//! Substrate's generic header, `sp_runtime::generic::Header`, has five fields:
+//!
+//! - `parent_hash`, like our `parent`, but a 32 byte hash rather than a `u64`.
+//! - `number`, which is our `height`. Polkadot uses a `u32`, and encodes it compactly.
+//! - `state_root` and `extrinsics_root`, exactly like ours.
+//! - `digest`, a list of log items. Consensus engines put their seals and other data here,
+//!   each item tagged with the four byte id of the engine it belongs to.
+//!
+//! This module converts our headers to and from that shape, and encodes them with SCALE, the
+//! codec Substrate uses everywhere, so the bytes line up with what Substrate tools expect. Our
+//! `u64` hashes are placed in the last eight bytes of a 32 byte hash, so they read the same in
+//! hex. Our consensus digest becomes a seal log item, and uncles, which Substrate does not have,
+//! become a consensus log item.
+//!
+//! The hashes themselves are not compatible. Substrate hashes headers with Blake2, and we use
+//! Rust's `DefaultHasher`, so the same header has a different hash in each world.
+
+use codec::{Decode, Encode};
+
+use super::Header;
+
+type Hash = u64;
+
+/// A 32 byte hash, as Substrate chains use.
+pub type H256 = [u8; 32];
+
+/// The four bytes that identify a consensus engine in a Substrate digest.
+pub type ConsensusEngineId = [u8; 4];
+
+/// The id that our engines put on their log items.
+pub const ENGINE_ID: ConsensusEngineId = *b"diy0";
+
+/// A header in the shape of `sp_runtime::generic::Header<u32, BlakeTwo256>`.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct SubstrateHeader {
+    pub parent_hash: H256,
+    #[codec(compact)]
+    pub number: u32,
+    pub state_root: H256,
+    pub extrinsics_root: H256,
+    pub digest: SubstrateDigest,
+}
+
+/// The log items of a header, in the shape of `sp_runtime::generic::Digest`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Encode, Decode)]
+pub struct SubstrateDigest {
+    pub logs: Vec<SubstrateDigestItem>,
+}
+
+/// One log item, in the shape of `sp_runtime::generic::DigestItem`. The indices are the ones
+/// Substrate encodes each kind of item with.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub enum SubstrateDigestItem {
+    /// Data for the given engine that the runtime reads before executing the block.
+    #[codec(index = 6)]
+    PreRuntime(ConsensusEngineId, Vec<u8>),
+    /// A message from the runtime to the given engine.
+    #[codec(index = 4)]
+    Consensus(ConsensusEngineId, Vec<u8>),
+    /// The given engine's seal. It is always the last item, and is not covered by itself.
+    #[codec(index = 5)]
+    Seal(ConsensusEngineId, Vec<u8>),
+    /// Anything else.
+    #[codec(index = 0)]
+    Other(Vec<u8>),
+    /// The runtime code or its heap pages changed in this block.
+    #[codec(index = 8)]
+    RuntimeEnvironmentUpdated,
+}
+
+/// The reasons that a header can not be converted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubstrateError {
+    /// The height does not fit in Substrate's `u32` block number.
+    HeightTooLarge,
+    /// A hash has more than eight bytes in use, so it is not one of ours.
+    HashTooLarge,
+    /// The digest does not have exactly one seal from our engine.
+    MissingSeal,
+    /// One of our log items could not be decoded.
+    Codec(codec::Error),
+}
+
+impl From<codec::Error> for SubstrateError {
+    fn from(error: codec::Error) -> Self {
+        SubstrateError::Codec(error)
+    }
+}
+
+/// Widen one of our hashes to 32 bytes.
+pub fn to_h256(hash: Hash) -> H256 {
+    let mut wide = [0; 32];
+    wide[24..].copy_from_slice(&hash.to_be_bytes());
+    wide
+}
+
+/// Narrow a 32 byte hash back to one of ours, if only the last eight bytes are in use.
+pub fn from_h256(wide: &H256) -> Result<Hash, SubstrateError> {
+    let (high, low) = wide.split_at(24);
+    if high.iter().any(|&byte| byte != 0) {
+        return Err(SubstrateError::HashTooLarge);
+    }
+    Ok(Hash::from_be_bytes(
+        low.try_into().expect("eight bytes remain"),
+    ))
+}
+
+impl<Digest: Encode> Header<Digest> {
+    /// This header in Substrate's shape.
+    pub fn to_substrate(&self) -> Result<SubstrateHeader, SubstrateError> {
+        let number = u32::try_from(self.height).map_err(|_| SubstrateError::HeightTooLarge)?;
+        let mut logs = Vec::new();
+        if !self.uncles.is_empty() {
+            logs.push(SubstrateDigestItem::Consensus(
+                ENGINE_ID,
+                self.uncles.encode(),
+            ));
+        }
+        logs.push(SubstrateDigestItem::Seal(
+            ENGINE_ID,
+            self.consensus_digest.encode(),
+        ));
+        Ok(SubstrateHeader {
+            parent_hash: to_h256(self.parent),
+            number,
+            state_root: to_h256(self.state_root),
+            extrinsics_root: to_h256(self.extrinsics_root),
+            digest: SubstrateDigest { logs },
+        })
+    }
+}
+
+impl<Digest: Decode> Header<Digest> {
+    /// Convert a header in Substrate's shape back to ours. Log items from other engines are
+    /// ignored.
+    pub fn from_substrate(header: &SubstrateHeader) -> Result<Self, SubstrateError> {
+        let mut uncles = Vec::new();
+        let mut seals = Vec::new();
+        for item in &header.digest.logs {
+            match item {
+                SubstrateDigestItem::Consensus(ENGINE_ID, data) => {
+                    uncles = Decode::decode(&mut &data[..])?;
+                }
+                SubstrateDigestItem::Seal(ENGINE_ID, data) => seals.push(data),
+                _ => {}
+            }
+        }
+        let [seal] = seals[..] else {
+            return Err(SubstrateError::MissingSeal);
+        };
+        Ok(Header {
+            parent: from_h256(&header.parent_hash)?,
+            height: header.number.into(),
+            state_root: from_h256(&header.state_root)?,
+            extrinsics_root: from_h256(&header.extrinsics_root)?,
+            uncles,
+            consensus_digest: Decode::decode(&mut &seal[..])?,
+        })
+    }
+}
+
+#[cfg(test)]
+fn header() -> Header<u64> {
+    Header {
+        parent: 0x0123_4567_89ab_cdef,
+        height: 300,
+        state_root: 2,
+        extrinsics_root: 3,
+        uncles: vec![4, 5],
+        consensus_digest: 42,
+    }
+}
+
+#[test]
+fn cs_13_headers_survive_the_trip_to_substrate() {
+    let header = header();
+    let substrate = header.to_substrate().unwrap();
+    assert_eq!(substrate.number, 300);
+    assert_eq!(
+        &substrate.parent_hash[24..],
+        &[0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]
+    );
+    assert_eq!(Header::from_substrate(&substrate), Ok(header.clone()));
+
+    let encoded = substrate.encode();
+    assert_eq!(SubstrateHeader::decode(&mut &encoded[..]), Ok(substrate));
+
+    // Pre-seal headers have nothing to seal with, but still carry an empty seal.
+    let pre_sealed = header.pre_sealed();
+    let substrate = pre_sealed.to_substrate().unwrap();
+    assert_eq!(Header::from_substrate(&substrate), Ok(pre_sealed));
+}
+
+#[test]
+fn cs_13_substrate_encoding_matches_the_real_thing() {
+    let header = Header {
+        uncles: vec![],
+        consensus_digest: (),
+        ..header().pre_sealed()
+    };
+    let encoded = header.to_substrate().unwrap().encode();
+    let mut expected = Vec::new();
+    expected.extend(to_h256(header.parent));
+    // The compact encoding of 300 takes two bytes: 300 shifted left by two, with the low bits
+    // set to 0b01.
+    expected.extend([0xb1, 0x04]);
+    expected.extend(to_h256(2));
+    expected.extend(to_h256(3));
+    // One log item: a seal, tagged with index 5, our engine id, and no data.
+    expected.extend([0x04, 0x05]);
+    expected.extend(ENGINE_ID);
+    expected.push(0x00);
+    assert_eq!(encoded, expected);
+}
+
+#[test]
+fn cs_13_foreign_headers_are_refused() {
+    let too_high = Header {
+        height: u64::from(u32::MAX) + 1,
+        ..header()
+    };
+    assert_eq!(too_high.to_substrate(), Err(SubstrateError::HeightTooLarge));
+
+    let mut substrate = header().to_substrate().unwrap();
+    substrate
+        .digest
+        .logs
+        .retain(|item| !matches!(item, SubstrateDigestItem::Seal(..)));
+    substrate
+        .digest
+        .logs
+        .push(SubstrateDigestItem::Seal(*b"BABE", vec![1]));
+    let missing_seal = Header::<u64>::from_substrate(&substrate);
+    assert_eq!(missing_seal, Err(SubstrateError::MissingSeal));
+
+    substrate.parent_hash = [0xff; 32];
+    assert_eq!(
+        from_h256(&substrate.parent_hash),
+        Err(SubstrateError::HashTooLarge)
+    );
+}
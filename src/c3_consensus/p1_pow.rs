@@ -4,39 +4,310 @@
 //! This is the same logic we implemented previously. Here we re-implement it in the
 //! generic consensus framework that we will use throughout the rest of the chapter.
 
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
 use super::{Consensus, Header};
+use crate::hash;
+
+type Hash = u64;
 
 /// A Proof of Work consensus engine. This is the same consensus logic that we
 /// implemented in the previous chapter. Here we simply re-implement it in the
 /// consensus framework that will be used throughout this chapter.
+///
+/// Unlike the previous chapter, the work is not done on the complete header. The
+/// nonce is the seal, and seals are always made over the pre-seal header. So the
+/// work is the hash of the pre-seal hash together with the nonce.
 pub struct Pow {
     threshold: u64,
 }
 
+/// The proof of work hash for a given pre-seal hash and nonce.
+pub fn work_hash(pre_hash: Hash, nonce: u64) -> Hash {
+    hash(&(pre_hash, nonce))
+}
+
 impl Consensus for Pow {
     type Digest = u64;
 
     /// Check that the provided header's hash is below the required threshold.
     /// This does not rely on the parent digest at all.
     fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 1")
+        // todo!("Exercise 1")
+        work_hash(header.pre_hash(), header.consensus_digest) <= self.threshold
     }
 
     /// Mine a new PoW seal for the partial header provided.
     /// This does not rely on the parent digest at all.
     fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        let pre_hash = hash(&partial_header);
+        let nonce = (0..=u64::MAX).find(|nonce| work_hash(pre_hash, *nonce) <= self.threshold)?;
+        Some(partial_header.seal(nonce))
+    }
+
+    /// The expected number of hashes needed to find a valid nonce.
+    fn work(&self, _: &Header<Self::Digest>) -> u128 {
+        (u64::MAX / self.threshold.max(1)) as u128
+    }
+}
+
+impl Pow {
+    /// Create a PoW consensus engine where roughly one in `difficulty` nonces is valid.
+    pub fn with_difficulty(difficulty: u64) -> Self {
+        Pow {
+            threshold: u64::MAX / difficulty.max(1),
+        }
+    }
+
+    /// Create a PoW consensus engine that accepts any work hash up to the given threshold.
+    pub fn with_threshold(threshold: u64) -> Self {
+        Pow { threshold }
+    }
+
+    /// The largest work hash this engine accepts.
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Search the given range of nonces for one that seals the given pre-hash. The search gives
+    /// up early, returning `None`, as soon as `stop` is set.
+    fn search(
+        &self,
+        pre_hash: Hash,
+        nonces: RangeInclusive<u64>,
+        stop: &AtomicBool,
+    ) -> Option<u64> {
+        for nonce in nonces {
+            if stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            if work_hash(pre_hash, nonce) <= self.threshold {
+                return Some(nonce);
+            }
+        }
+        None
+    }
+
+    /// Mine a seal for the partial header, unless mining is cancelled first.
+    ///
+    /// A node uses this when it wants to be able to abort its work, typically because a
+    /// competing block arrived and the block being mined is now stale. Once `cancel` is set,
+    /// mining stops and `None` is returned. The flag is usually shared through an `Arc`.
+    pub fn mine_cancellable(
+        &self,
+        pre_header: Header<()>,
+        cancel: &AtomicBool,
+    ) -> Option<Header<u64>> {
+        let nonce = self.search(hash(&pre_header), 0..=u64::MAX, cancel)?;
+        Some(pre_header.seal(nonce))
+    }
+
+    /// Mine a seal for the partial header using several threads at once.
+    ///
+    /// The nonce space is split into disjoint ranges, one per thread. As soon as any thread
+    /// finds a valid nonce, the others stop, and the header is sealed with the nonce that was
+    /// found first. This is not necessarily the same nonce that `seal` would find.
+    pub fn mine_parallel(&self, pre_header: Header<()>, threads: usize) -> Option<Header<u64>> {
+        let threads = threads.max(1) as u64;
+        let pre_hash = hash(&pre_header);
+        let found = AtomicBool::new(false);
+        let range_len = u64::MAX / threads;
+
+        let nonce = thread::scope(|s| {
+            let workers: Vec<_> = (0..threads)
+                .map(|i| {
+                    let start = i * range_len;
+                    let end = if i == threads - 1 {
+                        u64::MAX
+                    } else {
+                        start + range_len - 1
+                    };
+                    let found = &found;
+                    s.spawn(move || {
+                        let nonce = self.search(pre_hash, start..=end, found);
+                        if nonce.is_some() {
+                            found.store(true, Ordering::Relaxed);
+                        }
+                        nonce
+                    })
+                })
+                .collect();
+
+            // Every worker that found a nonce before noticing the others is done has a valid one.
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining threads do not panic"))
+                .next()
+        })?;
+
+        Some(pre_header.seal(nonce))
     }
 }
 
 /// Create a PoW consensus engine that has a difficulty threshold such that roughly 1 in 100 blocks
 /// with randomly drawn nonces will be valid. That is: the threshold should be u64::max_value() / 100.
 pub fn moderate_difficulty_pow() -> Pow {
-    todo!("Exercise 3")
+    // todo!("Exercise 3")
+    Pow {
+        threshold: u64::MAX / 100,
+    }
 }
 
 /// Create an instance of the PoW Consensus that behaves identically to the trivial
 /// consensus implementation for `()` from the module level.
 pub fn trivial_always_valid_pow() -> Pow {
-    todo!("Exercise 4")
+    // todo!("Exercise 4")
+    Pow {
+        threshold: u64::MAX,
+    }
+}
+
+#[cfg(test)]
+fn partial_header() -> Header<()> {
+    Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_1_seal_then_strip_round_trips() {
+    let sealed = partial_header().seal(42u64);
+
+    assert_eq!(sealed.consensus_digest, 42);
+    assert_eq!(sealed.pre_sealed(), partial_header());
+    assert_eq!(sealed.pre_hash(), hash(&partial_header()));
+}
+
+#[test]
+fn cs_1_pow_seal_is_valid() {
+    let pow = moderate_difficulty_pow();
+    let sealed = pow.seal(&0, partial_header()).unwrap();
+
+    assert_eq!(sealed.pre_sealed(), partial_header());
+    assert!(work_hash(sealed.pre_hash(), sealed.consensus_digest) <= pow.threshold);
+    assert!(pow.validate(&0, &sealed));
+}
+
+#[test]
+fn cs_1_tampering_with_pre_seal_header_invalidates_seal() {
+    let pow = moderate_difficulty_pow();
+    let mut tampered = pow.seal(&0, partial_header()).unwrap();
+
+    // Keep the nonce but change the sealed data until the old work no longer applies.
+    while work_hash(tampered.pre_hash(), tampered.consensus_digest) <= pow.threshold {
+        tampered.state_root += 1;
+    }
+    assert!(!pow.validate(&0, &tampered));
+}
+
+#[test]
+fn cs_1_trivial_pow_accepts_anything() {
+    let pow = trivial_always_valid_pow();
+
+    assert!(pow.validate(&0, &partial_header().seal(12345)));
+    assert_eq!(
+        pow.seal(&0, partial_header()),
+        Some(partial_header().seal(0))
+    );
+}
+
+#[test]
+fn cs_1_parallel_seal_is_valid() {
+    let pow = moderate_difficulty_pow();
+
+    for threads in [1, 2, 4] {
+        let sealed = pow.mine_parallel(partial_header(), threads).unwrap();
+        assert_eq!(sealed.pre_sealed(), partial_header());
+        assert!(pow.validate(&0, &sealed));
+    }
+}
+
+#[test]
+fn cs_1_parallel_seal_with_one_thread_matches_serial() {
+    let pow = Pow::with_difficulty(1_000);
+
+    assert_eq!(
+        pow.mine_parallel(partial_header(), 1),
+        pow.seal(&0, partial_header())
+    );
+}
+
+#[test]
+fn cs_1_cancellable_mining_finds_seal() {
+    let pow = moderate_difficulty_pow();
+    let cancel = AtomicBool::new(false);
+
+    assert_eq!(
+        pow.mine_cancellable(partial_header(), &cancel),
+        pow.seal(&0, partial_header())
+    );
+}
+
+#[test]
+fn cs_1_cancel_hard_mining() {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // Practically no nonce is good enough, so this would run for a very long time.
+    let pow = Pow::with_difficulty(u64::MAX);
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    let canceller = {
+        let cancel = Arc::clone(&cancel);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+        })
+    };
+
+    assert_eq!(pow.mine_cancellable(partial_header(), &cancel), None);
+    canceller.join().unwrap();
+}
+
+#[test]
+fn cs_1_already_cancelled_mining_does_no_work() {
+    let pow = trivial_always_valid_pow();
+    let cancel = AtomicBool::new(true);
+
+    assert_eq!(pow.mine_cancellable(partial_header(), &cancel), None);
+}
+
+/// Compare the serial and parallel miners. This takes a while, so it does not run by default.
+/// Run it with: `cargo test --release cs_1_bench_parallel_mining -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn cs_1_bench_parallel_mining() {
+    use std::time::Instant;
+
+    let pow = Pow::with_difficulty(2_000_000);
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    let headers: Vec<_> = (0..10)
+        .map(|height| Header {
+            height,
+            ..partial_header()
+        })
+        .collect();
+
+    let start = Instant::now();
+    for header in headers.clone() {
+        assert!(pow.seal(&0, header).is_some());
+    }
+    let serial = start.elapsed();
+
+    let start = Instant::now();
+    for header in headers {
+        assert!(pow.mine_parallel(header, threads).is_some());
+    }
+    let parallel = start.elapsed();
+
+    println!("serial:   {:?}", serial);
+    println!("parallel: {:?} ({} threads)", parallel, threads);
 }
@@ -19,39 +19,72 @@ pub struct SimplePoa {
 impl Consensus for SimplePoa {
     type Digest = ConsensusAuthority;
 
-    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 1")
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        // todo!("Exercise 1")
+        self.authorities.contains(&header.consensus_digest)
     }
 
+    /// Any authority may sign, so the first one does. There is no valid seal
+    /// when the authority set is empty.
     fn seal(
         &self,
-        parent_digest: &Self::Digest,
+        _: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        let signer = self.authorities.first()?;
+        Some(partial_header.seal(*signer))
+    }
+
+    fn author(&self, header: &Header<Self::Digest>) -> Option<ConsensusAuthority> {
+        header.consensus_digest.authority()
     }
 }
 
 /// A Proof of Authority consensus engine. Only one authority is valid at each block height.
 /// As ever, the genesis block does not require a seal. After that the authorities take turns
 /// in order.
-struct PoaRoundRobinByHeight {
-    authorities: Vec<ConsensusAuthority>,
+///
+/// This is the authoring rule used by Aura, where each height acts as a slot: the valid author
+/// for height `h` is `authorities[h % len]`.
+pub struct PoaRoundRobinByHeight {
+    /// The authority schedule. Authorities author blocks in the order given here.
+    pub authorities: Vec<ConsensusAuthority>,
+}
+
+impl PoaRoundRobinByHeight {
+    /// The only authority who may author the block at the given height, or `None` if the
+    /// authority set is empty.
+    pub fn expected_author(&self, height: u64) -> Option<ConsensusAuthority> {
+        if self.authorities.is_empty() {
+            return None;
+        }
+        let index = (height % self.authorities.len() as u64) as usize;
+        Some(self.authorities[index])
+    }
 }
 
 impl Consensus for PoaRoundRobinByHeight {
     type Digest = ConsensusAuthority;
 
-    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 3")
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        // todo!("Exercise 3")
+        self.expected_author(header.height) == Some(header.consensus_digest)
     }
 
+    /// The block is signed by whichever authority's turn it is.
     fn seal(
         &self,
-        parent_digest: &Self::Digest,
+        _: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 4")
+        // todo!("Exercise 4")
+        let signer = self.expected_author(partial_header.height)?;
+        Some(partial_header.seal(signer))
+    }
+
+    fn author(&self, header: &Header<Self::Digest>) -> Option<ConsensusAuthority> {
+        header.consensus_digest.authority()
     }
 }
 
@@ -91,3 +124,69 @@ impl Consensus for PoaRoundRobinBySlot {
         todo!("Exercise 6")
     }
 }
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: 0,
+        height,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_3_round_robin_schedule() {
+    use ConsensusAuthority::*;
+    let engine = PoaRoundRobinByHeight {
+        authorities: vec![Alice, Bob, Charlie],
+    };
+
+    assert_eq!(engine.expected_author(0), Some(Alice));
+    assert_eq!(engine.expected_author(1), Some(Bob));
+    assert_eq!(engine.expected_author(2), Some(Charlie));
+    assert_eq!(engine.expected_author(3), Some(Alice));
+    assert_eq!(PoaRoundRobinByHeight { authorities: vec![] }.expected_author(3), None);
+}
+
+#[test]
+fn cs_3_round_robin_seals_with_scheduled_author() {
+    use ConsensusAuthority::*;
+    let engine = PoaRoundRobinByHeight {
+        authorities: vec![Alice, Bob, Charlie],
+    };
+
+    for height in 1..=6 {
+        let header = engine.seal(&Alice, partial_header(height)).unwrap();
+        assert_eq!(Some(header.consensus_digest), engine.expected_author(height));
+        assert!(engine.validate(&Alice, &header));
+    }
+}
+
+#[test]
+fn cs_3_round_robin_rejects_wrong_author() {
+    use ConsensusAuthority::*;
+    let engine = PoaRoundRobinByHeight {
+        authorities: vec![Alice, Bob, Charlie],
+    };
+
+    // Height 4 belongs to Bob.
+    assert!(!engine.validate(&Alice, &partial_header(4).seal(Alice)));
+    assert!(!engine.validate(&Alice, &partial_header(4).seal(Charlie)));
+    assert!(engine.validate(&Alice, &partial_header(4).seal(Bob)));
+}
+
+#[test]
+fn cs_3_unsealed_headers_have_no_author() {
+    use ConsensusAuthority::*;
+    let engine = SimplePoa {
+        authorities: vec![Alice, Bob],
+    };
+    let unsealed = partial_header(0).seal(ConsensusAuthority::default());
+
+    assert_eq!(engine.author(&unsealed), None);
+    assert!(!engine.validate(&Alice, &unsealed));
+    assert_eq!(engine.author(&partial_header(1).seal(Bob)), Some(Bob));
+}
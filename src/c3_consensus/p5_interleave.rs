@@ -13,36 +13,47 @@ use super::{Consensus, ConsensusAuthority, Header};
 /// In order to implement a consensus that can be sealed with either work or a signature,
 /// we will need an enum that wraps the two individual digest types.
 #[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
-enum PowOrPoaDigest {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PowOrPoaDigest {
     Pow(u64),
     Poa(ConsensusAuthority),
 }
 
 impl From<u64> for PowOrPoaDigest {
-    fn from(_: u64) -> Self {
-        todo!("Exercise 1")
+    fn from(nonce: u64) -> Self {
+        // todo!("Exercise 1")
+        PowOrPoaDigest::Pow(nonce)
     }
 }
 
 impl TryFrom<PowOrPoaDigest> for u64 {
     type Error = ();
 
-    fn try_from(_: PowOrPoaDigest) -> Result<Self, Self::Error> {
-        todo!("Exercise 2")
+    fn try_from(digest: PowOrPoaDigest) -> Result<Self, Self::Error> {
+        // todo!("Exercise 2")
+        match digest {
+            PowOrPoaDigest::Pow(nonce) => Ok(nonce),
+            PowOrPoaDigest::Poa(_) => Err(()),
+        }
     }
 }
 
 impl From<ConsensusAuthority> for PowOrPoaDigest {
-    fn from(_: ConsensusAuthority) -> Self {
-        todo!("Exercise 3")
+    fn from(authority: ConsensusAuthority) -> Self {
+        // todo!("Exercise 3")
+        PowOrPoaDigest::Poa(authority)
     }
 }
 
 impl TryFrom<PowOrPoaDigest> for ConsensusAuthority {
     type Error = ();
 
-    fn try_from(_: PowOrPoaDigest) -> Result<Self, Self::Error> {
-        todo!("Exercise 4")
+    fn try_from(digest: PowOrPoaDigest) -> Result<Self, Self::Error> {
+        // todo!("Exercise 4")
+        match digest {
+            PowOrPoaDigest::Poa(authority) => Ok(authority),
+            PowOrPoaDigest::Pow(_) => Err(()),
+        }
     }
 }
 
@@ -8,28 +8,81 @@
 
 use std::marker::PhantomData;
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::p5_interleave::PowOrPoaDigest;
+use super::{Consensus, ConsensusAuthority, Header, Pow, SimplePoa};
 
 /// A Higher-order consensus engine that represents a change from one set of consensus rules (Before) to
 /// another set (After) at a specific block height
-struct Forked<D, Before, After> {
+pub struct Forked<D, Before, After> {
     /// The first block height at which the new consensus rules apply
     fork_height: u64,
-    phdata: PhantomData<(D, Before, After)>,
+    /// The engine that enforces the rules before the fork
+    before: Before,
+    /// The engine that enforces the rules from the fork height onwards
+    after: After,
+    phdata: PhantomData<D>,
+}
+
+impl<D, Before, After> Forked<D, Before, After> {
+    /// Create an engine that follows `before` below the fork height, and `after` from it onwards.
+    pub fn new(fork_height: u64, before: Before, after: After) -> Self {
+        Forked {
+            fork_height,
+            before,
+            after,
+            phdata: PhantomData,
+        }
+    }
+}
+
+/// Validate a header with an inner engine, converting the digests to the inner engine's type.
+///
+/// The first block after the fork has a parent digest from the old engine, which the new
+/// engine can not understand. There is no meaningful parent digest for it, so it gets the default.
+fn validate_with<D, E>(engine: &E, parent_digest: &D, header: &Header<D>) -> bool
+where
+    D: Clone + TryInto<E::Digest>,
+    E: Consensus,
+    E::Digest: Default,
+{
+    let Ok(digest) = header.consensus_digest.clone().try_into() else {
+        return false;
+    };
+    let parent_digest = parent_digest.clone().try_into().unwrap_or_default();
+    engine.validate(&parent_digest, &header.pre_sealed().seal(digest))
+}
+
+/// Seal a header with an inner engine, converting the digests to and from the inner engine's type.
+fn seal_with<D, E>(engine: &E, parent_digest: &D, partial_header: Header<()>) -> Option<Header<D>>
+where
+    D: Clone + TryInto<E::Digest>,
+    E: Consensus,
+    E::Digest: Default + Into<D>,
+{
+    let parent_digest = parent_digest.clone().try_into().unwrap_or_default();
+    let sealed = engine.seal(&parent_digest, partial_header)?;
+    let digest = sealed.consensus_digest.clone().into();
+    Some(sealed.pre_sealed().seal(digest))
 }
 
 impl<D, B, A> Consensus for Forked<D, B, A>
 where
     D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash,
+    D: TryInto<B::Digest> + TryInto<A::Digest>,
     B: Consensus,
     A: Consensus,
-    B::Digest: Into<D>,
-    A::Digest: Into<D>,
+    B::Digest: Into<D> + Default,
+    A::Digest: Into<D> + Default,
 {
     type Digest = D;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        todo!("Exercise 1")
+        // todo!("Exercise 1")
+        if header.height < self.fork_height {
+            validate_with(&self.before, parent_digest, header)
+        } else {
+            validate_with(&self.after, parent_digest, header)
+        }
     }
 
     fn seal(
@@ -37,7 +90,12 @@ where
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        if partial_header.height < self.fork_height {
+            seal_with(&self.before, parent_digest, partial_header)
+        } else {
+            seal_with(&self.after, parent_digest, partial_header)
+        }
     }
 }
 
@@ -77,10 +135,88 @@ fn even_after_given_height<Original: Consensus>(fork_height: u64) -> impl Consen
 /// the fundamentals are the same.
 ///
 /// For this task, you may use the PowOrPoaDigest type from the previous module if you like.
-fn pow_to_poa(
+///
+/// Blocks below the fork height must carry valid work, and blocks at or after it must be sealed
+/// by one of the authorities.
+pub fn pow_to_poa(
     fork_height: u64,
     difficulty: u64,
     authorities: Vec<ConsensusAuthority>,
-) -> impl Consensus {
-    todo!("Exercise 6")
+) -> Forked<PowOrPoaDigest, Pow, SimplePoa> {
+    // todo!("Exercise 6")
+    Forked::new(
+        fork_height,
+        Pow::with_difficulty(difficulty),
+        SimplePoa { authorities },
+    )
+}
+
+#[cfg(test)]
+fn sealed_chain(
+    engine: &impl Consensus<Digest = PowOrPoaDigest>,
+    length: u64,
+) -> Vec<Header<PowOrPoaDigest>> {
+    let mut parent_digest = PowOrPoaDigest::Pow(0);
+    (1..=length)
+        .map(|height| {
+            let partial = Header {
+                parent: height - 1,
+                height,
+                state_root: 0,
+                extrinsics_root: 0,
+                uncles: Vec::new(),
+                consensus_digest: (),
+            };
+            let header = engine.seal(&parent_digest, partial).unwrap();
+            parent_digest = header.consensus_digest;
+            header
+        })
+        .collect()
+}
+
+#[test]
+fn cs_6_pow_to_poa_chain_spans_the_fork() {
+    let engine = pow_to_poa(3, 100, vec![ConsensusAuthority::Bob]);
+    let chain = sealed_chain(&engine, 5);
+
+    assert!(matches!(chain[0].consensus_digest, PowOrPoaDigest::Pow(_)));
+    assert!(matches!(chain[1].consensus_digest, PowOrPoaDigest::Pow(_)));
+    assert_eq!(
+        chain[2].consensus_digest,
+        PowOrPoaDigest::Poa(ConsensusAuthority::Bob)
+    );
+    assert_eq!(
+        chain[4].consensus_digest,
+        PowOrPoaDigest::Poa(ConsensusAuthority::Bob)
+    );
+    assert!(engine.verify_sub_chain(&PowOrPoaDigest::Pow(0), &chain));
+}
+
+#[test]
+fn cs_6_pow_to_poa_rejects_wrong_seal_kind() {
+    let engine = pow_to_poa(3, 100, vec![ConsensusAuthority::Bob]);
+    let chain = sealed_chain(&engine, 4);
+
+    // An authority seal before the fork is not valid.
+    let mut early_poa = chain[1].clone();
+    early_poa.consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Bob);
+    assert!(!engine.validate(&chain[0].consensus_digest, &early_poa));
+
+    // Work after the fork is not valid, no matter how good it is.
+    let work_engine = pow_to_poa(u64::MAX, 1, vec![]);
+    let mut late_pow = chain[3].clone();
+    late_pow.consensus_digest = work_engine
+        .seal(&PowOrPoaDigest::Pow(0), late_pow.pre_sealed())
+        .unwrap()
+        .consensus_digest;
+    assert!(!engine.validate(&chain[2].consensus_digest, &late_pow));
+}
+
+#[test]
+fn cs_6_pow_to_poa_rejects_outsider_after_fork() {
+    let engine = pow_to_poa(3, 100, vec![ConsensusAuthority::Bob]);
+    let mut chain = sealed_chain(&engine, 4);
+
+    chain[3].consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Alice);
+    assert!(!engine.verify_sub_chain(&PowOrPoaDigest::Pow(0), &chain));
 }
@@ -0,0 +1,234 @@
+//! Until now our identity-based consensus engines have "signed" blocks by simply attaching the
+//! name of an authority. That is fine for learning the consensus logic, but it is obviously not
+//! secure. Anyone can write "Alice" into a header.
+//!
+//! Here we revisit Proof of Authority with real cryptography. Each authority holds an ed25519
+//! key pair, and the seal is a signature over the pre-seal hash. Nobody can forge a seal without
+//! the authority's secret key, and any change to the header invalidates the signature.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use super::{Consensus, Header};
+
+/// The seal of a block authored under `SignedPoa`.
+///
+/// The keys and signatures are stored as raw bytes, because that is how they would travel
+/// over the network, and because the digest type must be hashable.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignatureDigest {
+    /// The public key of the authority who signed the block.
+    pub signer: [u8; 32],
+    /// The ed25519 signature over the header's pre-seal hash.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::byte_array"))]
+    pub signature: [u8; 64],
+}
+
+/// The genesis header is not signed, so its digest is all zeros.
+impl Default for SignatureDigest {
+    fn default() -> Self {
+        SignatureDigest {
+            signer: [0; 32],
+            signature: [0; 64],
+        }
+    }
+}
+
+/// A Proof of Authority consensus engine that uses real ed25519 signatures. A block is valid
+/// if it is signed by any one of the authorities.
+pub struct SignedPoa {
+    /// The public keys of the authorities who may sign blocks.
+    pub authorities: Vec<VerifyingKey>,
+    /// This node's own key. Only needed for sealing, so nodes that just follow
+    /// the chain leave it empty.
+    pub signing_key: Option<SigningKey>,
+}
+
+impl SignedPoa {
+    /// An engine that can verify blocks but not author them.
+    pub fn verifier(authorities: Vec<VerifyingKey>) -> Self {
+        SignedPoa {
+            authorities,
+            signing_key: None,
+        }
+    }
+
+    /// An engine that can author blocks using the given key.
+    pub fn author(authorities: Vec<VerifyingKey>, signing_key: SigningKey) -> Self {
+        SignedPoa {
+            authorities,
+            signing_key: Some(signing_key),
+        }
+    }
+}
+
+/// The message that authorities sign: the pre-seal hash.
+fn signing_payload<Digest>(header: &Header<Digest>) -> [u8; 8] {
+    header.pre_hash().to_le_bytes()
+}
+
+/// Check that the given digest is a valid signature over the header's pre-seal hash by one of
+/// the given authorities. This is shared by every engine that uses signature digests.
+pub fn is_signed_by_authority<Digest>(
+    authorities: &[VerifyingKey],
+    header: &Header<Digest>,
+    digest: &SignatureDigest,
+) -> bool {
+    let Ok(signer) = VerifyingKey::from_bytes(&digest.signer) else {
+        return false;
+    };
+    authorities.contains(&signer)
+        && signer
+            .verify(
+                &signing_payload(header),
+                &Signature::from_bytes(&digest.signature),
+            )
+            .is_ok()
+}
+
+/// Sign the given pre-seal header with the given key, producing its seal.
+pub fn sign_header(signing_key: &SigningKey, partial_header: &Header<()>) -> SignatureDigest {
+    SignatureDigest {
+        signer: signing_key.verifying_key().to_bytes(),
+        signature: signing_key
+            .sign(&signing_payload(partial_header))
+            .to_bytes(),
+    }
+}
+
+impl Consensus for SignedPoa {
+    type Digest = SignatureDigest;
+
+    /// Check that the header is signed by one of the authorities.
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        is_signed_by_authority(&self.authorities, header, &header.consensus_digest)
+    }
+
+    /// Sign the partial header with this node's key. There is no valid seal when this
+    /// node does not have a key, or its key is not one of the authorities.
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        let signing_key = self.signing_key.as_ref()?;
+        if !self.authorities.contains(&signing_key.verifying_key()) {
+            return None;
+        }
+        let digest = sign_header(signing_key, &partial_header);
+        Some(partial_header.seal(digest))
+    }
+
+    fn human_name() -> String {
+        "Proof of Authority (ed25519)".into()
+    }
+}
+
+/// Deterministically derive a key pair from a small seed. This makes it easy for tests to
+/// create as many distinct authorities as they need, and to recreate the same ones later.
+/// Never derive real keys like this!
+#[cfg(test)]
+pub fn test_keypair(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+/// Derive the given number of test key pairs, using seeds 0, 1, 2, and so on.
+#[cfg(test)]
+pub fn test_keypairs(n: u8) -> Vec<SigningKey> {
+    (0..n).map(test_keypair).collect()
+}
+
+/// The public keys of the given key pairs.
+#[cfg(test)]
+pub fn public_keys(keys: &[SigningKey]) -> Vec<VerifyingKey> {
+    keys.iter().map(SigningKey::verifying_key).collect()
+}
+
+#[cfg(test)]
+fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: 0,
+        height,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_7_authority_seal_is_valid() {
+    let keys = test_keypairs(3);
+    let engine = SignedPoa::author(public_keys(&keys), keys[1].clone());
+
+    let header = engine
+        .seal(&SignatureDigest::default(), partial_header(1))
+        .unwrap();
+    assert_eq!(
+        header.consensus_digest.signer,
+        keys[1].verifying_key().to_bytes()
+    );
+    assert!(engine.validate(&SignatureDigest::default(), &header));
+
+    let verifier = SignedPoa::verifier(public_keys(&keys));
+    assert!(verifier.validate(&SignatureDigest::default(), &header));
+}
+
+#[test]
+fn cs_7_non_authority_can_not_seal() {
+    let keys = test_keypairs(3);
+    let outsider = test_keypair(99);
+
+    let engine = SignedPoa::author(public_keys(&keys), outsider.clone());
+    assert_eq!(
+        engine.seal(&SignatureDigest::default(), partial_header(1)),
+        None
+    );
+    assert_eq!(
+        SignedPoa::verifier(public_keys(&keys))
+            .seal(&SignatureDigest::default(), partial_header(1)),
+        None
+    );
+
+    // Even when the outsider signs directly, the block is not valid.
+    let header = partial_header(1).seal(sign_header(&outsider, &partial_header(1)));
+    assert!(!SignedPoa::verifier(public_keys(&keys)).validate(&SignatureDigest::default(), &header));
+}
+
+#[test]
+fn cs_7_tampered_header_does_not_verify() {
+    let keys = test_keypairs(2);
+    let engine = SignedPoa::author(public_keys(&keys), keys[0].clone());
+    let mut header = engine
+        .seal(&SignatureDigest::default(), partial_header(1))
+        .unwrap();
+
+    header.state_root = 7;
+    assert!(!engine.validate(&SignatureDigest::default(), &header));
+}
+
+#[test]
+fn cs_7_forged_signer_does_not_verify() {
+    let keys = test_keypairs(2);
+    let engine = SignedPoa::author(public_keys(&keys), keys[0].clone());
+    let mut header = engine
+        .seal(&SignatureDigest::default(), partial_header(1))
+        .unwrap();
+
+    // Claiming the signature came from a different authority does not work either.
+    header.consensus_digest.signer = keys[1].verifying_key().to_bytes();
+    assert!(!engine.validate(&SignatureDigest::default(), &header));
+}
+
+#[test]
+fn cs_7_verify_sub_chain_checks_every_signature() {
+    let keys = test_keypairs(2);
+    let engine = SignedPoa::author(public_keys(&keys), keys[0].clone());
+    let genesis = SignatureDigest::default();
+
+    let h1 = engine.seal(&genesis, partial_header(1)).unwrap();
+    let h2 = engine
+        .seal(&h1.consensus_digest, partial_header(2))
+        .unwrap();
+    assert!(engine.verify_sub_chain(&genesis, &[h1.clone(), h2.clone()]));
+
+    let mut bad_h2 = h2;
+    bad_h2.consensus_digest.signature[0] ^= 1;
+    assert!(!engine.verify_sub_chain(&genesis, &[h1, bad_h2]));
+}
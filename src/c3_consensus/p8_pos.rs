@@ -0,0 +1,139 @@
+//! Proof of Authority trusts a fixed set of authorities equally. In Proof of Stake, the
+//! authorities are those who have locked up tokens, and each authority's chance of authoring a
+//! block is proportional to how much they have at stake.
+//!
+//! In a real chain the stakes are recorded in the state, and change as users bond and unbond
+//! tokens. Our consensus engines only ever see headers, so here the engine is handed the stake
+//! table that was read from the state. Choosing who may author each block must be deterministic,
+//! so that every node agrees, but it should not be predictable far in advance. We use the parent
+//! hash as the random seed.
+
+use super::{Consensus, ConsensusAuthority, Header};
+use crate::hash;
+
+type Hash = u64;
+
+/// A toy Proof of Stake consensus engine. Exactly one staker is entitled to author each block,
+/// sampled from the stakes using the parent hash as a seed.
+pub struct SimplePos {
+    /// Each authority together with the amount they have staked. Authorities with no stake
+    /// are never selected.
+    pub stakes: Vec<(ConsensusAuthority, u64)>,
+}
+
+impl SimplePos {
+    /// The total amount staked by all authorities together.
+    pub fn total_stake(&self) -> u64 {
+        self.stakes.iter().map(|(_, stake)| stake).sum()
+    }
+
+    /// The authority who is entitled to author the child of the block with the given hash,
+    /// or `None` if nobody has staked anything.
+    ///
+    /// Think of the stakes as laid end to end on a number line. We draw a pseudo-random point
+    /// on that line, and whoever's stake it lands in is the author.
+    pub fn slot_author(&self, parent_hash: Hash) -> Option<ConsensusAuthority> {
+        let total = self.total_stake();
+        if total == 0 {
+            return None;
+        }
+        let mut point = hash(&parent_hash) % total;
+        for (authority, stake) in &self.stakes {
+            if point < *stake {
+                return Some(*authority);
+            }
+            point -= stake;
+        }
+        unreachable!("the point is always less than the total stake")
+    }
+}
+
+impl Consensus for SimplePos {
+    type Digest = ConsensusAuthority;
+
+    /// Check that the block was authored by the staker who was entitled to it.
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.slot_author(header.parent) == Some(header.consensus_digest)
+    }
+
+    /// Seal the block on behalf of whichever staker is entitled to author it.
+    fn seal(
+        &self,
+        _: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let author = self.slot_author(partial_header.parent)?;
+        Some(partial_header.seal(author))
+    }
+
+    fn author(&self, header: &Header<Self::Digest>) -> Option<ConsensusAuthority> {
+        header.consensus_digest.authority()
+    }
+
+    fn human_name() -> String {
+        "Proof of Stake".into()
+    }
+}
+
+#[cfg(test)]
+fn partial_header(parent: Hash) -> Header<()> {
+    Header {
+        parent,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[test]
+fn cs_8_only_entitled_author_is_valid() {
+    use ConsensusAuthority::*;
+    let pos = SimplePos {
+        stakes: vec![(Alice, 10), (Bob, 10), (Charlie, 10)],
+    };
+
+    for parent in 0..20 {
+        let sealed = pos.seal(&Alice, partial_header(parent)).unwrap();
+        assert_eq!(Some(sealed.consensus_digest), pos.slot_author(parent));
+        assert!(pos.validate(&Alice, &sealed));
+
+        for other in [Alice, Bob, Charlie] {
+            if other != sealed.consensus_digest {
+                assert!(!pos.validate(&Alice, &partial_header(parent).seal(other)));
+            }
+        }
+    }
+}
+
+#[test]
+fn cs_8_selection_is_proportional_to_stake() {
+    use ConsensusAuthority::*;
+    let pos = SimplePos {
+        stakes: vec![(Alice, 300), (Bob, 100), (Charlie, 0)],
+    };
+
+    let alice_slots = (0..1000)
+        .filter(|parent| pos.slot_author(*parent) == Some(Alice))
+        .count();
+    let charlie_slots = (0..1000)
+        .filter(|parent| pos.slot_author(*parent) == Some(Charlie))
+        .count();
+
+    // Alice holds three quarters of the stake, so she should author roughly 750 blocks.
+    assert!((650..850).contains(&alice_slots));
+    assert_eq!(charlie_slots, 0);
+}
+
+#[test]
+fn cs_8_no_stake_no_author() {
+    use ConsensusAuthority::*;
+    let pos = SimplePos {
+        stakes: vec![(Alice, 0)],
+    };
+
+    assert_eq!(pos.slot_author(0), None);
+    assert_eq!(pos.seal(&Alice, partial_header(0)), None);
+    assert!(!pos.validate(&Alice, &partial_header(0).seal(Alice)));
+}
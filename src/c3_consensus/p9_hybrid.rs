@@ -0,0 +1,186 @@
+//! Real-world consensus digests are rarely a single value. Substrate headers, for example,
+//! carry a digest log: a list of items, each of which is produced and checked by a different
+//! part of the system.
+//!
+//! Here we use a digest log to combine two engines we have already written. A block is only
+//! valid if it carries both a Proof of Work nonce and an authority's seal. Each engine only
+//! looks at its own item in the log, so the validity rules compose without either engine
+//! knowing about the other. Both seals are made over the same pre-seal header, so they can be
+//! produced independently and in either order.
+
+use super::{Consensus, ConsensusAuthority, Header, Pow, SimplePoa};
+
+/// A single entry in a header's digest log.
+#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DigestItem {
+    /// A Proof of Work nonce.
+    Work(u64),
+    /// The seal of a Proof of Authority authority.
+    Authority(ConsensusAuthority),
+    /// An announcement that the authority set will change to the given one.
+    /// See the authority changes part of this chapter.
+    AuthoritiesChange(Vec<ConsensusAuthority>),
+}
+
+/// The digest log. The genesis header has an empty log.
+pub type DigestLog = Vec<DigestItem>;
+
+/// Find the Proof of Work nonce in a digest log, if there is exactly one.
+fn work_item(log: &DigestLog) -> Option<u64> {
+    let mut nonces = log.iter().filter_map(|item| match item {
+        DigestItem::Work(nonce) => Some(*nonce),
+        _ => None,
+    });
+    match (nonces.next(), nonces.next()) {
+        (Some(nonce), None) => Some(nonce),
+        _ => None,
+    }
+}
+
+/// Find the authority seal in a digest log, if there is exactly one.
+fn authority_item(log: &DigestLog) -> Option<ConsensusAuthority> {
+    let mut authorities = log.iter().filter_map(|item| match item {
+        DigestItem::Authority(authority) => Some(*authority),
+        _ => None,
+    });
+    match (authorities.next(), authorities.next()) {
+        (Some(authority), None) => Some(authority),
+        _ => None,
+    }
+}
+
+/// A consensus engine that requires both valid Proof of Work and an authority's seal.
+///
+/// Authorities decide which blocks may be authored at all, while the work throttles how
+/// quickly even an authority can produce them.
+pub struct PowAndPoa {
+    pub pow: Pow,
+    pub poa: SimplePoa,
+}
+
+impl Consensus for PowAndPoa {
+    type Digest = DigestLog;
+
+    /// Each inner engine validates the header as if its own digest item were the whole seal.
+    /// The log must contain exactly one item for each engine and nothing else.
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        let log = &header.consensus_digest;
+        let (Some(nonce), Some(authority)) = (work_item(log), authority_item(log)) else {
+            return false;
+        };
+        let pre_header = header.pre_sealed();
+        let parent_nonce = work_item(parent_digest).unwrap_or_default();
+        let parent_authority = authority_item(parent_digest).unwrap_or_default();
+
+        log.len() == 2
+            && self.pow.validate(&parent_nonce, &pre_header.clone().seal(nonce))
+            && self.poa.validate(&parent_authority, &pre_header.seal(authority))
+    }
+
+    /// Ask each inner engine for its seal, and collect them into the log.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let parent_nonce = work_item(parent_digest).unwrap_or_default();
+        let parent_authority = authority_item(parent_digest).unwrap_or_default();
+
+        let work = self.pow.seal(&parent_nonce, partial_header.clone())?;
+        let authority = self.poa.seal(&parent_authority, partial_header.clone())?;
+        Some(partial_header.seal(vec![
+            DigestItem::Work(work.consensus_digest),
+            DigestItem::Authority(authority.consensus_digest),
+        ]))
+    }
+
+    fn author(&self, header: &Header<Self::Digest>) -> Option<ConsensusAuthority> {
+        authority_item(&header.consensus_digest)
+    }
+
+    fn human_name() -> String {
+        "Proof of Work + Proof of Authority".into()
+    }
+}
+
+#[cfg(test)]
+fn partial_header() -> Header<()> {
+    Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[cfg(test)]
+fn hybrid() -> PowAndPoa {
+    PowAndPoa {
+        pow: super::moderate_difficulty_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+    }
+}
+
+#[test]
+fn cs_9_hybrid_seal_is_valid() {
+    let engine = hybrid();
+    let sealed = engine.seal(&Vec::new(), partial_header()).unwrap();
+
+    assert_eq!(sealed.consensus_digest.len(), 2);
+    assert!(engine.validate(&Vec::new(), &sealed));
+    assert!(engine.verify_sub_chain(&Vec::new(), &[sealed]));
+}
+
+#[test]
+fn cs_9_hybrid_requires_both_items() {
+    let engine = hybrid();
+    let sealed = engine.seal(&Vec::new(), partial_header()).unwrap();
+    let nonce = work_item(&sealed.consensus_digest).unwrap();
+
+    let work_only = partial_header().seal(vec![DigestItem::Work(nonce)]);
+    let authority_only =
+        partial_header().seal(vec![DigestItem::Authority(ConsensusAuthority::Alice)]);
+    assert!(!engine.validate(&Vec::new(), &work_only));
+    assert!(!engine.validate(&Vec::new(), &authority_only));
+}
+
+#[test]
+fn cs_9_hybrid_rejects_either_bad_item() {
+    let engine = hybrid();
+    let sealed = engine.seal(&Vec::new(), partial_header()).unwrap();
+    let nonce = work_item(&sealed.consensus_digest).unwrap();
+
+    // A good nonce with an outsider's seal.
+    let outsider = partial_header().seal(vec![
+        DigestItem::Work(nonce),
+        DigestItem::Authority(ConsensusAuthority::Charlie),
+    ]);
+    assert!(!engine.validate(&Vec::new(), &outsider));
+
+    // A good seal with a nonce that does not meet the threshold.
+    let mut bad_nonce = nonce;
+    while engine.pow.validate(&0, &partial_header().seal(bad_nonce)) {
+        bad_nonce += 1;
+    }
+    let lazy = partial_header().seal(vec![
+        DigestItem::Work(bad_nonce),
+        DigestItem::Authority(ConsensusAuthority::Alice),
+    ]);
+    assert!(!engine.validate(&Vec::new(), &lazy));
+}
+
+#[test]
+fn cs_9_hybrid_rejects_duplicate_items() {
+    let engine = hybrid();
+    let mut sealed = engine.seal(&Vec::new(), partial_header()).unwrap();
+    sealed
+        .consensus_digest
+        .push(DigestItem::Authority(ConsensusAuthority::Bob));
+
+    assert!(!engine.validate(&Vec::new(), &sealed));
+}
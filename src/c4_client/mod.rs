@@ -12,12 +12,15 @@
 
 // TODO Exercise for later: Client does a hard fork at a particular block height. The fork logic is to change runtimes.
 
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
 use crate::{
     c1_state_machine::StateMachine,
-    c3_consensus::{Consensus, Header},
+    c2_blockchain::Checkpoints,
+    c3_consensus::{Consensus, EquivocationProof, Header},
 };
-use p1_data_structure::Block;
-use p3_fork_choice::ForkChoice;
+pub use p1_data_structure::Block;
 
 mod p1_data_structure;
 mod p2_importing_blocks;
@@ -25,6 +28,66 @@ mod p3_fork_choice;
 mod p4_transaction_pool;
 mod p5_authoring_blocks;
 mod p6_finality;
+mod p7_external_mining;
+mod p8_state_rollback;
+mod p9_full_client;
+mod p10_import_queue;
+mod p11_block_store;
+#[cfg(feature = "sled")]
+mod p12_sled_store;
+mod p13_pruning;
+mod p14_author;
+#[cfg(feature = "rpc")]
+mod p15_rpc;
+mod p16_events;
+mod p17_light_client;
+mod p18_warp_sync;
+mod p19_sync;
+mod p20_chain_spec;
+mod p21_metrics;
+mod p22_visualize;
+#[cfg(feature = "rlp")]
+mod p23_rlp;
+#[cfg(feature = "chain-file")]
+mod p24_chain_file;
+mod p25_verification_cache;
+
+pub use p2_importing_blocks::{ImportBlock, ImportError};
+pub use p3_fork_choice::{
+    ForkChoice, ForkTree, HeaviestChain, LongestChain, ReorgEvent, TieBreak, TreeNode,
+};
+pub use p4_transaction_pool::{
+    by_fee, by_tip, PoolError, PoolStatus, SimplePool, TransactionPool, ValidatingPool,
+    DEFAULT_BAN_DURATION, DEFAULT_BAN_THRESHOLD, MAX_STRUCK,
+};
+pub use p6_finality::{FinalityTracker, JustifiedHeader, Justification, Vote};
+pub use p7_external_mining::{work_channel, MinerHandle, Seal, WorkPackage, WorkServer};
+pub use p8_state_rollback::BestState;
+pub use p10_import_queue::{ImportQueue, ImportResult, OrphanPool, DEFAULT_ORPHAN_LIMIT};
+pub use p11_block_store::{BlockStore, MemoryStore};
+#[cfg(feature = "sled")]
+pub use p12_sled_store::{SledStore, SledStoreError, SCHEMA_VERSION};
+pub use p13_pruning::{Error, Pruning};
+pub use p14_author::Author;
+#[cfg(feature = "rpc")]
+pub use p15_rpc::{
+    stream_events, RpcError, EXTRINSIC_REFUSED, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND,
+    PARSE_ERROR, STATE_PRUNED,
+};
+pub use p16_events::ChainEvent;
+pub use p17_light_client::{LightClient, MerkleProof, MerkleState, Merklized, ProofError};
+pub use p18_warp_sync::{WarpProof, WarpSyncError};
+pub use p19_sync::{
+    SyncError, SyncRequest, SyncResponse, SyncWorker, MAX_BLOCKS_PER_RESPONSE,
+};
+pub use p20_chain_spec::{ChainSpec, ChainSpecError, ConsensusParams, FromChainSpec};
+pub use p21_metrics::Metrics;
+#[cfg(feature = "chain-file")]
+pub use p24_chain_file::{
+    crc32, read_chain_file, write_chain_file, ChainFileError, CHAIN_FILE_MAGIC, CHAIN_FILE_VERSION,
+    MAX_RECORD_LEN,
+};
+pub use p25_verification_cache::{VerificationCache, DEFAULT_VERIFICATION_CACHE_SIZE};
 
 type Hash = u64;
 
@@ -37,6 +100,8 @@ type Hash = u64;
 /// * consensus system - It can use any consensus engine that implements our trait.
 /// * Fork Choice - It can use any fork choice we discussed and more. This is explored shortly.
 /// * Transaction Pool - It can use any logic for queueing and prioritizing incoming future transactions.
+/// * Block storage - It can keep its blocks anywhere that implements `BlockStore`. By default they are
+///   kept in memory.
 ///
 /// As you work through the sections in this chapter, you will add features to the client
 /// by implementing more and more methods on it.
@@ -44,12 +109,14 @@ type Hash = u64;
 /// In practice the trait bounds here will always be the same:
 /// C: Client
 /// SM: StateMachine
-/// FC: ForkChoice<C>
+/// FC: ForkChoice
 /// P: TransactionPool<SM>
+/// S: BlockStore<C, SM>
 /// 
-/// But we leave them unconstrained here to avoid repeating many where clauses throughout the section.
-/// Instead we bind them on impl blocks.
-pub struct FullClient<C, SM, FC, P>
+/// The consensus engine and state machine are bound here because the block database
+/// is built from their associated types. We leave the others unconstrained to avoid
+/// repeating many where clauses throughout the section. Instead we bind them on impl blocks.
+pub struct FullClient<C: Consensus, SM: StateMachine, FC, P, S = MemoryStore<C, SM>>
 {
     /// The consensus engine used by this client.
     consensus_engine: C,
@@ -60,8 +127,27 @@ pub struct FullClient<C, SM, FC, P>
     /// The transaction pool used by this client.
     transaction_pool: P,
 
-    // TODO: You are free to add more fields here, and you will probably need to.
-    // Please document them as you add them.
+    /// Every block this client has imported, along with the best and finalized blocks.
+    store: S,
+    /// The post-state of every imported block, keyed by block hash.
+    states: HashMap<Hash, SM::State>,
+    /// A working copy of the post-state of the best block, which follows the best chain.
+    best_state: SM::State,
+    /// How the imported blocks are related, along with their cumulative work.
+    /// This is what the fork choice rule looks at.
+    fork_tree: ForkTree,
+    /// Everyone who wants to hear about the chain's progress.
+    event_subscribers: Vec<Sender<ChainEvent>>,
+    /// Hash of the genesis block this client was initialized with.
+    genesis_hash: Hash,
+    /// Trusted blocks. Blocks that contradict them are never imported.
+    checkpoints: Checkpoints,
+    /// Equivocations noticed while importing blocks, waiting to be reported on chain.
+    equivocations: Vec<EquivocationProof<C::Digest>>,
+    /// Which states to keep, and which to prune.
+    pruning: Pruning,
+    /// How the client has been doing since it started.
+    metrics: Metrics,
+    /// Blocks that recently passed full verification, along with their post-states.
+    verified: VerificationCache<SM::State>,
 }
-
-//TODO Consider exploring LightClient as well. It may import headers but not blocks for example.
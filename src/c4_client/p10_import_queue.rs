@@ -0,0 +1,408 @@
+//! Blocks arrive from the network in bursts, in no particular order, and some of them are
+//! invalid. Executing a block is by far the most expensive part of importing it, so a client
+//! should not execute anything until it has checked everything that is cheap to check: that
+//! the block is new, that it links to a known parent at the right height, and that its seal is
+//! valid. A peer that sends garbage then costs us very little.
+//!
+//! Blocks whose parent is not known yet are not necessarily bad. Their parent may simply
+//! still be on its way. The import queue holds on to them in an orphan pool, and imports them
+//! as soon as their parent has been imported.
+//!
+//! Anyone can make up blocks whose parent does not exist, so the orphan pool must not grow
+//! without bound. It holds a limited number of blocks, and when it is full it evicts the
+//! orphan that has been waiting the longest. That block's parent has had the most time to
+//! arrive and has not, so it is the least likely to ever be imported.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::{Block, BlockStore, ForkChoice, FullClient, ImportError};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+
+type Hash = u64;
+
+/// The number of orphans that an import queue holds unless told otherwise.
+pub const DEFAULT_ORPHAN_LIMIT: usize = 256;
+
+/// What happened to a block that went through the import queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportResult {
+    /// The block was imported.
+    Imported,
+    /// The block had already been imported.
+    AlreadyKnown,
+    /// The block is invalid, for the given reason, and was thrown away.
+    Bad(ImportError),
+    /// The block's parent is not known. The queue holds on to the block until it is.
+    Orphan,
+    /// The block was an orphan, and was thrown away to make room for other orphans.
+    Evicted,
+}
+
+impl From<ImportError> for ImportResult {
+    fn from(error: ImportError) -> Self {
+        match error {
+            ImportError::AlreadyKnown => ImportResult::AlreadyKnown,
+            ImportError::UnknownParent => ImportResult::Orphan,
+            error => ImportResult::Bad(error),
+        }
+    }
+}
+
+/// Blocks whose parent is not known yet, waiting for that parent to be imported.
+pub struct OrphanPool<C: Consensus, SM: StateMachine> {
+    /// The most blocks that the pool holds at once.
+    limit: usize,
+    /// The held blocks, keyed by the hash of their parent.
+    by_parent: HashMap<Hash, Vec<Block<C, SM>>>,
+    /// The hashes of the held blocks, in the order they arrived.
+    arrivals: VecDeque<Hash>,
+}
+
+impl<C: Consensus, SM: StateMachine> OrphanPool<C, SM> {
+    /// An empty pool that holds at most the given number of blocks.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            by_parent: HashMap::new(),
+            arrivals: VecDeque::new(),
+        }
+    }
+
+    /// The number of blocks held.
+    pub fn len(&self) -> usize {
+        self.arrivals.len()
+    }
+
+    /// Whether the pool holds no blocks at all.
+    pub fn is_empty(&self) -> bool {
+        self.arrivals.is_empty()
+    }
+
+    /// The most blocks that the pool holds at once.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Whether the block with the given hash is held.
+    pub fn contains(&self, hash: Hash) -> bool {
+        self.arrivals.contains(&hash)
+    }
+
+    /// Hold on to the given block until its parent is imported. If the pool is full, the
+    /// blocks that have been waiting the longest are evicted to make room. Returns the hashes
+    /// of the evicted blocks. Blocks that are already held are ignored.
+    pub fn insert(&mut self, block: Block<C, SM>) -> Vec<Hash> {
+        let hash = block.hash();
+        if self.contains(hash) {
+            return Vec::new();
+        }
+        self.arrivals.push_back(hash);
+        self.by_parent
+            .entry(block.header.parent)
+            .or_default()
+            .push(block);
+
+        let mut evicted = Vec::new();
+        while self.arrivals.len() > self.limit {
+            let Some(oldest) = self.arrivals.pop_front() else {
+                break;
+            };
+            self.by_parent.retain(|_, children| {
+                children.retain(|child| child.hash() != oldest);
+                !children.is_empty()
+            });
+            evicted.push(oldest);
+        }
+        evicted
+    }
+
+    /// Remove and return every held block whose parent is the given block, in the order they
+    /// arrived.
+    pub fn take_children(&mut self, parent: Hash) -> Vec<Block<C, SM>> {
+        let children = self.by_parent.remove(&parent).unwrap_or_default();
+        for child in &children {
+            let hash = child.hash();
+            self.arrivals.retain(|held| *held != hash);
+        }
+        children
+    }
+}
+
+/// Blocks waiting to be imported into a client.
+pub struct ImportQueue<C: Consensus, SM: StateMachine> {
+    /// Blocks that have not been looked at yet, in the order they arrived.
+    incoming: VecDeque<Block<C, SM>>,
+    /// Blocks whose parent is not known yet.
+    orphans: OrphanPool<C, SM>,
+}
+
+// Derive would require `C` and `SM` themselves to implement `Default`.
+impl<C: Consensus, SM: StateMachine> Default for ImportQueue<C, SM> {
+    fn default() -> Self {
+        Self::with_orphan_limit(DEFAULT_ORPHAN_LIMIT)
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> ImportQueue<C, SM> {
+    /// An empty queue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty queue that holds at most the given number of orphans.
+    pub fn with_orphan_limit(limit: usize) -> Self {
+        Self {
+            incoming: VecDeque::new(),
+            orphans: OrphanPool::new(limit),
+        }
+    }
+
+    /// Add a block to the end of the queue.
+    pub fn push(&mut self, block: Block<C, SM>) {
+        self.incoming.push_back(block);
+    }
+
+    /// The number of blocks that have not been looked at yet.
+    pub fn pending(&self) -> usize {
+        self.incoming.len()
+    }
+
+    /// The number of blocks waiting for their parent to be imported.
+    pub fn orphans(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// The blocks waiting for their parent to be imported.
+    pub fn orphan_pool(&self) -> &OrphanPool<C, SM> {
+        &self.orphans
+    }
+
+    /// Import every queued block into the given client, and report what happened to each.
+    ///
+    /// Each block is first checked without being executed, and only executed if that
+    /// succeeds. When a block is imported, any orphans waiting for it are imported right after
+    /// it, so they are reported twice: once as `Orphan`, and once more when they are imported.
+    /// When a block turns out to be bad, so do all the orphans waiting for it. Orphans that are
+    /// evicted to make room for others are reported as `Evicted`.
+    pub fn process<FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+    ) -> Vec<(Hash, ImportResult)>
+    where
+        C::Digest: Default,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let mut results = Vec::new();
+        while let Some(block) = self.incoming.pop_front() {
+            let block_hash = block.hash();
+            let result = match client.verify_header(&block) {
+                Ok(()) => client
+                    .try_import_block(block)
+                    .map_or_else(ImportResult::from, |_| ImportResult::Imported),
+                Err(ImportError::UnknownParent) => {
+                    let evicted = self.orphans.insert(block);
+                    results.push((block_hash, ImportResult::Orphan));
+                    for hash in evicted {
+                        results.push((hash, ImportResult::Evicted));
+                    }
+                    continue;
+                }
+                Err(error) => ImportResult::from(error),
+            };
+            results.push((block_hash, result));
+
+            match result {
+                ImportResult::Imported => {
+                    let children = self.orphans.take_children(block_hash);
+                    for child in children.into_iter().rev() {
+                        self.incoming.push_front(child);
+                    }
+                }
+                ImportResult::Bad(error) => {
+                    self.reject_descendants(block_hash, error, &mut results)
+                }
+                ImportResult::AlreadyKnown | ImportResult::Orphan | ImportResult::Evicted => {}
+            }
+        }
+        results
+    }
+
+    /// Throw away every orphan that descends from the given bad block.
+    fn reject_descendants(
+        &mut self,
+        bad_block: Hash,
+        error: ImportError,
+        results: &mut Vec<(Hash, ImportResult)>,
+    ) {
+        let mut bad = vec![bad_block];
+        while let Some(parent) = bad.pop() {
+            for child in self.orphans.take_children(parent) {
+                let child_hash = child.hash();
+                results.push((child_hash, ImportResult::Bad(error)));
+                bad.push(child_hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use super::ImportBlock;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), ()>;
+
+#[cfg(test)]
+fn genesis_client() -> TestClient {
+    FullClient::new((), Currency, (), (), Balances::from([(Alice, 100)]))
+}
+
+/// A chain of the given length on top of genesis, in which every block mints to Bob.
+#[cfg(test)]
+fn chain(client: &TestClient, length: usize) -> Vec<Block<(), Currency>> {
+    let mut parent = client.get_block(client.genesis_hash()).unwrap();
+    let mut state = client.get_state(client.genesis_hash()).unwrap();
+    let mut blocks = Vec::new();
+    for _ in 0..length {
+        let mint = CurrencyTransaction::Mint { to: Bob, amount: 1 };
+        let block = parent.child(&(), &state, vec![mint.clone()]).unwrap();
+        state = Currency::next_state(&state, &mint);
+        blocks.push(block.clone());
+        parent = block;
+    }
+    blocks
+}
+
+#[test]
+fn cl_10_in_order_blocks_are_imported() {
+    let mut client = genesis_client();
+    let blocks = chain(&client, 3);
+    let mut queue = ImportQueue::new();
+    for block in &blocks {
+        queue.push(block.clone());
+    }
+    queue.push(blocks[0].clone());
+    assert_eq!(queue.pending(), 4);
+
+    let results = queue.process(&mut client);
+    assert_eq!(
+        results,
+        vec![
+            (blocks[0].hash(), ImportResult::Imported),
+            (blocks[1].hash(), ImportResult::Imported),
+            (blocks[2].hash(), ImportResult::Imported),
+            (blocks[0].hash(), ImportResult::AlreadyKnown),
+        ]
+    );
+    assert_eq!(client.best_block(), blocks[2].hash());
+    assert_eq!(queue.pending(), 0);
+}
+
+#[test]
+fn cl_10_orphans_are_imported_when_parent_arrives() {
+    let mut client = genesis_client();
+    let blocks = chain(&client, 3);
+    let mut queue = ImportQueue::new();
+
+    queue.push(blocks[2].clone());
+    queue.push(blocks[1].clone());
+    assert_eq!(
+        queue.process(&mut client),
+        vec![
+            (blocks[2].hash(), ImportResult::Orphan),
+            (blocks[1].hash(), ImportResult::Orphan),
+        ]
+    );
+    assert_eq!(queue.orphans(), 2);
+    assert_eq!(client.best_block(), client.genesis_hash());
+
+    // The missing block arrives, and pulls the whole chain in behind it.
+    queue.push(blocks[0].clone());
+    assert_eq!(
+        queue.process(&mut client),
+        vec![
+            (blocks[0].hash(), ImportResult::Imported),
+            (blocks[1].hash(), ImportResult::Imported),
+            (blocks[2].hash(), ImportResult::Imported),
+        ]
+    );
+    assert_eq!(queue.orphans(), 0);
+    assert_eq!(client.best_block(), blocks[2].hash());
+}
+
+#[test]
+fn cl_10_bad_blocks_take_their_orphans_with_them() {
+    let mut client = genesis_client();
+    let blocks = chain(&client, 3);
+    let mut bad = blocks[0].clone();
+    bad.header.state_root = 0;
+    let mut bad_link = blocks[1].clone();
+    bad_link.header.height = 7;
+
+    let mut queue = ImportQueue::new();
+    queue.push(blocks[2].clone());
+    queue.push(bad_link.clone());
+    queue.push(blocks[1].clone());
+    queue.push(bad.clone());
+    queue.push(blocks[0].clone());
+    let results = queue.process(&mut client);
+
+    // The wrong height fails the cheap checks, so that block is never executed. The wrong
+    // state root is only caught by executing the block.
+    let invalid = ImportResult::Bad(ImportError::InvalidBlock);
+    assert_eq!(
+        results,
+        vec![
+            (blocks[2].hash(), ImportResult::Orphan),
+            (bad_link.hash(), ImportResult::Orphan),
+            (blocks[1].hash(), ImportResult::Orphan),
+            (bad.hash(), invalid),
+            (blocks[0].hash(), ImportResult::Imported),
+            (bad_link.hash(), invalid),
+            (blocks[1].hash(), ImportResult::Imported),
+            (blocks[2].hash(), ImportResult::Imported),
+        ]
+    );
+    assert_eq!(client.best_block(), blocks[2].hash());
+    assert_eq!(queue.orphans(), 0);
+}
+
+#[test]
+fn cl_10_full_orphan_pool_evicts_oldest() {
+    let mut client = genesis_client();
+    let blocks = chain(&client, 4);
+    let mut queue = ImportQueue::with_orphan_limit(2);
+
+    for block in blocks[1..].iter().rev() {
+        queue.push(block.clone());
+    }
+    queue.push(blocks[2].clone());
+    assert_eq!(
+        queue.process(&mut client),
+        vec![
+            (blocks[3].hash(), ImportResult::Orphan),
+            (blocks[2].hash(), ImportResult::Orphan),
+            (blocks[1].hash(), ImportResult::Orphan),
+            (blocks[3].hash(), ImportResult::Evicted),
+            (blocks[2].hash(), ImportResult::Orphan),
+        ]
+    );
+    assert_eq!(queue.orphans(), 2);
+    assert!(!queue.orphan_pool().contains(blocks[3].hash()));
+
+    // The evicted block is forgotten, but the rest of the chain is still imported.
+    queue.push(blocks[0].clone());
+    queue.process(&mut client);
+    assert_eq!(client.best_block(), blocks[2].hash());
+    assert!(queue.orphan_pool().is_empty());
+
+    // The evicted block can still be imported if it is sent again.
+    queue.push(blocks[3].clone());
+    queue.process(&mut client);
+    assert_eq!(client.best_block(), blocks[3].hash());
+}
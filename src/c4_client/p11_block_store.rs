@@ -0,0 +1,302 @@
+//! So far our client has kept every block in a `HashMap`. That is fine for tests, but a real
+//! node must keep its blocks on disk, so that it does not have to download the whole chain
+//! again every time it restarts. Different nodes make different choices here. Some use an
+//! embedded key-value database, some use a custom file format, and some keep only recent
+//! blocks at all.
+//!
+//! So the client does not talk to a `HashMap` directly. It talks to a `BlockStore`, which
+//! stores headers and bodies separately, and remembers which block is best and which is
+//! finalized. Headers are small and are needed for almost everything. Bodies are large, and
+//! are only needed to execute blocks or to serve them to peers. Keeping them apart lets a
+//! backend treat them differently. The in-memory store here is the one the client uses unless
+//! it is given another.
+
+use std::collections::HashMap;
+
+use super::p1_data_structure::execute;
+use super::{Block, FullClient, Header};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// Somewhere to keep the blocks that a client has imported.
+pub trait BlockStore<C: Consensus, SM: StateMachine> {
+    /// Store the given header. Returns its hash. Storing a header twice has no effect.
+    fn insert_header(&mut self, header: Header<C::Digest>) -> Hash;
+
+    /// Store the body of the block with the given hash, together with the context it is
+    /// executed in. The header must already be stored.
+    fn insert_body(&mut self, hash: Hash, body: Vec<SM::Transition>, context: BlockContext);
+
+    /// Look up a header. Numbers are resolved along the best chain.
+    /// Returns None if the block is not known.
+    fn header(&self, id: BlockId) -> Option<Header<C::Digest>>;
+
+    /// Look up the body of a block. Returns None if the body is not known.
+    fn body(&self, hash: Hash) -> Option<Vec<SM::Transition>>;
+
+    /// Look up the context that a block's body is executed in.
+    /// Returns None if the body is not known.
+    fn context(&self, hash: Hash) -> Option<BlockContext>;
+
+    /// Record that the block with the given hash, whose header must already be stored, is now
+    /// the best block. From now on, numbers are resolved along its chain.
+    fn set_best(&mut self, hash: Hash);
+
+    /// The hash of the best block.
+    fn best(&self) -> Hash;
+
+    /// Record that the block with the given hash is now finalized.
+    fn set_finalized(&mut self, hash: Hash);
+
+    /// The hash of the latest finalized block.
+    fn finalized(&self) -> Hash;
+
+    /// Whether the header of the block with the given hash is stored.
+    fn contains(&self, hash: Hash) -> bool {
+        self.header(BlockId::Hash(hash)).is_some()
+    }
+
+    /// Store a whole block. Returns its hash.
+    fn insert_block(&mut self, block: Block<C, SM>) -> Hash {
+        let hash = self.insert_header(block.header);
+        self.insert_body(hash, block.body, block.context);
+        hash
+    }
+
+    /// Look up a whole block. Returns None unless both its header and body are stored.
+    fn block(&self, hash: Hash) -> Option<Block<C, SM>> {
+        Some(Block {
+            header: self.header(BlockId::Hash(hash))?,
+            body: self.body(hash)?,
+            context: self.context(hash)?,
+        })
+    }
+}
+
+/// A block store that keeps everything in memory, and forgets it all when dropped.
+pub struct MemoryStore<C: Consensus, SM: StateMachine> {
+    headers: HashMap<Hash, Header<C::Digest>>,
+    bodies: HashMap<Hash, (Vec<SM::Transition>, BlockContext)>,
+    /// The hash of every block on the best chain, indexed by height.
+    canonical: Vec<Hash>,
+    best: Hash,
+    finalized: Hash,
+}
+
+// Derive would require `C` and `SM` themselves to implement `Default`.
+impl<C: Consensus, SM: StateMachine> Default for MemoryStore<C, SM> {
+    fn default() -> Self {
+        Self {
+            headers: HashMap::new(),
+            bodies: HashMap::new(),
+            canonical: Vec::new(),
+            best: 0,
+            finalized: 0,
+        }
+    }
+}
+
+impl<C, SM> BlockStore<C, SM> for MemoryStore<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Clone,
+{
+    fn insert_header(&mut self, header: Header<C::Digest>) -> Hash {
+        let hash = hash(&header);
+        self.headers.entry(hash).or_insert(header);
+        hash
+    }
+
+    fn insert_body(&mut self, hash: Hash, body: Vec<SM::Transition>, context: BlockContext) {
+        if self.headers.contains_key(&hash) {
+            self.bodies.insert(hash, (body, context));
+        }
+    }
+
+    fn header(&self, id: BlockId) -> Option<Header<C::Digest>> {
+        let hash = match id {
+            BlockId::Hash(hash) => hash,
+            BlockId::Number(number) => *self.canonical.get(usize::try_from(number).ok()?)?,
+        };
+        self.headers.get(&hash).cloned()
+    }
+
+    fn body(&self, hash: Hash) -> Option<Vec<SM::Transition>> {
+        self.bodies.get(&hash).map(|(body, _)| body.clone())
+    }
+
+    fn context(&self, hash: Hash) -> Option<BlockContext> {
+        self.bodies.get(&hash).map(|(_, context)| *context)
+    }
+
+    fn set_best(&mut self, hash: Hash) {
+        let Some(header) = self.headers.get(&hash) else {
+            return;
+        };
+        self.best = hash;
+
+        // Walk back until the new chain meets the old one. Everything below that point is
+        // already indexed correctly.
+        let mut height = header.height as usize;
+        let mut hash = hash;
+        self.canonical.resize(height + 1, 0);
+        while self.canonical[height] != hash {
+            self.canonical[height] = hash;
+            match self.headers.get(&hash) {
+                Some(header) if height > 0 => {
+                    hash = header.parent;
+                    height -= 1;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn best(&self) -> Hash {
+        self.best
+    }
+
+    fn set_finalized(&mut self, hash: Hash) {
+        self.finalized = hash;
+    }
+
+    fn finalized(&self) -> Hash {
+        self.finalized
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    S: BlockStore<C, SM>,
+{
+    /// Re-execute the best chain that the store already holds, so that the client knows the
+    /// state at each of its blocks again. Blocks that are not on the best chain are not
+    /// replayed, and neither is anything after the first block that fails to verify.
+    pub(crate) fn replay_best_chain(&mut self) {
+        let best = self.store.best();
+        let finalized = self.store.finalized();
+
+        let mut parent_hash = self.genesis_hash;
+        let mut height = 1;
+        while parent_hash != best {
+            let Some(header) = self.store.header(BlockId::Number(height)) else {
+                break;
+            };
+            let block_hash = hash(&header);
+            let (Some(parent), Some(block)) =
+                (self.store.block(parent_hash), self.store.block(block_hash))
+            else {
+                break;
+            };
+            let pre_state = &self.states[&parent_hash];
+            let chain = std::slice::from_ref(&block);
+            if !parent.verify_sub_chain(&self.consensus_engine, pre_state, chain) {
+                break;
+            }
+
+            let post_state = execute::<SM>(pre_state, &block.body, &block.context);
+            let work = self.consensus_engine.work(&block.header);
+            self.fork_tree.insert(block_hash, parent_hash, work);
+            self.states.insert(block_hash, post_state);
+            parent_hash = block_hash;
+            height += 1;
+        }
+
+        self.store.set_best(parent_hash);
+        self.best_state = self.states[&parent_hash].clone();
+        if !self.fork_tree.finalize(finalized) {
+            self.store.set_finalized(self.genesis_hash);
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestStore = MemoryStore<(), LightSwitch>;
+
+#[test]
+fn cl_11_headers_and_bodies_are_stored_separately() {
+    let mut store = TestStore::default();
+    let genesis = Block::<(), LightSwitch>::genesis(&false);
+    let child = genesis.child(&(), &false, vec![(), ()]).unwrap();
+
+    let genesis_hash = store.insert_block(genesis.clone());
+    let child_hash = store.insert_header(child.header.clone());
+    assert!(store.contains(child_hash));
+    assert_eq!(
+        store.header(BlockId::Hash(child_hash)),
+        Some(child.header.clone())
+    );
+    assert_eq!(store.body(child_hash), None);
+    assert_eq!(store.block(child_hash), None);
+
+    store.insert_body(child_hash, child.body.clone(), *child.context());
+    assert_eq!(store.body(child_hash), Some(vec![(), ()]));
+    assert_eq!(store.block(child_hash), Some(child));
+    assert_eq!(store.block(genesis_hash), Some(genesis));
+
+    // A body without a header is not stored.
+    store.insert_body(42, vec![()], BlockContext::default());
+    assert_eq!(store.body(42), None);
+}
+
+#[test]
+fn cl_11_numbers_follow_best_chain() {
+    let mut store = TestStore::default();
+    let genesis = Block::<(), LightSwitch>::genesis(&false);
+    let a1 = genesis.child(&(), &false, vec![]).unwrap();
+    let a2 = a1.child(&(), &false, vec![]).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    for block in [&genesis, &a1, &a2, &b1] {
+        store.insert_block(block.clone());
+    }
+    assert_eq!(store.header(BlockId::Number(0)), None);
+
+    store.set_best(a2.hash());
+    assert_eq!(store.best(), a2.hash());
+    assert_eq!(
+        store.header(BlockId::Number(0)),
+        Some(genesis.header.clone())
+    );
+    assert_eq!(store.header(BlockId::Number(1)), Some(a1.header.clone()));
+    assert_eq!(store.header(BlockId::Number(2)), Some(a2.header.clone()));
+
+    // Switching to a shorter branch forgets the numbers above it.
+    store.set_best(b1.hash());
+    assert_eq!(store.header(BlockId::Number(1)), Some(b1.header.clone()));
+    assert_eq!(store.header(BlockId::Number(2)), None);
+    assert_eq!(store.header(BlockId::Hash(a2.hash())), Some(a2.header));
+
+    store.set_finalized(b1.hash());
+    assert_eq!(store.finalized(), b1.hash());
+}
+
+#[test]
+fn cl_11_client_keeps_store_up_to_date() {
+    use super::ImportBlock;
+
+    let mut client = FullClient::with_store((), LightSwitch, (), (), TestStore::default(), false);
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![]).unwrap();
+    assert!(client.import_block(b1.clone()));
+    assert!(client.import_block(b2.clone()));
+    assert!(client.manually_finalize_block(b1.hash()));
+
+    let store = client.store();
+    assert_eq!(store.best(), b2.hash());
+    assert_eq!(store.finalized(), b1.hash());
+    assert_eq!(store.header(BlockId::Number(1)), Some(b1.header));
+    assert_eq!(store.block(b2.hash()), Some(b2));
+}
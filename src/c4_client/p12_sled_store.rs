@@ -0,0 +1,320 @@
+//! A node that keeps its blocks in memory forgets the whole chain whenever it stops. Here we
+//! keep them in sled, an embedded key-value database, so that a node can restart and carry on
+//! from where it left off.
+//!
+//! Headers, bodies, and the best chain's index from height to hash each live in their own
+//! sled tree. The best and finalized blocks are stored under fixed keys, so that a restarted
+//! client knows which chain to pick up. The database also records the version of the layout it
+//! was written with. Code that expects a different layout refuses to open it, rather than
+//! misreading it.
+//!
+//! This part is only compiled with the `sled` feature.
+
+use std::marker::PhantomData;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{BlockStore, Header};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// The version of the on-disk layout that this store reads and writes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+const BEST_KEY: &[u8] = b"best";
+const FINALIZED_KEY: &[u8] = b"finalized";
+
+// The `BlockStore` trait has no way to report errors. A node whose disk fails can not carry on
+// anyway, so it stops with this message.
+const DATABASE_FAILED: &str = "the block database failed";
+
+/// The reasons that a sled store can not be opened.
+#[derive(Debug)]
+pub enum SledStoreError {
+    /// The database could not be opened or read.
+    Database(sled::Error),
+    /// The database was written with a different layout, whose version is given.
+    UnsupportedSchema(u32),
+}
+
+impl From<sled::Error> for SledStoreError {
+    fn from(error: sled::Error) -> Self {
+        SledStoreError::Database(error)
+    }
+}
+
+/// A block store that keeps everything in a sled database on disk.
+pub struct SledStore<C: Consensus, SM: StateMachine> {
+    /// The database itself. Its default tree holds the schema version and the best and
+    /// finalized block pointers.
+    db: sled::Db,
+    /// Every stored header, keyed by hash.
+    headers: sled::Tree,
+    /// Every stored body and its context, keyed by hash.
+    bodies: sled::Tree,
+    /// The hash of every block on the best chain, keyed by height.
+    canonical: sled::Tree,
+    _phantom: PhantomData<(C, SM)>,
+}
+
+impl<C: Consensus, SM: StateMachine> SledStore<C, SM> {
+    /// Open the database at the given path, creating it if it does not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SledStoreError> {
+        let db = sled::open(path)?;
+        match db.get(SCHEMA_VERSION_KEY)? {
+            None => {
+                db.insert(SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_be_bytes())?;
+            }
+            Some(bytes) => {
+                // A version that can not even be read is certainly not ours.
+                let version = <[u8; 4]>::try_from(bytes.as_ref()).map_or(0, u32::from_be_bytes);
+                if version != SCHEMA_VERSION {
+                    return Err(SledStoreError::UnsupportedSchema(version));
+                }
+            }
+        }
+
+        Ok(Self {
+            headers: db.open_tree("headers")?,
+            bodies: db.open_tree("bodies")?,
+            canonical: db.open_tree("canonical")?,
+            db,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// The hash stored under the given key, if any.
+    fn get_hash(tree: &sled::Tree, key: &[u8]) -> Option<Hash> {
+        let bytes = tree.get(key).expect(DATABASE_FAILED)?;
+        <[u8; 8]>::try_from(bytes.as_ref())
+            .ok()
+            .map(u64::from_be_bytes)
+    }
+}
+
+/// Read and decode the value stored under the given key, if any.
+fn get<T: DeserializeOwned>(tree: &sled::Tree, key: &[u8]) -> Option<T> {
+    let bytes = tree.get(key).expect(DATABASE_FAILED)?;
+    Some(bincode::deserialize(&bytes).expect(DATABASE_FAILED))
+}
+
+/// Encode the given value and store it under the given key.
+fn put<T: Serialize>(tree: &sled::Tree, key: &[u8], value: &T) {
+    let bytes = bincode::serialize(value).expect(DATABASE_FAILED);
+    tree.insert(key, bytes).expect(DATABASE_FAILED);
+}
+
+impl<C, SM> BlockStore<C, SM> for SledStore<C, SM>
+where
+    C: Consensus,
+    C::Digest: Serialize + DeserializeOwned,
+    SM: StateMachine,
+    SM::Transition: Serialize + DeserializeOwned,
+{
+    fn insert_header(&mut self, header: Header<C::Digest>) -> Hash {
+        let hash = hash(&header);
+        if !self.contains(hash) {
+            put(&self.headers, &hash.to_be_bytes(), &header);
+        }
+        hash
+    }
+
+    fn insert_body(&mut self, hash: Hash, body: Vec<SM::Transition>, context: BlockContext) {
+        if self.contains(hash) {
+            put(&self.bodies, &hash.to_be_bytes(), &(body, context));
+        }
+    }
+
+    fn header(&self, id: BlockId) -> Option<Header<C::Digest>> {
+        let hash = match id {
+            BlockId::Hash(hash) => hash,
+            BlockId::Number(number) => Self::get_hash(&self.canonical, &number.to_be_bytes())?,
+        };
+        get(&self.headers, &hash.to_be_bytes())
+    }
+
+    fn body(&self, hash: Hash) -> Option<Vec<SM::Transition>> {
+        get::<(Vec<SM::Transition>, BlockContext)>(&self.bodies, &hash.to_be_bytes())
+            .map(|(body, _)| body)
+    }
+
+    fn context(&self, hash: Hash) -> Option<BlockContext> {
+        get::<(Vec<SM::Transition>, BlockContext)>(&self.bodies, &hash.to_be_bytes())
+            .map(|(_, context)| context)
+    }
+
+    /// The new best block is flushed to disk straight away, so that a node which stops
+    /// suddenly still knows which chain it was following.
+    fn set_best(&mut self, best: Hash) {
+        let Some(mut header) = self.header(BlockId::Hash(best)) else {
+            return;
+        };
+
+        // Forget the numbers above the new best block.
+        let above = (header.height + 1).to_be_bytes();
+        for key in self.canonical.range(above..).keys() {
+            self.canonical
+                .remove(key.expect(DATABASE_FAILED))
+                .expect(DATABASE_FAILED);
+        }
+
+        // Walk back until the new chain meets the old one. Everything below that point is
+        // already indexed correctly.
+        let mut hash = best;
+        loop {
+            let height = header.height.to_be_bytes();
+            if Self::get_hash(&self.canonical, &height) == Some(hash) {
+                break;
+            }
+            self.canonical
+                .insert(height, &hash.to_be_bytes())
+                .expect(DATABASE_FAILED);
+            match self.header(BlockId::Hash(header.parent)) {
+                Some(parent) if header.height > 0 => {
+                    hash = header.parent;
+                    header = parent;
+                }
+                _ => break,
+            }
+        }
+
+        self.db
+            .insert(BEST_KEY, &best.to_be_bytes())
+            .expect(DATABASE_FAILED);
+        self.db.flush().expect(DATABASE_FAILED);
+    }
+
+    fn best(&self) -> Hash {
+        Self::get_hash(&self.db, BEST_KEY).unwrap_or_default()
+    }
+
+    fn set_finalized(&mut self, hash: Hash) {
+        self.db
+            .insert(FINALIZED_KEY, &hash.to_be_bytes())
+            .expect(DATABASE_FAILED);
+        self.db.flush().expect(DATABASE_FAILED);
+    }
+
+    fn finalized(&self) -> Hash {
+        Self::get_hash(&self.db, FINALIZED_KEY).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+use super::{FullClient, ImportBlock};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestClient = FullClient<(), LightSwitch, (), (), SledStore<(), LightSwitch>>;
+
+/// A fresh directory for a test database, which is removed again when dropped.
+#[cfg(test)]
+struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("diy-blockchain-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TempDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Open a client on the database in the given directory. Sled keeps its lock on the files
+/// until its background flusher notices that the last handle is gone, so reopening straight
+/// after a restart may have to wait a moment.
+#[cfg(test)]
+fn open_client(dir: &TempDir, genesis_state: bool) -> TestClient {
+    let mut attempts = 0;
+    let store = loop {
+        match SledStore::open(&dir.0) {
+            Ok(store) => break store,
+            Err(SledStoreError::Database(_)) if attempts < 50 => {
+                attempts += 1;
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            Err(error) => panic!("could not open the database: {error:?}"),
+        }
+    };
+    FullClient::with_store((), LightSwitch, (), (), store, genesis_state)
+}
+
+#[test]
+fn cl_12_chain_survives_restart() {
+    let dir = TempDir::new("restart");
+    let mut client = open_client(&dir, false);
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![(), ()]).unwrap();
+    let fork = genesis.child(&(), &false, vec![]).unwrap();
+    assert!(client.import_block(b1.clone()));
+    assert!(client.import_block(b2.clone()));
+    assert!(client.import_block(fork));
+    assert!(client.manually_finalize_block(b1.hash()));
+    drop(client);
+
+    // The restarted client follows the same chain, and knows the state at each of its blocks.
+    let mut client = open_client(&dir, false);
+    assert_eq!(client.best_block(), b2.hash());
+    assert_eq!(client.store().finalized(), b1.hash());
+    assert_eq!(
+        client.store().header(BlockId::Number(1)),
+        Some(b1.header.clone())
+    );
+    assert_eq!(client.get_state(b1.hash()), Some(true));
+    assert_eq!(client.get_state(b2.hash()), Some(true));
+
+    // It can also carry on importing, and still respects finality.
+    let b3 = b2.child(&(), &true, vec![()]).unwrap();
+    assert!(client.import_block(b3.clone()));
+    assert_eq!(client.best_block(), b3.hash());
+    let late_fork = genesis.child(&(), &false, vec![(), (), ()]).unwrap();
+    assert!(!client.import_block(late_fork));
+}
+
+#[test]
+fn cl_12_different_genesis_starts_over() {
+    let dir = TempDir::new("genesis");
+    let mut client = open_client(&dir, false);
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    assert!(client.import_block(genesis.child(&(), &false, vec![()]).unwrap()));
+    drop(client);
+
+    // A client with another genesis state can not use the stored chain.
+    let client = open_client(&dir, true);
+    assert_ne!(client.genesis_hash(), genesis.hash());
+    assert_eq!(client.best_block(), client.genesis_hash());
+    assert_eq!(client.store().best(), client.genesis_hash());
+    assert_eq!(client.store().finalized(), client.genesis_hash());
+}
+
+#[test]
+fn cl_12_unknown_schema_is_refused() {
+    let dir = TempDir::new("schema");
+    drop(SledStore::<(), LightSwitch>::open(&dir.0).unwrap());
+
+    let db = sled::open(&dir.0).unwrap();
+    db.insert(SCHEMA_VERSION_KEY, &2u32.to_be_bytes()).unwrap();
+    drop(db);
+
+    assert!(matches!(
+        SledStore::<(), LightSwitch>::open(&dir.0),
+        Err(SledStoreError::UnsupportedSchema(2))
+    ));
+}
@@ -0,0 +1,259 @@
+//! Our client keeps the post-state of every block it has ever imported. Real states are large,
+//! so a real node that did this would soon run out of space. Most nodes only need recent
+//! states: new blocks are built on top of recent blocks, and most users ask about recent
+//! blocks. So they prune the rest.
+//!
+//! A pruning client keeps the state of the last few blocks on every branch that can still
+//! become best. It also keeps the state of the genesis block, the finalized block, and every
+//! checkpoint, because those are never reverted and are handy places to start from. Every
+//! other state is thrown away, including the states of branches that conflict with finality.
+//! The blocks themselves are still kept, so a pruned state could always be recomputed by
+//! executing the chain again.
+//!
+//! Some nodes do need every state, for example those behind block explorers, which answer
+//! questions about any block in history. These are called archive nodes. An archive client
+//! never prunes, so it can return the exact state at any block it has imported, even on a
+//! branch that lost.
+
+use super::{BlockStore, ForkChoice, FullClient};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+
+/// Which states a client keeps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pruning {
+    /// Keep the state of every imported block. This is what clients do unless told otherwise.
+    #[default]
+    Archive,
+    /// Only keep the state of the given number of most recent blocks on each viable branch,
+    /// along with the genesis, finalized, and checkpointed states. The state of the best block
+    /// is always kept, so at least one recent block is.
+    KeepRecent(u64),
+}
+
+/// The reasons that a client can not answer a question about the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The client does not know the block.
+    UnknownBlock,
+    /// The client knows the block, but has pruned its state.
+    StatePruned,
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// Keep the states that the given policy asks for.
+    pub fn with_pruning(mut self, pruning: Pruning) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Whether this client keeps the state of every block it imports.
+    pub fn is_archive(&self) -> bool {
+        self.pruning == Pruning::Archive
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// Throw away every state that the pruning policy does not keep. Archive clients keep
+    /// them all.
+    pub(crate) fn prune_states(&mut self) {
+        let Pruning::KeepRecent(keep_recent) = self.pruning else {
+            return;
+        };
+        let keep_recent = keep_recent.max(1);
+        let tree = &self.fork_tree;
+        let best_height = tree.get(self.best_block()).map_or(0, |node| node.height);
+        let finalized = tree.finalized();
+        let genesis = self.genesis_hash;
+        let checkpoints = &self.checkpoints;
+
+        self.states.retain(|hash, _| {
+            let Some(node) = tree.get(*hash) else {
+                return false;
+            };
+            let recent = node.height + keep_recent > best_height;
+            *hash == genesis
+                || *hash == finalized
+                || checkpoints.matches(node.height, *hash)
+                || (recent && tree.descends_from(*hash, finalized))
+        });
+    }
+}
+
+#[cfg(test)]
+use super::{Block, ImportBlock, ImportError};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+#[cfg(test)]
+use crate::c2_blockchain::{BlockId, Checkpoints};
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), ()>;
+
+#[cfg(test)]
+fn pruning_client(keep_recent: u64) -> TestClient {
+    FullClient::new((), Currency, (), (), Balances::from([(Alice, 100)]))
+        .with_pruning(Pruning::KeepRecent(keep_recent))
+}
+
+/// Build and import a chain of the given length on top of the given block, in which every
+/// block mints the given amount to Bob.
+#[cfg(test)]
+fn extend(
+    client: &mut TestClient,
+    from: u64,
+    length: usize,
+    amount: u64,
+) -> Vec<Block<(), Currency>> {
+    let mut parent = client.get_block(from).unwrap();
+    let mut state = client.get_state(from).unwrap();
+    let mut blocks = Vec::new();
+    for _ in 0..length {
+        let mint = CurrencyTransaction::Mint { to: Bob, amount };
+        let block = parent.child(&(), &state, vec![mint.clone()]).unwrap();
+        state = Currency::next_state(&state, &mint);
+        assert!(client.import_block(block.clone()));
+        blocks.push(block.clone());
+        parent = block;
+    }
+    blocks
+}
+
+#[test]
+fn cl_13_only_recent_states_are_kept() {
+    let mut client = pruning_client(2);
+    let genesis = client.genesis_hash();
+    extend(&mut client, genesis, 5, 1);
+
+    assert_eq!(client.state_at(BlockId::Number(1)), Err(Error::StatePruned));
+    assert_eq!(client.state_at(BlockId::Number(3)), Err(Error::StatePruned));
+    assert_eq!(
+        client.state_at(BlockId::Number(4)),
+        Ok(Balances::from([(Alice, 100), (Bob, 4)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Number(5)),
+        Ok(Balances::from([(Alice, 100), (Bob, 5)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Number(0)),
+        Ok(Balances::from([(Alice, 100)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Number(6)),
+        Err(Error::UnknownBlock)
+    );
+
+    // Pruned blocks are still stored, but can no longer be built on.
+    let b1 = client.resolve(BlockId::Number(1)).unwrap();
+    let b1 = client.get_block(b1).unwrap();
+    let b1_state = Balances::from([(Alice, 100), (Bob, 1)]);
+    let late = b1.child(&(), &b1_state, vec![]).unwrap();
+    assert_eq!(client.try_import_block(late), Err(ImportError::StatePruned));
+}
+
+#[test]
+fn cl_13_finalized_and_checkpointed_states_are_kept() {
+    let mut client = pruning_client(1);
+    let genesis = client.genesis_hash();
+    let blocks = extend(&mut client, genesis, 6, 1);
+    let checkpoint = (1, blocks[0].hash());
+    let mut client = pruning_client(1).with_checkpoints(Checkpoints::new([checkpoint]));
+    for block in &blocks[..4] {
+        assert!(client.import_block(block.clone()));
+    }
+    assert!(client.manually_finalize_block(blocks[3].hash()));
+    for block in &blocks[4..] {
+        assert!(client.import_block(block.clone()));
+    }
+
+    assert!(client.state_at(BlockId::Number(1)).is_ok());
+    assert_eq!(client.state_at(BlockId::Number(2)), Err(Error::StatePruned));
+    assert!(client.state_at(BlockId::Number(4)).is_ok());
+    assert_eq!(client.state_at(BlockId::Number(5)), Err(Error::StatePruned));
+    assert!(client.state_at(BlockId::Number(6)).is_ok());
+}
+
+#[test]
+fn cl_13_recent_forks_are_kept_until_they_conflict_with_finality() {
+    let mut client = pruning_client(2);
+    let genesis = client.genesis_hash();
+    let main = extend(&mut client, genesis, 3, 1);
+    let fork = extend(&mut client, main[1].hash(), 1, 7);
+    // The tips are at the same height, so which one is best depends on their hashes.
+    assert!([main[2].hash(), fork[0].hash()].contains(&client.best_block()));
+
+    // The fork is as recent as the best chain, so it may still become best.
+    let fork_tip = BlockId::Hash(fork[0].hash());
+    assert!(client.state_at(fork_tip).is_ok());
+
+    // Once the fork conflicts with finality, it never will.
+    assert!(client.manually_finalize_block(main[2].hash()));
+    assert_eq!(client.state_at(fork_tip), Err(Error::StatePruned));
+    assert!(client.state_at(BlockId::Hash(main[2].hash())).is_ok());
+    assert_eq!(
+        client.state_at(BlockId::Hash(main[0].hash())),
+        Err(Error::StatePruned)
+    );
+}
+
+#[test]
+fn cl_13_archive_keeps_every_historical_state() {
+    let mut client = FullClient::new((), Currency, (), (), Balances::from([(Alice, 100)]))
+        .with_pruning(Pruning::Archive);
+    assert!(client.is_archive());
+    let genesis = client.genesis_hash();
+    let main = extend(&mut client, genesis, 50, 2);
+    assert!(client.manually_finalize_block(main[39].hash()));
+
+    for height in [1, 7, 25, 40, 49, 50] {
+        assert_eq!(
+            client.state_at(BlockId::Number(height)),
+            Ok(Balances::from([(Alice, 100), (Bob, 2 * height)])),
+            "state at height {height}"
+        );
+    }
+    assert_eq!(
+        client.state_at(BlockId::Number(0)),
+        Ok(Balances::from([(Alice, 100)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Number(51)),
+        Err(Error::UnknownBlock)
+    );
+}
+
+#[test]
+fn cl_13_archive_keeps_states_of_abandoned_forks() {
+    let mut client = FullClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    assert!(client.is_archive());
+    let genesis = client.genesis_hash();
+    let fork = extend(&mut client, genesis, 3, 5);
+    let main = extend(&mut client, genesis, 30, 1);
+    assert!(client.manually_finalize_block(main[20].hash()));
+    assert_eq!(client.best_block(), main[29].hash());
+
+    // The fork lost long ago, and conflicts with finality, but its states are still there.
+    for (height, block) in fork.iter().enumerate() {
+        let height = height as u64 + 1;
+        assert_eq!(
+            client.state_at(BlockId::Hash(block.hash())),
+            Ok(Balances::from([(Alice, 100), (Bob, 5 * height)]))
+        );
+    }
+    assert_eq!(
+        client.state_at(BlockId::Number(3)),
+        Ok(Balances::from([(Alice, 100), (Bob, 3)]))
+    );
+}
@@ -0,0 +1,238 @@
+//! In the previous sections, blocks were authored whenever a test asked for one, with whatever
+//! extrinsics the test passed in. A real authoring node runs a service that does this by
+//! itself. When it is time for a new block, it takes the extrinsics that are waiting in the
+//! transaction pool, executes them on top of the best state, seals the result, and imports it
+//! like any other block. Its peers then hear about the block as usual.
+//!
+//! When it is time depends on the chain. Some chains have fixed block times, so the service
+//! authors on a timer. Others, like development chains, author as soon as someone asks. Our
+//! author supports both. It does not own a clock. Whoever drives it passes in the current time,
+//! which keeps it deterministic and easy to test.
+
+use super::{BlockStore, ForkChoice, FullClient, TransactionPool};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::{Consensus, ConsensusAuthority};
+
+type Hash = u64;
+
+/// A service that authors blocks from the extrinsics in a client's transaction pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Author {
+    /// How much time passes between blocks when authoring on a timer.
+    block_time: u64,
+    /// The most extrinsics that go in a single block.
+    max_extrinsics: usize,
+    /// Who the blocks are authored by, if the consensus engine cares.
+    identity: Option<ConsensusAuthority>,
+    /// The time at which the next block is due when authoring on a timer.
+    next_block_at: u64,
+}
+
+impl Author {
+    /// An author that produces a block every `block_time`, with at most `max_extrinsics`
+    /// extrinsics in each. The first block is due straight away.
+    pub fn new(block_time: u64, max_extrinsics: usize) -> Self {
+        Self {
+            block_time,
+            max_extrinsics,
+            identity: None,
+            next_block_at: 0,
+        }
+    }
+
+    /// Declare the given authority as the author of every block.
+    pub fn with_identity(mut self, identity: ConsensusAuthority) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// The time at which the next block is due when authoring on a timer.
+    pub fn next_block_at(&self) -> u64 {
+        self.next_block_at
+    }
+
+    /// Tell the author what time it is. If a block is due, one is authored. Returns the hash of
+    /// the new block, if any.
+    pub fn on_tick<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        now: u64,
+    ) -> Option<Hash>
+    where
+        C: Consensus,
+        C::Digest: Default,
+        SM: StateMachine,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        P: TransactionPool<SM>,
+        S: BlockStore<C, SM>,
+    {
+        if now < self.next_block_at {
+            return None;
+        }
+        // Even if this slot is missed, the next one is a whole block time away.
+        self.next_block_at = now + self.block_time;
+        self.author(client, now)
+    }
+
+    /// Author a block right now, on top of the client's best block, import it, and prune the
+    /// pool. Every valid uncle that the client knows of is referenced. Returns the hash of the
+    /// new block.
+    ///
+    /// Returns None if the block could not be sealed or imported. The extrinsics that were
+    /// taken for it are then put back in the pool.
+    pub fn author<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        now: u64,
+    ) -> Option<Hash>
+    where
+        C: Consensus,
+        C::Digest: Default,
+        SM: StateMachine,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        P: TransactionPool<SM>,
+        S: BlockStore<C, SM>,
+    {
+        let parent_hash = client.best_block();
+        let parent = client.store.block(parent_hash)?;
+        let extrinsics: Vec<_> = std::iter::from_fn(|| client.transaction_pool.next_from_pool())
+            .take(self.max_extrinsics)
+            .collect();
+
+        // Time never runs backwards within a chain.
+        let timestamp = now.max(parent.context().timestamp);
+        let context = parent.child_context(timestamp, self.identity);
+        let uncles = client.uncle_candidates(parent_hash);
+        let block = parent.seal_child(
+            &client.consensus_engine,
+            client.best_state(),
+            extrinsics.clone(),
+            uncles,
+            context,
+        );
+
+        match block.map(|block| client.try_import_block(block)) {
+            Some(Ok(hash)) => {
+                client.metrics.authored_blocks += 1;
+                client.prune_pool(hash);
+                Some(hash)
+            }
+            _ => {
+                for t in extrinsics {
+                    client.transaction_pool.try_insert(t);
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, SimplePool};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+#[cfg(test)]
+use crate::c2_blockchain::BlockId;
+#[cfg(test)]
+use crate::c3_consensus::SimplePoa;
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), SimplePool<Currency>>;
+
+#[cfg(test)]
+fn client() -> TestClient {
+    FullClient::new(
+        (),
+        Currency,
+        (),
+        SimplePool::default(),
+        Balances::from([(Alice, 100)]),
+    )
+}
+
+#[cfg(test)]
+fn pay(amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount,
+    }
+}
+
+#[test]
+fn cl_14_author_on_demand_drains_pool() {
+    let mut client = client();
+    let mut author = Author::new(10, 2);
+    for amount in [1, 2, 3] {
+        client.submit_transaction(pay(amount));
+    }
+
+    let b1 = author.author(&mut client, 5).unwrap();
+    assert_eq!(client.best_block(), b1);
+    assert_eq!(client.get_block(b1).unwrap().body, vec![pay(1), pay(2)]);
+    assert_eq!(client.get_block(b1).unwrap().context().timestamp, 5);
+    assert_eq!(client.pool_size(), 1);
+
+    let b2 = author.author(&mut client, 6).unwrap();
+    assert_eq!(client.get_block(b2).unwrap().body, vec![pay(3)]);
+    assert_eq!(client.pool_size(), 0);
+    assert_eq!(
+        client.state_at(BlockId::Hash(b2)),
+        Ok(Balances::from([(Alice, 94), (Bob, 6)]))
+    );
+
+    // With nothing in the pool, blocks are simply empty.
+    let b3 = author.author(&mut client, 7).unwrap();
+    assert!(client.get_block(b3).unwrap().body.is_empty());
+}
+
+#[test]
+fn cl_14_author_on_tick_follows_block_time() {
+    let mut client = client();
+    let mut author = Author::new(10, 100);
+    client.submit_transaction(pay(1));
+
+    let b1 = author.on_tick(&mut client, 0).unwrap();
+    assert_eq!(author.next_block_at(), 10);
+    client.submit_transaction(pay(2));
+    assert_eq!(author.on_tick(&mut client, 9), None);
+    assert_eq!(client.best_block(), b1);
+
+    let b2 = author.on_tick(&mut client, 13).unwrap();
+    assert_eq!(author.next_block_at(), 23);
+    assert_eq!(client.best_header().parent, b1);
+    assert_eq!(client.get_block(b2).unwrap().body, vec![pay(2)]);
+}
+
+#[test]
+fn cl_14_failed_authoring_returns_extrinsics_to_pool() {
+    let poa = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+    let mut client = FullClient::new(
+        poa,
+        Currency,
+        (),
+        SimplePool::default(),
+        Balances::from([(Alice, 100)]),
+    );
+    client.submit_transaction(pay(1));
+
+    // Bob is not the authority who gets to seal the next block.
+    let mut impostor = Author::new(10, 100).with_identity(ConsensusAuthority::Bob);
+    assert_eq!(impostor.author(&mut client, 0), None);
+    assert!(client.pool_contains(pay(1)));
+    assert_eq!(client.best_block(), client.genesis_hash());
+
+    let mut author = Author::new(10, 100).with_identity(ConsensusAuthority::Alice);
+    let b1 = author.author(&mut client, 0).unwrap();
+    assert_eq!(client.get_block(b1).unwrap().body, vec![pay(1)]);
+    assert_eq!(
+        client.get_block(b1).unwrap().context().author,
+        Some(ConsensusAuthority::Alice)
+    );
+}
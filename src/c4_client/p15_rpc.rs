@@ -0,0 +1,571 @@
+//! So far, the only way to talk to our client has been to call its methods from Rust. Real
+//! nodes are also used by wallets, block explorers, and scripts, which are usually written in
+//! other languages and run in other processes. So nodes expose a remote procedure call (RPC)
+//! interface, most often JSON-RPC over HTTP.
+//!
+//! A JSON-RPC request names a method and passes it a list of parameters. The response carries
+//! either a result or an error, along with the id of the request it answers. The methods here
+//! follow the names that Substrate nodes use, so that the same tools can talk to both.
+//!
+//! The server is deliberately minimal. It handles one connection at a time, and one request
+//! per connection. Everything it knows about HTTP fits in a couple of functions. Real nodes use
+//! a proper HTTP library.
+//!
+//! Callers that want to hear about new blocks as they happen open a WebSocket instead. The
+//! client's events are streamed over it as JSON-RPC notifications, one per event.
+//!
+//! This part is only compiled with the `rpc` feature.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::{BlockStore, ChainEvent, ForkChoice, FullClient, TransactionPool};
+use crate::c1_state_machine::StateMachine;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// The longest request body that the server reads. RPC calls are small, and the server
+/// allocates whatever length the caller declares, so it can not take the caller's word for it.
+pub const MAX_REQUEST_LEN: usize = 1024 * 1024;
+/// The longest request line or header line that the server reads.
+pub const MAX_LINE_LEN: u64 = 8 * 1024;
+/// The most bytes of request line and headers that the server reads before the body. Without
+/// it, a caller could keep sending short header lines and hold the server forever.
+pub const MAX_HEAD_LEN: usize = 32 * 1024;
+/// How long the server waits for a caller that has stopped sending. It serves one connection
+/// at a time, so a caller that never finishes its request would otherwise hold up every other.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The request is not valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The request is JSON, but not a JSON-RPC request.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The requested method does not exist.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The method's parameters are missing or malformed.
+pub const INVALID_PARAMS: i64 = -32602;
+/// The client knows the block, but has pruned its state.
+pub const STATE_PRUNED: i64 = 4003;
+/// The transaction pool refused the extrinsic.
+pub const EXTRINSIC_REFUSED: i64 = 1010;
+
+/// Why an RPC call failed, as reported to the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcError {
+    /// One of the error codes above.
+    pub code: i64,
+    /// A human readable explanation.
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// The positional parameter at the given index, or None if it is missing or null.
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<Option<T>, RpcError> {
+    match params.get(index) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => T::deserialize(value)
+            .map(Some)
+            .map_err(|error| RpcError::new(INVALID_PARAMS, error.to_string())),
+    }
+}
+
+/// Encode a result that is known to be serializable.
+fn to_value<T: Serialize>(value: T) -> Value {
+    serde_json::to_value(value).expect("the result types all serialize to JSON")
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Serialize,
+    SM: StateMachine,
+    SM::State: Serialize,
+    SM::Transition: DeserializeOwned + std::hash::Hash,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Call the given RPC method with the given positional parameters.
+    ///
+    /// * `chain_getHeader [hash?]` - The header of the given block, or of the best block.
+    /// * `chain_getBlockHash [number?]` - The hash of the block with the given number on the
+    ///   best chain, or of the best block.
+    /// * `chain_getBestNumber []` - The height of the best block.
+    /// * `state_getValue [key?, hash?]` - The value stored under the given key in the state of
+    ///   the given block, or of the best block. Without a key, the whole state.
+    /// * `author_submitExtrinsic [extrinsic]` - Submit an extrinsic to the transaction pool.
+    ///   Returns its hash.
+    ///
+    /// Blocks that the client does not know about give a null result.
+    pub fn call_rpc(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "chain_getHeader" => {
+                let hash = param(params, 0)?.unwrap_or_else(|| self.best_block());
+                Ok(to_value(self.store.header(BlockId::Hash(hash))))
+            }
+            "chain_getBlockHash" => {
+                let id = match param(params, 0)? {
+                    Some(number) => BlockId::Number(number),
+                    None => BlockId::Hash(self.best_block()),
+                };
+                Ok(to_value(self.store.header(id).map(|header| hash(&header))))
+            }
+            "chain_getBestNumber" => {
+                let best = self.store.header(BlockId::Hash(self.best_block()));
+                Ok(to_value(best.map(|header| header.height)))
+            }
+            "state_getValue" => {
+                let key: Option<Value> = param(params, 0)?;
+                let hash: Hash = param(params, 1)?.unwrap_or_else(|| self.best_block());
+                if !self.store.contains(hash) {
+                    return Ok(Value::Null);
+                }
+                let state = self.states.get(&hash).ok_or_else(|| {
+                    RpcError::new(STATE_PRUNED, "the state of this block has been pruned")
+                })?;
+                let state = to_value(state);
+                Ok(match key {
+                    None => state,
+                    Some(Value::String(key)) => state.get(key).cloned().unwrap_or_default(),
+                    Some(Value::Number(index)) => index
+                        .as_u64()
+                        .and_then(|index| state.get(index as usize).cloned())
+                        .unwrap_or_default(),
+                    Some(_) => {
+                        return Err(RpcError::new(INVALID_PARAMS, "keys are strings or numbers"))
+                    }
+                })
+            }
+            "author_submitExtrinsic" => {
+                let extrinsic: SM::Transition = param(params, 0)?
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing extrinsic"))?;
+                let extrinsic_hash = hash(&extrinsic);
+                if !self.transaction_pool.try_insert(extrinsic) {
+                    return Err(RpcError::new(EXTRINSIC_REFUSED, "the pool refused it"));
+                }
+                Ok(to_value(extrinsic_hash))
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "method not found")),
+        }
+    }
+
+    /// Answer a JSON-RPC 2.0 request, both given as JSON text.
+    pub fn handle_rpc(&mut self, request: &str) -> String {
+        let (id, outcome) = match serde_json::from_str::<Value>(request) {
+            Err(error) => {
+                let error = RpcError::new(PARSE_ERROR, error.to_string());
+                (Value::Null, Err(error))
+            }
+            Ok(request) => {
+                let id = request.get("id").cloned().unwrap_or_default();
+                let outcome = match (request.get("method"), request.get("params")) {
+                    (Some(Value::String(method)), None) => self.call_rpc(method, &[]),
+                    (Some(Value::String(method)), Some(Value::Array(params))) => {
+                        self.call_rpc(method, params)
+                    }
+                    (Some(Value::String(_)), Some(_)) => Err(RpcError::new(
+                        INVALID_PARAMS,
+                        "parameters are given by position",
+                    )),
+                    _ => Err(RpcError::new(INVALID_REQUEST, "missing method")),
+                };
+                (id, outcome)
+            }
+        };
+
+        let response = match outcome {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": error.code, "message": error.message },
+            }),
+        };
+        response.to_string()
+    }
+
+    /// Read one HTTP request from the given connection, answer it, and close the connection.
+    /// Only `POST` requests carry RPC calls. A `GET` of `/metrics` is answered with the
+    /// client's metrics in the Prometheus text format. Anything else is refused, and so are
+    /// bodies longer than `MAX_REQUEST_LEN`, lines longer than `MAX_LINE_LEN`, heads longer
+    /// than `MAX_HEAD_LEN`, and lengths that are not numbers.
+    pub fn serve_rpc_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let Some(request_line) = read_line(&mut reader)? else {
+            return write_response(&stream, HEAD_TOO_LARGE, JSON, "");
+        };
+
+        let mut head_len = request_line.len();
+        let mut content_length = Ok(0);
+        loop {
+            let Some(line) = read_line(&mut reader)? else {
+                return write_response(&stream, HEAD_TOO_LARGE, JSON, "");
+            };
+            head_len += line.len();
+            if head_len > MAX_HEAD_LEN {
+                return write_response(&stream, HEAD_TOO_LARGE, JSON, "");
+            }
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>();
+                }
+            }
+        }
+        let Ok(content_length) = content_length else {
+            return write_response(&stream, "400 Bad Request", JSON, "");
+        };
+
+        if request_line.starts_with("GET /metrics ") {
+            let metrics = self.metrics().to_prometheus();
+            return write_response(&stream, "200 OK", PROMETHEUS_TEXT, &metrics);
+        }
+        if !request_line.starts_with("POST ") {
+            return write_response(&stream, "405 Method Not Allowed", JSON, "");
+        }
+        if content_length > MAX_REQUEST_LEN {
+            return write_response(&stream, "413 Payload Too Large", JSON, "");
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        let response = self.handle_rpc(&String::from_utf8_lossy(&body));
+        write_response(&stream, "200 OK", JSON, &response)
+    }
+
+    /// Serve RPC requests from the given listener, one connection at a time, until accepting a
+    /// connection fails. Connections that fail part way through are simply dropped.
+    pub fn serve_rpc(&mut self, listener: &TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept()?;
+            let _ = self.serve_rpc_connection(stream);
+        }
+    }
+}
+
+const JSON: &str = "application/json";
+const HEAD_TOO_LARGE: &str = "431 Request Header Fields Too Large";
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+
+/// Read one line of at most `MAX_LINE_LEN` bytes, or None if the line is longer than that. The
+/// line is empty at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = reader.take(MAX_LINE_LEN).read_line(&mut line)?;
+    Ok((read < MAX_LINE_LEN as usize || line.ends_with('\n')).then_some(line))
+}
+
+/// Write an HTTP response with the given status, content type, and body.
+fn write_response(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Accept a WebSocket on the given connection, and send each of the given events over it as a
+/// `chain_event` notification. Returns once the events stop, or the other end hangs up.
+///
+/// A node usually subscribes to its client's events for each connection it accepts, and
+/// streams them on a thread of their own, so that slow listeners do not hold up the client.
+pub fn stream_events(stream: TcpStream, events: Receiver<ChainEvent>) -> io::Result<()> {
+    let mut socket =
+        tungstenite::accept(stream).map_err(|error| io::Error::other(error.to_string()))?;
+    for event in events {
+        let notification = json!({ "jsonrpc": "2.0", "method": "chain_event", "params": event });
+        socket
+            .send(tungstenite::Message::text(notification.to_string()))
+            .map_err(|error| io::Error::other(error.to_string()))?;
+    }
+    socket
+        .close(None)
+        .map_err(|error| io::Error::other(error.to_string()))
+}
+
+#[cfg(test)]
+use super::{Author, ImportBlock, SimplePool};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), SimplePool<Currency>>;
+
+/// A client whose best chain is two blocks long. The first block pays Bob 10.
+#[cfg(test)]
+fn client() -> TestClient {
+    let mut client = FullClient::new(
+        (),
+        Currency,
+        (),
+        SimplePool::default(),
+        Balances::from([(Alice, 100)]),
+    );
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let b1 = genesis.child(&(), &genesis_state, vec![pay(10)]).unwrap();
+    let b1_state = Balances::from([(Alice, 90), (Bob, 10)]);
+    let b2 = b1.child(&(), &b1_state, vec![]).unwrap();
+    assert!(client.import_block(b1));
+    assert!(client.import_block(b2));
+    client
+}
+
+#[cfg(test)]
+fn pay(amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount,
+    }
+}
+
+/// Make a call, and decode its response.
+#[cfg(test)]
+fn call(client: &mut TestClient, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+    let response: Value = serde_json::from_str(&client.handle_rpc(&request.to_string())).unwrap();
+    assert_eq!(response["id"], 7);
+    response
+}
+
+#[test]
+fn cl_15_chain_methods() {
+    let mut client = client();
+    let best = client.best_block();
+    let b1 = client.store().header(BlockId::Number(1)).unwrap();
+
+    let best_number = call(&mut client, "chain_getBestNumber", json!([]));
+    assert_eq!(best_number["result"], 2);
+    let best_hash = call(&mut client, "chain_getBlockHash", json!([]));
+    assert_eq!(best_hash["result"], best);
+    assert_eq!(
+        call(&mut client, "chain_getBlockHash", json!([1]))["result"],
+        hash(&b1)
+    );
+    assert_eq!(
+        call(&mut client, "chain_getBlockHash", json!([3]))["result"],
+        Value::Null
+    );
+    assert_eq!(
+        call(&mut client, "chain_getHeader", json!([hash(&b1)]))["result"],
+        to_value(&b1)
+    );
+    assert_eq!(
+        call(&mut client, "chain_getHeader", json!([]))["result"]["parent"],
+        hash(&b1)
+    );
+}
+
+#[test]
+fn cl_15_state_and_author_methods() {
+    let mut client = client();
+    let b1 = client.resolve(BlockId::Number(1)).unwrap();
+
+    assert_eq!(
+        call(&mut client, "state_getValue", json!(["Bob"]))["result"],
+        10
+    );
+    assert_eq!(
+        call(&mut client, "state_getValue", json!([]))["result"],
+        json!({ "Alice": 90, "Bob": 10 })
+    );
+    let genesis = client.genesis_hash();
+    assert_eq!(
+        call(&mut client, "state_getValue", json!(["Bob", genesis]))["result"],
+        Value::Null
+    );
+    assert_eq!(
+        call(&mut client, "state_getValue", json!(["Alice", b1]))["result"],
+        90
+    );
+
+    let extrinsic = json!({ "Transfer": { "from": "Alice", "to": "Bob", "amount": 5 } });
+    let response = call(&mut client, "author_submitExtrinsic", json!([extrinsic]));
+    assert_eq!(response["result"], hash(&pay(5)));
+    assert!(client.pool_contains(pay(5)));
+
+    let response = call(&mut client, "author_submitExtrinsic", json!(["Transfer"]));
+    assert_eq!(response["error"]["code"], INVALID_PARAMS);
+}
+
+#[test]
+fn cl_15_malformed_requests_get_errors() {
+    let mut client = client();
+    assert_eq!(
+        call(&mut client, "chain_getNothing", json!([]))["error"]["code"],
+        METHOD_NOT_FOUND
+    );
+    assert_eq!(
+        call(&mut client, "chain_getHeader", json!({ "hash": 1 }))["error"]["code"],
+        INVALID_PARAMS
+    );
+
+    let response: Value = serde_json::from_str(&client.handle_rpc("{")).unwrap();
+    assert_eq!(response["error"]["code"], PARSE_ERROR);
+    assert_eq!(response["id"], Value::Null);
+    let response: Value = serde_json::from_str(&client.handle_rpc(r#"{"id":1}"#)).unwrap();
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+}
+
+#[test]
+fn cl_15_serves_over_http() {
+    let mut client = client();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let caller = std::thread::spawn(move || {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"chain_getBestNumber","params":[]}"#;
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    client.serve_rpc_connection(stream).unwrap();
+    let response = caller.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(r#"{"id":1,"jsonrpc":"2.0","result":2}"#));
+}
+
+#[test]
+fn cl_15_refuses_oversized_requests() {
+    let mut client = client();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let caller = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_LEN + 1
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    client.serve_rpc_connection(stream).unwrap();
+    let response = caller.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+}
+
+#[test]
+fn cl_15_refuses_bad_request_heads() {
+    let mut client = client();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut respond = |request: String| {
+        let caller = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().unwrap();
+        client.serve_rpc_connection(stream).unwrap();
+        caller.join().unwrap()
+    };
+
+    // Endless short headers run into the limit on the whole head.
+    let request_line = "POST / HTTP/1.1\r\n";
+    let header = "X-Filler: 0\r\n";
+    let headers = header.repeat((MAX_HEAD_LEN - request_line.len()) / header.len() + 1);
+    let response = respond(format!("{request_line}{headers}"));
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+    // A single header that is too long is refused, rather than split into two.
+    let long = "a".repeat(MAX_LINE_LEN as usize - "X-Long: ".len());
+    let response = respond(format!("POST / HTTP/1.1\r\nX-Long: {long}"));
+    assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+    let response = respond("POST / HTTP/1.1\r\nContent-Length: lots\r\n\r\n".into());
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[test]
+fn cl_15_serves_metrics_over_http() {
+    let mut client = client();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let caller = std::thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    client.serve_rpc_connection(stream).unwrap();
+    let response = caller.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain"));
+    assert!(response.contains("\nnode_best_height 2\n"));
+}
+
+#[test]
+fn cl_15_streams_events_over_websocket() {
+    let mut client = client();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let events = client.subscribe_events();
+    let streamer = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        stream_events(stream, events)
+    });
+    let (mut socket, _) = tungstenite::connect(format!("ws://{address}")).unwrap();
+
+    client.submit_transaction(pay(1));
+    let best = Author::new(1, 10).author(&mut client, 0).unwrap();
+
+    let message = socket.read().unwrap().into_text().unwrap();
+    let notification: Value = serde_json::from_str(&message).unwrap();
+    assert_eq!(notification["method"], "chain_event");
+    assert_eq!(
+        notification["params"],
+        json!({ "NewBestBlock": { "hash": best, "height": 3 } })
+    );
+
+    // Once the client goes away, so does the stream.
+    drop(client);
+    assert!(streamer.join().unwrap().is_ok());
+}
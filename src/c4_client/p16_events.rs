@@ -0,0 +1,148 @@
+//! User interfaces, wallets, and tests all want to know when the chain makes progress. They
+//! could ask the client for its best block over and over, but that is wasteful, and they would
+//! still miss anything that happened between two questions. Instead, they subscribe. The
+//! client then tells each subscriber about every new best block, every newly finalized block,
+//! and every reorg, in the order they happen.
+//!
+//! Inside the node, subscribers are channels, so they can wait for the next event on another
+//! thread. Outside the node, the same events are streamed over a WebSocket by the RPC server.
+
+use std::sync::mpsc::{channel, Receiver};
+
+use super::{BlockStore, FullClient, ReorgEvent};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+
+type Hash = u64;
+
+/// Something that happened to the chain that a client follows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChainEvent {
+    /// The best block changed. This follows every import that extends the best chain, as well
+    /// as every reorg.
+    NewBestBlock { hash: Hash, height: u64 },
+    /// The given block, and so all of its ancestors, will never be reverted.
+    Finalized { hash: Hash, height: u64 },
+    /// The best chain switched to a different branch. A new best block event follows.
+    Reorg(ReorgEvent),
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// Get notified about every new best block, finalized block, and reorg from now on.
+    pub fn subscribe_events(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.event_subscribers.push(sender);
+        receiver
+    }
+
+    /// Tell every subscriber about the given event. Subscribers that have hung up are
+    /// forgotten.
+    pub(crate) fn notify(&mut self, event: ChainEvent) {
+        self.event_subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// The height of the given block, as far as the fork tree knows.
+    pub(crate) fn height_of(&self, hash: Hash) -> u64 {
+        self.fork_tree.get(hash).map_or(0, |node| node.height)
+    }
+}
+
+#[cfg(test)]
+use super::{Block, ImportBlock, LongestChain, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestClient = FullClient<(), LightSwitch, LongestChain, ()>;
+
+#[cfg(test)]
+fn client() -> TestClient {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::new((), LightSwitch, fork_choice, (), false)
+}
+
+/// Build a child of the given block whose body is the given number of toggles.
+#[cfg(test)]
+fn child(parent: &Block<(), LightSwitch>, toggles: usize) -> Block<(), LightSwitch> {
+    parent.child(&(), &false, vec![(); toggles]).unwrap()
+}
+
+#[test]
+fn cl_16_events_follow_best_chain_and_finality() {
+    let mut client = client();
+    let events = client.subscribe_events();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+
+    let a1 = child(&genesis, 0);
+    let a2 = child(&a1, 0);
+    let b1 = child(&genesis, 2);
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1));
+    assert!(client.import_block(a2.clone()));
+    assert!(client.manually_finalize_block(a1.hash()));
+
+    // The fork never became best, so it is not mentioned.
+    let new_best = |block: &Block<(), LightSwitch>, height| ChainEvent::NewBestBlock {
+        hash: block.hash(),
+        height,
+    };
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![
+            new_best(&a1, 1),
+            new_best(&a2, 2),
+            ChainEvent::Finalized {
+                hash: a1.hash(),
+                height: 1
+            },
+        ]
+    );
+}
+
+#[test]
+fn cl_16_reorgs_come_before_new_best_block() {
+    let mut client = client();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let a1 = child(&genesis, 0);
+    let b1 = child(&genesis, 2);
+    let b2 = child(&b1, 0);
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1.clone()));
+
+    let events = client.subscribe_events();
+    let waiter = std::thread::spawn(move || events.iter().take(2).collect::<Vec<_>>());
+    assert!(client.import_block(b2.clone()));
+
+    assert_eq!(
+        waiter.join().unwrap(),
+        vec![
+            ChainEvent::Reorg(ReorgEvent {
+                retracted: vec![a1.hash()],
+                enacted: vec![b1.hash(), b2.hash()],
+                common_ancestor: genesis.hash(),
+            }),
+            ChainEvent::NewBestBlock {
+                hash: b2.hash(),
+                height: 2
+            },
+        ]
+    );
+}
+
+#[test]
+fn cl_16_dropped_event_subscriber_is_forgotten() {
+    let mut client = client();
+    drop(client.subscribe_events());
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    assert!(client.import_block(child(&genesis, 0)));
+    assert!(client.event_subscribers.is_empty());
+}
@@ -0,0 +1,452 @@
+//! A full client downloads and executes every block, so it knows the complete state of the
+//! chain. That takes more bandwidth, storage, and time than a phone or a browser can spare.
+//! Light clients make a different trade. They only download headers, which are tiny, and
+//! check that each one is sealed according to the consensus rules and links to its parent.
+//! This is enough to follow the best chain, but not to know the state.
+//!
+//! When a light client needs to know part of the state, such as a balance, it asks a full
+//! node. It does not simply trust the answer. The full node sends a Merkle proof along with
+//! it, which the light client checks against the state root in a header it has verified
+//! itself. A dishonest full node can refuse to answer, but it can not lie.
+//!
+//! For that to work, the state root must be the root of a Merkle tree. So far our state roots
+//! have been plain hashes of the whole state. Here we wrap a key-value state so that its hash
+//! is the root of a Merkle tree over its entries, and wrap its state machine to match.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{BlockStore, ForkChoice, ForkTree, FullClient, Header, ImportError};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// The hash of a single entry in a Merkle tree. Leaves and inner nodes are hashed with
+/// different tags, so that an inner node can never pass for an entry.
+fn leaf_hash<K: std::hash::Hash, V: std::hash::Hash>(key: &K, value: &V) -> Hash {
+    hash(&(0u8, key, value))
+}
+
+/// The hash of an inner node of a Merkle tree.
+fn node_hash(left: Hash, right: Hash) -> Hash {
+    hash(&(1u8, left, right))
+}
+
+/// A key-value state whose hash is the root of a Merkle tree over its entries, in key order.
+/// Each entry can be proven on its own against that root.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MerkleState<K, V>(pub BTreeMap<K, V>);
+
+impl<K, V> MerkleState<K, V>
+where
+    K: Ord + Clone + std::hash::Hash,
+    V: Clone + std::hash::Hash,
+{
+    /// Every level of the Merkle tree, from the leaves up to the root. A node without a
+    /// sibling is carried up to the next level as it is. The tree of an empty state has no
+    /// levels at all.
+    fn levels(&self) -> Vec<Vec<Hash>> {
+        let leaves: Vec<_> = self.0.iter().map(|(k, v)| leaf_hash(k, v)).collect();
+        if leaves.is_empty() {
+            return Vec::new();
+        }
+        let mut levels = vec![leaves];
+        loop {
+            let level = &levels[levels.len() - 1];
+            if level.len() == 1 {
+                return levels;
+            }
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node_hash(*left, *right),
+                    [only] => *only,
+                    _ => unreachable!("chunks are never empty"),
+                })
+                .collect();
+            levels.push(next);
+        }
+    }
+
+    /// The root of the Merkle tree over this state's entries. The empty state's root is zero.
+    pub fn root(&self) -> Hash {
+        self.levels().last().map_or(0, |root| root[0])
+    }
+
+    /// A proof of the value stored under the given key. Returns None if nothing is stored
+    /// there.
+    pub fn prove(&self, key: &K) -> Option<MerkleProof<K, V>> {
+        let index = self.0.keys().position(|k| k == key)?;
+        let levels = self.levels();
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &levels[..levels.len() - 1] {
+            let sibling = position ^ 1;
+            if let Some(sibling) = level.get(sibling) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+
+        Some(MerkleProof {
+            key: key.clone(),
+            value: self.0[key].clone(),
+            index,
+            leaf_count: self.0.len(),
+            siblings,
+        })
+    }
+}
+
+impl<K, V> std::hash::Hash for MerkleState<K, V>
+where
+    K: Ord + Clone + std::hash::Hash,
+    V: Clone + std::hash::Hash,
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.root().hash(state)
+    }
+}
+
+/// Proof that a value is stored under a key in a `MerkleState` with a particular root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MerkleProof<K, V> {
+    key: K,
+    value: V,
+    /// The entry's position among all the entries, in key order.
+    index: usize,
+    /// How many entries the state has.
+    leaf_count: usize,
+    /// The hash of the entry's sibling on each level that it has one, from the leaves up.
+    siblings: Vec<Hash>,
+}
+
+impl<K: std::hash::Hash, V: std::hash::Hash> MerkleProof<K, V> {
+    /// The key that this proof is about.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The value that this proof claims is stored under the key.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    /// The root of the state that this proof is valid for. Returns None if the proof is
+    /// malformed, in which case it is valid for no state at all.
+    pub fn root(&self) -> Option<Hash> {
+        if self.index >= self.leaf_count {
+            return None;
+        }
+        let mut node = leaf_hash(&self.key, &self.value);
+        let mut siblings = self.siblings.iter();
+        let (mut position, mut width) = (self.index, self.leaf_count);
+        while width > 1 {
+            if position % 2 == 1 {
+                node = node_hash(*siblings.next()?, node);
+            } else if position + 1 < width {
+                node = node_hash(node, *siblings.next()?);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none().then_some(node)
+    }
+}
+
+/// A state machine whose state is kept in a `MerkleState`, so that light clients can check
+/// proofs about it. It behaves exactly like the wrapped machine.
+pub struct Merklized<SM>(pub SM);
+
+impl<SM, K, V> StateMachine for Merklized<SM>
+where
+    SM: StateMachine<State = BTreeMap<K, V>>,
+{
+    type State = MerkleState<K, V>;
+    type Transition = SM::Transition;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        MerkleState(SM::next_state(&starting_state.0, t))
+    }
+
+    fn next_state_in_block(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+        context: &BlockContext,
+    ) -> Self::State {
+        MerkleState(SM::next_state_in_block(&starting_state.0, t, context))
+    }
+
+    fn human_name() -> String {
+        format!("Merklized {}", SM::human_name())
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// A proof of the value stored under the given key in the state of the given block, for a
+    /// light client to check. Returns None if the block's state is not known, or nothing is
+    /// stored under the key.
+    pub fn prove_value<K, V>(&self, block_hash: Hash, key: &K) -> Option<MerkleProof<K, V>>
+    where
+        SM: StateMachine<State = MerkleState<K, V>>,
+        K: Ord + Clone + std::hash::Hash,
+        V: Clone + std::hash::Hash,
+    {
+        self.states.get(&block_hash)?.prove(key)
+    }
+}
+
+/// The reasons that a light client rejects a state proof.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofError {
+    /// The light client does not know the block that the proof is about.
+    UnknownBlock,
+    /// The proof is about a different key than the one asked for.
+    WrongKey,
+    /// The proof does not match the block's state root.
+    InvalidProof,
+}
+
+/// A client that only follows headers, and checks proofs about the state instead of executing
+/// blocks.
+pub struct LightClient<C: Consensus, FC> {
+    /// The consensus engine used to check each header's seal.
+    consensus_engine: C,
+    /// The fork choice strategy used to pick the best header.
+    fork_choice: FC,
+    /// Every header imported so far, keyed by hash.
+    headers: HashMap<Hash, Header<C::Digest>>,
+    /// How the imported headers are related, along with their cumulative work.
+    fork_tree: ForkTree,
+}
+
+impl<C, FC> LightClient<C, FC>
+where
+    C: Consensus,
+    FC: ForkChoice,
+{
+    /// Create a light client that trusts the given genesis header. It usually comes from the
+    /// chain's specification, and must be the same one that full nodes use.
+    pub fn new(consensus_engine: C, fork_choice: FC, genesis: Header<C::Digest>) -> Self {
+        let genesis_hash = hash(&genesis);
+        Self {
+            consensus_engine,
+            fork_choice,
+            headers: HashMap::from([(genesis_hash, genesis)]),
+            fork_tree: ForkTree::new(genesis_hash),
+        }
+    }
+
+    /// The hash of the genesis header.
+    pub fn genesis_hash(&self) -> Hash {
+        self.fork_tree.root()
+    }
+
+    /// Check the given header's seal and link to its parent, and import it.
+    /// Returns the hash of the imported header.
+    pub fn import_header(&mut self, header: Header<C::Digest>) -> Result<Hash, ImportError> {
+        let header_hash = hash(&header);
+        if self.headers.contains_key(&header_hash) {
+            return Err(ImportError::AlreadyKnown);
+        }
+        let Some(parent) = self.headers.get(&header.parent) else {
+            return Err(ImportError::UnknownParent);
+        };
+        if !parent.verify_child(&header)
+            || !self
+                .consensus_engine
+                .validate(&parent.consensus_digest, &header)
+        {
+            return Err(ImportError::InvalidBlock);
+        }
+
+        let work = self.consensus_engine.work(&header);
+        self.fork_tree.insert(header_hash, header.parent, work);
+        self.headers.insert(header_hash, header);
+        Ok(header_hash)
+    }
+
+    /// Look up an imported header.
+    pub fn header(&self, header_hash: Hash) -> Option<&Header<C::Digest>> {
+        self.headers.get(&header_hash)
+    }
+
+    /// The hash of the best header according to the fork choice rule.
+    pub fn best_hash(&self) -> Hash {
+        self.fork_choice.best_leaf(&self.fork_tree)
+    }
+
+    /// The best header according to the fork choice rule.
+    pub fn best_header(&self) -> &Header<C::Digest> {
+        &self.headers[&self.best_hash()]
+    }
+
+    /// Check that the given proof shows what is stored under the given key in the state of
+    /// the given block. Returns the proven value.
+    pub fn verify_value<K, V>(
+        &self,
+        block_hash: Hash,
+        key: &K,
+        proof: &MerkleProof<K, V>,
+    ) -> Result<V, ProofError>
+    where
+        K: PartialEq + std::hash::Hash,
+        V: Clone + std::hash::Hash,
+    {
+        let header = self
+            .headers
+            .get(&block_hash)
+            .ok_or(ProofError::UnknownBlock)?;
+        if proof.key() != key {
+            return Err(ProofError::WrongKey);
+        }
+        // The state root is the hash of the state, which in turn is the hash of its root.
+        match proof.root() {
+            Some(root) if hash(&root) == header.state_root => Ok(proof.value().clone()),
+            _ => Err(ProofError::InvalidProof),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::LongestChain;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User, User::*};
+#[cfg(test)]
+use crate::c2_blockchain::BlockId;
+#[cfg(test)]
+use crate::c3_consensus::{work_hash, Pow};
+
+#[cfg(test)]
+type TestClient = FullClient<Pow, Merklized<Currency>, LongestChain, ()>;
+
+#[cfg(test)]
+fn full_client() -> TestClient {
+    FullClient::new(
+        Pow::with_difficulty(4),
+        Merklized(Currency),
+        LongestChain::default(),
+        (),
+        MerkleState(Balances::from([(Alice, 100)])),
+    )
+}
+
+#[cfg(test)]
+fn light_client(full: &TestClient) -> LightClient<Pow, LongestChain> {
+    let genesis = full.store().header(BlockId::Number(0)).unwrap();
+    LightClient::new(Pow::with_difficulty(4), LongestChain::default(), genesis)
+}
+
+#[cfg(test)]
+fn pay(to: User, amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: Alice,
+        to,
+        amount,
+    }
+}
+
+#[test]
+fn cl_17_every_entry_can_be_proven() {
+    for size in 0..10u64 {
+        let state = MerkleState((0..size).map(|k| (k, k * 10)).collect::<BTreeMap<_, _>>());
+        for key in 0..size {
+            let proof = state.prove(&key).unwrap();
+            assert_eq!(proof.value(), &(key * 10));
+            assert_eq!(proof.root(), Some(state.root()), "key {key} of {size}");
+        }
+        assert_eq!(state.prove(&size), None);
+    }
+    assert_eq!(hash(&MerkleState::<u8, u8>::default()), hash(&0u64));
+}
+
+#[test]
+fn cl_17_light_client_follows_best_header() {
+    let mut full = full_client();
+    let mut light = light_client(&full);
+    assert_eq!(light.best_hash(), full.genesis_hash());
+
+    let b1 = full.author_block(vec![pay(Bob, 10)]).unwrap();
+    let b2 = full.author_block(vec![]).unwrap();
+    let h1 = full.store().header(BlockId::Hash(b1)).unwrap();
+    let h2 = full.store().header(BlockId::Hash(b2)).unwrap();
+
+    // Headers arrive in order, or not at all.
+    assert_eq!(
+        light.import_header(h2.clone()),
+        Err(ImportError::UnknownParent)
+    );
+    assert_eq!(light.import_header(h1.clone()), Ok(b1));
+    assert_eq!(
+        light.import_header(h1.clone()),
+        Err(ImportError::AlreadyKnown)
+    );
+    assert_eq!(light.import_header(h2.clone()), Ok(b2));
+    assert_eq!(light.best_hash(), b2);
+    assert_eq!(light.best_header(), &h2);
+
+    // Tampering with a header breaks its seal, or its link to its parent.
+    // Some tampered headers still happen to meet the threshold, so pick one that does not.
+    let threshold = Pow::with_difficulty(4).threshold();
+    let forged = (1..)
+        .map(|bit| Header {
+            state_root: h1.state_root ^ bit,
+            ..h1.clone()
+        })
+        .find(|header| work_hash(header.pre_hash(), header.consensus_digest) > threshold)
+        .unwrap();
+    assert_eq!(light.import_header(forged), Err(ImportError::InvalidBlock));
+    let mut orphaned = h2;
+    orphaned.parent = full.genesis_hash();
+    assert_eq!(
+        light.import_header(orphaned),
+        Err(ImportError::InvalidBlock)
+    );
+}
+
+#[test]
+fn cl_17_light_client_checks_state_proofs() {
+    let mut full = full_client();
+    let mut light = light_client(&full);
+    let b1 = full.author_block(vec![pay(Bob, 10)]).unwrap();
+    let b2 = full.author_block(vec![pay(Charlie, 5)]).unwrap();
+    for hash in [b1, b2] {
+        let header = full.store().header(BlockId::Hash(hash)).unwrap();
+        light.import_header(header).unwrap();
+    }
+
+    let proof = full.prove_value(b2, &Bob).unwrap();
+    assert_eq!(light.verify_value(b2, &Bob, &proof), Ok(10));
+    let proof = full.prove_value(b2, &Alice).unwrap();
+    assert_eq!(light.verify_value(b2, &Alice, &proof), Ok(85));
+    assert_eq!(full.prove_value(b1, &Charlie), None);
+
+    // A proof is only good for the key and the block it was made for.
+    assert_eq!(
+        light.verify_value(b2, &Bob, &proof),
+        Err(ProofError::WrongKey)
+    );
+    assert_eq!(
+        light.verify_value(b1, &Alice, &proof),
+        Err(ProofError::InvalidProof)
+    );
+    assert_eq!(
+        light.verify_value(42, &Alice, &proof),
+        Err(ProofError::UnknownBlock)
+    );
+
+    // A full node can not lie about the value.
+    let mut lie = proof;
+    lie.value = 1000;
+    assert_eq!(
+        light.verify_value(b2, &Alice, &lie),
+        Err(ProofError::InvalidProof)
+    );
+}
@@ -0,0 +1,295 @@
+//! A fresh client that starts from genesis has to download and execute every block in history
+//! before it can follow the chain. On an old chain that takes days. Finality offers a shortcut.
+//! A finalized block can never be reverted, so a client that is convinced a block is final
+//! does not need its history at all. It only needs that block, and the state after it.
+//!
+//! This is called warp sync. A full node hands the fresh client a warp proof: a finalized
+//! block, a justification that proves it is final, and the state at that block. The client
+//! checks the justification against the authority set it was configured with, checks that the
+//! block's state root matches the state, and then starts from that block as if it were
+//! genesis. From there on it imports blocks normally.
+//!
+//! Real chains also have to prove every change of the authority set since genesis. Ours never
+//! changes, so the configured set is all a client needs.
+
+use std::fmt;
+
+use super::p1_data_structure::extrinsics_root;
+use super::{Block, BlockStore, ForkTree, FullClient, Justification, MemoryStore};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::{Consensus, ConsensusAuthority};
+use crate::hash;
+
+/// Everything that a fresh client needs to start following the chain from a finalized block.
+// Serde would otherwise require the consensus engine and state machine themselves to be
+// serializable, so we only bound the types that are actually stored.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "C::Digest: serde::Serialize, SM::Transition: serde::Serialize, \
+                     SM::State: serde::Serialize",
+        deserialize = "C::Digest: serde::de::DeserializeOwned, \
+                       SM::Transition: serde::de::DeserializeOwned, \
+                       SM::State: serde::de::DeserializeOwned"
+    ))
+)]
+pub struct WarpProof<C: Consensus, SM: StateMachine> {
+    /// The finalized block to start from. Its body is needed to check the children's context.
+    pub block: Block<C, SM>,
+    /// Proof that the block is final.
+    pub justification: Justification,
+    /// The state after the block.
+    pub state: SM::State,
+}
+
+// Deriving these traits would require the consensus engine and state machine
+// themselves to implement them, so we write them by hand instead.
+impl<C: Consensus, SM: StateMachine> Clone for WarpProof<C, SM>
+where
+    SM::State: Clone,
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        WarpProof {
+            block: self.block.clone(),
+            justification: self.justification.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> PartialEq for WarpProof<C, SM>
+where
+    SM::State: PartialEq,
+    SM::Transition: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.block == other.block
+            && self.justification == other.justification
+            && self.state == other.state
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> fmt::Debug for WarpProof<C, SM>
+where
+    SM::State: fmt::Debug,
+    SM::Transition: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarpProof")
+            .field("block", &self.block)
+            .field("justification", &self.justification)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// The reasons that a warp proof is refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarpSyncError {
+    /// The justification is for a different block.
+    BlockMismatch,
+    /// The justification does not convince the configured authority set.
+    InvalidJustification,
+    /// The block's body or context does not match its header.
+    InvalidBlock,
+    /// The state does not match the block's state root.
+    StateMismatch,
+}
+
+impl<C, SM> WarpProof<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: std::hash::Hash,
+    SM::Transition: std::hash::Hash,
+{
+    /// Check that this proof shows its block is final according to the given authority set,
+    /// and that its state is the state at that block.
+    pub fn verify(&self, authorities: &[ConsensusAuthority]) -> Result<(), WarpSyncError> {
+        if self.justification.block_hash != self.block.hash() {
+            return Err(WarpSyncError::BlockMismatch);
+        }
+        if !self.justification.verify_justification(authorities) {
+            return Err(WarpSyncError::InvalidJustification);
+        }
+        let header = &self.block.header;
+        if header.extrinsics_root != extrinsics_root(&self.block.body, self.block.context())
+            || self.block.context().height != header.height
+        {
+            return Err(WarpSyncError::InvalidBlock);
+        }
+        if hash(&self.state) != header.state_root {
+            return Err(WarpSyncError::StateMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    S: BlockStore<C, SM>,
+{
+    /// A warp proof for the block that the given justification finalizes, for a fresh client
+    /// to start from. Returns None if the block or its state is not known. The justification
+    /// itself is not checked.
+    pub fn warp_proof(&self, justification: Justification) -> Option<WarpProof<C, SM>> {
+        let block_hash = justification.block_hash;
+        Some(WarpProof {
+            block: self.store.block(block_hash)?,
+            state: self.states.get(&block_hash)?.clone(),
+            justification,
+        })
+    }
+}
+
+impl<C, SM, FC, P> FullClient<C, SM, FC, P>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+{
+    /// Create a client that starts from the finalized block in the given warp proof, rather
+    /// than from genesis, once the proof convinces the given authority set.
+    ///
+    /// The client knows nothing before that block. It treats the block as its genesis, so
+    /// that is the hash `genesis_hash` returns.
+    pub fn from_warp_proof(
+        consensus_engine: C,
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        authorities: &[ConsensusAuthority],
+        proof: WarpProof<C, SM>,
+    ) -> Result<Self, WarpSyncError> {
+        proof.verify(authorities)?;
+
+        let height = proof.block.header.height;
+        let mut store = MemoryStore::default();
+        let start = store.insert_block(proof.block);
+        store.set_best(start);
+        store.set_finalized(start);
+
+        Ok(FullClient::from_parts(
+            consensus_engine,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            store,
+            ForkTree::starting_at(start, height),
+            proof.state,
+        ))
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, Vote};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+#[cfg(test)]
+use crate::c2_blockchain::BlockId;
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), ()>;
+
+#[cfg(test)]
+const AUTHORITIES: [ConsensusAuthority; 3] = [
+    ConsensusAuthority::Alice,
+    ConsensusAuthority::Bob,
+    ConsensusAuthority::Charlie,
+];
+
+/// A full client whose chain is five blocks long. Each block mints one coin for Bob.
+#[cfg(test)]
+fn synced_client() -> TestClient {
+    let mut client = FullClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    for _ in 0..5 {
+        let mint = CurrencyTransaction::Mint { to: Bob, amount: 1 };
+        client.author_block(vec![mint]).unwrap();
+    }
+    client
+}
+
+#[cfg(test)]
+fn justify(block_hash: u64, voters: &[ConsensusAuthority]) -> Justification {
+    Justification {
+        block_hash,
+        votes: voters
+            .iter()
+            .map(|&voter| Vote { block_hash, voter })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+fn warp(proof: WarpProof<(), Currency>) -> Result<TestClient, WarpSyncError> {
+    FullClient::from_warp_proof((), Currency, (), (), &AUTHORITIES, proof)
+}
+
+#[test]
+fn cl_18_warp_synced_client_follows_chain() {
+    let full = synced_client();
+    let b3 = full.resolve(BlockId::Number(3)).unwrap();
+    let proof = full.warp_proof(justify(b3, &AUTHORITIES)).unwrap();
+    let mut client = warp(proof).unwrap();
+
+    assert_eq!(client.genesis_hash(), b3);
+    assert_eq!(client.best_block(), b3);
+    assert_eq!(client.best_header().height, 3);
+    assert_eq!(client.store().finalized(), b3);
+    assert_eq!(
+        client.state_at(BlockId::Number(3)),
+        Ok(Balances::from([(Alice, 100), (Bob, 3)]))
+    );
+    assert_eq!(client.resolve(BlockId::Number(2)), None);
+
+    // From here on, blocks are imported as usual.
+    for height in [4, 5] {
+        let hash = full.resolve(BlockId::Number(height)).unwrap();
+        assert!(client.import_block(full.get_block(hash).unwrap()));
+    }
+    assert_eq!(client.best_block(), full.best_block());
+    assert_eq!(client.best_header().height, 5);
+    assert_eq!(client.best_state(), full.best_state());
+    let b6 = client.author_block(vec![]).unwrap();
+    assert_eq!(client.resolve(BlockId::Number(6)), Some(b6));
+}
+
+#[test]
+fn cl_18_warp_proof_must_convince_authorities() {
+    let full = synced_client();
+    let b3 = full.resolve(BlockId::Number(3)).unwrap();
+    let b4 = full.resolve(BlockId::Number(4)).unwrap();
+    let proof = full.warp_proof(justify(b3, &AUTHORITIES)).unwrap();
+
+    // Two of three authorities is not more than two thirds.
+    let mut weak = proof.clone();
+    weak.justification = justify(b3, &AUTHORITIES[..2]);
+    assert!(matches!(
+        warp(weak),
+        Err(WarpSyncError::InvalidJustification)
+    ));
+
+    let mut elsewhere = proof.clone();
+    elsewhere.justification = justify(b4, &AUTHORITIES);
+    assert!(matches!(warp(elsewhere), Err(WarpSyncError::BlockMismatch)));
+
+    let mut rich = proof.clone();
+    rich.state.insert(Charlie, 1_000);
+    assert!(matches!(warp(rich), Err(WarpSyncError::StateMismatch)));
+
+    let mut padded = proof;
+    padded
+        .block
+        .body
+        .push(CurrencyTransaction::Mint { to: Bob, amount: 1 });
+    padded.justification = justify(padded.block.hash(), &AUTHORITIES);
+    assert!(matches!(warp(padded), Err(WarpSyncError::InvalidBlock)));
+
+    assert_eq!(full.warp_proof(justify(42, &AUTHORITIES)), None);
+}
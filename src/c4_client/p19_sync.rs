@@ -0,0 +1,448 @@
+//! A client that has been offline, or has just started, is behind its peers. It catches up by
+//! asking a peer for the blocks it is missing. Blocks are large, so this is done in two steps.
+//! First the client asks for a batch of headers along the peer's best chain. Headers are small,
+//! and are enough to tell where the client's chain and the peer's meet. Then it asks for the
+//! bodies of the headers it does not have yet, and imports the complete blocks.
+//!
+//! The client may have followed a fork that the peer has abandoned. In that case the first
+//! header it gets back does not build on anything it knows. So it asks again from further
+//! back, doubling the distance each time, until the headers connect to its own chain.
+//!
+//! The `SyncWorker` here only decides what to ask and what to do with the answers. It does
+//! not care how the messages travel, so it works just as well over channels or a network as it
+//! does when calling the peer directly.
+
+use super::{Block, BlockStore, ForkChoice, FullClient, Header, ImportError};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// The most headers or bodies that a client sends in a single response.
+pub const MAX_BLOCKS_PER_RESPONSE: usize = 128;
+
+/// A request that a syncing client sends to a peer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncRequest {
+    /// The headers of up to `max` consecutive blocks on the peer's best chain, starting with
+    /// the one at height `from`.
+    GetHeaders { from: u64, max: usize },
+    /// The bodies of the blocks with the given hashes.
+    GetBodies { hashes: Vec<Hash> },
+}
+
+/// A peer's response to a `SyncRequest`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SyncResponse<Digest, Transition> {
+    /// The requested headers, in order of height. The list stops early at the peer's best
+    /// block, so an empty list means the peer has nothing new.
+    Headers(Vec<Header<Digest>>),
+    /// The requested bodies, in the order they were asked for, along with the context each
+    /// is executed in. The list stops early at the first body the peer does not have.
+    Bodies(Vec<(Vec<Transition>, BlockContext)>),
+}
+
+/// The reasons that syncing from a peer fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncError {
+    /// The peer answered something other than what was asked.
+    UnexpectedResponse,
+    /// The peer's headers do not form a chain.
+    DisconnectedHeaders,
+    /// The peer's headers do not start at the height that was asked for, or skip heights.
+    /// Trusting them would let the peer steer which heights are asked for next.
+    UnexpectedHeights,
+    /// Even the peer's first block does not build on our genesis, so the peer follows another
+    /// chain entirely.
+    DifferentGenesis,
+    /// A block from the peer could not be imported.
+    Import(ImportError),
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// Answer a syncing peer's request. At most `MAX_BLOCKS_PER_RESPONSE` headers or bodies
+    /// are sent at once.
+    pub fn answer_sync_request(
+        &self,
+        request: &SyncRequest,
+    ) -> SyncResponse<C::Digest, SM::Transition> {
+        match request {
+            SyncRequest::GetHeaders { from, max } => {
+                let headers = (*from..)
+                    .take((*max).min(MAX_BLOCKS_PER_RESPONSE))
+                    .map_while(|height| self.store.header(BlockId::Number(height)))
+                    .collect();
+                SyncResponse::Headers(headers)
+            }
+            SyncRequest::GetBodies { hashes } => {
+                let bodies = hashes
+                    .iter()
+                    .take(MAX_BLOCKS_PER_RESPONSE)
+                    .map_while(|hash| Some((self.store.body(*hash)?, self.store.context(*hash)?)))
+                    .collect();
+                SyncResponse::Bodies(bodies)
+            }
+        }
+    }
+}
+
+/// Drives a client to the best block of a peer, one batch of blocks at a time.
+pub struct SyncWorker<Digest> {
+    /// How many blocks to ask for at once.
+    batch_size: usize,
+    /// The height of the next header to ask for.
+    next_height: u64,
+    /// How far to step back the next time the peer's headers do not connect to our chain.
+    step_back: u64,
+    /// Headers whose bodies have been asked for, in order of height.
+    pending: Vec<Header<Digest>>,
+    /// Whether the peer has no headers beyond those we have.
+    caught_up: bool,
+}
+
+impl<Digest> SyncWorker<Digest>
+where
+    Digest: Clone + std::hash::Hash,
+{
+    /// A worker that asks for up to `batch_size` blocks at a time, starting just above the
+    /// given client's best block.
+    pub fn new<C, SM, FC, P, S>(client: &FullClient<C, SM, FC, P, S>, batch_size: usize) -> Self
+    where
+        C: Consensus<Digest = Digest>,
+        SM: StateMachine,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let best_height = client.height_of(client.best_block());
+        Self {
+            batch_size: batch_size.max(1),
+            next_height: best_height + 1,
+            step_back: 1,
+            pending: Vec::new(),
+            caught_up: false,
+        }
+    }
+
+    /// Whether the last headers request found nothing new, so the client has caught up.
+    pub fn is_caught_up(&self) -> bool {
+        self.caught_up
+    }
+
+    /// The next request to send to the peer, or None once the client has caught up.
+    pub fn next_request(&self) -> Option<SyncRequest> {
+        if !self.pending.is_empty() {
+            let hashes = self.pending.iter().map(hash).collect();
+            return Some(SyncRequest::GetBodies { hashes });
+        }
+        if self.caught_up {
+            return None;
+        }
+        Some(SyncRequest::GetHeaders {
+            from: self.next_height,
+            max: self.batch_size,
+        })
+    }
+
+    /// Handle the peer's response to the last request. Returns the number of blocks imported.
+    pub fn on_response<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        response: SyncResponse<Digest, SM::Transition>,
+    ) -> Result<usize, SyncError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        match response {
+            SyncResponse::Headers(headers) if self.pending.is_empty() => {
+                self.on_headers(client, headers)?;
+                Ok(0)
+            }
+            SyncResponse::Bodies(bodies) if !self.pending.is_empty() => {
+                self.on_bodies(client, bodies)
+            }
+            _ => Err(SyncError::UnexpectedResponse),
+        }
+    }
+
+    /// Remember the headers that we do not have yet, so that their bodies are asked for next.
+    /// The headers must be the ones that were asked for: at most a batch of them, starting at
+    /// the requested height, one height after another.
+    fn on_headers<C, SM, FC, P, S>(
+        &mut self,
+        client: &FullClient<C, SM, FC, P, S>,
+        headers: Vec<Header<Digest>>,
+    ) -> Result<(), SyncError>
+    where
+        C: Consensus<Digest = Digest>,
+        SM: StateMachine,
+        S: BlockStore<C, SM>,
+    {
+        let Some(first) = headers.first() else {
+            self.caught_up = true;
+            return Ok(());
+        };
+        if headers.len() > self.batch_size {
+            return Err(SyncError::UnexpectedResponse);
+        }
+        if headers
+            .windows(2)
+            .any(|pair| pair[1].parent != hash(&pair[0]))
+        {
+            return Err(SyncError::DisconnectedHeaders);
+        }
+        if first.height != self.next_height
+            || headers
+                .windows(2)
+                .any(|pair| pair[0].height.checked_add(1) != Some(pair[1].height))
+        {
+            return Err(SyncError::UnexpectedHeights);
+        }
+
+        // The peer's chain forks off below these headers, so look further back.
+        if !client.store.contains(first.parent) {
+            if first.height <= 1 {
+                return Err(SyncError::DifferentGenesis);
+            }
+            self.next_height = first.height.saturating_sub(self.step_back).max(1);
+            self.step_back *= 2;
+            return Ok(());
+        }
+
+        self.step_back = 1;
+        self.next_height = headers[headers.len() - 1].height + 1;
+        self.pending = headers
+            .into_iter()
+            .filter(|header| !client.store.contains(hash(header)))
+            .collect();
+        Ok(())
+    }
+
+    /// Import the blocks whose bodies arrived. Headers whose bodies did not arrive are asked
+    /// for again later. Returns the number of blocks imported.
+    fn on_bodies<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        bodies: Vec<(Vec<SM::Transition>, BlockContext)>,
+    ) -> Result<usize, SyncError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        if bodies.is_empty() || bodies.len() > self.pending.len() {
+            return Err(SyncError::UnexpectedResponse);
+        }
+        let headers: Vec<_> = self.pending.drain(..bodies.len()).collect();
+        if let Some(missing) = self.pending.first() {
+            self.next_height = missing.height;
+            self.pending.clear();
+        }
+
+        let mut imported = 0;
+        for (header, (body, context)) in headers.into_iter().zip(bodies) {
+            let block = Block {
+                header,
+                body,
+                context,
+            };
+            match client.try_import_block(block) {
+                Ok(_) => imported += 1,
+                Err(ImportError::AlreadyKnown) => {}
+                Err(error) => return Err(SyncError::Import(error)),
+            }
+        }
+        Ok(imported)
+    }
+
+    /// Bring the given client up to the given peer's best block, asking the peer directly.
+    /// Returns the number of blocks imported.
+    pub fn sync_from<C, SM, FC, P, S, PeerFC, PeerP, PeerS>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        peer: &FullClient<C, SM, PeerFC, PeerP, PeerS>,
+    ) -> Result<usize, SyncError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+        PeerS: BlockStore<C, SM>,
+    {
+        let mut imported = 0;
+        while let Some(request) = self.next_request() {
+            imported += self.on_response(client, peer.answer_sync_request(&request))?;
+        }
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, LongestChain, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestClient = FullClient<(), LightSwitch, LongestChain, ()>;
+
+#[cfg(test)]
+fn client(genesis_state: bool) -> TestClient {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::new((), LightSwitch, fork_choice, (), genesis_state)
+}
+
+/// Extend the given client's best chain with the given number of blocks, each of whose
+/// bodies is the given number of toggles.
+#[cfg(test)]
+fn extend(client: &mut TestClient, blocks: usize, toggles: usize) {
+    for _ in 0..blocks {
+        let parent = client.get_block(client.best_block()).unwrap();
+        let child = parent
+            .child(&(), client.best_state(), vec![(); toggles])
+            .unwrap();
+        assert!(client.import_block(child));
+    }
+}
+
+#[test]
+fn cl_19_lagging_client_catches_up_in_batches() {
+    let mut peer = client(false);
+    extend(&mut peer, 10, 1);
+    let mut lagging = client(false);
+    for height in 1..=2 {
+        let hash = peer.resolve(BlockId::Number(height)).unwrap();
+        assert!(lagging.import_block(peer.get_block(hash).unwrap()));
+    }
+
+    let mut worker = SyncWorker::new(&lagging, 3);
+    assert_eq!(
+        worker.next_request(),
+        Some(SyncRequest::GetHeaders { from: 3, max: 3 })
+    );
+    let mut imported = 0;
+    while let Some(request) = worker.next_request() {
+        if let SyncRequest::GetBodies { hashes } = &request {
+            assert!(hashes.len() <= 3);
+        }
+        imported += worker
+            .on_response(&mut lagging, peer.answer_sync_request(&request))
+            .unwrap();
+    }
+
+    assert_eq!(imported, 8);
+    assert!(worker.is_caught_up());
+    assert_eq!(lagging.best_block(), peer.best_block());
+    assert_eq!(lagging.best_state(), peer.best_state());
+
+    // Peers never send more than they have, nor more than the cap.
+    let request = SyncRequest::GetHeaders {
+        from: 0,
+        max: usize::MAX,
+    };
+    assert!(matches!(
+        peer.answer_sync_request(&request),
+        SyncResponse::Headers(headers) if headers.len() == 11
+    ));
+    let request = SyncRequest::GetBodies {
+        hashes: vec![peer.best_block(), 42, peer.genesis_hash()],
+    };
+    assert!(matches!(
+        peer.answer_sync_request(&request),
+        SyncResponse::Bodies(bodies) if bodies.len() == 1
+    ));
+}
+
+#[test]
+fn cl_19_client_on_abandoned_fork_switches_to_peer_chain() {
+    let mut peer = client(false);
+    extend(&mut peer, 6, 1);
+    let mut forked = client(false);
+    extend(&mut forked, 3, 2);
+
+    let mut worker = SyncWorker::new(&forked, 4);
+    assert_eq!(worker.sync_from(&mut forked, &peer), Ok(6));
+    assert_eq!(forked.best_block(), peer.best_block());
+    assert_eq!(forked.best_state(), peer.best_state());
+
+    // Once caught up, a worker has nothing more to ask.
+    let mut worker = SyncWorker::new(&forked, 4);
+    assert_eq!(worker.sync_from(&mut forked, &peer), Ok(0));
+    assert_eq!(worker.next_request(), None);
+}
+
+#[test]
+fn cl_19_sync_fails_on_bad_responses() {
+    let mut peer = client(false);
+    extend(&mut peer, 3, 1);
+
+    let mut stranger = client(true);
+    let mut worker = SyncWorker::new(&stranger, 4);
+    assert_eq!(
+        worker.sync_from(&mut stranger, &peer),
+        Err(SyncError::DifferentGenesis)
+    );
+
+    let mut lagging = client(false);
+    let mut worker = SyncWorker::new(&lagging, 4);
+    assert_eq!(
+        worker.on_response(&mut lagging, SyncResponse::Bodies(vec![])),
+        Err(SyncError::UnexpectedResponse)
+    );
+    let header = |height| peer.store().header(BlockId::Number(height)).unwrap();
+    let gap = SyncResponse::Headers(vec![header(1), header(3)]);
+    assert_eq!(
+        worker.on_response(&mut lagging, gap),
+        Err(SyncError::DisconnectedHeaders)
+    );
+
+    // The worker asked for headers from height 1, and at most 4 of them.
+    let late = SyncResponse::Headers(vec![header(2), header(3)]);
+    assert_eq!(
+        worker.on_response(&mut lagging, late),
+        Err(SyncError::UnexpectedHeights)
+    );
+    let skipping = Header {
+        parent: hash(&header(1)),
+        height: 7,
+        ..header(2)
+    };
+    let skipping = SyncResponse::Headers(vec![header(1), skipping]);
+    assert_eq!(
+        worker.on_response(&mut lagging, skipping),
+        Err(SyncError::UnexpectedHeights)
+    );
+    extend(&mut peer, 2, 1);
+    let header = |height| peer.store().header(BlockId::Number(height)).unwrap();
+    let too_many = SyncResponse::Headers((1..=5).map(header).collect());
+    assert_eq!(
+        worker.on_response(&mut lagging, too_many),
+        Err(SyncError::UnexpectedResponse)
+    );
+    assert_eq!(
+        worker.next_request(),
+        Some(SyncRequest::GetHeaders { from: 1, max: 4 })
+    );
+}
@@ -16,62 +16,354 @@
 //!
 //! This abstraction is the key idea behind blockchain _frameworks_ like Substrate or the Cosmos SDK.
 
-use super::{Consensus, ForkChoice, Header, StateMachine};
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{
+    BlockStore, Checkpoints, Consensus, ForkTree, Header, MemoryStore, Metrics, Pruning,
+    StateMachine, VerificationCache,
+};
+use crate::c1_state_machine::BlockContext;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::ConsensusAuthority;
+use crate::hash;
 
 use super::FullClient;
 type Hash = u64;
 
-impl<Digest> Header<Digest> {
+impl<Digest: std::hash::Hash> Header<Digest> {
     /// Returns a new valid genesis header.
-    fn genesis(genesis_state_root: Hash) -> Self {
-        todo!("Exercise 1")
+    ///
+    /// By convention the genesis header is not sealed, so it carries the
+    /// default digest for the consensus engine in use.
+    fn genesis(genesis_state_root: Hash) -> Self
+    where
+        Digest: Default,
+    {
+        // todo!("Exercise 1")
+        Header {
+            parent: 0,
+            height: 0,
+            state_root: genesis_state_root,
+            extrinsics_root: extrinsics_root::<()>(&[], &BlockContext::default()),
+            uncles: Vec::new(),
+            consensus_digest: Digest::default(),
+        }
     }
 
     /// Create and return a valid child header.
-    fn child(&self, state_root: Hash, extrinsics_root: Hash) -> Self {
-        todo!("Exercise 2")
+    ///
+    /// The returned header is not sealed yet. It is up to the consensus engine
+    /// to turn it into a complete header.
+    fn child(&self, state_root: Hash, extrinsics_root: Hash) -> Header<()> {
+        // todo!("Exercise 2")
+        Header {
+            parent: hash(self),
+            height: self.height + 1,
+            state_root,
+            extrinsics_root,
+            uncles: Vec::new(),
+            consensus_digest: (),
+        }
     }
 
     /// Verify a single child header.
-    fn verify_child(&self, child: &Self) -> bool {
-        todo!("Exercise 3")
+    pub(crate) fn verify_child(&self, child: &Self) -> bool {
+        // todo!("Exercise 3")
+        child.parent == hash(self) && child.height == self.height + 1
     }
 
     /// Verify that all the given headers form a valid chain from this header to the tip.
     fn verify_sub_chain(&self, chain: &[Self]) -> bool {
-        todo!("Exercise 4")
+        // todo!("Exercise 4")
+        let mut parent = self;
+        for child in chain {
+            if !parent.verify_child(child) {
+                return false;
+            }
+            parent = child;
+        }
+        true
     }
 }
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+
+// Serde would otherwise require the consensus engine and state machine themselves to be
+// serializable, so we only bound the types that are actually stored.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "C::Digest: serde::Serialize, SM::Transition: serde::Serialize",
+        deserialize = "C::Digest: serde::de::DeserializeOwned, \
+                       SM::Transition: serde::de::DeserializeOwned"
+    ))
+)]
 pub struct Block<C: Consensus, SM: StateMachine> {
-    header: Header<C::Digest>,
-    body: Vec<SM::Transition>,
+    pub(crate) header: Header<C::Digest>,
+    pub(crate) body: Vec<SM::Transition>,
+    /// What the state machine is told about this block while executing its body.
+    ///
+    /// The context is committed to by the header's extrinsics root, along with the body, much
+    /// like Substrate puts the timestamp in an inherent extrinsic. Otherwise anyone relaying the
+    /// block could change its timestamp without changing its hash. Its fields are also checked
+    /// against the header and the consensus engine during verification.
+    pub(crate) context: BlockContext,
+}
+
+// Deriving these traits would require the consensus engine and state machine
+// themselves to implement them, so we write them by hand instead.
+impl<C: Consensus, SM: StateMachine> Clone for Block<C, SM>
+where
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        Block {
+            header: self.header.clone(),
+            body: self.body.clone(),
+            context: self.context,
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> PartialEq for Block<C, SM>
+where
+    SM::Transition: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.body == other.body && self.context == other.context
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> fmt::Debug for Block<C, SM>
+where
+    SM::Transition: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Block")
+            .field("header", &self.header)
+            .field("body", &self.body)
+            .field("context", &self.context)
+            .finish()
+    }
 }
 
 impl<C: Consensus, SM: StateMachine> Block<C, SM> {
+    /// The hash of this block, which is the hash of its header.
+    pub fn hash(&self) -> Hash {
+        hash(&self.header)
+    }
+
+    /// The context that this block's body is executed in.
+    pub fn context(&self) -> &BlockContext {
+        &self.context
+    }
+
+    /// Check that this block's context is consistent with its header and with its parent.
+    ///
+    /// The timestamp may never go backwards. Engines that can tell who authored a block, such
+    /// as Proof of Authority, must agree with the declared author. Other engines, such as Proof
+    /// of Work, let the author declare whoever they like, much like a coinbase beneficiary.
+    fn context_is_valid(&self, consensus: &C, parent: &Self) -> bool {
+        let author_matches = match (self.context.author, consensus.author(&self.header)) {
+            (Some(declared), Some(sealed_by)) => declared == sealed_by,
+            _ => true,
+        };
+        self.context.height == self.header.height
+            && self.context.timestamp >= parent.context.timestamp
+            && author_matches
+    }
+}
+
+/// The extrinsics root of a block with the given body and context. It commits to both.
+pub(crate) fn extrinsics_root<T: std::hash::Hash>(body: &[T], context: &BlockContext) -> Hash {
+    hash(&(body, context))
+}
+
+/// Apply all of the given extrinsics, in order, to the given pre-state, within the block
+/// described by the given context.
+pub(crate) fn execute<SM>(
+    pre_state: &SM::State,
+    extrinsics: &[SM::Transition],
+    context: &BlockContext,
+) -> SM::State
+where
+    SM: StateMachine,
+    SM::State: Clone,
+{
+    extrinsics.iter().fold(pre_state.clone(), |state, t| {
+        SM::next_state_in_block(&state, t, context)
+    })
+}
+
+impl<C, SM> Block<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+{
     /// Returns a new valid genesis block. By convention this block has no extrinsics.
-    pub fn genesis(genesis_state: &SM::State) -> Self {
-        todo!("Exercise 5")
+    pub fn genesis(genesis_state: &SM::State) -> Self
+    where
+        C::Digest: Default,
+    {
+        // todo!("Exercise 5")
+        Block {
+            header: Header::genesis(hash(genesis_state)),
+            body: Vec::new(),
+            context: BlockContext::default(),
+        }
     }
 
     /// Create and return a valid child block.
-    pub fn child(&self, pre_state: &SM::State, extrinsics: Vec<u8>) -> Self {
-        todo!("Exercise 6")
+    ///
+    /// The new block is sealed by the given consensus engine. This returns None
+    /// if the engine is unable to seal it.
+    pub fn child(
+        &self,
+        consensus: &C,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+    ) -> Option<Self> {
+        // todo!("Exercise 6")
+        self.child_with_uncles(consensus, pre_state, extrinsics, Vec::new())
+    }
+
+    /// Create and return a valid child block that also references the given uncles.
+    ///
+    /// This does not check that the uncles are valid. Only a client that knows
+    /// about the uncle blocks can do that.
+    pub fn child_with_uncles(
+        &self,
+        consensus: &C,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        uncles: Vec<Hash>,
+    ) -> Option<Self> {
+        let context = self.child_context(self.context.timestamp, None);
+        self.seal_child(consensus, pre_state, extrinsics, uncles, context)
+    }
+
+    /// Create and return a valid child block that was authored at the given time by the given
+    /// author. Returns None if the engine is unable to seal it, or if it would be sealed by
+    /// someone other than the given author.
+    pub fn child_in_context(
+        &self,
+        consensus: &C,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        timestamp: u64,
+        author: Option<ConsensusAuthority>,
+    ) -> Option<Self> {
+        let context = self.child_context(timestamp, author);
+        let child = self.seal_child(consensus, pre_state, extrinsics, Vec::new(), context)?;
+        child.context_is_valid(consensus, self).then_some(child)
+    }
+
+    /// The context of a child of this block with the given timestamp and author.
+    pub(crate) fn child_context(
+        &self,
+        timestamp: u64,
+        author: Option<ConsensusAuthority>,
+    ) -> BlockContext {
+        BlockContext {
+            height: self.header.height + 1,
+            timestamp,
+            author,
+        }
+    }
+
+    /// Seal a child block with the given extrinsics, uncles, and context. This does not check
+    /// the context.
+    pub(crate) fn seal_child(
+        &self,
+        consensus: &C,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        uncles: Vec<Hash>,
+        context: BlockContext,
+    ) -> Option<Self> {
+        let mut partial_header = self.partial_child(pre_state, &extrinsics, &context);
+        partial_header.uncles = uncles;
+        let header = consensus.seal(&self.header.consensus_digest, partial_header)?;
+
+        Some(Block {
+            header,
+            body: extrinsics,
+            context,
+        })
+    }
+
+    /// Execute the given extrinsics on top of this block, and return the pre-seal header of the
+    /// resulting child block. The caller is responsible for sealing it.
+    pub(crate) fn partial_child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: &[SM::Transition],
+        context: &BlockContext,
+    ) -> Header<()> {
+        let post_state = execute::<SM>(pre_state, extrinsics, context);
+        self.header
+            .child(hash(&post_state), extrinsics_root(extrinsics, context))
+    }
+
+    /// Run every check on the given child block that does not require executing it: the link
+    /// to this block, the seal, the extrinsics root, and the block context. These are cheap,
+    /// so clients run them first, and only execute blocks that pass.
+    pub(crate) fn verify_child_header(&self, consensus: &C, child: &Self) -> bool {
+        self.header.verify_child(&child.header)
+            && consensus.validate(&self.header.consensus_digest, &child.header)
+            && child.header.extrinsics_root == extrinsics_root(&child.body, &child.context)
+            && child.context_is_valid(consensus, self)
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
-    pub fn verify_sub_chain(&self, pre_state: &SM::State, chain: &[Self]) -> bool {
-        todo!("Exercise 7")
+    ///
+    /// The pre-state is the state after this block has been executed. It is checked
+    /// against this block's state root before anything else is verified.
+    pub fn verify_sub_chain(&self, consensus: &C, pre_state: &SM::State, chain: &[Self]) -> bool {
+        // todo!("Exercise 7")
+        if hash(pre_state) != self.header.state_root {
+            return false;
+        }
+
+        let mut parent = self;
+        let mut state = pre_state.clone();
+        for block in chain {
+            if !parent.verify_child_header(consensus, block) {
+                return false;
+            }
+
+            state = execute::<SM>(&state, &block.body, &block.context);
+            if hash(&state) != block.header.state_root {
+                return false;
+            }
+            parent = block;
+        }
+        true
     }
 }
 
 /// Create and return a block chain that is n blocks long starting from the given genesis state.
 /// The blocks should not contain any transactions.
-fn create_empty_chain<C: Consensus, SM: StateMachine>(
-    n: u64,
-    genesis_state: &SM::State,
-) -> Vec<Block<C, SM>> {
-    todo!("Exercise 8")
+#[cfg(test)]
+fn create_empty_chain<C, SM>(n: u64, consensus: &C, genesis_state: &SM::State) -> Vec<Block<C, SM>>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+{
+    // todo!("Exercise 8")
+    let mut chain = vec![Block::genesis(genesis_state)];
+    while (chain.len() as u64) < n {
+        let child = chain[chain.len() - 1]
+            .child(consensus, genesis_state, Vec::new())
+            .expect("consensus engine should be able to seal an empty block");
+        chain.push(child);
+    }
+    chain
 }
 
 // To wrap this section up, we will implement the first two simple methods on our client.
@@ -79,10 +371,111 @@ fn create_empty_chain<C: Consensus, SM: StateMachine>(
 // genesis block.
 impl<C, SM, FC, P> FullClient<C, SM, FC, P>
 where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+{
+    pub fn new(
+        consensus_engine: C,
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        genesis_state: SM::State,
+    ) -> Self {
+        // todo!("Exercise 9")
+        Self::with_store(
+            consensus_engine,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            MemoryStore::default(),
+            genesis_state,
+        )
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Default,
     SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    S: BlockStore<C, SM>,
 {
-    fn new(genesis_state: SM::State) -> Self {
-        todo!("Exercise 9")
+    /// Create a client that keeps its blocks in the given store.
+    ///
+    /// If the store already holds a best chain built on the same genesis block, for example
+    /// because it is kept on disk and the node has restarted, the client picks up that chain.
+    pub fn with_store(
+        consensus_engine: C,
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        store: S,
+        genesis_state: SM::State,
+    ) -> Self {
+        let genesis = Block::genesis(&genesis_state);
+        Self::with_genesis(
+            consensus_engine,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            store,
+            genesis,
+            genesis_state,
+        )
+    }
+
+    /// Create a client that keeps its blocks in the given store, and starts from the given
+    /// genesis block, whose post-state is the given one.
+    pub(crate) fn with_genesis(
+        consensus_engine: C,
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        mut store: S,
+        genesis: Block<C, SM>,
+        genesis_state: SM::State,
+    ) -> Self {
+        let genesis_hash = store.insert_block(genesis);
+        let resuming = store.header(BlockId::Number(0)).map(|header| hash(&header));
+        if resuming != Some(genesis_hash) {
+            store.set_best(genesis_hash);
+            store.set_finalized(genesis_hash);
+        }
+
+        let mut client = Self::from_parts(
+            consensus_engine,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            store,
+            ForkTree::new(genesis_hash),
+            genesis_state,
+        );
+        if resuming == Some(genesis_hash) {
+            client.replay_best_chain();
+        }
+        client
+    }
+
+    /// Use the given checkpoints when importing blocks.
+    pub fn with_checkpoints(mut self, checkpoints: Checkpoints) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// The hash of the genesis block this client was initialized with.
+    pub fn genesis_hash(&self) -> Hash {
+        self.genesis_hash
+    }
+
+    /// The store that this client keeps its blocks in.
+    pub fn store(&self) -> &S {
+        &self.store
     }
 }
 
@@ -90,15 +483,219 @@ where
 // Depending on the state machine definition there may not _be_ a default
 // genesis state. There is only a default client when there is also a
 // default genesis state.
+impl<C: Consensus, SM: StateMachine, FC, P, S> FullClient<C, SM, FC, P, S> {
+    /// Put a client together from its parts. The root of the given fork tree is the block the
+    /// client starts from, and the given state is that block's post-state. The store must
+    /// already hold that block.
+    pub(super) fn from_parts(
+        consensus_engine: C,
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        store: S,
+        fork_tree: ForkTree,
+        state: SM::State,
+    ) -> Self
+    where
+        SM::State: Clone,
+    {
+        let start = fork_tree.root();
+        FullClient {
+            consensus_engine,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            store,
+            best_state: state.clone(),
+            states: HashMap::from([(start, state)]),
+            fork_tree,
+            event_subscribers: Vec::new(),
+            genesis_hash: start,
+            checkpoints: Checkpoints::default(),
+            equivocations: Vec::new(),
+            pruning: Pruning::default(),
+            metrics: Metrics::default(),
+            verified: VerificationCache::default(),
+        }
+    }
+}
+
 impl<C, SM, FC, P> Default for FullClient<C, SM, FC, P>
 where
-    C: Consensus,
-    SM: StateMachine,
-    FC: ForkChoice<C>,
+    C: Consensus + Default,
+    C::Digest: Default,
+    SM: StateMachine + Default,
+    SM::State: Clone + Default + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: Default,
+    P: Default,
 {
     fn default() -> Self {
-        todo!("Exerise 10")
+        // todo!("Exerise 10")
+        Self::new(
+            C::default(),
+            SM::default(),
+            FC::default(),
+            P::default(),
+            SM::State::default(),
+        )
     }
 }
 
-//TODO tests
+#[test]
+fn cl_1_empty_chain_is_valid() {
+    use crate::c1_state_machine::LightSwitch;
+
+    let chain = create_empty_chain::<(), LightSwitch>(4, &(), &false);
+
+    assert_eq!(chain.len(), 4);
+    assert!(chain[0].verify_sub_chain(&(), &false, &chain[1..]));
+}
+
+#[test]
+fn cl_1_child_block_executes_extrinsics() {
+    use crate::c1_state_machine::LightSwitch;
+
+    let g = Block::<(), LightSwitch>::genesis(&false);
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+
+    assert_eq!(b1.header.height, 1);
+    assert_eq!(b1.header.state_root, hash(&true));
+    assert!(g.verify_sub_chain(&(), &false, &[b1]));
+}
+
+#[test]
+fn cl_1_wrong_state_root_does_not_check() {
+    use crate::c1_state_machine::LightSwitch;
+
+    let g = Block::<(), LightSwitch>::genesis(&false);
+    let mut b1 = g.child(&(), &false, vec![()]).unwrap();
+    b1.body = vec![(), ()];
+    b1.header.extrinsics_root = extrinsics_root(&b1.body, &b1.context);
+
+    assert!(!g.verify_sub_chain(&(), &false, &[b1]));
+}
+
+#[test]
+fn cl_1_same_chain_code_with_different_engines() {
+    use crate::c1_state_machine::LightSwitch;
+    use crate::c3_consensus::{moderate_difficulty_pow, ConsensusAuthority, SimplePoa};
+
+    fn check<C: Consensus>(engine: &C)
+    where
+        C::Digest: Default,
+    {
+        let chain = create_empty_chain::<C, LightSwitch>(3, engine, &false);
+        assert!(chain[0].verify_sub_chain(engine, &false, &chain[1..]));
+    }
+
+    check(&());
+    check(&moderate_difficulty_pow());
+    check(&SimplePoa {
+        authorities: vec![ConsensusAuthority::Bob, ConsensusAuthority::Charlie],
+    });
+}
+
+#[test]
+fn cl_1_chain_sealed_by_one_engine_does_not_check_with_another() {
+    use crate::c1_state_machine::LightSwitch;
+    use crate::c3_consensus::{ConsensusAuthority, SimplePoa};
+
+    let alice_only = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+    let bob_only = SimplePoa {
+        authorities: vec![ConsensusAuthority::Bob],
+    };
+    let chain = create_empty_chain::<SimplePoa, LightSwitch>(3, &alice_only, &false);
+
+    assert!(chain[0].verify_sub_chain(&alice_only, &false, &chain[1..]));
+    assert!(!chain[0].verify_sub_chain(&bob_only, &false, &chain[1..]));
+}
+
+#[test]
+fn cl_1_block_context_drives_deadlines() {
+    use crate::c1_state_machine::{
+        hash_lock, Balances, Escrow, EscrowState, EscrowTransition, User::*,
+    };
+
+    let genesis_state = EscrowState::new(Balances::from([(Alice, 100)]));
+    let g = Block::<(), Escrow>::genesis(&genesis_state);
+    let lock = EscrowTransition::Lock {
+        sender: Alice,
+        recipient: Bob,
+        amount: 40,
+        hash_lock: hash_lock(7),
+        deadline: 3,
+    };
+    let refund = EscrowTransition::Refund { lock: 0 };
+
+    // Nobody submits `NewBlock` transitions. The escrow learns the height from the context.
+    let b1 = g.child(&(), &genesis_state, vec![lock.clone()]).unwrap();
+    let s1 = execute::<Escrow>(&genesis_state, &[lock], b1.context());
+    let b2 = b1.child(&(), &s1, vec![refund.clone()]).unwrap();
+    let s2 = execute::<Escrow>(&s1, std::slice::from_ref(&refund), b2.context());
+    assert_eq!(s2.locks.len(), 1);
+
+    let b3 = b2.child(&(), &s2, vec![refund.clone()]).unwrap();
+    let s3 = execute::<Escrow>(&s2, &[refund], b3.context());
+    assert_eq!(s3.height, 3);
+    assert!(s3.locks.is_empty());
+    assert_eq!(s3.balance(Alice), 100);
+
+    assert!(g.verify_sub_chain(&(), &genesis_state, &[b1, b2, b3]));
+}
+
+#[test]
+fn cl_1_block_context_must_match_chain() {
+    use crate::c1_state_machine::LightSwitch;
+    use crate::c3_consensus::SimplePoa;
+
+    let alice_only = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+    let g = Block::<SimplePoa, LightSwitch>::genesis(&false);
+    let b1 = g
+        .child_in_context(
+            &alice_only,
+            &false,
+            vec![],
+            1_000,
+            Some(ConsensusAuthority::Alice),
+        )
+        .unwrap();
+    assert_eq!(b1.context().height, 1);
+    assert!(g.verify_sub_chain(&alice_only, &false, std::slice::from_ref(&b1)));
+
+    // Only Alice can seal, so the block can not claim to be authored by Bob.
+    let bob = Some(ConsensusAuthority::Bob);
+    assert!(g
+        .child_in_context(&alice_only, &false, vec![], 1_000, bob)
+        .is_none());
+    let mut wrong_author = b1.clone();
+    wrong_author.context.author = bob;
+    assert!(!g.verify_sub_chain(&alice_only, &false, &[wrong_author]));
+
+    // The context is committed to by the header, so it can not be changed in transit, even
+    // though the block's hash stays the same.
+    let mut later = b1.clone();
+    later.context.timestamp = 2_000;
+    assert_eq!(later.hash(), b1.hash());
+    assert!(!g.verify_sub_chain(&alice_only, &false, &[later]));
+
+    let mut wrong_height = b1.clone();
+    wrong_height.context.height = 5;
+    assert!(!g.verify_sub_chain(&alice_only, &false, &[wrong_height]));
+
+    // Time never goes backwards.
+    assert!(b1
+        .child_in_context(&alice_only, &false, vec![], 999, None)
+        .is_none());
+    let b2 = b1
+        .child_in_context(&alice_only, &false, vec![], 1_000, None)
+        .unwrap();
+    assert!(g.verify_sub_chain(&alice_only, &false, &[b1.clone(), b2.clone()]));
+    let mut earlier = b2;
+    earlier.context.timestamp = 999;
+    assert!(!g.verify_sub_chain(&alice_only, &false, &[b1, earlier]));
+}
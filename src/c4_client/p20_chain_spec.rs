@@ -0,0 +1,338 @@
+//! Everything that makes one network different from another has so far been a constant in the
+//! code: the genesis state, the mining difficulty, the authority set. Starting a new test
+//! network meant recompiling. Real nodes read these from a chain specification file instead,
+//! so the same binary can join any network it is given a spec for.
+//!
+//! A chain spec names the chain, gives its genesis state, sets the parameters of its consensus
+//! engine, and lists the heights at which its planned forks activate. Two nodes booted from
+//! the same spec have the same genesis block and the same engine, so they follow the same
+//! chain. The genesis block commits to the chain's name, so two specs that differ only in
+//! their names still describe different chains.
+//!
+//! A node that does not know how to follow one of the planned forks would leave the chain at
+//! that fork's height, so it refuses to boot from the spec at all.
+//!
+//! Reading a spec from a JSON file needs the `json` feature.
+
+use std::collections::BTreeMap;
+
+use super::{Block, BlockStore, FullClient, MemoryStore};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::{Consensus, ConsensusAuthority, PoaRoundRobinByHeight, Pow, SimplePoa};
+use crate::hash;
+
+/// The parameters of a chain's consensus engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConsensusParams {
+    /// The largest work hash that proof of work accepts.
+    pub threshold: u64,
+    /// The authorities that may author blocks under proof of authority.
+    pub authorities: Vec<ConsensusAuthority>,
+}
+
+impl ConsensusParams {
+    /// A proof of work engine with this threshold.
+    pub fn pow(&self) -> Pow {
+        Pow::with_threshold(self.threshold)
+    }
+
+    /// A proof of authority engine in which any of these authorities may author.
+    pub fn poa(&self) -> SimplePoa {
+        SimplePoa {
+            authorities: self.authorities.clone(),
+        }
+    }
+
+    /// A proof of authority engine in which these authorities take turns, in order.
+    pub fn round_robin(&self) -> PoaRoundRobinByHeight {
+        PoaRoundRobinByHeight {
+            authorities: self.authorities.clone(),
+        }
+    }
+}
+
+/// Consensus engines that can be built from a chain spec.
+pub trait FromChainSpec: Consensus + Sized {
+    /// The names of the forks that this engine knows how to follow.
+    const FORKS: &'static [&'static str] = &[];
+
+    /// Build the engine from the given parameters, activating the given forks at their heights.
+    fn from_spec(params: &ConsensusParams, forks: &BTreeMap<String, u64>) -> Self;
+}
+
+impl FromChainSpec for Pow {
+    fn from_spec(params: &ConsensusParams, _: &BTreeMap<String, u64>) -> Self {
+        params.pow()
+    }
+}
+
+impl FromChainSpec for SimplePoa {
+    fn from_spec(params: &ConsensusParams, _: &BTreeMap<String, u64>) -> Self {
+        params.poa()
+    }
+}
+
+impl FromChainSpec for PoaRoundRobinByHeight {
+    fn from_spec(params: &ConsensusParams, _: &BTreeMap<String, u64>) -> Self {
+        params.round_robin()
+    }
+}
+
+/// Everything that sets one chain apart from another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainSpec<State> {
+    /// The name of the chain. Nodes use it to tell networks apart before comparing genesis
+    /// blocks.
+    pub chain_id: String,
+    /// The state in the genesis block.
+    pub genesis: State,
+    /// The parameters of the consensus engine.
+    pub consensus: ConsensusParams,
+    /// The height at which each planned fork activates, by name.
+    pub forks: BTreeMap<String, u64>,
+}
+
+impl<State> ChainSpec<State> {
+    /// The height at which the named fork activates, or None if the chain never plans it.
+    pub fn fork_height(&self, fork: &str) -> Option<u64> {
+        self.forks.get(fork).copied()
+    }
+
+    /// Whether the named fork is active for the block at the given height.
+    pub fn is_active(&self, fork: &str, height: u64) -> bool {
+        self.fork_height(fork)
+            .is_some_and(|activation| height >= activation)
+    }
+
+    /// The consensus engine that this spec describes. Fails if the spec plans a fork that the
+    /// engine does not know how to follow.
+    pub fn engine<C: FromChainSpec>(&self) -> Result<C, ChainSpecError> {
+        if let Some(fork) = self
+            .forks
+            .keys()
+            .find(|fork| !C::FORKS.contains(&fork.as_str()))
+        {
+            return Err(ChainSpecError::UnknownFork(fork.clone()));
+        }
+        Ok(C::from_spec(&self.consensus, &self.forks))
+    }
+
+    /// The genesis block of this chain. Nothing comes before it, so it names the hash of the
+    /// chain id as its parent.
+    pub fn genesis_block<C, SM>(&self) -> Block<C, SM>
+    where
+        C: Consensus,
+        C::Digest: Default,
+        SM: StateMachine<State = State>,
+        State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+    {
+        let mut genesis = Block::genesis(&self.genesis);
+        genesis.header.parent = hash(&self.chain_id);
+        genesis
+    }
+}
+
+/// The reasons that a chain spec can not be loaded, or a client booted from it.
+#[derive(Debug)]
+pub enum ChainSpecError {
+    /// The file could not be read.
+    #[cfg(feature = "json")]
+    Io(std::io::Error),
+    /// The file is not a valid chain spec.
+    #[cfg(feature = "json")]
+    Parse(serde_json::Error),
+    /// The spec plans a fork, by this name, that the consensus engine does not know how to
+    /// follow.
+    UnknownFork(String),
+}
+
+#[cfg(feature = "json")]
+impl From<std::io::Error> for ChainSpecError {
+    fn from(error: std::io::Error) -> Self {
+        ChainSpecError::Io(error)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for ChainSpecError {
+    fn from(error: serde_json::Error) -> Self {
+        ChainSpecError::Parse(error)
+    }
+}
+
+#[cfg(feature = "json")]
+impl<State: serde::de::DeserializeOwned> ChainSpec<State> {
+    /// Read a chain spec from the JSON file at the given path.
+    pub fn from_json(path: impl AsRef<std::path::Path>) -> Result<Self, ChainSpecError> {
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json_str(&json)
+    }
+
+    /// Read a chain spec from the given JSON.
+    pub fn from_json_str(json: &str) -> Result<Self, ChainSpecError> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+impl<C, SM, FC, P> FullClient<C, SM, FC, P>
+where
+    C: FromChainSpec,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+{
+    /// Create a client for the chain that the given spec describes, with the consensus engine
+    /// that the spec describes. Fails if the engine can not follow every planned fork.
+    pub fn from_chain_spec(
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        spec: &ChainSpec<SM::State>,
+    ) -> Result<Self, ChainSpecError> {
+        Self::from_chain_spec_with_store(
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            MemoryStore::default(),
+            spec,
+        )
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: FromChainSpec,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    S: BlockStore<C, SM>,
+{
+    /// Create a client for the chain that the given spec describes, which keeps its blocks in
+    /// the given store.
+    pub fn from_chain_spec_with_store(
+        state_machine: SM,
+        fork_choice: FC,
+        transaction_pool: P,
+        store: S,
+        spec: &ChainSpec<SM::State>,
+    ) -> Result<Self, ChainSpecError> {
+        Ok(FullClient::with_genesis(
+            spec.engine()?,
+            state_machine,
+            fork_choice,
+            transaction_pool,
+            store,
+            spec.genesis_block(),
+            spec.genesis.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+use super::ImportBlock;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, User::*};
+#[cfg(test)]
+use crate::c2_blockchain::BlockId;
+
+#[cfg(test)]
+fn spec() -> ChainSpec<Balances> {
+    ChainSpec {
+        chain_id: "testnet".into(),
+        genesis: Balances::from([(Alice, 100), (Bob, 50)]),
+        consensus: ConsensusParams {
+            threshold: u64::MAX / 4,
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+        forks: BTreeMap::from([("bigger_blocks".into(), 10)]),
+    }
+}
+
+#[cfg(test)]
+type PowClient = FullClient<Pow, Currency, (), ()>;
+
+#[test]
+fn cl_20_clients_boot_from_chain_spec() {
+    let spec = ChainSpec {
+        forks: BTreeMap::new(),
+        ..spec()
+    };
+    let mut client = PowClient::from_chain_spec(Currency, (), (), &spec).unwrap();
+    assert_eq!(client.best_state(), &spec.genesis);
+    assert_eq!(
+        client.consensus_engine.threshold(),
+        spec.consensus.threshold
+    );
+    let b1 = client.author_block(vec![]).unwrap();
+    assert_eq!(client.resolve(BlockId::Number(1)), Some(b1));
+
+    // A second node booted from the same spec agrees on genesis, and so follows the chain.
+    let mut other = PowClient::from_chain_spec(Currency, (), (), &spec).unwrap();
+    assert_eq!(other.genesis_hash(), client.genesis_hash());
+    assert!(other.import_block(client.get_block(b1).unwrap()));
+
+    // The authorities in the spec seal blocks under proof of authority.
+    let mut poa =
+        FullClient::<PoaRoundRobinByHeight, _, _, _>::from_chain_spec(Currency, (), (), &spec)
+            .unwrap();
+    assert!(poa.author_block(vec![]).is_some());
+}
+
+#[test]
+fn cl_20_chain_ids_and_forks_set_chains_apart() {
+    let unforked = ChainSpec {
+        forks: BTreeMap::new(),
+        ..spec()
+    };
+    let renamed = ChainSpec {
+        chain_id: "othernet".into(),
+        ..unforked.clone()
+    };
+    let client = PowClient::from_chain_spec(Currency, (), (), &unforked).unwrap();
+    let other = PowClient::from_chain_spec(Currency, (), (), &renamed).unwrap();
+    assert_ne!(other.genesis_hash(), client.genesis_hash());
+
+    // None of our engines knows how to make blocks bigger.
+    let spec = spec();
+    assert!(matches!(
+        PowClient::from_chain_spec(Currency, (), (), &spec),
+        Err(ChainSpecError::UnknownFork(fork)) if fork == "bigger_blocks"
+    ));
+    assert_eq!(spec.fork_height("bigger_blocks"), Some(10));
+    assert!(!spec.is_active("bigger_blocks", 9));
+    assert!(spec.is_active("bigger_blocks", 10));
+    assert!(!spec.is_active("smaller_blocks", 10));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn cl_20_chain_spec_loads_from_json() {
+    let json = r#"{
+        "chain_id": "testnet",
+        "genesis": { "Alice": 100, "Bob": 50 },
+        "consensus": {
+            "threshold": 4611686018427387903,
+            "authorities": ["Alice", "Bob"]
+        },
+        "forks": { "bigger_blocks": 10 }
+    }"#;
+    let path = std::env::temp_dir().join(format!("chain-spec-{}.json", std::process::id()));
+    std::fs::write(&path, json).unwrap();
+    let loaded = ChainSpec::from_json(&path);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(loaded.unwrap(), spec());
+
+    assert!(matches!(
+        ChainSpec::<Balances>::from_json(&path),
+        Err(ChainSpecError::Io(_))
+    ));
+    assert!(matches!(
+        ChainSpec::<Balances>::from_json_str(r#"{ "chain_id": "testnet" }"#),
+        Err(ChainSpecError::Parse(_))
+    ));
+}
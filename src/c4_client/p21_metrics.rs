@@ -0,0 +1,193 @@
+//! A node that runs unattended needs some way to tell its operator how it is doing. Is it
+//! importing blocks? How long do imports take? Is the chain reorganizing a lot? Is the pool
+//! filling up? The client counts these things as it goes, and hands out a snapshot of the
+//! counts on request.
+//!
+//! Monitoring systems such as Prometheus collect numbers like these from many nodes at once.
+//! Prometheus reads them in a simple text format, which the RPC server offers at `/metrics`.
+
+use std::time::Duration;
+
+use super::{BlockStore, FullClient, TransactionPool};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+
+/// How a client has been doing since it started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Blocks imported, including those the client authored itself.
+    pub imported_blocks: u64,
+    /// Blocks the client authored, or mined, itself.
+    pub authored_blocks: u64,
+    /// Times the best chain switched to a different branch.
+    pub reorgs: u64,
+    /// Transactions waiting in the pool.
+    pub pool_size: usize,
+    /// The height of the best block.
+    pub best_height: u64,
+    /// The total time spent importing blocks.
+    pub import_time: Duration,
+    /// Penalties given to peers that misbehaved.
+    pub peer_penalties: u64,
+    /// Peers that misbehaved so badly that they were disconnected and banned.
+    pub banned_peers: u64,
+    /// Blocks that were accepted without executing them again, because they had been
+    /// verified before. See `VerificationCache`.
+    pub verification_cache_hits: u64,
+    /// Blocks that had not been verified before, or had been forgotten.
+    pub verification_cache_misses: u64,
+}
+
+impl Metrics {
+    /// The average time it took to import a block, or None if no block has been imported.
+    pub fn average_import_time(&self) -> Option<Duration> {
+        if self.imported_blocks == 0 {
+            return None;
+        }
+        Some(self.import_time.div_f64(self.imported_blocks as f64))
+    }
+
+    /// The fraction of blocks that were found in the verification cache, or None if no block
+    /// has been looked up.
+    pub fn verification_cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.verification_cache_hits + self.verification_cache_misses;
+        if lookups == 0 {
+            return None;
+        }
+        Some(self.verification_cache_hits as f64 / lookups as f64)
+    }
+
+    /// These metrics in the Prometheus text format.
+    pub fn to_prometheus(&self) -> String {
+        format!(
+            "# HELP node_imported_blocks_total Blocks imported.\n\
+             # TYPE node_imported_blocks_total counter\n\
+             node_imported_blocks_total {}\n\
+             # HELP node_authored_blocks_total Blocks authored.\n\
+             # TYPE node_authored_blocks_total counter\n\
+             node_authored_blocks_total {}\n\
+             # HELP node_reorgs_total Reorgs of the best chain.\n\
+             # TYPE node_reorgs_total counter\n\
+             node_reorgs_total {}\n\
+             # HELP node_pool_size Transactions in the pool.\n\
+             # TYPE node_pool_size gauge\n\
+             node_pool_size {}\n\
+             # HELP node_best_height Height of the best block.\n\
+             # TYPE node_best_height gauge\n\
+             node_best_height {}\n\
+             # HELP node_import_seconds_total Time spent importing blocks.\n\
+             # TYPE node_import_seconds_total counter\n\
+             node_import_seconds_total {}\n\
+             # HELP node_peer_penalties_total Penalties given to misbehaving peers.\n\
+             # TYPE node_peer_penalties_total counter\n\
+             node_peer_penalties_total {}\n\
+             # HELP node_banned_peers_total Peers banned for misbehaving.\n\
+             # TYPE node_banned_peers_total counter\n\
+             node_banned_peers_total {}\n\
+             # HELP node_verification_cache_hits_total Blocks found in the verification cache.\n\
+             # TYPE node_verification_cache_hits_total counter\n\
+             node_verification_cache_hits_total {}\n\
+             # HELP node_verification_cache_misses_total Blocks not in the verification cache.\n\
+             # TYPE node_verification_cache_misses_total counter\n\
+             node_verification_cache_misses_total {}\n",
+            self.imported_blocks,
+            self.authored_blocks,
+            self.reorgs,
+            self.pool_size,
+            self.best_height,
+            self.import_time.as_secs_f64(),
+            self.peer_penalties,
+            self.banned_peers,
+            self.verification_cache_hits,
+            self.verification_cache_misses,
+        )
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// A snapshot of how this client has been doing since it started.
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            pool_size: self.transaction_pool.size(),
+            best_height: self.height_of(self.store.best()),
+            ..self.metrics
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> FullClient<C, SM, FC, P, S> {
+    /// The counters, for the parts of a node outside the client that keep some of them.
+    pub(crate) fn metrics_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, LongestChain, SimplePool, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestClient = FullClient<(), LightSwitch, LongestChain, SimplePool<LightSwitch>>;
+
+#[cfg(test)]
+fn client() -> TestClient {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::new((), LightSwitch, fork_choice, SimplePool::default(), false)
+}
+
+#[test]
+fn cl_21_metrics_count_imports_authoring_and_reorgs() {
+    let mut client = client();
+    assert_eq!(client.metrics(), Metrics::default());
+    assert_eq!(client.metrics().average_import_time(), None);
+
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let a1 = genesis.child(&(), &false, vec![]).unwrap();
+    let b1 = genesis.child(&(), &false, vec![(), ()]).unwrap();
+    let b2 = b1.child(&(), &false, vec![]).unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1));
+    assert!(!client.import_block(a1));
+    assert!(client.import_block(b2));
+    client.author_block(vec![]).unwrap();
+    client.transaction_pool.try_insert(());
+
+    let metrics = client.metrics();
+    assert_eq!(metrics.imported_blocks, 4);
+    assert_eq!(metrics.authored_blocks, 1);
+    assert_eq!(metrics.reorgs, 1);
+    assert_eq!(metrics.pool_size, 1);
+    assert_eq!(metrics.best_height, 3);
+    assert!(metrics.average_import_time().is_some());
+}
+
+#[test]
+fn cl_21_metrics_render_as_prometheus_text() {
+    let metrics = Metrics {
+        imported_blocks: 4,
+        reorgs: 1,
+        import_time: Duration::from_millis(500),
+        ..Metrics::default()
+    };
+    let text = metrics.to_prometheus();
+    assert!(text.contains(
+        "# HELP node_imported_blocks_total Blocks imported.\n\
+         # TYPE node_imported_blocks_total counter\n\
+         node_imported_blocks_total 4\n"
+    ));
+    assert!(text.contains("node_reorgs_total 1\n"));
+    assert!(text.contains("# TYPE node_pool_size gauge\nnode_pool_size 0\n"));
+    assert!(text.contains("node_import_seconds_total 0.5\n"));
+    assert!(text.contains("node_banned_peers_total 0\n"));
+    assert!(text.contains("node_verification_cache_hits_total 0\n"));
+    assert_eq!(text.lines().count(), 30);
+}
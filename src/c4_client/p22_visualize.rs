@@ -0,0 +1,141 @@
+//! Fork choice is much easier to follow in a picture than in a list of hashes. The client can
+//! draw its fork tree in the DOT language, which Graphviz turns into an image:
+//!
+//! ```text
+//! dot -Tsvg forks.dot -o forks.svg
+//! ```
+//!
+//! Each block is a box showing its height, hash, and state root. The best chain is drawn in
+//! red, so it is easy to see which branch the fork choice rule picked, and to watch it jump
+//! when a reorg happens. Finalized blocks are shaded grey. Blocks on other branches below the
+//! finalized block can never become part of the best chain again.
+
+use std::fmt::Write;
+
+use super::{BlockStore, ForkChoice, FullClient};
+use crate::c1_state_machine::StateMachine;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hex::Hex;
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// The client's fork tree in the DOT language, with the best chain and finalized blocks
+    /// marked. Blocks are listed by height, so the output only changes when the tree does.
+    pub fn to_dot(&self) -> String {
+        let tree = &self.fork_tree;
+        let root = tree.root();
+        let mut best_chain = tree.path_back_to(self.best_block(), root);
+        best_chain.push(root);
+        let mut finalized = tree.path_back_to(tree.finalized(), root);
+        finalized.push(root);
+
+        let mut blocks: Vec<_> = tree.iter().collect();
+        blocks.sort_by_key(|&(hash, node)| (node.height, hash));
+
+        let mut dot = String::from("digraph forks {\n    rankdir=LR;\n    node [shape=box];\n");
+        for &(hash, node) in &blocks {
+            let mut label = format!("#{} {}", node.height, Hex(hash));
+            if let Some(header) = self.store.header(BlockId::Hash(hash)) {
+                write!(label, "\\nstate {}", Hex(header.state_root)).unwrap();
+            }
+            let mut attributes = format!("label=\"{label}\"");
+            if best_chain.contains(&hash) {
+                attributes.push_str(", color=red, penwidth=2");
+            }
+            if finalized.contains(&hash) {
+                attributes.push_str(", style=filled, fillcolor=lightgrey");
+            }
+            writeln!(dot, "    \"{}\" [{attributes}];", Hex(hash)).unwrap();
+        }
+        for &(hash, node) in &blocks {
+            if hash == root {
+                continue;
+            }
+            write!(dot, "    \"{}\" -> \"{}\"", Hex(node.parent), Hex(hash)).unwrap();
+            if best_chain.contains(&hash) {
+                dot.push_str(" [color=red, penwidth=2]");
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, LongestChain, SimplePool, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+fn client() -> FullClient<(), LightSwitch, LongestChain, SimplePool<LightSwitch>> {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::new((), LightSwitch, fork_choice, SimplePool::default(), false)
+}
+
+/// The line that declares the given block.
+#[cfg(test)]
+fn node_line(dot: &str, hash: u64) -> &str {
+    let id = format!("    \"{}\" [", Hex(hash));
+    dot.lines().find(|line| line.starts_with(&id)).unwrap()
+}
+
+#[test]
+fn cl_22_dot_marks_best_chain_and_finality() {
+    let mut client = client();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let a1 = genesis.child(&(), &false, vec![]).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![]).unwrap();
+    for block in [&a1, &b1, &b2] {
+        assert!(client.import_block(block.clone()));
+    }
+    assert!(client.manually_finalize_block(b1.hash()));
+
+    let dot = client.to_dot();
+    assert!(dot.starts_with("digraph forks {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(dot.lines().filter(|line| line.contains(" -> ")).count(), 3);
+
+    let state = Hex(b2.header.state_root);
+    let b2_line = node_line(&dot, b2.hash());
+    assert!(b2_line.contains(&format!("label=\"#2 {}\\nstate {state}\"", Hex(b2.hash()))));
+    assert!(b2_line.contains("color=red"));
+    assert!(!b2_line.contains("fillcolor"));
+    assert!(node_line(&dot, b1.hash()).contains("color=red, penwidth=2, style=filled"));
+    assert!(!node_line(&dot, a1.hash()).contains("color"));
+
+    let best_edge = format!(
+        "\"{}\" -> \"{}\" [color=red",
+        Hex(b1.hash()),
+        Hex(b2.hash())
+    );
+    assert!(dot.contains(&best_edge));
+    let side_edge = format!("\"{}\" -> \"{}\";", Hex(genesis.hash()), Hex(a1.hash()));
+    assert!(dot.contains(&side_edge));
+}
+
+#[test]
+fn cl_22_dot_output_does_not_depend_on_import_order() {
+    let mut first = client();
+    let mut second = client();
+    let genesis = first.get_block(first.genesis_hash()).unwrap();
+    let a1 = genesis.child(&(), &false, vec![]).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![]).unwrap();
+    for block in [&a1, &b1, &b2] {
+        first.import_block(block.clone());
+    }
+    for block in [&b1, &b2, &a1] {
+        second.import_block(block.clone());
+    }
+    assert_eq!(first.to_dot(), second.to_dot());
+}
@@ -0,0 +1,312 @@
+//! Ethereum encodes almost everything it hashes or sends with RLP, the Recursive Length Prefix
+//! encoding. RLP only knows two things: byte strings, and lists of items, which may be byte
+//! strings or lists themselves. Each is prefixed with its length, and that is all. Numbers are
+//! byte strings in big-endian order without leading zeros, and structures are lists of their
+//! fields, in order. Nothing says what the fields mean, so both sides must agree on the layout.
+//!
+//! Our layouts follow Ethereum's habits:
+//!
+//! - A header is the list `[parent, height, state root, extrinsics root, uncles, digest]`.
+//!   Hashes are fixed-size byte strings, like Ethereum's 32 byte hashes, but only 8 bytes long.
+//! - A block is the list `[header, body, context]`, with the body a list of transitions.
+//! - Enums are encoded as a number for the variant, and when the variant has fields, as a list
+//!   that starts with that number, much as Ethereum prefixes typed transactions.
+//!
+//! The consensus digest and the transitions are encoded however their own types choose. We
+//! give RLP encodings to the currency transactions, so that currency blocks can be encoded.
+
+use rlp::{Decodable, DecoderError, Encodable, Rlp, RlpStream};
+
+use super::Block;
+use crate::c1_state_machine::{BlockContext, CurrencyTransaction, StateMachine, User};
+use crate::c3_consensus::{Consensus, ConsensusAuthority, Header};
+
+type Hash = u64;
+
+fn append_hash(s: &mut RlpStream, hash: Hash) {
+    s.append(&hash.to_be_bytes().as_slice());
+}
+
+fn decode_hash(rlp: &Rlp) -> Result<Hash, DecoderError> {
+    let bytes = rlp.data()?.try_into();
+    let bytes = bytes.map_err(|_| DecoderError::RlpInvalidLength)?;
+    Ok(Hash::from_be_bytes(bytes))
+}
+
+/// Fail unless the given item is a list of exactly the given length.
+fn expect_list(rlp: &Rlp, len: usize) -> Result<(), DecoderError> {
+    if !rlp.is_list() {
+        return Err(DecoderError::RlpExpectedToBeList);
+    }
+    if rlp.item_count()? != len {
+        return Err(DecoderError::RlpIncorrectListLen);
+    }
+    Ok(())
+}
+
+impl<Digest: Encodable> Encodable for Header<Digest> {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(6);
+        append_hash(s, self.parent);
+        s.append(&self.height);
+        append_hash(s, self.state_root);
+        append_hash(s, self.extrinsics_root);
+        s.begin_list(self.uncles.len());
+        for &uncle in &self.uncles {
+            append_hash(s, uncle);
+        }
+        s.append(&self.consensus_digest);
+    }
+}
+
+impl<Digest: Decodable> Decodable for Header<Digest> {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        expect_list(rlp, 6)?;
+        let uncles = rlp.at(4)?;
+        Ok(Header {
+            parent: decode_hash(&rlp.at(0)?)?,
+            height: rlp.val_at(1)?,
+            state_root: decode_hash(&rlp.at(2)?)?,
+            extrinsics_root: decode_hash(&rlp.at(3)?)?,
+            uncles: uncles
+                .iter()
+                .map(|uncle| decode_hash(&uncle))
+                .collect::<Result<_, _>>()?,
+            consensus_digest: rlp.val_at(5)?,
+        })
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Encodable for Block<C, SM>
+where
+    C::Digest: Encodable,
+    SM::Transition: Encodable,
+{
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.header);
+        s.append_list(&self.body);
+        s.append(&self.context);
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Decodable for Block<C, SM>
+where
+    C::Digest: Decodable,
+    SM::Transition: Decodable,
+{
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        expect_list(rlp, 3)?;
+        Ok(Block {
+            header: rlp.val_at(0)?,
+            body: rlp.list_at(1)?,
+            context: rlp.val_at(2)?,
+        })
+    }
+}
+
+impl Encodable for BlockContext {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(3);
+        s.append(&self.height);
+        s.append(&self.timestamp);
+        s.append(&self.author);
+    }
+}
+
+impl Decodable for BlockContext {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        expect_list(rlp, 3)?;
+        Ok(BlockContext {
+            height: rlp.val_at(0)?,
+            timestamp: rlp.val_at(1)?,
+            author: rlp.val_at(2)?,
+        })
+    }
+}
+
+impl Encodable for ConsensusAuthority {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        // A single item, so it must not be counted again by whoever appends it.
+        s.append_internal(&(*self as u8));
+    }
+}
+
+impl Decodable for ConsensusAuthority {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.as_val::<u8>()? {
+            0 => Ok(ConsensusAuthority::Alice),
+            1 => Ok(ConsensusAuthority::Bob),
+            2 => Ok(ConsensusAuthority::Charlie),
+            3 => Ok(ConsensusAuthority::Unsealed),
+            _ => Err(DecoderError::Custom("unknown authority")),
+        }
+    }
+}
+
+impl Encodable for User {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.append_internal(&(*self as u8));
+    }
+}
+
+impl Decodable for User {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        match rlp.as_val::<u8>()? {
+            0 => Ok(User::Alice),
+            1 => Ok(User::Bob),
+            2 => Ok(User::Charlie),
+            _ => Err(DecoderError::Custom("unknown user")),
+        }
+    }
+}
+
+impl Encodable for CurrencyTransaction {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        match self {
+            CurrencyTransaction::Mint { to, amount } => {
+                s.begin_list(3).append(&0u8).append(to).append(amount);
+            }
+            CurrencyTransaction::Burn { from, amount } => {
+                s.begin_list(3).append(&1u8).append(from).append(amount);
+            }
+            CurrencyTransaction::Transfer { from, to, amount } => {
+                s.begin_list(4)
+                    .append(&2u8)
+                    .append(from)
+                    .append(to)
+                    .append(amount);
+            }
+        }
+    }
+}
+
+impl Decodable for CurrencyTransaction {
+    fn decode(rlp: &Rlp) -> Result<Self, DecoderError> {
+        let kind: u8 = rlp.val_at(0)?;
+        match kind {
+            0 => {
+                expect_list(rlp, 3)?;
+                Ok(CurrencyTransaction::Mint {
+                    to: rlp.val_at(1)?,
+                    amount: rlp.val_at(2)?,
+                })
+            }
+            1 => {
+                expect_list(rlp, 3)?;
+                Ok(CurrencyTransaction::Burn {
+                    from: rlp.val_at(1)?,
+                    amount: rlp.val_at(2)?,
+                })
+            }
+            2 => {
+                expect_list(rlp, 4)?;
+                Ok(CurrencyTransaction::Transfer {
+                    from: rlp.val_at(1)?,
+                    to: rlp.val_at(2)?,
+                    amount: rlp.val_at(3)?,
+                })
+            }
+            _ => Err(DecoderError::Custom("unknown transaction")),
+        }
+    }
+}
+
+#[cfg(test)]
+use super::{FullClient, ImportBlock, LongestChain, SimplePool, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency};
+#[cfg(test)]
+use crate::c3_consensus::{trivial_always_valid_pow, Pow};
+
+#[test]
+fn cl_23_headers_are_encoded_like_ethereum() {
+    let header = Header {
+        parent: 1,
+        height: 1024,
+        state_root: 2,
+        extrinsics_root: 3,
+        uncles: vec![],
+        consensus_digest: 0u64,
+    };
+    let hash = |n: u8| [0x88, 0, 0, 0, 0, 0, 0, 0, n];
+    let mut expected = vec![0xc0 + 32];
+    expected.extend(hash(1));
+    // A number is its big-endian bytes without leading zeros, prefixed with their count.
+    expected.extend([0x82, 0x04, 0x00]);
+    expected.extend(hash(2));
+    expected.extend(hash(3));
+    // An empty list, then zero, which is the empty byte string.
+    expected.extend([0xc0, 0x80]);
+    assert_eq!(rlp::encode(&header).to_vec(), expected);
+    assert_eq!(rlp::decode(&expected), Ok(header));
+}
+
+#[test]
+fn cl_23_blocks_survive_rlp() {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis = Balances::from([(User::Alice, 100)]);
+    let pool = SimplePool::default();
+    let mut client: FullClient<Pow, Currency, LongestChain, SimplePool<Currency>> = FullClient::new(
+        trivial_always_valid_pow(),
+        Currency,
+        fork_choice,
+        pool,
+        genesis,
+    );
+    let body = vec![
+        CurrencyTransaction::Mint {
+            to: User::Bob,
+            amount: 5,
+        },
+        CurrencyTransaction::Burn {
+            from: User::Alice,
+            amount: 1,
+        },
+        CurrencyTransaction::Transfer {
+            from: User::Alice,
+            to: User::Charlie,
+            amount: 30,
+        },
+    ];
+    let block_hash = client.author_block(body).unwrap();
+    let mut block = client.get_block(block_hash).unwrap();
+    assert_eq!(block.body.len(), 3);
+    block.context.author = Some(ConsensusAuthority::Bob);
+    block.header.uncles = vec![client.genesis_hash(), 7];
+
+    let encoded = rlp::encode(&block);
+    let decoded: Block<Pow, Currency> = rlp::decode(&encoded).unwrap();
+    assert_eq!(decoded, block);
+}
+
+#[test]
+fn cl_23_malformed_rlp_is_refused() {
+    let header = Header {
+        parent: 1,
+        height: 2,
+        state_root: 3,
+        extrinsics_root: 4,
+        uncles: vec![5],
+        consensus_digest: 6u64,
+    };
+    let mut encoded = rlp::encode(&header).to_vec();
+    // Shorten the uncle's hash by a byte.
+    let uncle = encoded.len() - 11;
+    encoded[uncle] -= 1;
+    encoded[uncle + 1] = 0x87;
+    encoded.remove(uncle + 2);
+    encoded[0] -= 1;
+    let decoded = rlp::decode::<Header<u64>>(&encoded);
+    assert_eq!(decoded, Err(DecoderError::RlpInvalidLength));
+
+    let unknown_user = rlp::encode_list::<u8, u8>(&[2, 9, 1, 1]);
+    let decoded = rlp::decode::<CurrencyTransaction>(&unknown_user);
+    assert_eq!(decoded, Err(DecoderError::Custom("unknown user")));
+
+    let short_transfer = rlp::encode_list::<u8, u8>(&[2, 0, 1]);
+    let decoded = rlp::decode::<CurrencyTransaction>(&short_transfer);
+    assert_eq!(decoded, Err(DecoderError::RlpIncorrectListLen));
+}
@@ -0,0 +1,277 @@
+//! Nodes share chains in files as well as over the network, to back them up, or to give a new
+//! node a head start. A file format has to outlive the program that wrote it, so it needs a
+//! little more care than a message on the wire:
+//!
+//! - It starts with four magic bytes, `DIYC`, so that other files are not mistaken for chains.
+//! - Next comes a format version. A reader refuses versions it does not know, rather than
+//!   misreading them.
+//! - Then the number of records, so a reader can tell a complete file from a truncated one.
+//! - Each record is a block, encoded with bincode, prefixed with its length, and followed by
+//!   a CRC-32 checksum of its bytes. Disks and downloads do corrupt data now and then, and
+//!   the checksum catches that before the block is decoded.
+//!
+//! All numbers are big-endian. The checksum is computed by hand, rather than with Rust's
+//! `DefaultHasher`, because files must read the same with every version of Rust.
+//!
+//! This part needs the `chain-file` feature. It has nothing to do with how a node stores its
+//! blocks, so it does not need `sled`.
+
+use std::io::{self, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::Block;
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+
+/// The bytes every chain file starts with.
+pub const CHAIN_FILE_MAGIC: [u8; 4] = *b"DIYC";
+
+/// The version of the format that this module reads and writes.
+pub const CHAIN_FILE_VERSION: u16 = 1;
+
+/// The longest record, in bytes, that a reader is willing to read.
+pub const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// The reasons that a chain file can not be written or read.
+#[derive(Debug)]
+pub enum ChainFileError {
+    /// The file could not be written or read.
+    Io(io::Error),
+    /// The file does not start with the magic bytes.
+    NotAChainFile,
+    /// The file was written in a version of the format that this reader does not know.
+    UnsupportedVersion(u16),
+    /// The file ends before the given record is complete.
+    Truncated { record: u64 },
+    /// The given record claims to be longer than `MAX_RECORD_LEN`.
+    RecordTooLong { record: u64, len: usize },
+    /// The given record's bytes do not match its checksum.
+    ChecksumMismatch { record: u64 },
+    /// The given record does not hold a block.
+    Malformed { record: u64, error: bincode::Error },
+    /// There are more bytes after the last record.
+    TrailingBytes,
+}
+
+impl From<io::Error> for ChainFileError {
+    fn from(error: io::Error) -> Self {
+        ChainFileError::Io(error)
+    }
+}
+
+/// The CRC-32 checksum of the given bytes, as used by zip, PNG, and Ethernet.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Write the given blocks as a chain file.
+pub fn write_chain_file<C, SM>(
+    writer: &mut impl Write,
+    blocks: &[Block<C, SM>],
+) -> Result<(), ChainFileError>
+where
+    C: Consensus,
+    C::Digest: Serialize,
+    SM: StateMachine,
+    SM::Transition: Serialize,
+{
+    writer.write_all(&CHAIN_FILE_MAGIC)?;
+    writer.write_all(&CHAIN_FILE_VERSION.to_be_bytes())?;
+    writer.write_all(&(blocks.len() as u64).to_be_bytes())?;
+    for (record, block) in (0..).zip(blocks) {
+        let payload = bincode::serialize(block)
+            .map_err(|error| ChainFileError::Malformed { record, error })?;
+        if payload.len() > MAX_RECORD_LEN {
+            let len = payload.len();
+            return Err(ChainFileError::RecordTooLong { record, len });
+        }
+        writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+        writer.write_all(&payload)?;
+        writer.write_all(&crc32(&payload).to_be_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read the given number of bytes. Running out of them part way through the given record means
+/// the file was truncated.
+fn read_bytes<const N: usize>(
+    reader: &mut impl Read,
+    record: u64,
+) -> Result<[u8; N], ChainFileError> {
+    let mut bytes = [0; N];
+    read_into(reader, &mut bytes, record)?;
+    Ok(bytes)
+}
+
+fn read_into(reader: &mut impl Read, bytes: &mut [u8], record: u64) -> Result<(), ChainFileError> {
+    reader
+        .read_exact(bytes)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => ChainFileError::Truncated { record },
+            _ => ChainFileError::Io(error),
+        })
+}
+
+/// Read the blocks in a chain file, checking every record on the way.
+pub fn read_chain_file<C, SM>(reader: &mut impl Read) -> Result<Vec<Block<C, SM>>, ChainFileError>
+where
+    C: Consensus,
+    C::Digest: DeserializeOwned,
+    SM: StateMachine,
+    SM::Transition: DeserializeOwned,
+{
+    let mut magic = [0; 4];
+    let mut read = 0;
+    while read < magic.len() {
+        match reader.read(&mut magic[read..])? {
+            0 => return Err(ChainFileError::NotAChainFile),
+            n => read += n,
+        }
+    }
+    if magic != CHAIN_FILE_MAGIC {
+        return Err(ChainFileError::NotAChainFile);
+    }
+    let version = u16::from_be_bytes(read_bytes(reader, 0)?);
+    if version != CHAIN_FILE_VERSION {
+        return Err(ChainFileError::UnsupportedVersion(version));
+    }
+    let count = u64::from_be_bytes(read_bytes(reader, 0)?);
+
+    let mut blocks = Vec::new();
+    for record in 0..count {
+        let len = u32::from_be_bytes(read_bytes(reader, record)?) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(ChainFileError::RecordTooLong { record, len });
+        }
+        let mut payload = vec![0; len];
+        read_into(reader, &mut payload, record)?;
+        let checksum = u32::from_be_bytes(read_bytes(reader, record)?);
+        if checksum != crc32(&payload) {
+            return Err(ChainFileError::ChecksumMismatch { record });
+        }
+        let block = bincode::deserialize(&payload)
+            .map_err(|error| ChainFileError::Malformed { record, error })?;
+        blocks.push(block);
+    }
+    if reader.read(&mut [0])? != 0 {
+        return Err(ChainFileError::TrailingBytes);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+use super::{FullClient, ImportBlock, LongestChain, SimplePool, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+
+#[cfg(test)]
+type TestBlock = Block<(), Currency>;
+
+/// The blocks of a short currency chain.
+#[cfg(test)]
+fn blocks() -> Vec<TestBlock> {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis = Balances::from([(User::Alice, 100)]);
+    let mut client = FullClient::new(
+        (),
+        Currency,
+        fork_choice,
+        SimplePool::<Currency>::default(),
+        genesis,
+    );
+    (1..=3)
+        .map(|amount| {
+            let transfer = CurrencyTransaction::Transfer {
+                from: User::Alice,
+                to: User::Bob,
+                amount,
+            };
+            let hash = client.author_block(vec![transfer]).unwrap();
+            client.get_block(hash).unwrap()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn file(blocks: &[TestBlock]) -> Vec<u8> {
+    let mut file = Vec::new();
+    write_chain_file(&mut file, blocks).unwrap();
+    file
+}
+
+#[test]
+fn cl_24_chains_survive_a_file() {
+    let blocks = blocks();
+    let file = file(&blocks);
+    assert_eq!(file[..4], *b"DIYC");
+    assert_eq!(file[4..6], [0, 1]);
+    assert_eq!(file[6..14], 3u64.to_be_bytes());
+    assert_eq!(
+        read_chain_file::<(), Currency>(&mut &file[..]).unwrap(),
+        blocks
+    );
+    assert!(read_chain_file::<(), Currency>(&mut &self::file(&[])[..])
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn cl_24_checksums_catch_corruption() {
+    // The check value that every CRC-32 implementation is tested against.
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+    let mut file = file(&blocks());
+    let last = file.len() - 5;
+    file[last] ^= 1;
+    let error = read_chain_file::<(), Currency>(&mut &file[..]).unwrap_err();
+    assert!(matches!(
+        error,
+        ChainFileError::ChecksumMismatch { record: 2 }
+    ));
+}
+
+#[test]
+fn cl_24_bad_files_are_refused_gracefully() {
+    let read = |bytes: &[u8]| read_chain_file::<(), Currency>(&mut &bytes[..]).unwrap_err();
+    let file = file(&blocks());
+
+    assert!(matches!(read(b""), ChainFileError::NotAChainFile));
+    assert!(matches!(
+        read(b"{\"blocks\": []}"),
+        ChainFileError::NotAChainFile
+    ));
+    assert!(matches!(
+        read(b"DIYC\x00\x02"),
+        ChainFileError::UnsupportedVersion(2)
+    ));
+    assert!(matches!(
+        read(&file[..5]),
+        ChainFileError::Truncated { record: 0 }
+    ));
+    let cut = read(&file[..file.len() - 1]);
+    assert!(matches!(cut, ChainFileError::Truncated { record: 2 }));
+
+    let mut huge = file[..14].to_vec();
+    huge.extend([0xff; 4]);
+    assert!(matches!(
+        read(&huge),
+        ChainFileError::RecordTooLong { record: 0, .. }
+    ));
+
+    let mut longer = file.clone();
+    longer.push(0);
+    assert!(matches!(read(&longer), ChainFileError::TrailingBytes));
+}
@@ -0,0 +1,280 @@
+//! Executing a block is the most expensive part of verifying it, and a node on a busy network
+//! is often asked to verify the same block more than once. Gossip delivers each block from
+//! several peers, and a node that checks a block before relaying it checks it again when it
+//! imports it.
+//!
+//! So the client remembers the post-states of blocks that recently passed full verification.
+//! A block that it remembers is not executed again, and importing it reuses the remembered
+//! post-state. Blocks are remembered by their hash together with their context. The hash only
+//! commits to the body and context once the header's extrinsics root has been checked against
+//! them, so the cheap header checks still run every time, and the context is part of the key
+//! so that a block can never pick up a post-state that was computed in a different context.
+//! The memory is bounded: when it is full, the least recently used block is forgotten.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::p1_data_structure::execute;
+use super::{Block, BlockStore, Consensus, ForkChoice, FullClient, ImportError, StateMachine};
+use crate::c1_state_machine::BlockContext;
+use crate::hash;
+
+type Hash = u64;
+
+/// How the cache tells blocks apart.
+type Key = (Hash, BlockContext);
+
+/// The default number of blocks a client remembers as verified.
+pub const DEFAULT_VERIFICATION_CACHE_SIZE: usize = 1024;
+
+/// The post-states of blocks that passed full verification, least recently used first out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerificationCache<State> {
+    capacity: usize,
+    /// The post-state of each block, along with when it was last used.
+    entries: HashMap<Key, (u64, State)>,
+    /// The blocks, ordered by when they were last used.
+    by_use: BTreeMap<u64, Key>,
+    /// Counts uses, to order them.
+    clock: u64,
+}
+
+impl<State> Default for VerificationCache<State> {
+    fn default() -> Self {
+        Self::new(DEFAULT_VERIFICATION_CACHE_SIZE)
+    }
+}
+
+impl<State> VerificationCache<State> {
+    /// An empty cache that remembers at most the given number of blocks. A cache with no
+    /// capacity remembers nothing.
+    pub fn new(capacity: usize) -> Self {
+        VerificationCache {
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The most blocks that the cache remembers at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of blocks remembered.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no blocks are remembered at all.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// The post-state of the given block executed in the given context, if it passed
+    /// verification. This counts as a use of the block.
+    pub fn get(&mut self, block_hash: Hash, context: &BlockContext) -> Option<&State> {
+        let now = self.tick();
+        let (used, state) = self.entries.get_mut(&(block_hash, *context))?;
+        self.by_use.remove(used);
+        self.by_use.insert(now, (block_hash, *context));
+        *used = now;
+        Some(state)
+    }
+
+    /// Forget the given block, returning its post-state if it was remembered.
+    pub fn take(&mut self, block_hash: Hash, context: &BlockContext) -> Option<State> {
+        let (used, state) = self.entries.remove(&(block_hash, *context))?;
+        self.by_use.remove(&used);
+        Some(state)
+    }
+
+    /// Remember that the given block passed verification in the given context and led to the
+    /// given post-state, forgetting the least recently used block if the cache is full.
+    pub fn insert(&mut self, block_hash: Hash, context: BlockContext, post_state: State) {
+        if self.get(block_hash, &context).is_some() || self.capacity == 0 {
+            return;
+        }
+        if self.len() == self.capacity {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        let now = self.tick();
+        self.entries
+            .insert((block_hash, context), (now, post_state));
+        self.by_use.insert(now, (block_hash, context));
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// Remember at most the given number of verified blocks.
+    pub fn with_verification_cache(mut self, capacity: usize) -> Self {
+        self.verified = VerificationCache::new(capacity);
+        self
+    }
+
+    /// Count a lookup in the verification cache in the metrics.
+    fn count_lookup(&mut self, hit: bool) {
+        if hit {
+            self.metrics.verification_cache_hits += 1;
+        } else {
+            self.metrics.verification_cache_misses += 1;
+        }
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// Run every check on a block, executing it too, without importing it. This is what a node
+    /// does before relaying a block to its peers. Blocks that pass are remembered, so importing
+    /// them afterwards, or checking them again when another peer announces them, is cheap.
+    pub fn verify_block(&mut self, block: &Block<C, SM>) -> Result<(), ImportError> {
+        self.verify_header(block)?;
+        let block_hash = block.hash();
+        let hit = self.verified.get(block_hash, &block.context).is_some();
+        self.count_lookup(hit);
+        if !hit {
+            let post_state = self.execute_checked(block)?;
+            self.verified.insert(block_hash, block.context, post_state);
+        }
+        Ok(())
+    }
+
+    /// The post-state of a block whose header has already been checked, for importing it.
+    /// Blocks that were verified before are not executed again. The block is forgotten by the
+    /// cache, because once it is imported its post-state is kept with the other states.
+    pub(crate) fn verified_post_state(
+        &mut self,
+        block: &Block<C, SM>,
+    ) -> Result<SM::State, ImportError> {
+        let cached = self.verified.take(block.hash(), &block.context);
+        self.count_lookup(cached.is_some());
+        match cached {
+            Some(post_state) => Ok(post_state),
+            None => self.execute_checked(block),
+        }
+    }
+
+    /// Execute a block whose header has already been checked, and check its state root.
+    fn execute_checked(&self, block: &Block<C, SM>) -> Result<SM::State, ImportError> {
+        let Some(pre_state) = self.states.get(&block.header.parent) else {
+            return Err(ImportError::StatePruned);
+        };
+        let post_state = execute::<SM>(pre_state, &block.body, &block.context);
+        if hash(&post_state) != block.header.state_root {
+            return Err(ImportError::InvalidBlock);
+        }
+        Ok(post_state)
+    }
+}
+
+#[cfg(test)]
+use super::{ImportBlock, LongestChain, SimplePool, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+
+#[cfg(test)]
+type TestClient = FullClient<(), LightSwitch, LongestChain, SimplePool<LightSwitch>>;
+
+#[cfg(test)]
+fn client() -> TestClient {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    FullClient::new((), LightSwitch, fork_choice, SimplePool::default(), false)
+}
+
+#[test]
+fn cl_25_cache_forgets_least_recently_used() {
+    let context = BlockContext::default();
+    let mut cache = VerificationCache::new(2);
+    cache.insert(1, context, 'a');
+    cache.insert(2, context, 'b');
+    assert_eq!(cache.get(1, &context), Some(&'a'));
+    cache.insert(3, context, 'c');
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(1, &context), Some(&'a'));
+    assert_eq!(cache.get(2, &context), None);
+    assert_eq!(cache.take(3, &context), Some('c'));
+    assert_eq!(cache.get(3, &context), None);
+
+    let mut empty = VerificationCache::new(0);
+    empty.insert(1, context, 'a');
+    assert!(empty.is_empty());
+    assert_eq!(empty.get(1, &context), None);
+}
+
+#[test]
+fn cl_25_verified_blocks_are_not_executed_again() {
+    let mut client = client();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+
+    assert_eq!(client.verify_block(&b1), Ok(()));
+    assert_eq!(client.verify_block(&b1), Ok(()));
+    assert_eq!(client.try_import_block(b1.clone()), Ok(b1.hash()));
+    let metrics = client.metrics();
+    assert_eq!(metrics.verification_cache_hits, 2);
+    assert_eq!(metrics.verification_cache_misses, 1);
+    assert_eq!(metrics.verification_cache_hit_rate(), Some(2.0 / 3.0));
+}
+
+#[test]
+fn cl_25_invalid_blocks_are_not_remembered() {
+    let mut client = client();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let mut bad = genesis.child(&(), &false, vec![()]).unwrap();
+    bad.header.state_root = 0;
+
+    assert_eq!(client.verify_block(&bad), Err(ImportError::InvalidBlock));
+    assert_eq!(client.verify_block(&bad), Err(ImportError::InvalidBlock));
+    assert_eq!(client.metrics().verification_cache_hits, 0);
+    assert_eq!(client.metrics().verification_cache_hit_rate(), Some(0.0));
+
+    let mut forgetful = self::client().with_verification_cache(0);
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    assert_eq!(forgetful.verify_block(&b1), Ok(()));
+    assert!(forgetful.import_block(b1));
+    assert_eq!(forgetful.metrics().verification_cache_hits, 0);
+}
+
+#[test]
+fn cl_25_remembered_blocks_are_still_checked_against_their_header() {
+    let mut client = client();
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let b1 = genesis.child(&(), &false, vec![()]).unwrap();
+    assert_eq!(client.verify_block(&b1), Ok(()));
+
+    // These have the same hash as the block that was verified, but not the same contents.
+    let mut later = b1.clone();
+    later.context.timestamp += 1;
+    assert_eq!(later.hash(), b1.hash());
+    assert_eq!(client.verify_block(&later), Err(ImportError::InvalidBlock));
+    let mut longer = b1.clone();
+    longer.body.push(());
+    assert_eq!(client.verify_block(&longer), Err(ImportError::InvalidBlock));
+
+    // Importing the block reuses its post-state, which the cache then no longer needs.
+    assert_eq!(client.try_import_block(b1.clone()), Ok(b1.hash()));
+    assert_eq!(client.get_state(b1.hash()), Some(true));
+    assert!(client.verified.is_empty());
+}
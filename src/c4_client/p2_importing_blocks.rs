@@ -1,7 +1,48 @@
 //! We being implementing our client with the most fundamental task, which is importing
 //! blocks and headers. Full clients import entire blocks while light clients only import headers.
 
-use super::{Block, Consensus, FullClient, StateMachine};
+use std::collections::HashSet;
+use std::time::Instant;
+
+use super::{
+    Block, BlockStore, Consensus, EquivocationProof, ForkChoice, FullClient, StateMachine,
+};
+use crate::c2_blockchain::BlockId;
+use crate::hash;
+
+/// How many generations back an uncle may be referenced. An uncle's height must be
+/// at least one and at most this many blocks below the block that includes it.
+pub const UNCLE_WINDOW: u64 = 6;
+
+/// The reasons that a block can not be imported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportError {
+    /// The block has already been imported.
+    AlreadyKnown,
+    /// The block's parent has not been imported.
+    UnknownParent,
+    /// The block is on a different chain than one of the client's checkpoints.
+    ContradictsCheckpoint,
+    /// The block is on a different chain than the finalized block.
+    ConflictsWithFinality,
+    /// The block's seal, execution, or link to its parent is invalid.
+    InvalidBlock,
+    /// The block references an uncle that it is not allowed to reference.
+    InvalidUncles,
+    /// The block's parent is known, but its state has been pruned, so the block can not be
+    /// executed.
+    StatePruned,
+}
+
+impl ImportError {
+    /// Whether the block itself is bad, so that whoever sent it is to blame. A block may also be
+    /// refused because of what this client knows, for example because its parent has been
+    /// pruned or it is on a fork that this client has already finalized against. Such blocks
+    /// can be perfectly valid, and an honest peer that is behind can easily send them.
+    pub fn is_invalid(&self) -> bool {
+        matches!(self, ImportError::InvalidBlock | ImportError::InvalidUncles)
+    }
+}
 
 /// A trait that represents the ability to import complete blocks of the chain.
 ///
@@ -29,29 +70,222 @@ pub trait ImportBlock<C: Consensus, SM: StateMachine> {
     fn all_leaves(&self) -> Vec<u64>;
 }
 
-impl<C, SM, FC, P> ImportBlock<C, SM> for FullClient<C, SM, FC, P>
+impl<C, SM, FC, P, S> ImportBlock<C, SM> for FullClient<C, SM, FC, P, S>
     where
     C: Consensus,
+    C::Digest: Default,
     SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
 {
-    fn import_block(&mut self, _: Block<C, SM>) -> bool {
-        todo!("Exercise 1")
+    fn import_block(&mut self, block: Block<C, SM>) -> bool {
+        // todo!("Exercise 1")
+        self.try_import_block(block).is_ok()
     }
 
     fn get_block(&self, block_hash: u64) -> Option<Block<C, SM>> {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        self.store.block(block_hash)
     }
 
     fn get_state(&self, block_hash: u64) -> Option<<SM as StateMachine>::State> {
-        todo!("Exercise 3")
+        // todo!("Exercise 3")
+        self.states.get(&block_hash).cloned()
     }
 
     fn is_leaf(&self, block_hash: u64) -> Option<bool> {
-        todo!("Exercise 4")
+        // todo!("Exercise 4")
+        self.fork_tree.is_leaf(block_hash)
     }
 
     fn all_leaves(&self) -> Vec<u64> {
-        todo!("Exercise 5")
+        // todo!("Exercise 5")
+        self.fork_tree.leaves().to_vec()
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// Run every check on a block that does not require executing it. Blocks that fail here
+    /// are rejected before any time is spent on their extrinsics.
+    pub fn verify_header(&self, block: &Block<C, SM>) -> Result<(), ImportError> {
+        let block_hash = block.hash();
+        if self.store.contains(block_hash) {
+            return Err(ImportError::AlreadyKnown);
+        }
+        // Every imported chain passes through every checkpoint height, so rejecting
+        // contradicting blocks is enough to keep the client on the checkpointed chain.
+        if self.checkpoints.contradicts(block.header.height, block_hash) {
+            return Err(ImportError::ContradictsCheckpoint);
+        }
+
+        let parent_hash = block.header.parent;
+        let Some(parent) = self.store.block(parent_hash) else {
+            return Err(ImportError::UnknownParent);
+        };
+        // A finalized block is never reverted, so no block may branch off below it.
+        if !self.fork_tree.descends_from(parent_hash, self.fork_tree.finalized()) {
+            return Err(ImportError::ConflictsWithFinality);
+        }
+        if !parent.verify_child_header(&self.consensus_engine, block) {
+            return Err(ImportError::InvalidBlock);
+        }
+        if !self.uncles_are_valid(parent_hash, &block.header.uncles) {
+            return Err(ImportError::InvalidUncles);
+        }
+        Ok(())
+    }
+
+    /// Attempt to import a block, explaining why when it can not be imported.
+    /// Returns the hash of the imported block.
+    pub fn try_import_block(&mut self, block: Block<C, SM>) -> Result<u64, ImportError> {
+        let started = Instant::now();
+        self.verify_header(&block)?;
+
+        let block_hash = block.hash();
+        let parent_hash = block.header.parent;
+        // Blocks that were verified before, for example before being relayed, need not be
+        // executed again.
+        let post_state = self.verified_post_state(&block)?;
+
+        // The block is valid, but its author may have signed another block at the same height.
+        let equivocations: Vec<_> = self
+            .fork_tree
+            .iter()
+            .filter(|(_, node)| node.height == block.header.height)
+            .filter_map(|(other, _)| self.store.header(BlockId::Hash(other)))
+            .filter_map(|other| {
+                EquivocationProof::new(&self.consensus_engine, other, block.header.clone())
+            })
+            .collect();
+        self.equivocations.extend(equivocations);
+
+        #[cfg(feature = "strict-invariants")]
+        debug_assert!(
+            SM::invariants_hold(&post_state),
+            "block {} broke an invariant of the {} state machine",
+            crate::hex::Hex(block_hash),
+            SM::human_name()
+        );
+        let work = self.consensus_engine.work(&block.header);
+        self.fork_tree.insert(block_hash, parent_hash, work);
+        self.states.insert(block_hash, post_state);
+        self.store.insert_block(block);
+        self.update_best_block();
+        self.metrics.imported_blocks += 1;
+        self.metrics.import_time += started.elapsed();
+        Ok(block_hash)
+    }
+}
+
+// Uncles (called ommers in Ethereum) are blocks that lost a fork race but are still
+// acknowledged by a later block on the winning chain. Only the client can check
+// uncle references, because doing so requires knowledge of blocks that are _not_
+// part of the chain being verified.
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: BlockStore<C, SM>,
+{
+    /// Walk back from the given block collecting up to `UNCLE_WINDOW + 1` ancestors
+    /// (including the block itself) along with every uncle they already reference.
+    fn recent_ancestry(&self, block_hash: u64) -> (HashSet<u64>, HashSet<u64>) {
+        let mut ancestors = HashSet::new();
+        let mut referenced = HashSet::new();
+        let mut current = self.store.header(BlockId::Hash(block_hash));
+        while let Some(header) = current {
+            if ancestors.len() as u64 > UNCLE_WINDOW {
+                break;
+            }
+            ancestors.insert(hash(&header));
+            referenced.extend(header.uncles.iter().copied());
+            current = self.store.header(BlockId::Hash(header.parent));
+        }
+        (ancestors, referenced)
+    }
+
+    /// Check whether the given block could be referenced as an uncle by a new child of `parent_hash`.
+    ///
+    /// A valid uncle is a known block that is not itself an ancestor of the new block, whose parent
+    /// is a recent ancestor, and which has not already been referenced by a recent ancestor.
+    fn is_valid_uncle(
+        &self,
+        uncle_hash: u64,
+        child_height: u64,
+        ancestors: &HashSet<u64>,
+        referenced: &HashSet<u64>,
+    ) -> bool {
+        let Some(uncle) = self.store.header(BlockId::Hash(uncle_hash)) else {
+            return false;
+        };
+
+        let depth = child_height.saturating_sub(uncle.height);
+        (1..=UNCLE_WINDOW).contains(&depth)
+            && !ancestors.contains(&uncle_hash)
+            && ancestors.contains(&uncle.parent)
+            && !referenced.contains(&uncle_hash)
+    }
+
+    /// Check that all of the uncles referenced by a new child of `parent_hash` are valid,
+    /// and that no uncle is referenced twice.
+    fn uncles_are_valid(&self, parent_hash: u64, uncles: &[u64]) -> bool {
+        let Some(parent) = self.store.header(BlockId::Hash(parent_hash)) else {
+            return false;
+        };
+        let child_height = parent.height + 1;
+        let (ancestors, mut referenced) = self.recent_ancestry(parent_hash);
+
+        for uncle in uncles {
+            if !self.is_valid_uncle(*uncle, child_height, &ancestors, &referenced) {
+                return false;
+            }
+            referenced.insert(*uncle);
+        }
+        true
+    }
+
+    /// The cumulative work of the given block, counting from genesis.
+    /// Returns None if the block is not known.
+    pub fn total_work(&self, block_hash: u64) -> Option<u128> {
+        self.fork_tree.get(block_hash).map(|node| node.total_work)
+    }
+
+    /// Take the equivocations that were noticed while importing blocks. Each one should be
+    /// reported on chain, for example as a slashing transaction, so that the offender is punished.
+    pub fn take_equivocations(&mut self) -> Vec<EquivocationProof<C::Digest>> {
+        std::mem::take(&mut self.equivocations)
+    }
+
+    /// Collect every known block that a new child of `parent_hash` may reference as an uncle.
+    ///
+    /// Candidates are returned highest first, with ties broken by hash, so authors including
+    /// only some of them make a deterministic choice.
+    pub fn uncle_candidates(&self, parent_hash: u64) -> Vec<u64> {
+        let Some(parent) = self.store.header(BlockId::Hash(parent_hash)) else {
+            return Vec::new();
+        };
+        let child_height = parent.height + 1;
+        let (ancestors, referenced) = self.recent_ancestry(parent_hash);
+
+        let mut candidates: Vec<(u64, u64)> = self
+            .fork_tree
+            .iter()
+            .filter(|(h, _)| self.is_valid_uncle(*h, child_height, &ancestors, &referenced))
+            .map(|(h, node)| (node.height, h))
+            .collect();
+        candidates.sort_by(|a, b| b.cmp(a));
+        candidates.into_iter().map(|(_, h)| h).collect()
     }
 }
 
@@ -75,3 +309,186 @@ impl<C, SM, FC, P> ImportBlock<C, SM> for FullClient<C, SM, FC, P>
 // Import a forked chain and make sure both leaves' statuses are right.
 
 // Same previous 4 scenarios except with the `all_leaves` method.
+
+#[cfg(test)]
+type TestClient = FullClient<(), crate::c1_state_machine::LightSwitch, (), ()>;
+
+#[test]
+fn cl_2_import_valid_block() {
+    let mut client = TestClient::new((), crate::c1_state_machine::LightSwitch, (), (), false);
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+
+    assert!(client.import_block(b1.clone()));
+    assert_eq!(client.get_block(b1.hash()), Some(b1.clone()));
+    assert_eq!(client.get_state(b1.hash()), Some(true));
+    assert_eq!(client.is_leaf(client.genesis_hash), Some(false));
+    assert_eq!(client.all_leaves(), vec![b1.hash()]);
+}
+
+#[test]
+fn cl_2_import_block_with_unknown_parent_fails() {
+    let mut client = TestClient::new((), crate::c1_state_machine::LightSwitch, (), (), false);
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let b1 = g.child(&(), &false, vec![]).unwrap();
+    let b2 = b1.child(&(), &false, vec![]).unwrap();
+
+    assert!(!client.import_block(b2));
+}
+
+/// Builds the following tree and returns the client along with blocks `[g, a1, b1, a2]`.
+///
+/// G -- A1 -- A2
+///  \-- B1
+#[cfg(test)]
+fn client_with_orphan() -> (TestClient, Vec<Block<(), crate::c1_state_machine::LightSwitch>>) {
+    let mut client = TestClient::new((), crate::c1_state_machine::LightSwitch, (), (), false);
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let a1 = g.child(&(), &false, vec![]).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+    let a2 = a1.child(&(), &false, vec![]).unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1.clone()));
+    assert!(client.import_block(a2.clone()));
+    (client, vec![g, a1, b1, a2])
+}
+
+#[test]
+fn cl_2_uncle_candidates_include_orphaned_sibling() {
+    let (client, blocks) = client_with_orphan();
+    let (a1, b1, a2) = (&blocks[1], &blocks[2], &blocks[3]);
+
+    assert_eq!(client.uncle_candidates(a2.hash()), vec![b1.hash()]);
+    // From the other side of the fork, it is the main chain block that looks orphaned.
+    assert_eq!(client.uncle_candidates(b1.hash()), vec![a1.hash()]);
+}
+
+#[test]
+fn cl_2_import_block_with_valid_uncle() {
+    let (mut client, blocks) = client_with_orphan();
+    let (b1, a2) = (&blocks[2], &blocks[3]);
+
+    let a3 = a2.child_with_uncles(&(), &false, vec![], vec![b1.hash()]).unwrap();
+    assert!(client.import_block(a3.clone()));
+
+    // Once referenced, the uncle may not be referenced again.
+    assert!(client.uncle_candidates(a3.hash()).is_empty());
+    let a4 = a3.child_with_uncles(&(), &false, vec![], vec![b1.hash()]).unwrap();
+    assert!(!client.import_block(a4));
+}
+
+#[test]
+fn cl_2_import_block_with_ancestor_as_uncle_fails() {
+    let (mut client, blocks) = client_with_orphan();
+    let (a1, a2) = (&blocks[1], &blocks[3]);
+
+    let a3 = a2.child_with_uncles(&(), &false, vec![], vec![a1.hash()]).unwrap();
+    assert!(!client.import_block(a3));
+}
+
+#[test]
+fn cl_2_import_block_with_stale_uncle_fails() {
+    let (mut client, blocks) = client_with_orphan();
+    let b1 = &blocks[2];
+
+    let mut tip = blocks[3].clone();
+    for _ in 0..UNCLE_WINDOW {
+        tip = tip.child(&(), &false, vec![]).unwrap();
+        assert!(client.import_block(tip.clone()));
+    }
+
+    assert!(client.uncle_candidates(tip.hash()).is_empty());
+    let stale = tip.child_with_uncles(&(), &false, vec![], vec![b1.hash()]).unwrap();
+    assert!(!client.import_block(stale));
+}
+
+#[test]
+fn cl_2_import_block_contradicting_checkpoint_fails() {
+    use super::Checkpoints;
+
+    let client = TestClient::new((), crate::c1_state_machine::LightSwitch, (), (), false);
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let a1 = g.child(&(), &false, vec![]).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+    let mut client = client.with_checkpoints(Checkpoints::new([(1, a1.hash())]));
+
+    assert!(!client.import_block(b1.clone()));
+    assert_eq!(client.get_block(b1.hash()), None);
+    assert!(client.import_block(a1.clone()));
+    assert_eq!(client.all_leaves(), vec![a1.hash()]);
+}
+
+#[test]
+fn cl_2_import_equivocating_block_produces_slash() {
+    use crate::c1_state_machine::{Staking, StakingLedger, StakingTransition, Stakes};
+    use crate::c3_consensus::{ConsensusAuthority::*, SimplePoa};
+
+    let poa = || SimplePoa {
+        authorities: vec![Alice, Bob],
+    };
+    let genesis_state = StakingLedger::new(Stakes::from([(Alice, 100), (Bob, 100)]));
+    let mut client =
+        FullClient::<SimplePoa, Staking, (), ()>::new(poa(), Staking, (), (), genesis_state.clone());
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    // Simple PoA always seals with the first authority, so Alice authors both of these.
+    let bond = StakingTransition::Bond { who: Bob, amount: 1 };
+    let a1 = g.child(&poa(), &genesis_state, vec![]).unwrap();
+    let b1 = g.child(&poa(), &genesis_state, vec![bond]).unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.take_equivocations().is_empty());
+    assert!(client.import_block(b1));
+
+    let proofs = client.take_equivocations();
+    assert_eq!(proofs.len(), 1);
+    assert_eq!(proofs[0].offender, Alice);
+    assert!(proofs[0].verify(&poa()));
+    assert!(client.take_equivocations().is_empty());
+
+    // Reporting the proof on chain slashes Alice.
+    let a1_state = client.get_state(a1.hash()).unwrap();
+    let slash = StakingTransition::from(&proofs[0]);
+    let a2 = a1.child(&poa(), &a1_state, vec![slash]).unwrap();
+    assert!(client.import_block(a2.clone()));
+    assert_eq!(
+        client.get_state(a2.hash()).map(|ledger| ledger.active),
+        Some(Stakes::from([(Alice, 90), (Bob, 100)]))
+    );
+}
+
+#[test]
+fn cl_2_import_tracks_total_work() {
+    use crate::c1_state_machine::LightSwitch;
+    use crate::c3_consensus::{moderate_difficulty_pow, Pow};
+
+    let pow = moderate_difficulty_pow();
+    let mut client =
+        FullClient::<Pow, LightSwitch, (), ()>::new(moderate_difficulty_pow(), LightSwitch, (), (), false);
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let b1 = g.child(&pow, &false, vec![]).unwrap();
+    let b2 = b1.child(&pow, &false, vec![]).unwrap();
+    assert!(client.import_block(b1.clone()));
+    assert!(client.import_block(b2.clone()));
+
+    assert_eq!(client.total_work(client.genesis_hash), Some(0));
+    assert_eq!(client.total_work(b1.hash()), Some(100));
+    assert_eq!(client.total_work(b2.hash()), Some(200));
+    assert_eq!(client.total_work(12345), None);
+}
+
+#[test]
+#[cfg(all(feature = "strict-invariants", debug_assertions))]
+#[should_panic(expected = "broke an invariant")]
+fn cl_2_import_checks_invariants() {
+    use crate::c1_state_machine::{Balances, Ledger, TrackedCurrency, User::Alice};
+
+    // The genesis ledger is already broken: Alice's balance is not part of the issuance.
+    let genesis = Ledger {
+        balances: Balances::from([(Alice, 10)]),
+        issuance: 0,
+    };
+    let mut client = FullClient::<(), _, (), ()>::new((), TrackedCurrency, (), (), genesis.clone());
+    let g = client.get_block(client.genesis_hash).unwrap();
+    let b1 = g.child(&(), &genesis, vec![]).unwrap();
+    client.import_block(b1);
+}
@@ -4,8 +4,222 @@
 //! The concepts are identical here, but now that we have a client tracking a proper block database,
 //! we can explore more advanced fork choice algorithms. In particular, we can now explore GHOST.
 
-use super::{Header, FullClient, Consensus};
-use crate::c3_consensus::{Pow, SimplePoa, ConsensusAuthority};
+use std::collections::HashMap;
+#[cfg(test)]
+use std::sync::mpsc::Receiver;
+
+use super::{BlockStore, ChainEvent, FullClient, Consensus, StateMachine};
+
+type Hash = u64;
+
+/// What fork choice rules know about a single block in the tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeNode {
+    /// The hash of the parent block.
+    pub parent: Hash,
+    /// The height of the block.
+    pub height: u64,
+    /// The cumulative work of the block, from genesis up to and including it.
+    pub total_work: u128,
+}
+
+/// The shape of the block tree that a client has imported so far.
+///
+/// Fork choice rules only need to know how blocks are related and how much work went into them,
+/// so the tree does not store headers or bodies. This keeps it independent of the consensus
+/// engine and state machine, which in turn lets one fork choice rule work with any client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForkTree {
+    /// The hash of the genesis block. It is the root of the tree.
+    root: Hash,
+    /// Every known block, keyed by its hash.
+    nodes: HashMap<Hash, TreeNode>,
+    /// Blocks that do not have any known children, in the order they were first seen.
+    leaves: Vec<Hash>,
+    /// The latest finalized block. Only its descendants can be part of the best chain.
+    finalized: Hash,
+}
+
+impl ForkTree {
+    /// A tree containing only the given genesis block.
+    pub fn new(root: Hash) -> Self {
+        Self::starting_at(root, 0)
+    }
+
+    /// A tree containing only the given block, at the given height. Clients that do not start
+    /// from genesis use this, so that heights in the tree still match the chain's.
+    pub fn starting_at(root: Hash, height: u64) -> Self {
+        let genesis = TreeNode {
+            parent: 0,
+            height,
+            total_work: 0,
+        };
+        ForkTree {
+            root,
+            nodes: HashMap::from([(root, genesis)]),
+            leaves: vec![root],
+            finalized: root,
+        }
+    }
+
+    /// Add a block with the given amount of work to the tree. The parent must already be in the
+    /// tree. Returns whether the block was added.
+    pub fn insert(&mut self, hash: Hash, parent: Hash, work: u128) -> bool {
+        if self.nodes.contains_key(&hash) {
+            return false;
+        }
+        let Some(parent_node) = self.nodes.get(&parent) else {
+            return false;
+        };
+
+        let node = TreeNode {
+            parent,
+            height: parent_node.height + 1,
+            total_work: parent_node.total_work + work,
+        };
+        self.nodes.insert(hash, node);
+        self.leaves.retain(|leaf| *leaf != parent);
+        self.leaves.push(hash);
+        true
+    }
+
+    /// The hash of the genesis block.
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Look up a block in the tree.
+    pub fn get(&self, hash: Hash) -> Option<&TreeNode> {
+        self.nodes.get(&hash)
+    }
+
+    /// Every block in the tree, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (Hash, &TreeNode)> + '_ {
+        self.nodes.iter().map(|(hash, node)| (*hash, node))
+    }
+
+    /// Whether the given block is in the tree.
+    pub fn contains(&self, hash: Hash) -> bool {
+        self.nodes.contains_key(&hash)
+    }
+
+    /// The blocks without any known children, in the order they were first seen.
+    pub fn leaves(&self) -> &[Hash] {
+        &self.leaves
+    }
+
+    /// The latest finalized block. Until something else is finalized, that is the genesis block.
+    pub fn finalized(&self) -> Hash {
+        self.finalized
+    }
+
+    /// Mark the given block as final. It must descend from the block that is currently final,
+    /// because finality never goes backwards, and never switches branches.
+    /// Returns whether the block was finalized.
+    pub fn finalize(&mut self, hash: Hash) -> bool {
+        if !self.descends_from(hash, self.finalized) {
+            return false;
+        }
+        self.finalized = hash;
+        true
+    }
+
+    /// Whether the first block descends from the second one. Every block descends from itself.
+    /// Returns false when either block is not known.
+    pub fn descends_from(&self, hash: Hash, ancestor: Hash) -> bool {
+        let Some(ancestor_node) = self.nodes.get(&ancestor) else {
+            return false;
+        };
+        let mut current = hash;
+        while current != ancestor {
+            match self.nodes.get(&current) {
+                Some(node) if node.height > ancestor_node.height => current = node.parent,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// The most recent block that both given blocks descend from.
+    /// Returns None if either block is not known.
+    pub fn common_ancestor(&self, a: Hash, b: Hash) -> Option<Hash> {
+        let (mut a, mut b) = (a, b);
+        loop {
+            if a == b {
+                return Some(a);
+            }
+            let (node_a, node_b) = (self.nodes.get(&a)?, self.nodes.get(&b)?);
+            if node_a.height >= node_b.height {
+                a = node_a.parent;
+            }
+            if node_b.height >= node_a.height {
+                b = node_b.parent;
+            }
+        }
+    }
+
+    /// The blocks from the given block back to, but not including, the given ancestor, newest first.
+    pub(crate) fn path_back_to(&self, hash: Hash, ancestor: Hash) -> Vec<Hash> {
+        let mut path = Vec::new();
+        let mut current = hash;
+        while current != ancestor {
+            path.push(current);
+            current = self.nodes[&current].parent;
+        }
+        path
+    }
+
+    /// The leaves that descend from the finalized block. The best chain must end at one of them.
+    pub fn viable_leaves(&self) -> impl Iterator<Item = Hash> + '_ {
+        self.leaves
+            .iter()
+            .copied()
+            .filter(|leaf| self.descends_from(*leaf, self.finalized))
+    }
+
+    /// Whether the given block is a leaf. Returns None if the block is not known.
+    pub fn is_leaf(&self, hash: Hash) -> Option<bool> {
+        self.contains(hash).then(|| self.leaves.contains(&hash))
+    }
+}
+
+/// How to choose between branches that a fork choice rule considers equally good.
+///
+/// Nodes see blocks in different orders, so preferring the first seen branch can leave nodes
+/// that saw a tie from different sides disagreeing indefinitely. Preferring the lowest hash
+/// gives every node the same answer, no matter the order they saw the blocks in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// Prefer the leaf with the lowest hash.
+    #[default]
+    LowestHash,
+    /// Prefer the leaf that was seen first.
+    FirstSeen,
+}
+
+/// The viable leaf that scores highest according to the given key, with ties broken by the
+/// given strategy.
+fn best_leaf_by<K: Ord>(
+    tree: &ForkTree,
+    tie_break: TieBreak,
+    key: impl Fn(&TreeNode) -> K,
+) -> Hash {
+    let mut best = tree.finalized();
+    let mut best_key = None;
+    for leaf in tree.viable_leaves() {
+        let leaf_key = key(&tree.nodes[&leaf]);
+        let better = match &best_key {
+            None => true,
+            Some(k) if leaf_key == *k => tie_break == TieBreak::LowestHash && leaf < best,
+            Some(k) => leaf_key > *k,
+        };
+        if better {
+            best = leaf;
+            best_key = Some(leaf_key);
+        }
+    }
+    best
+}
 
 /// A means for a blockchain client to decide which chain is best among the many
 /// that it potentially knows about.
@@ -14,62 +228,65 @@ use crate::c3_consensus::{Pow, SimplePoa, ConsensusAuthority};
 /// consensus
 ///
 /// Some implementations are light and just make a quick comparison, like the longest chain rule.
-/// Others are more complex and associate additional logic with block import, like GHOST.
-pub trait ForkChoice<C: Consensus> {
-    /// Return the hash of the best block currently known according to this fork choice rule.
-    fn best_block(&self, header: Header<C::Digest>) -> Option<u64>;
+/// Others are more complex and need to look at the whole tree, like GHOST.
+pub trait ForkChoice {
+    /// Return the hash of the leaf at the tip of the best chain in the given tree.
+    ///
+    /// Finalized blocks are never reverted, so the best chain must always include the tree's
+    /// finalized block. In other words the best leaf is one of the tree's viable leaves.
+    fn best_leaf(&self, tree: &ForkTree) -> Hash;
+}
 
-    /// Perform some bookkeeping activities when importing a new block.
-    fn import_hook(&mut self, header: Header<C::Digest>);
+/// The trivial fork choice rule, for clients that do not care about forks. It follows the longest
+/// chain, which is what most nodes would do anyway.
+impl ForkChoice for () {
+    fn best_leaf(&self, tree: &ForkTree) -> Hash {
+        LongestChain::default().best_leaf(tree)
+    }
 }
 
 /// The chain with the highest block height is the best
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct LongestChain {
-    // You may add fields here if you need to.
+    /// How to choose between chains of the same height.
+    pub tie_break: TieBreak,
 }
 
-impl<C: Consensus> ForkChoice<C> for LongestChain {
-    fn best_block(&self, header: Header<C::Digest>) -> Option<u64> {
-        todo!("Exercise 1")
-    }
-
-    fn import_hook(&mut self, header: Header<C::Digest>) {
-        todo!("Exercise 2")
+impl ForkChoice for LongestChain {
+    fn best_leaf(&self, tree: &ForkTree) -> Hash {
+        // todo!("Exercise 1")
+        best_leaf_by(tree, self.tie_break, |node| node.height)
     }
 }
 
 /// The chain with the most accumulated proof of work is the best.
-/// This fork choice rule only makes sense with the PoW consensus engine
-/// and the generics reflect that.
+///
+/// Each block's work comes from the consensus engine, so a single hard block can outweigh
+/// several easy ones. This fork choice rule only makes sense with the PoW consensus engine.
+/// With other engines every block counts the same, and it is no different from the longest chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct HeaviestChain {
-    // You may add fields here if you need to.
+    /// How to choose between chains with the same work.
+    pub tie_break: TieBreak,
 }
 
-impl ForkChoice<Pow> for HeaviestChain {
-    fn best_block(&self, header: Header<u64>) -> Option<u64> {
-        todo!("Exercise 3")
-    }
-
-    fn import_hook(&mut self, header: Header<u64>) {
-        todo!("Exercise 4")
+impl ForkChoice for HeaviestChain {
+    fn best_leaf(&self, tree: &ForkTree) -> Hash {
+        // todo!("Exercise 3")
+        best_leaf_by(tree, self.tie_break, |node| node.total_work)
     }
 }
 
 /// The chain with the most signatures from the Alice authority is the best.
-/// This fork choice rule only makes sense with the PoA consensus engine
-/// and the generics reflect that.
+/// This fork choice rule only makes sense with the PoA consensus engine.
 pub struct MostAliceSigs {
     // You may add fields here if you need to.
 }
 
-impl ForkChoice<SimplePoa> for MostAliceSigs {
-    fn best_block(&self, header: Header<ConsensusAuthority>) -> Option<u64> {
+impl ForkChoice for MostAliceSigs {
+    fn best_leaf(&self, _tree: &ForkTree) -> Hash {
         todo!("Exercise 5")
     }
-
-    fn import_hook(&mut self, header: Header<ConsensusAuthority>) {
-        todo!("Exercise 6")
-    }
 }
 
 /// In the Greedy Heaviest Observed Subtree rule, the fork choice is iterative.
@@ -79,26 +296,436 @@ pub struct Ghost {
     // You may add fields here if you need to.
 }
 
-impl ForkChoice<Pow> for Ghost {
-    fn best_block(&self, header: Header<u64>) -> Option<u64> {
+impl ForkChoice for Ghost {
+    fn best_leaf(&self, _tree: &ForkTree) -> Hash {
         todo!("Exercise 7")
     }
-
-    fn import_hook(&mut self, header: Header<u64>) {
-        todo!("Exercise 8")
-    }
 }
 
 // Finally, we will provide a convenience method directly on our client that simply calls
 // into the corresponding method on the ForkChoice rule. You may need to add some trait
 // bounds to make this work.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P> {
+impl<C: Consensus, SM: StateMachine, FC: ForkChoice, P, S> FullClient<C, SM, FC, P, S> {
     /// Return the hash of the best block currently known to the client
-    fn best_block(&self) -> u64 {
-        todo!("Exercise 9")
+    pub fn best_block(&self) -> u64 {
+        // todo!("Exercise 9")
+        self.fork_choice.best_leaf(&self.fork_tree)
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> FullClient<C, SM, FC, P, S> {
+    /// The tree of blocks imported so far, as seen by the fork choice rule.
+    pub fn fork_tree(&self) -> &ForkTree {
+        &self.fork_tree
+    }
+}
+
+/// A switch of the best chain from one branch to another.
+///
+/// Anything that follows the best chain needs to know about these. For example, the transaction
+/// pool must put transactions from the retracted blocks back in the queue, and user interfaces
+/// must stop showing the retracted blocks as confirmed. Subscribers to the client's events hear
+/// of them as `ChainEvent::Reorg`. Simply extending the best chain is not a reorg.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReorgEvent {
+    /// The blocks that left the best chain, newest first. This is the order to undo them in.
+    pub retracted: Vec<Hash>,
+    /// The blocks that joined the best chain, oldest first. This is the order to apply them in.
+    pub enacted: Vec<Hash>,
+    /// The last block that both the old and the new best chain include.
+    pub common_ancestor: Hash,
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// Ask the fork choice rule for the best block again, move the best state to it, and tell
+    /// the subscribers if the best block has changed or the best chain has switched branches.
+    /// States that are no longer needed are pruned. This must be called whenever the fork tree
+    /// changes.
+    pub(crate) fn update_best_block(&mut self) {
+        let old_best = self.store.best();
+        let new_best = self.best_block();
+        self.store.set_best(new_best);
+        self.prune_states();
+
+        let Some(common_ancestor) = self.fork_tree.common_ancestor(old_best, new_best) else {
+            return;
+        };
+        let mut enacted = self.fork_tree.path_back_to(new_best, common_ancestor);
+        enacted.reverse();
+        self.move_best_state(old_best, common_ancestor, &enacted);
+
+        if common_ancestor != old_best {
+            let event = ReorgEvent {
+                retracted: self.fork_tree.path_back_to(old_best, common_ancestor),
+                enacted,
+                common_ancestor,
+            };
+            self.notify(ChainEvent::Reorg(event));
+            self.metrics.reorgs += 1;
+        }
+        if new_best != old_best {
+            let height = self.height_of(new_best);
+            self.notify(ChainEvent::NewBestBlock {
+                hash: new_best,
+                height,
+            });
+        }
     }
 }
 
 //TODO lots of tests for all the algos.
 // Especially a subtle one in Ghost, where importing a new
 // header causes a re-org to a different header than the one that was imported.
+
+#[test]
+fn cl_3_fork_tree_tracks_leaves_and_work() {
+    let mut tree = ForkTree::new(1);
+    assert!(tree.insert(2, 1, 5));
+    assert!(tree.insert(3, 2, 5));
+    assert!(tree.insert(4, 1, 7));
+
+    assert_eq!(tree.leaves(), &[3, 4]);
+    assert_eq!(tree.is_leaf(2), Some(false));
+    assert_eq!(tree.is_leaf(9), None);
+    assert_eq!(
+        tree.get(3),
+        Some(&TreeNode {
+            parent: 2,
+            height: 2,
+            total_work: 10
+        })
+    );
+
+    // Blocks must be new, and their parent must already be known.
+    assert!(!tree.insert(3, 2, 5));
+    assert!(!tree.insert(5, 9, 5));
+}
+
+#[test]
+fn cl_3_longest_chain_picks_highest_leaf() {
+    let mut tree = ForkTree::new(1);
+    assert_eq!(LongestChain::default().best_leaf(&tree), 1);
+
+    tree.insert(2, 1, 1);
+    tree.insert(3, 2, 1);
+    tree.insert(4, 1, 100);
+    assert_eq!(LongestChain::default().best_leaf(&tree), 3);
+}
+
+/// Longest chain with the first seen tie break. Tests that are about the order in which blocks
+/// arrive use it so that ties do not depend on block hashes.
+#[cfg(test)]
+const FIRST_SEEN: LongestChain = LongestChain {
+    tie_break: TieBreak::FirstSeen,
+};
+
+#[test]
+fn cl_3_longest_chain_tie_break() {
+    let mut tree = ForkTree::new(1);
+    tree.insert(3, 1, 1);
+    tree.insert(2, 1, 1);
+    assert_eq!(FIRST_SEEN.best_leaf(&tree), 3);
+    assert_eq!(LongestChain::default().best_leaf(&tree), 2);
+}
+
+#[test]
+fn cl_3_lowest_hash_tie_break_ignores_arrival_order() {
+    let mut one_way = ForkTree::new(1);
+    one_way.insert(7, 1, 1);
+    one_way.insert(4, 1, 1);
+    one_way.insert(9, 1, 1);
+
+    let mut other_way = ForkTree::new(1);
+    other_way.insert(9, 1, 1);
+    other_way.insert(4, 1, 1);
+    other_way.insert(7, 1, 1);
+
+    let rule = LongestChain::default();
+    assert_eq!(rule.best_leaf(&one_way), 4);
+    assert_eq!(rule.best_leaf(&other_way), 4);
+    assert_ne!(FIRST_SEEN.best_leaf(&one_way), FIRST_SEEN.best_leaf(&other_way));
+}
+
+#[test]
+fn cl_3_client_follows_longest_branch_imported_out_of_order() {
+    use super::p2_importing_blocks::ImportBlock;
+    use crate::c1_state_machine::LightSwitch;
+
+    let mut client = FullClient::<(), LightSwitch, LongestChain, ()>::new(
+        (),
+        LightSwitch,
+        FIRST_SEEN,
+        (),
+        false,
+    );
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    // G -- A1 -- A2 -- A3
+    //  \-- B1 -- B2
+    let a1 = g.child(&(), &false, vec![]).unwrap();
+    let a2 = a1.child(&(), &false, vec![]).unwrap();
+    let a3 = a2.child(&(), &false, vec![]).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![]).unwrap();
+
+    // The branches arrive interleaved, and the best block switches back and forth.
+    assert!(client.import_block(a1.clone()));
+    assert_eq!(client.best_block(), a1.hash());
+    assert!(client.import_block(b1.clone()));
+    assert_eq!(client.best_block(), a1.hash());
+    assert!(client.import_block(b2.clone()));
+    assert_eq!(client.best_block(), b2.hash());
+    assert!(client.import_block(a2.clone()));
+    assert_eq!(client.best_block(), b2.hash());
+    assert!(client.import_block(a3.clone()));
+    assert_eq!(client.best_block(), a3.hash());
+
+    assert_eq!(client.fork_tree().leaves(), &[b2.hash(), a3.hash()]);
+}
+
+#[test]
+fn cl_3_heaviest_chain_diverges_from_longest_chain() {
+    // 1 -- 2 -- 3 -- 4     three easy blocks
+    //  \-- 5               one hard block
+    let mut tree = ForkTree::new(1);
+    tree.insert(2, 1, 10);
+    tree.insert(3, 2, 10);
+    tree.insert(4, 3, 10);
+    tree.insert(5, 1, 45);
+
+    assert_eq!(LongestChain::default().best_leaf(&tree), 4);
+    assert_eq!(HeaviestChain::default().best_leaf(&tree), 5);
+
+    // Extending the easy branch far enough wins it back.
+    tree.insert(6, 4, 10);
+    tree.insert(7, 6, 10);
+    assert_eq!(HeaviestChain::default().best_leaf(&tree), 7);
+}
+
+#[test]
+fn cl_3_heaviest_chain_tie_break() {
+    let mut tree = ForkTree::new(1);
+    tree.insert(5, 1, 20);
+    tree.insert(2, 1, 10);
+    tree.insert(4, 2, 10);
+
+    let first_seen = HeaviestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    assert_eq!(first_seen.best_leaf(&tree), 5);
+    assert_eq!(HeaviestChain::default().best_leaf(&tree), 4);
+}
+
+/// A toy consensus engine for exploring fork choice, in which authors simply claim how hard
+/// their block was to author. Real PoW engines can not just claim their difficulty like this.
+#[cfg(test)]
+struct ClaimedDifficulty(u64);
+
+#[cfg(test)]
+impl Consensus for ClaimedDifficulty {
+    type Digest = u64;
+
+    fn validate(&self, _: &u64, _: &super::Header<u64>) -> bool {
+        true
+    }
+
+    fn seal(&self, _: &u64, partial_header: super::Header<()>) -> Option<super::Header<u64>> {
+        Some(partial_header.seal(self.0))
+    }
+
+    fn work(&self, header: &super::Header<u64>) -> u128 {
+        header.consensus_digest as u128
+    }
+}
+
+#[test]
+fn cl_3_client_follows_heaviest_branch() {
+    use super::p2_importing_blocks::ImportBlock;
+    use crate::c1_state_machine::LightSwitch;
+
+    let mut client = FullClient::<ClaimedDifficulty, LightSwitch, HeaviestChain, ()>::new(
+        ClaimedDifficulty(0),
+        LightSwitch,
+        HeaviestChain::default(),
+        (),
+        false,
+    );
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    // G -- A1 -- A2 -- A3     difficulty 1 each
+    //  \-- B1                 difficulty 5
+    let (easy, hard) = (ClaimedDifficulty(1), ClaimedDifficulty(5));
+    let a1 = g.child(&easy, &false, vec![]).unwrap();
+    let a2 = a1.child(&easy, &false, vec![]).unwrap();
+    let a3 = a2.child(&easy, &false, vec![]).unwrap();
+    let b1 = g.child(&hard, &false, vec![()]).unwrap();
+
+    for block in [&a1, &a2, &a3, &b1] {
+        assert!(client.import_block(block.clone()));
+    }
+    assert_eq!(LongestChain::default().best_leaf(client.fork_tree()), a3.hash());
+    assert_eq!(client.best_block(), b1.hash());
+    assert_eq!(client.total_work(b1.hash()), Some(5));
+}
+
+#[test]
+fn cl_3_best_leaf_descends_from_finalized_block() {
+    // 1 -- 2 -- 3 -- 4
+    //  \-- 5
+    let mut tree = ForkTree::new(1);
+    tree.insert(2, 1, 1);
+    tree.insert(3, 2, 1);
+    tree.insert(4, 3, 1);
+    tree.insert(5, 1, 10);
+    assert!(tree.finalize(5));
+
+    assert_eq!(tree.viable_leaves().collect::<Vec<_>>(), vec![5]);
+    assert_eq!(LongestChain::default().best_leaf(&tree), 5);
+    assert_eq!(HeaviestChain::default().best_leaf(&tree), 5);
+
+    // Finality never switches branches or goes backwards.
+    assert!(!tree.finalize(2));
+    assert!(!tree.finalize(1));
+    assert!(!tree.finalize(9));
+    assert_eq!(tree.finalized(), 5);
+}
+
+#[test]
+fn cl_3_descends_from() {
+    let mut tree = ForkTree::new(1);
+    tree.insert(2, 1, 1);
+    tree.insert(3, 2, 1);
+    tree.insert(4, 1, 1);
+
+    assert!(tree.descends_from(3, 1));
+    assert!(tree.descends_from(3, 3));
+    assert!(!tree.descends_from(3, 4));
+    assert!(!tree.descends_from(1, 3));
+    assert!(!tree.descends_from(9, 1));
+}
+
+#[test]
+fn cl_3_common_ancestor() {
+    // 1 -- 2 -- 3 -- 4
+    //       \-- 5
+    let mut tree = ForkTree::new(1);
+    tree.insert(2, 1, 1);
+    tree.insert(3, 2, 1);
+    tree.insert(4, 3, 1);
+    tree.insert(5, 2, 1);
+
+    assert_eq!(tree.common_ancestor(4, 5), Some(2));
+    assert_eq!(tree.common_ancestor(5, 4), Some(2));
+    assert_eq!(tree.common_ancestor(4, 3), Some(3));
+    assert_eq!(tree.common_ancestor(4, 4), Some(4));
+    assert_eq!(tree.common_ancestor(4, 9), None);
+}
+
+/// Extend the given block with `n` empty children, import them all, and return them.
+#[cfg(test)]
+fn extend_and_import<FC: ForkChoice>(
+    client: &mut FullClient<(), crate::c1_state_machine::LightSwitch, FC, ()>,
+    from: &super::Block<(), crate::c1_state_machine::LightSwitch>,
+    n: usize,
+) -> Vec<super::Block<(), crate::c1_state_machine::LightSwitch>> {
+    use super::p2_importing_blocks::ImportBlock;
+
+    let mut blocks = Vec::new();
+    let mut parent = from.clone();
+    for _ in 0..n {
+        let state = client.get_state(parent.hash()).unwrap();
+        let child = parent.child(&(), &state, vec![]).unwrap();
+        assert!(client.import_block(child.clone()));
+        blocks.push(child.clone());
+        parent = child;
+    }
+    blocks
+}
+
+/// The reorgs among the events received so far.
+#[cfg(test)]
+fn reorgs(events: &Receiver<ChainEvent>) -> Vec<ReorgEvent> {
+    events
+        .try_iter()
+        .filter_map(|event| match event {
+            ChainEvent::Reorg(reorg) => Some(reorg),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn cl_3_deep_reorg_is_notified() {
+    use super::p2_importing_blocks::ImportBlock;
+    use crate::c1_state_machine::LightSwitch;
+
+    let mut client = FullClient::<(), LightSwitch, LongestChain, ()>::new(
+        (),
+        LightSwitch,
+        FIRST_SEEN,
+        (),
+        false,
+    );
+    let events = client.subscribe_events();
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    // G -- C1 -- C2 -- A1 .. A5
+    //             \--- B1 .. B7
+    let common = extend_and_import(&mut client, &g, 2);
+    let a = extend_and_import(&mut client, &common[1], 5);
+    assert_eq!(client.best_block(), a[4].hash());
+
+    // The B branch starts differently. A tie does not switch branches, so B only overtakes
+    // with its sixth block.
+    let b1 = common[1].child(&(), &false, vec![()]).unwrap();
+    assert!(client.import_block(b1.clone()));
+    let mut b = vec![b1.clone()];
+    b.extend(extend_and_import(&mut client, &b1, 6));
+
+    // Extending the best chain, and building a shorter fork, are not reorgs.
+    assert_eq!(client.best_block(), b[6].hash());
+    let [event] = reorgs(&events).try_into().unwrap();
+
+    assert_eq!(event.common_ancestor, common[1].hash());
+    assert_eq!(event.retracted, a.iter().rev().map(|b| b.hash()).collect::<Vec<_>>());
+    assert_eq!(event.enacted, b[..6].iter().map(|b| b.hash()).collect::<Vec<_>>());
+}
+
+#[test]
+fn cl_3_reorg_back_and_forth_is_notified_each_time() {
+    use super::p2_importing_blocks::ImportBlock;
+    use crate::c1_state_machine::LightSwitch;
+
+    let mut client = FullClient::<(), LightSwitch, LongestChain, ()>::new(
+        (),
+        LightSwitch,
+        FIRST_SEEN,
+        (),
+        false,
+    );
+    let events = client.subscribe_events();
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    let a1 = g.child(&(), &false, vec![]).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1.clone()));
+    let b2 = extend_and_import(&mut client, &b1, 1).remove(0);
+    let a3 = extend_and_import(&mut client, &a1, 2).remove(1);
+    assert_eq!(client.best_block(), a3.hash());
+
+    let events = reorgs(&events);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].retracted, vec![a1.hash()]);
+    assert_eq!(events[0].enacted, vec![b1.hash(), b2.hash()]);
+    assert_eq!(events[1].retracted, vec![b2.hash(), b1.hash()]);
+    assert_eq!(events[1].enacted.last(), Some(&a3.hash()));
+}
@@ -9,9 +9,17 @@
 //! * Making the current transactions available for a block authoring process
 //! * Re-queueing transactions from orphaned blocks when re-orgs happen (This one happens IRL; might not cover it in BFS; TBD)
 
-use std::{collections::VecDeque, marker::PhantomData};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+    marker::PhantomData,
+};
 
-use super::{FullClient, StateMachine};
+use super::{BlockStore, Consensus, FullClient, StateMachine};
+use crate::c1_state_machine::{Nonced, PaysFee, Sender, TransitionError, TryStateMachine};
+use crate::hash;
+
+type Hash = u64;
 
 /// An abstraction over the notion of transaction pool.
 pub trait TransactionPool<SM: StateMachine> {
@@ -34,55 +42,107 @@ pub trait TransactionPool<SM: StateMachine> {
     /// The notion of next is opaque and implementation dependent.
     /// Different chains prioritize transactions differently, usually by economic means.
     fn next_from_pool(&mut self) -> Option<SM::Transition>;
+
+    /// Forget the transactions that were included in a newly imported block. Pools that check
+    /// transactions against the state also recheck the rest against the new best state.
+    fn prune(&mut self, imported_block: &[SM::Transition], _best_state: &SM::State)
+    where
+        SM::Transition: Clone,
+    {
+        for t in imported_block {
+            self.remove(t.clone());
+        }
+    }
 }
 
 
 // First we add some new user-facing methods to the client.
 // These are basically wrappers around methods that the pool itself provides.
-impl<C, SM, FC, P> FullClient<C, SM, FC, P>           
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>           
     where
+    C: Consensus,
     SM: StateMachine,
+    P: TransactionPool<SM>,
 {
     /// Submit a transaction to the client's transaction pool to hopefully
-    /// be included in a future block.
-    pub fn submit_transaction(&mut self, t: SM::Transition) {
-        todo!("Exercise 1")
+    /// be included in a future block. Returns whether the pool accepted it.
+    pub fn submit_transaction(&mut self, t: SM::Transition) -> bool {
+        // todo!("Exercise 1")
+        self.transaction_pool.try_insert(t)
     }
 
     /// Get the total number of transactions in the node's
     /// transaction pool.
     pub fn pool_size(&self) -> usize {
-        todo!("Exercise 2")
+        // todo!("Exercise 2")
+        self.transaction_pool.size()
     }
 
     /// Check whether a a given transaction is in the client's transaction pool.
     pub fn pool_contains(&self, t: SM::Transition) -> bool {
-        todo!("Exercise 3")
+        // todo!("Exercise 3")
+        self.transaction_pool.contains(t)
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Clone,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Tell the pool that the given block has been imported, so that it drops the transactions
+    /// included in it. Returns whether the block was known.
+    pub fn prune_pool(&mut self, imported_block: Hash) -> bool {
+        let Some(body) = self.store.body(imported_block) else {
+            return false;
+        };
+        self.transaction_pool.prune(&body, &self.best_state);
+        true
     }
 }
 
 /// A simple state machine that is just a first-in-first-out queue.
 pub struct SimplePool<SM: StateMachine>(VecDeque<SM::Transition>);
 
-impl<SM: StateMachine> TransactionPool<SM> for SimplePool<SM> {
+// Derive would require `SM` itself to implement `Default`.
+impl<SM: StateMachine> Default for SimplePool<SM> {
+    fn default() -> Self {
+        SimplePool(VecDeque::new())
+    }
+}
+
+impl<SM> TransactionPool<SM> for SimplePool<SM>
+where
+    SM: StateMachine,
+    SM::Transition: PartialEq,
+{
     fn try_insert(&mut self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        // todo!()
+        self.0.push_back(t);
+        true
     }
 
     fn remove(&mut self, t: <SM as StateMachine>::Transition) {
-        todo!()
+        // todo!()
+        self.0.retain(|queued| *queued != t);
     }
 
     fn size(&self) -> usize {
-        todo!()
+        // todo!()
+        self.0.len()
     }
 
     fn contains(&self, t: <SM as StateMachine>::Transition) -> bool {
-        todo!()
+        // todo!()
+        self.0.contains(&t)
     }
 
     fn next_from_pool(&mut self) -> Option<<SM as StateMachine>::Transition> {
-        todo!()
+        // todo!()
+        self.0.pop_front()
     }
 }
 
@@ -163,6 +223,380 @@ where
     }
 }
 
+/// The reasons that a transaction pool refuses a transaction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PoolError {
+    /// The transaction is already in the pool.
+    Duplicate,
+    /// The transaction can not be applied to the current best state.
+    Invalid(TransitionError),
+    /// The transaction was submitted while invalid too many times, and is refused outright
+    /// for a while.
+    Banned,
+    /// A transaction from the same sender with the same nonce is already in the pool, and this
+    /// one does not pay a higher tip to replace it.
+    Underpriced,
+}
+
+/// The number of invalid submissions of the same transaction after which it is banned, unless
+/// the pool is told otherwise.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 3;
+
+/// The number of blocks that a ban lasts, unless the pool is told otherwise.
+pub const DEFAULT_BAN_DURATION: u64 = 10;
+
+/// The most transactions whose invalid submissions a pool counts at once. Once it counts this
+/// many, it forgets one of those that were last struck longest ago, so that submitting many
+/// different invalid transactions can not fill up its memory.
+pub const MAX_STRUCK: usize = 1_000;
+
+/// How many transactions a pool holds, and why.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    /// Transactions that can go in the next block.
+    pub ready: usize,
+    /// Transactions that are not ready, because they conflict with ready ones, or depend on
+    /// ones that have left the pool. They may become ready later, or may never.
+    pub future: usize,
+    /// Transactions that are currently refused outright.
+    pub banned: usize,
+}
+
+/// A prioritizer for `ValidatingPool` that hands out the transactions that pay the highest fees
+/// first, which is what authors who keep the fees want.
+pub fn by_fee<T: PaysFee>(t: &T) -> u64 {
+    t.fee()
+}
+
+/// A prioritizer for `ValidatingPool` that hands out the transactions that pay the highest tips
+/// first. The fee is the going rate for everyone, so the tip is what tells senders apart.
+pub fn by_tip<T: PaysFee>(t: &T) -> u64 {
+    t.tip()
+}
+
+/// A transaction in a pool, along with what the pool needs to know about it.
+struct Pooled<T> {
+    priority: u64,
+    /// The number of blocks the pool had seen when the transaction was first submitted.
+    submitted_at: u64,
+    transaction: T,
+}
+
+/// A transaction pool that only accepts transactions that are valid on top of the best state,
+/// or on top of the transactions that are already ready, and hands them out highest priority
+/// first. The priority is usually the fee that the sender is willing to pay.
+///
+/// Valid transactions can still conflict with one another. For example, two transfers may
+/// each be affordable, but not both. So the pool hands out transactions in the order of
+/// `ready`, which only includes a transaction if it is still valid after the ones before it.
+/// That also keeps each sender's transactions in the order of their nonces, when the state
+/// machine checks them: a transaction is only valid once the one with the nonce before it is
+/// in. The ready transactions are worked out whenever the pool changes, rather than every
+/// time one is handed out.
+///
+/// The pool counts blocks by how often it is pruned. Transactions can be given a lifetime in
+/// blocks, after which they expire, so that the pool does not fill up with transactions that
+/// will never be included. Anyone who keeps submitting the same invalid transaction is
+/// wasting the pool's time, so that transaction is banned for a while. Strikes against a
+/// transaction are forgotten once a ban would have run out, and the pool only remembers the
+/// transactions it strikes and bans by their hashes.
+pub struct ValidatingPool<SM: TryStateMachine, F> {
+    /// A means of determining a transaction's priority.
+    prioritizer: F,
+    /// The state that incoming transactions are checked against.
+    best_state: SM::State,
+    /// Every transaction in the pool, in the order they arrived.
+    pending: Vec<Pooled<SM::Transition>>,
+    /// Transactions that were handed out since the best state last changed. They are assumed
+    /// to be on their way into a block, so the rest are checked on top of them.
+    taken: Vec<Pooled<SM::Transition>>,
+    /// What `ready` returns, in order.
+    ready: VecDeque<SM::Transition>,
+    /// The state after the taken transactions and then the ready ones.
+    ready_state: SM::State,
+    /// The number of blocks the pool has seen.
+    blocks: u64,
+    /// The number of blocks after which transactions expire. None means they never do.
+    mortality: Option<u64>,
+    /// The number of invalid submissions after which a transaction is banned.
+    ban_threshold: u32,
+    /// The number of blocks that a ban lasts.
+    ban_duration: u64,
+    /// The hashes of transactions that were submitted while invalid, how many times, and the
+    /// block count at the last time. There are never more than `MAX_STRUCK` of them.
+    strikes: BTreeMap<Hash, (u32, u64)>,
+    /// The hashes of banned transactions, and the block count at which their ban ends.
+    banned: BTreeMap<Hash, u64>,
+}
+
+impl<SM, F> ValidatingPool<SM, F>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone + PartialEq + std::hash::Hash,
+    F: Fn(&SM::Transition) -> u64,
+{
+    /// An empty pool that checks transactions against the given best state.
+    pub fn new(best_state: SM::State, prioritizer: F) -> Self {
+        Self {
+            prioritizer,
+            ready_state: best_state.clone(),
+            best_state,
+            pending: Vec::new(),
+            taken: Vec::new(),
+            ready: VecDeque::new(),
+            blocks: 0,
+            mortality: None,
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+            strikes: BTreeMap::new(),
+            banned: BTreeMap::new(),
+        }
+    }
+
+    /// Let transactions expire once the given number of blocks have been imported since they
+    /// were submitted.
+    pub fn with_mortality(mut self, blocks: u64) -> Self {
+        self.mortality = Some(blocks);
+        self
+    }
+
+    /// Ban a transaction for `duration` blocks once it has been submitted while invalid
+    /// `threshold` times.
+    pub fn with_ban_policy(mut self, threshold: u32, duration: u64) -> Self {
+        self.ban_threshold = threshold;
+        self.ban_duration = duration;
+        self
+    }
+
+    /// Add a transaction to the pool, explaining why when it is refused.
+    pub fn submit(&mut self, t: SM::Transition) -> Result<(), PoolError> {
+        self.admit(t, self.blocks)
+    }
+
+    /// Add a transaction that was first submitted at the given block count. A transaction that
+    /// is invalid on top of the best state may still depend on ready ones, like a transaction
+    /// whose nonce follows theirs, so it is also tried on top of them.
+    fn admit(&mut self, t: SM::Transition, submitted_at: u64) -> Result<(), PoolError> {
+        if self.is_banned(&t) {
+            return Err(PoolError::Banned);
+        }
+        if self
+            .pending
+            .iter()
+            .chain(&self.taken)
+            .any(|pooled| pooled.transaction == t)
+        {
+            return Err(PoolError::Duplicate);
+        }
+        let valid = SM::try_next_state(&self.best_state, &t)
+            .or_else(|_| SM::try_next_state(&self.ready_state, &t));
+        if let Err(error) = valid {
+            self.strike(t);
+            return Err(PoolError::Invalid(error));
+        }
+        self.pending.push(Pooled {
+            priority: (self.prioritizer)(&t),
+            submitted_at,
+            transaction: t,
+        });
+        self.refresh();
+        Ok(())
+    }
+
+    /// Whether the given transaction is currently banned.
+    pub fn is_banned(&self, t: &SM::Transition) -> bool {
+        self.banned.contains_key(&hash(t))
+    }
+
+    /// Count an invalid submission of the given transaction, and ban it if that was one too
+    /// many.
+    fn strike(&mut self, t: SM::Transition) {
+        let key = hash(&t);
+        if !self.strikes.contains_key(&key) && self.strikes.len() >= MAX_STRUCK {
+            let oldest = self.strikes.iter().min_by_key(|(_, (_, at))| *at);
+            if let Some((&oldest, _)) = oldest {
+                self.strikes.remove(&oldest);
+            }
+        }
+        let (strikes, at) = self.strikes.entry(key).or_insert((0, self.blocks));
+        *strikes += 1;
+        *at = self.blocks;
+        if *strikes >= self.ban_threshold {
+            self.strikes.remove(&key);
+            self.banned.insert(key, self.blocks + self.ban_duration);
+        }
+    }
+
+    /// The transactions that can go in the next block, in the order they should go in.
+    ///
+    /// Transactions are tried highest priority first, and those with equal priority in the
+    /// order they arrived. Each one is only included if it is valid on top of the ones before
+    /// it. Skipped transactions are tried again once others have been included, because they
+    /// may depend on them.
+    pub fn ready(&self) -> Vec<SM::Transition> {
+        self.ready.iter().cloned().collect()
+    }
+
+    /// Work out the ready transactions again, after the pool has changed.
+    fn refresh(&mut self) {
+        let mut state = self.best_state.clone();
+        for pooled in &self.taken {
+            state = SM::next_state(&state, &pooled.transaction);
+        }
+        let mut candidates: Vec<_> = self.pending.iter().collect();
+        candidates.sort_by_key(|pooled| Reverse(pooled.priority));
+
+        let mut ready = VecDeque::new();
+        loop {
+            let before = ready.len();
+            candidates.retain(|pooled| {
+                let Ok(next) = SM::try_next_state(&state, &pooled.transaction) else {
+                    return true;
+                };
+                state = next;
+                ready.push_back(pooled.transaction.clone());
+                false
+            });
+            if ready.len() == before {
+                break;
+            }
+        }
+        self.ready = ready;
+        self.ready_state = state;
+    }
+
+    /// How many transactions are ready, how many are waiting, and how many are banned.
+    pub fn pool_status(&self) -> PoolStatus {
+        let ready = self.ready.len();
+        PoolStatus {
+            ready,
+            future: self.pending.len() - ready,
+            banned: self.banned.len(),
+        }
+    }
+}
+
+impl<SM, F> ValidatingPool<SM, F>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone + PartialEq + std::hash::Hash + Nonced + PaysFee,
+    F: Fn(&SM::Transition) -> u64,
+{
+    /// Add a transaction to the pool, replacing the waiting transaction from the same sender
+    /// with the same nonce, if there is one. The replacement must pay a strictly higher tip, so
+    /// that nobody can keep the pool busy by swapping transactions for free. Returns the
+    /// transaction that was replaced.
+    ///
+    /// Transactions that were already handed out to the author can not be replaced.
+    pub fn submit_replacing(
+        &mut self,
+        t: SM::Transition,
+    ) -> Result<Option<SM::Transition>, PoolError> {
+        let slot = (t.sender(), t.nonce());
+        let Some(index) = self
+            .pending
+            .iter()
+            .position(|pooled| (pooled.transaction.sender(), pooled.transaction.nonce()) == slot)
+        else {
+            return self.submit(t).map(|()| None);
+        };
+        if t.tip() <= self.pending[index].transaction.tip() {
+            return Err(PoolError::Underpriced);
+        }
+        let replaced = self.pending.remove(index);
+        self.refresh();
+        if let Err(error) = self.submit(t) {
+            self.pending.insert(index, replaced);
+            self.refresh();
+            return Err(error);
+        }
+        Ok(Some(replaced.transaction))
+    }
+}
+
+impl<SM, F> TransactionPool<SM> for ValidatingPool<SM, F>
+where
+    SM: TryStateMachine,
+    SM::State: Clone,
+    SM::Transition: Clone + PartialEq + std::hash::Hash,
+    F: Fn(&SM::Transition) -> u64,
+{
+    /// A transaction that was handed out and comes back, for example because its block could
+    /// not be sealed, is no longer assumed to be on its way into a block. It keeps its place
+    /// in line for expiry.
+    fn try_insert(&mut self, t: SM::Transition) -> bool {
+        let submitted_at = match self.taken.iter().position(|pooled| pooled.transaction == t) {
+            Some(index) => {
+                let submitted_at = self.taken.remove(index).submitted_at;
+                self.refresh();
+                submitted_at
+            }
+            None => self.blocks,
+        };
+        self.admit(t, submitted_at).is_ok()
+    }
+
+    fn remove(&mut self, t: SM::Transition) {
+        self.pending.retain(|pooled| pooled.transaction != t);
+        self.refresh();
+    }
+
+    fn size(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn contains(&self, t: SM::Transition) -> bool {
+        self.pending.iter().any(|pooled| pooled.transaction == t)
+    }
+
+    /// The first transaction that `ready` would return. The rest stay ready in the same order,
+    /// because they were already checked on top of it.
+    fn next_from_pool(&mut self) -> Option<SM::Transition> {
+        let t = self.ready.pop_front()?;
+        let index = self.pending.iter().position(|p| p.transaction == t)?;
+        self.taken.push(self.pending.remove(index));
+        Some(t)
+    }
+
+    /// Transactions that were handed out but did not make it into the block go back in the
+    /// pool. Every transaction that is no longer valid on top of the new best state, or of the
+    /// ready transactions, is dropped, and so is every transaction that has expired. Bans that
+    /// have run out are lifted, and strikes that are as old as a ban are forgotten.
+    fn prune(&mut self, imported_block: &[SM::Transition], best_state: &SM::State) {
+        self.best_state = best_state.clone();
+        self.blocks += 1;
+        let blocks = self.blocks;
+        self.banned.retain(|_, until| *until > blocks);
+        let ban_duration = self.ban_duration;
+        self.strikes
+            .retain(|_, (_, at)| *at + ban_duration > blocks);
+
+        for returned in std::mem::take(&mut self.taken) {
+            if !self
+                .pending
+                .iter()
+                .any(|p| p.transaction == returned.transaction)
+            {
+                self.pending.push(returned);
+            }
+        }
+        let mortality = self.mortality;
+        self.pending.retain(|pooled| {
+            let expired =
+                mortality.is_some_and(|lifetime| pooled.submitted_at + lifetime <= blocks);
+            !expired && !imported_block.contains(&pooled.transaction)
+        });
+        self.refresh();
+        let ready = &self.ready;
+        self.pending.retain(|pooled| {
+            ready.contains(&pooled.transaction)
+                || SM::try_next_state(best_state, &pooled.transaction).is_ok()
+        });
+    }
+}
+
 //TODO tests
 
 // #[test]
@@ -190,4 +624,328 @@ where
 
 
 // More tests for block importing to make sure that transactions that are imported
-// to the chain are correctly removed from the pool.
\ No newline at end of file
+// to the chain are correctly removed from the pool.
+
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User, User::*};
+
+/// Transfers pay a fee equal to the amount they move. Everything else pays nothing.
+#[cfg(test)]
+fn fee(t: &CurrencyTransaction) -> u64 {
+    match t {
+        CurrencyTransaction::Transfer { amount, .. } => *amount,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+type TestPool = ValidatingPool<Currency, fn(&CurrencyTransaction) -> u64>;
+
+#[cfg(test)]
+fn validating_pool(best_state: Balances) -> TestPool {
+    ValidatingPool::new(best_state, fee)
+}
+
+#[cfg(test)]
+fn transfer(from: User, to: User, amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer { from, to, amount }
+}
+
+#[test]
+fn cl_4_validating_pool_refuses_invalid_and_duplicates() {
+    let mut pool = validating_pool(Balances::from([(Alice, 100)]));
+    assert_eq!(pool.submit(transfer(Alice, Bob, 60)), Ok(()));
+    assert_eq!(
+        pool.submit(transfer(Alice, Bob, 60)),
+        Err(PoolError::Duplicate)
+    );
+    assert_eq!(
+        pool.submit(transfer(Bob, Alice, 61)),
+        Err(PoolError::Invalid(TransitionError::InsufficientFunds))
+    );
+    assert!(!pool.try_insert(transfer(Alice, Alice, 41)));
+    assert_eq!(pool.size(), 1);
+    assert!(pool.contains(transfer(Alice, Bob, 60)));
+
+    // Bob has nothing yet, but he will once the ready transfer is in.
+    assert_eq!(pool.submit(transfer(Bob, Alice, 1)), Ok(()));
+    assert_eq!(pool.size(), 2);
+}
+
+#[test]
+fn cl_4_ready_orders_by_priority_and_skips_conflicts() {
+    let mut pool = validating_pool(Balances::from([(Alice, 100), (Bob, 20)]));
+    for t in [
+        transfer(Alice, Bob, 30),
+        transfer(Alice, Charlie, 70),
+        transfer(Alice, Bob, 50),
+    ] {
+        assert_eq!(pool.submit(t), Ok(()));
+    }
+    // Once the highest paying transfer is in, Alice can only afford one of the others.
+    assert_eq!(
+        pool.ready(),
+        vec![transfer(Alice, Charlie, 70), transfer(Alice, Bob, 30)]
+    );
+
+    // Once Bob has paid Charlie, he can only pay Alice back after Alice has paid him.
+    let mut pool = validating_pool(Balances::from([(Alice, 100), (Bob, 20)]));
+    for t in [
+        transfer(Bob, Alice, 10),
+        transfer(Bob, Charlie, 20),
+        transfer(Alice, Bob, 10),
+    ] {
+        assert_eq!(pool.submit(t), Ok(()));
+    }
+    let ready = vec![
+        transfer(Bob, Charlie, 20),
+        transfer(Alice, Bob, 10),
+        transfer(Bob, Alice, 10),
+    ];
+    assert_eq!(pool.ready(), ready);
+    for t in ready {
+        assert_eq!(pool.next_from_pool(), Some(t));
+    }
+    assert_eq!(pool.next_from_pool(), None);
+    assert_eq!(pool.size(), 0);
+}
+
+#[test]
+fn cl_4_prune_rechecks_against_new_best_state() {
+    let mut pool = validating_pool(Balances::from([(Alice, 100)]));
+    for t in [
+        transfer(Alice, Bob, 40),
+        transfer(Alice, Charlie, 30),
+        transfer(Alice, Bob, 20),
+    ] {
+        assert_eq!(pool.submit(t), Ok(()));
+    }
+    assert_eq!(pool.next_from_pool(), Some(transfer(Alice, Bob, 40)));
+    assert_eq!(pool.next_from_pool(), Some(transfer(Alice, Charlie, 30)));
+
+    // Someone else's block only included one of the transfers we handed out, and Alice spent
+    // most of the rest of her money in it too.
+    let imported = vec![transfer(Alice, Charlie, 30), transfer(Alice, Bob, 55)];
+    let best_state = imported
+        .iter()
+        .fold(Balances::from([(Alice, 100)]), |state, t| {
+            Currency::next_state(&state, t)
+        });
+    pool.prune(&imported, &best_state);
+
+    assert_eq!(pool.size(), 0);
+    assert_eq!(pool.ready(), vec![]);
+    assert_eq!(pool.submit(transfer(Alice, Bob, 15)), Ok(()));
+    assert_eq!(pool.ready(), vec![transfer(Alice, Bob, 15)]);
+}
+
+#[test]
+fn cl_4_transactions_expire_after_mortality() {
+    let state = Balances::from([(Alice, 100)]);
+    let mut pool = validating_pool(state.clone()).with_mortality(2);
+    assert_eq!(pool.submit(transfer(Alice, Bob, 1)), Ok(()));
+    pool.prune(&[], &state);
+    assert_eq!(pool.submit(transfer(Alice, Bob, 2)), Ok(()));
+
+    // A transaction that was handed out and came back is no younger than it was.
+    assert_eq!(pool.next_from_pool(), Some(transfer(Alice, Bob, 2)));
+    assert_eq!(pool.next_from_pool(), Some(transfer(Alice, Bob, 1)));
+    assert!(pool.try_insert(transfer(Alice, Bob, 1)));
+    pool.prune(&[], &state);
+    assert_eq!(pool.ready(), vec![transfer(Alice, Bob, 2)]);
+
+    pool.prune(&[], &state);
+    assert_eq!(pool.size(), 0);
+
+    // Pools are immortal unless told otherwise.
+    let mut pool = validating_pool(state.clone());
+    assert_eq!(pool.submit(transfer(Alice, Bob, 1)), Ok(()));
+    for _ in 0..100 {
+        pool.prune(&[], &state);
+    }
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn cl_4_repeatedly_invalid_transactions_are_banned() {
+    let state = Balances::from([(Alice, 100)]);
+    let mut pool = validating_pool(state.clone()).with_ban_policy(2, 3);
+    let broke = transfer(Bob, Alice, 5);
+    assert_eq!(
+        pool.submit(broke.clone()),
+        Err(PoolError::Invalid(TransitionError::InsufficientFunds))
+    );
+    assert!(!pool.is_banned(&broke));
+    assert!(!pool.try_insert(broke.clone()));
+    assert!(pool.is_banned(&broke));
+
+    // Even once Bob can afford it, the ban has to run out first.
+    let rich = Balances::from([(Alice, 100), (Bob, 100)]);
+    pool.prune(&[], &rich);
+    pool.prune(&[], &rich);
+    assert_eq!(pool.submit(broke.clone()), Err(PoolError::Banned));
+    pool.prune(&[], &rich);
+    assert!(!pool.is_banned(&broke));
+    assert_eq!(pool.submit(broke), Ok(()));
+}
+
+#[test]
+fn cl_4_strikes_are_bounded_and_forgotten() {
+    let state = Balances::from([(Alice, 100)]);
+    let mut pool = validating_pool(state.clone()).with_ban_policy(2, 3);
+    let broke = |amount| transfer(Bob, Alice, amount);
+    for amount in 1..=MAX_STRUCK as u64 + 1 {
+        assert!(pool.submit(broke(amount)).is_err());
+    }
+    assert_eq!(pool.strikes.len(), MAX_STRUCK);
+    let last = broke(MAX_STRUCK as u64 + 1);
+    assert!(pool.strikes.contains_key(&hash(&last)));
+
+    // Strikes run out along with the ban that they would have led to.
+    for _ in 0..3 {
+        pool.prune(&[], &state);
+    }
+    assert!(pool.strikes.is_empty());
+    assert!(pool.submit(broke(1)).is_err());
+    assert!(!pool.is_banned(&broke(1)));
+}
+
+#[test]
+fn cl_4_handed_out_transactions_are_not_pooled_twice() {
+    let state = Balances::from([(Alice, 100)]);
+    let mut pool = validating_pool(state.clone());
+    assert_eq!(pool.submit(transfer(Alice, Bob, 60)), Ok(()));
+    assert_eq!(pool.next_from_pool(), Some(transfer(Alice, Bob, 60)));
+    assert_eq!(
+        pool.submit(transfer(Alice, Bob, 60)),
+        Err(PoolError::Duplicate)
+    );
+    pool.prune(&[], &state);
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn cl_4_pool_status_counts_ready_future_and_banned() {
+    let mut pool = validating_pool(Balances::from([(Alice, 100)])).with_ban_policy(1, 10);
+    assert_eq!(pool.pool_status(), PoolStatus::default());
+    for t in [
+        transfer(Alice, Bob, 60),
+        transfer(Alice, Charlie, 50),
+        transfer(Alice, Bob, 40),
+    ] {
+        assert_eq!(pool.submit(t), Ok(()));
+    }
+    assert!(pool.submit(transfer(Charlie, Bob, 1)).is_err());
+
+    assert_eq!(
+        pool.pool_status(),
+        PoolStatus {
+            ready: 2,
+            future: 1,
+            banned: 1,
+        }
+    );
+}
+
+#[test]
+fn cl_4_nonces_are_handed_out_in_order() {
+    use crate::c1_state_machine::{SignedCurrency, SignedExtrinsic, SignedState};
+
+    let start = SignedState::new(Balances::from([(Alice, 100)]), &Charlie.public_key());
+    let signed =
+        |nonce| SignedExtrinsic::sign(transfer(Alice, Bob, 10), nonce, &Alice.signing_key());
+    // Later nonces have the higher priority, but can only go in after the earlier ones.
+    let mut pool = ValidatingPool::<SignedCurrency, _>::new(start.clone(), |t| t.nonce);
+    assert_eq!(
+        pool.submit(signed(1)),
+        Err(PoolError::Invalid(TransitionError::Invalid))
+    );
+    for nonce in 0..3 {
+        assert_eq!(pool.submit(signed(nonce)), Ok(()));
+    }
+    assert_eq!(pool.ready(), vec![signed(0), signed(1), signed(2)]);
+    assert_eq!(pool.next_from_pool(), Some(signed(0)));
+    assert_eq!(pool.ready(), vec![signed(1), signed(2)]);
+
+    // Once the first transfer is in a block, it can not be submitted again, and the others
+    // stay ready.
+    let best_state = SignedCurrency::next_state(&start, &signed(0));
+    pool.prune(&[signed(0)], &best_state);
+    assert_eq!(
+        pool.submit(signed(0)),
+        Err(PoolError::Invalid(TransitionError::Invalid))
+    );
+    assert_eq!(pool.ready(), vec![signed(1), signed(2)]);
+
+    // Without the transfer before it, a transfer waits.
+    pool.remove(signed(1));
+    assert_eq!(
+        pool.pool_status(),
+        PoolStatus {
+            ready: 0,
+            future: 1,
+            banned: 0,
+        }
+    );
+}
+
+#[test]
+fn cl_4_pool_orders_by_fee() {
+    use crate::c1_state_machine::{Paid, PaidCurrency, PaidState};
+
+    let paid = |from, to, fee| Paid {
+        call: transfer(from, to, 10),
+        fee,
+        tip: None,
+        nonce: 0,
+    };
+    let start = PaidState::new(Balances::from([(Alice, 100), (Bob, 100), (Charlie, 100)]));
+    let mut pool = ValidatingPool::<PaidCurrency, _>::new(start, by_fee);
+    for t in [
+        paid(Alice, Bob, 1),
+        paid(Charlie, Alice, 5),
+        paid(Bob, Charlie, 3),
+    ] {
+        assert_eq!(pool.submit(t), Ok(()));
+    }
+    assert_eq!(
+        pool.ready(),
+        vec![
+            paid(Charlie, Alice, 5),
+            paid(Bob, Charlie, 3),
+            paid(Alice, Bob, 1)
+        ]
+    );
+}
+
+#[test]
+fn cl_4_higher_tips_replace_and_go_first() {
+    use crate::c1_state_machine::{Paid, PaidCurrency, PaidState};
+
+    let tipped = |to, nonce, tip| Paid {
+        call: transfer(Alice, to, 10),
+        fee: 1,
+        tip: Some(tip),
+        nonce,
+    };
+    let start = PaidState::new(Balances::from([(Alice, 100)]));
+    let mut pool = ValidatingPool::<PaidCurrency, _>::new(start, by_tip);
+    assert_eq!(pool.submit_replacing(tipped(Bob, 0, 2)), Ok(None));
+    assert_eq!(pool.submit_replacing(tipped(Bob, 1, 5)), Ok(None));
+
+    // Replacing a transaction takes a strictly higher tip.
+    assert_eq!(
+        pool.submit_replacing(tipped(Charlie, 0, 2)),
+        Err(PoolError::Underpriced)
+    );
+    assert_eq!(
+        pool.submit_replacing(tipped(Charlie, 0, 3)),
+        Ok(Some(tipped(Bob, 0, 2)))
+    );
+    assert_eq!(
+        pool.submit_replacing(tipped(Charlie, 1, 1_000)),
+        Err(PoolError::Invalid(TransitionError::InsufficientFunds))
+    );
+    // A higher tip can not jump ahead of an earlier nonce.
+    assert_eq!(pool.ready(), vec![tipped(Charlie, 0, 3), tipped(Bob, 1, 5)]);
+}
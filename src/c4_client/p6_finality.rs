@@ -5,14 +5,465 @@
 //! Although we elide the details of the game itself, this model still allows us to explore
 //! the consequences of having some blocks that are never reverted.
 
-use super::FullClient;
+use std::collections::{HashMap, HashSet};
 
-impl<C, SM, FC, P> FullClient<C, SM, FC, P> {
+use super::{BlockStore, ChainEvent, Consensus, ForkChoice, FullClient, Header, StateMachine};
+use crate::c3_consensus::ConsensusAuthority;
+use crate::hash;
+
+type Hash = u64;
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
     /// Mark the given block as final so that it will never be reverted.
     /// Returns whether or not the block was known and marked successfully.
     pub fn manually_finalize_block(&mut self, block_hash: u64) -> bool {
-        todo!("Exercise 1")
+        // todo!("Exercise 1")
+        let newly_finalized = self.fork_tree.finalized() != block_hash;
+        if !self.fork_tree.finalize(block_hash) {
+            return false;
+        }
+        self.store.set_finalized(block_hash);
+        if newly_finalized {
+            let height = self.height_of(block_hash);
+            self.notify(ChainEvent::Finalized {
+                hash: block_hash,
+                height,
+            });
+        }
+        // The best block may have been on a branch that is no longer viable.
+        self.update_best_block();
+        true
+    }
+}
+
+/// A single authority's vote that a particular block should be final.
+///
+/// As in the consensus chapter, the authority itself stands in for a real cryptographic
+/// signature over the block hash.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vote {
+    /// The block being voted for.
+    pub block_hash: Hash,
+    /// The authority casting the vote.
+    pub voter: ConsensusAuthority,
+}
+
+/// Proof that a block is final. It is a set of finality votes for the block, and is
+/// convincing as long as more than two thirds of the authorities cast one of them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Justification {
+    /// The block being justified.
+    pub block_hash: Hash,
+    /// The finality votes for that block.
+    pub votes: Vec<Vote>,
+}
+
+impl Justification {
+    /// Check that this justification proves finality according to the given authority set.
+    ///
+    /// Every vote must be for the justified block and come from a member of the authority set.
+    /// Duplicate votes from the same authority only count once. The justification is valid when
+    /// strictly more than two thirds of the authorities voted.
+    pub fn verify_justification(&self, authorities: &[ConsensusAuthority]) -> bool {
+        let mut voters = HashSet::new();
+        for vote in &self.votes {
+            if vote.block_hash != self.block_hash || !authorities.contains(&vote.voter) {
+                return false;
+            }
+            voters.insert(vote.voter);
+        }
+
+        let authority_count = authorities.iter().collect::<HashSet<_>>().len();
+        voters.len() * 3 > authority_count * 2
+    }
+}
+
+/// A header along with an optional justification that proves it is final.
+///
+/// The justification is not part of the header itself because it can only be created after
+/// the header already exists. Keeping it alongside the header also means that attaching one
+/// later does not change the block hash.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JustifiedHeader<Digest> {
+    pub header: Header<Digest>,
+    pub justification: Option<Justification>,
+}
+
+impl<Digest: std::hash::Hash> JustifiedHeader<Digest> {
+    /// Wrap a header that has not been justified yet.
+    pub fn new(header: Header<Digest>) -> Self {
+        JustifiedHeader {
+            header,
+            justification: None,
+        }
+    }
+
+    /// Attach a justification to this header, replacing any previous one.
+    /// Returns whether the justification was attached. It is refused when it
+    /// justifies some other block.
+    pub fn attach_justification(&mut self, justification: Justification) -> bool {
+        if justification.block_hash != hash(&self.header) {
+            return false;
+        }
+        self.justification = Some(justification);
+        true
+    }
+
+    /// Check that this header has a justification, and that it proves finality according
+    /// to the given authority set.
+    pub fn verify_justification(&self, authorities: &[ConsensusAuthority]) -> bool {
+        match &self.justification {
+            Some(j) => j.block_hash == hash(&self.header) && j.verify_justification(authorities),
+            None => false,
+        }
+    }
+}
+
+/// Tracks finality votes and decides which blocks are final, in the style of GRANDPA.
+///
+/// Unlike a justification, which proves finality of one exact block, a vote here is also a vote
+/// for every ancestor of the block voted for. So authorities do not all need to vote for the
+/// same block. It is enough that more than two thirds of the voting weight has voted for blocks
+/// that share a common ancestor, and that ancestor becomes final.
+pub struct FinalityTracker {
+    /// Each authority together with its voting weight.
+    authorities: Vec<(ConsensusAuthority, u64)>,
+    /// The parent hash and height of every known block.
+    blocks: HashMap<Hash, (Hash, u64)>,
+    /// The latest vote of each authority.
+    votes: HashMap<ConsensusAuthority, Hash>,
+    /// The finalized head. Finality never goes backwards.
+    finalized: Hash,
+}
+
+impl FinalityTracker {
+    /// Start tracking finality from the given genesis header, which is final by definition.
+    pub fn new<Digest: std::hash::Hash>(
+        authorities: Vec<(ConsensusAuthority, u64)>,
+        genesis: &Header<Digest>,
+    ) -> Self {
+        let genesis_hash = hash(genesis);
+        FinalityTracker {
+            authorities,
+            blocks: HashMap::from([(genesis_hash, (genesis.parent, genesis.height))]),
+            votes: HashMap::new(),
+            finalized: genesis_hash,
+        }
+    }
+
+    /// Learn about a new block so that votes for it, or its descendants, can be counted.
+    /// Returns whether the block's parent was known, and so the block was added.
+    pub fn import_header<Digest: std::hash::Hash>(&mut self, header: &Header<Digest>) -> bool {
+        if !self.blocks.contains_key(&header.parent) {
+            return false;
+        }
+        self.blocks
+            .insert(hash(header), (header.parent, header.height));
+        true
+    }
+
+    /// The given block and all of its known ancestors, starting with the block itself.
+    fn ancestry(&self, block_hash: Hash) -> impl Iterator<Item = Hash> + '_ {
+        std::iter::successors(Some(block_hash), |h| {
+            self.blocks
+                .get(h)
+                .map(|(parent, _)| *parent)
+                .filter(|parent| self.blocks.contains_key(parent))
+        })
+    }
+
+    /// Whether `descendant` is `ancestor` itself or one of its descendants.
+    fn is_descendant(&self, ancestor: Hash, descendant: Hash) -> bool {
+        self.ancestry(descendant).any(|h| h == ancestor)
+    }
+
+    /// The voting weight of the given authority, or `None` if it is not an authority.
+    fn weight_of(&self, voter: ConsensusAuthority) -> Option<u64> {
+        self.authorities
+            .iter()
+            .find(|(authority, _)| *authority == voter)
+            .map(|(_, weight)| *weight)
+    }
+
+    /// Count a vote and update the finalized head. Returns whether the vote was accepted.
+    ///
+    /// Votes are refused when they come from outside the authority set, are for unknown blocks,
+    /// or conflict with the voter's previous vote. A new vote may only move the voter's vote
+    /// further along the same chain. Voting for a block on a different fork is an equivocation.
+    pub fn import_vote(&mut self, vote: Vote) -> bool {
+        if self.weight_of(vote.voter).is_none() || !self.blocks.contains_key(&vote.block_hash) {
+            return false;
+        }
+        if let Some(&previous) = self.votes.get(&vote.voter) {
+            if !self.is_descendant(previous, vote.block_hash) {
+                return false;
+            }
+        }
+        self.votes.insert(vote.voter, vote.block_hash);
+
+        if let Some(new_head) = self.best_supermajority_block() {
+            if self.is_descendant(self.finalized, new_head) {
+                self.finalized = new_head;
+            }
+        }
+        true
+    }
+
+    /// The highest block that more than two thirds of the voting weight has voted for,
+    /// either directly or by voting for one of its descendants.
+    fn best_supermajority_block(&self) -> Option<Hash> {
+        let total_weight: u64 = self.authorities.iter().map(|(_, weight)| weight).sum();
+        let mut support = HashMap::<Hash, u64>::new();
+        for (voter, block_hash) in &self.votes {
+            let weight = self.weight_of(*voter).unwrap_or_default();
+            for ancestor in self.ancestry(*block_hash) {
+                *support.entry(ancestor).or_default() += weight;
+            }
+        }
+
+        support
+            .into_iter()
+            .filter(|(_, weight)| *weight as u128 * 3 > total_weight as u128 * 2)
+            .max_by_key(|(block_hash, _)| self.blocks[block_hash].1)
+            .map(|(block_hash, _)| block_hash)
+    }
+
+    /// The most recent finalized block.
+    pub fn finalized_head(&self) -> Hash {
+        self.finalized
+    }
+}
+
+#[cfg(test)]
+fn votes_for(block_hash: Hash, voters: &[ConsensusAuthority]) -> Justification {
+    Justification {
+        block_hash,
+        votes: voters.iter().map(|&voter| Vote { block_hash, voter }).collect(),
+    }
+}
+
+#[cfg(test)]
+const ALL_AUTHORITIES: [ConsensusAuthority; 3] = [
+    ConsensusAuthority::Alice,
+    ConsensusAuthority::Bob,
+    ConsensusAuthority::Charlie,
+];
+
+#[test]
+fn cl_6_justification_with_supermajority() {
+    use ConsensusAuthority::*;
+
+    assert!(votes_for(7, &[Alice, Bob, Charlie]).verify_justification(&ALL_AUTHORITIES));
+    // Exactly two thirds is not enough.
+    assert!(!votes_for(7, &[Alice, Bob]).verify_justification(&ALL_AUTHORITIES));
+    assert!(votes_for(7, &[Alice, Bob]).verify_justification(&[Alice, Bob]));
+}
+
+#[test]
+fn cl_6_duplicate_votes_count_once() {
+    use ConsensusAuthority::*;
+
+    let j = votes_for(7, &[Alice, Alice, Bob, Bob]);
+    assert!(!j.verify_justification(&ALL_AUTHORITIES));
+}
+
+#[test]
+fn cl_6_justification_with_foreign_or_mismatched_vote() {
+    use ConsensusAuthority::*;
+
+    assert!(!votes_for(7, &[Alice, Bob, Charlie]).verify_justification(&[Alice, Bob]));
+
+    let mut j = votes_for(7, &[Alice, Bob, Charlie]);
+    j.votes[2].block_hash = 8;
+    assert!(!j.verify_justification(&ALL_AUTHORITIES));
+}
+
+#[test]
+fn cl_6_attach_justification_to_header() {
+    use ConsensusAuthority::*;
+
+    let header = Header::<()> {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    };
+    let mut justified = JustifiedHeader::new(header.clone());
+    assert!(!justified.verify_justification(&ALL_AUTHORITIES));
+
+    assert!(!justified.attach_justification(votes_for(hash(&header) + 1, &ALL_AUTHORITIES)));
+    assert!(justified.attach_justification(votes_for(hash(&header), &[Alice, Bob, Charlie])));
+    assert!(justified.verify_justification(&ALL_AUTHORITIES));
+}
+#[cfg(test)]
+fn genesis() -> Header<()> {
+    Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[cfg(test)]
+fn header_on(parent: &Header<()>, fork: u64) -> Header<()> {
+    Header {
+        parent: hash(parent),
+        height: parent.height + 1,
+        state_root: fork,
+        extrinsics_root: 0,
+        uncles: Vec::new(),
+        consensus_digest: (),
+    }
+}
+
+#[cfg(test)]
+fn vote_for(header: &Header<()>, voter: ConsensusAuthority) -> Vote {
+    Vote {
+        block_hash: hash(header),
+        voter,
+    }
+}
+
+#[cfg(test)]
+fn equal_weights() -> Vec<(ConsensusAuthority, u64)> {
+    ALL_AUTHORITIES.iter().map(|&a| (a, 1)).collect()
+}
+
+#[test]
+fn cl_6_tracker_finalizes_common_ancestor() {
+    use ConsensusAuthority::*;
+
+    //       /-- a2 -- a3
+    // g -- 1
+    //       \-- b2
+    let g = genesis();
+    let b1 = header_on(&g, 0);
+    let a2 = header_on(&b1, 0);
+    let a3 = header_on(&a2, 0);
+    let b2 = header_on(&b1, 1);
+
+    let mut tracker = FinalityTracker::new(equal_weights(), &g);
+    for header in [&b1, &a2, &a3, &b2] {
+        assert!(tracker.import_header(header));
+    }
+    assert_eq!(tracker.finalized_head(), hash(&g));
+
+    // Votes on conflicting forks still agree on their common ancestor.
+    assert!(tracker.import_vote(vote_for(&a3, Alice)));
+    assert!(tracker.import_vote(vote_for(&a2, Bob)));
+    assert_eq!(tracker.finalized_head(), hash(&g));
+    assert!(tracker.import_vote(vote_for(&b2, Charlie)));
+    assert_eq!(tracker.finalized_head(), hash(&b1));
+
+    // Charlie can not switch forks after voting.
+    assert!(!tracker.import_vote(vote_for(&a3, Charlie)));
+    assert_eq!(tracker.finalized_head(), hash(&b1));
+}
+
+#[test]
+fn cl_6_tracker_progresses_along_a_chain() {
+    use ConsensusAuthority::*;
+
+    let g = genesis();
+    let b1 = header_on(&g, 0);
+    let b2 = header_on(&b1, 0);
+    let mut tracker = FinalityTracker::new(equal_weights(), &g);
+    tracker.import_header(&b1);
+    tracker.import_header(&b2);
+
+    for voter in ALL_AUTHORITIES {
+        assert!(tracker.import_vote(vote_for(&b1, voter)));
     }
+    assert_eq!(tracker.finalized_head(), hash(&b1));
+
+    // Moving votes further along the same chain is fine.
+    assert!(tracker.import_vote(vote_for(&b2, Alice)));
+    assert!(tracker.import_vote(vote_for(&b2, Bob)));
+    assert_eq!(tracker.finalized_head(), hash(&b1));
+    assert!(tracker.import_vote(vote_for(&b2, Charlie)));
+    assert_eq!(tracker.finalized_head(), hash(&b2));
+}
+
+#[test]
+fn cl_6_tracker_counts_voting_weight() {
+    use ConsensusAuthority::*;
+
+    let g = genesis();
+    let b1 = header_on(&g, 0);
+    let mut tracker = FinalityTracker::new(vec![(Alice, 5), (Bob, 1), (Charlie, 1)], &g);
+    tracker.import_header(&b1);
+
+    // Alice alone holds more than two thirds of the weight.
+    assert!(tracker.import_vote(vote_for(&b1, Alice)));
+    assert_eq!(tracker.finalized_head(), hash(&b1));
+}
+
+#[test]
+fn cl_6_tracker_refuses_bad_votes() {
+    use ConsensusAuthority::*;
+
+    let g = genesis();
+    let b1 = header_on(&g, 0);
+    let orphan = header_on(&b1, 0);
+    let mut tracker = FinalityTracker::new(vec![(Alice, 1), (Bob, 1)], &g);
+
+    // The parent of this header is not known yet.
+    assert!(!tracker.import_header(&orphan));
+    assert!(!tracker.import_vote(vote_for(&orphan, Alice)));
+
+    tracker.import_header(&b1);
+    assert!(!tracker.import_vote(vote_for(&b1, Charlie)));
+    assert_eq!(tracker.finalized_head(), hash(&g));
 }
 
-//TODO tests
\ No newline at end of file
+#[test]
+fn cl_6_manual_finality_rejects_conflicting_blocks() {
+    use super::{ImportBlock, ImportError, LongestChain};
+    use crate::c1_state_machine::LightSwitch;
+
+    let mut client = FullClient::<(), LightSwitch, LongestChain, ()>::new(
+        (),
+        LightSwitch,
+        LongestChain::default(),
+        (),
+        false,
+    );
+    let g = client.get_block(client.genesis_hash).unwrap();
+
+    // G -- A1 -- A2
+    //  \-- B1 -- B2 -- B3
+    let a1 = g.child(&(), &false, vec![]).unwrap();
+    let a2 = a1.child(&(), &false, vec![]).unwrap();
+    let b1 = g.child(&(), &false, vec![()]).unwrap();
+    let b2 = b1.child(&(), &true, vec![]).unwrap();
+    let b3 = b2.child(&(), &true, vec![]).unwrap();
+
+    assert_eq!(client.try_import_block(a1.clone()), Ok(a1.hash()));
+    assert_eq!(client.try_import_block(b1.clone()), Ok(b1.hash()));
+    assert!(client.manually_finalize_block(a1.hash()));
+    assert!(!client.manually_finalize_block(b1.hash()));
+
+    // The B branch was already known, but it can not grow any more.
+    assert_eq!(client.try_import_block(b2), Err(ImportError::ConflictsWithFinality));
+    assert_eq!(client.try_import_block(a2.clone()), Ok(a2.hash()));
+    assert_eq!(client.best_block(), a2.hash());
+
+    // Even a longer conflicting branch does not win, because it can not be imported.
+    assert_eq!(client.try_import_block(b3), Err(ImportError::UnknownParent));
+    assert_eq!(client.best_block(), a2.hash());
+}
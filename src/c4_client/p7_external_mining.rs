@@ -0,0 +1,280 @@
+//! Real Proof of Work nodes rarely mine inside the node itself. Mining is done by separate
+//! programs, often on specialized hardware, that ask the node for work and hand back seals.
+//! This is the idea behind Bitcoin's `getblocktemplate` and Ethereum's `eth_getWork` and
+//! `eth_submitWork`.
+//!
+//! The miner never needs to see the block. All it needs is the pre-seal hash and the
+//! threshold. The node keeps the rest of the block to itself, and only assembles the
+//! complete block once a seal comes back.
+//!
+//! Here the node and miner are connected by channels, so the miner can live on another
+//! thread. Replacing the channels with a network connection would let it live in
+//! another process entirely.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::p2_importing_blocks::ImportBlock;
+use super::{Block, BlockStore, ForkChoice, FullClient, Header, StateMachine};
+use crate::c1_state_machine::BlockContext;
+use crate::c3_consensus::{work_hash, Pow};
+
+type Hash = u64;
+
+/// Everything a miner needs in order to search for a seal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkPackage {
+    /// Identifies this package when the seal is submitted.
+    pub id: u64,
+    /// The pre-seal hash of the block being mined.
+    pub pre_hash: Hash,
+    /// The largest work hash the node will accept.
+    pub threshold: u64,
+}
+
+impl WorkPackage {
+    /// Whether the given nonce is a valid seal for this package.
+    pub fn is_solved_by(&self, nonce: u64) -> bool {
+        work_hash(self.pre_hash, nonce) <= self.threshold
+    }
+
+    /// Search for a valid nonce, starting from zero.
+    pub fn solve(&self) -> Option<u64> {
+        (0..=u64::MAX).find(|nonce| self.is_solved_by(*nonce))
+    }
+}
+
+/// A seal submitted by a miner for a previously issued work package.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Seal {
+    /// The id of the package that was solved.
+    pub package_id: u64,
+    /// The nonce that solves it.
+    pub nonce: u64,
+}
+
+/// A block waiting for its seal: the pre-seal header, the body, and the context that the body
+/// was executed in.
+type PendingBlock<SM> = (
+    Header<()>,
+    Vec<<SM as StateMachine>::Transition>,
+    BlockContext,
+);
+
+/// The node's end of the work protocol. It hands out work packages and remembers the
+/// blocks they belong to until a seal comes back.
+pub struct WorkServer<SM: StateMachine> {
+    /// The id that the next work package will get.
+    next_id: u64,
+    /// Blocks waiting for a seal, keyed by work package id.
+    pending: HashMap<u64, PendingBlock<SM>>,
+    work: Sender<WorkPackage>,
+    seals: Receiver<Seal>,
+}
+
+/// The miner's end of the work protocol.
+pub struct MinerHandle {
+    work: Receiver<WorkPackage>,
+    seals: Sender<Seal>,
+}
+
+/// Create the two connected ends of the work protocol.
+pub fn work_channel<SM: StateMachine>() -> (WorkServer<SM>, MinerHandle) {
+    let (work_sender, work_receiver) = channel();
+    let (seal_sender, seal_receiver) = channel();
+    let server = WorkServer {
+        next_id: 0,
+        pending: HashMap::new(),
+        work: work_sender,
+        seals: seal_receiver,
+    };
+    let miner = MinerHandle {
+        work: work_receiver,
+        seals: seal_sender,
+    };
+    (server, miner)
+}
+
+impl<SM: StateMachine> WorkServer<SM> {
+    /// Send a work package for the given pre-seal header to the miner, and remember the
+    /// block until it is sealed. Returns the package id, or `None` if the miner has hung up.
+    fn push_work(
+        &mut self,
+        pre_header: Header<()>,
+        body: Vec<SM::Transition>,
+        context: BlockContext,
+        threshold: u64,
+    ) -> Option<u64> {
+        let id = self.next_id;
+        let package = WorkPackage {
+            id,
+            pre_hash: pre_header.pre_hash(),
+            threshold,
+        };
+        self.work.send(package).ok()?;
+        self.next_id += 1;
+        self.pending.insert(id, (pre_header, body, context));
+        Some(id)
+    }
+
+    /// Wait for the miner to submit a seal. Returns `None` once the miner has hung up.
+    pub fn recv_seal(&self) -> Option<Seal> {
+        self.seals.recv().ok()
+    }
+
+    /// Return a seal if the miner has submitted one, without waiting.
+    pub fn try_recv_seal(&self) -> Option<Seal> {
+        self.seals.try_recv().ok()
+    }
+
+    /// The number of work packages that have not been sealed yet.
+    pub fn pending_work(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl MinerHandle {
+    /// Wait for the next work package. Returns `None` once the node has hung up.
+    pub fn get_work(&self) -> Option<WorkPackage> {
+        self.work.recv().ok()
+    }
+
+    /// Submit a seal for the given work package. Returns whether the node was still there
+    /// to receive it. It does not tell whether the seal is valid.
+    pub fn submit_seal(&self, package_id: u64, nonce: u64) -> bool {
+        self.seals.send(Seal { package_id, nonce }).is_ok()
+    }
+}
+
+impl<SM, FC, P, S> FullClient<Pow, SM, FC, P, S>
+where
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    S: BlockStore<Pow, SM>,
+{
+    /// Build a block with the given extrinsics on top of the given parent, and send it to the
+    /// miner as a work package. Returns the package id, or `None` if the parent is unknown or
+    /// the miner has hung up.
+    pub fn publish_work(
+        &self,
+        server: &mut WorkServer<SM>,
+        parent_hash: Hash,
+        extrinsics: Vec<SM::Transition>,
+    ) -> Option<u64> {
+        let parent = self.store.block(parent_hash)?;
+        let pre_state = self.states.get(&parent_hash)?;
+        let context = parent.child_context(parent.context.timestamp, None);
+        let pre_header = parent.partial_child(pre_state, &extrinsics, &context);
+        let threshold = self.consensus_engine.threshold();
+        server.push_work(pre_header, extrinsics, context, threshold)
+    }
+
+    /// Complete the block that the given seal belongs to and import it. Returns the hash of
+    /// the new block, or `None` if the package is unknown or the block could not be imported.
+    ///
+    /// A package stays pending after an invalid seal, so the miner may still submit a good one.
+    pub fn import_seal(&mut self, server: &mut WorkServer<SM>, seal: Seal) -> Option<Hash> {
+        let (pre_header, body, context) = server.pending.get(&seal.package_id)?;
+        let block = Block {
+            header: pre_header.clone().seal(seal.nonce),
+            body: body.clone(),
+            context: *context,
+        };
+        let block_hash = block.hash();
+        if !self.import_block(block) {
+            return None;
+        }
+        server.pending.remove(&seal.package_id);
+        self.metrics.authored_blocks += 1;
+        Some(block_hash)
+    }
+}
+
+#[cfg(test)]
+type TestClient = FullClient<Pow, crate::c1_state_machine::LightSwitch, (), ()>;
+
+#[cfg(test)]
+fn test_client() -> TestClient {
+    TestClient::new(
+        Pow::with_difficulty(50),
+        crate::c1_state_machine::LightSwitch,
+        (),
+        (),
+        false,
+    )
+}
+
+#[test]
+fn cl_7_miner_thread_seals_published_work() {
+    let mut client = test_client();
+    let (mut server, miner) = work_channel();
+
+    let miner = std::thread::spawn(move || {
+        while let Some(package) = miner.get_work() {
+            let nonce = package.solve().unwrap();
+            miner.submit_seal(package.id, nonce);
+        }
+    });
+
+    let mut parent = client.genesis_hash;
+    for _ in 0..3 {
+        client.publish_work(&mut server, parent, vec![()]).unwrap();
+        let seal = server.recv_seal().unwrap();
+        parent = client.import_seal(&mut server, seal).unwrap();
+    }
+    assert_eq!(server.pending_work(), 0);
+    assert_eq!(client.get_block(parent).unwrap().header.height, 3);
+    assert_eq!(client.get_state(parent), Some(true));
+
+    // Hanging up the node stops the miner.
+    drop(server);
+    miner.join().unwrap();
+}
+
+#[test]
+fn cl_7_invalid_seal_is_rejected() {
+    let mut client = test_client();
+    let (mut server, miner) = work_channel();
+
+    let id = client
+        .publish_work(&mut server, client.genesis_hash, vec![()])
+        .unwrap();
+    let package = miner.get_work().unwrap();
+    let bad_nonce = (0..).find(|n| !package.is_solved_by(*n)).unwrap();
+
+    miner.submit_seal(id, bad_nonce);
+    let seal = server.recv_seal().unwrap();
+    assert_eq!(client.import_seal(&mut server, seal), None);
+    assert_eq!(server.pending_work(), 1);
+
+    // The package is still open for a valid seal.
+    miner.submit_seal(id, package.solve().unwrap());
+    let seal = server.recv_seal().unwrap();
+    assert!(client.import_seal(&mut server, seal).is_some());
+    assert_eq!(server.pending_work(), 0);
+}
+
+#[test]
+fn cl_7_seal_for_unknown_package_is_rejected() {
+    let mut client = test_client();
+    let (mut server, miner) = work_channel();
+
+    client
+        .publish_work(&mut server, client.genesis_hash, vec![])
+        .unwrap();
+    let package = miner.get_work().unwrap();
+
+    miner.submit_seal(package.id + 1, package.solve().unwrap());
+    let seal = server.try_recv_seal().unwrap();
+    assert_eq!(client.import_seal(&mut server, seal), None);
+}
+
+#[test]
+fn cl_7_publish_work_on_unknown_parent_fails() {
+    let client = test_client();
+    let (mut server, _miner) = work_channel();
+
+    assert_eq!(client.publish_work(&mut server, 12345, vec![()]), None);
+}
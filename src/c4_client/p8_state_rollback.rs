@@ -0,0 +1,360 @@
+//! Our client keeps the post-state of every block it imports. That makes it easy to look up
+//! any state, but real states are far too large to copy for every block. Real clients keep
+//! one working copy of the state at the tip of the best chain, and change it in place as new
+//! blocks arrive.
+//!
+//! The trouble comes when the best chain switches branches. The working copy must first be
+//! rolled back to the common ancestor, and only then can the blocks of the new branch be
+//! applied. Re-executing the whole chain from genesis would work, but it gets slower as the
+//! chain gets longer. Instead, we keep an undo token for every extrinsic on the best chain, and
+//! use them to walk the state back one block at a time.
+//!
+//! Not every state machine can undo its transitions. For those, the client keeps its own
+//! working copy of the best state, and rolls it back by starting again from the state of the
+//! common ancestor. If that state has been pruned, the client goes back further, to the
+//! nearest ancestor whose state it still has, and re-executes the blocks from there.
+
+use std::collections::HashMap;
+
+use super::p1_data_structure::execute;
+use super::{BlockStore, ForkChoice, FullClient};
+use crate::c1_state_machine::{StateMachine, UndoableStateMachine};
+use crate::c3_consensus::Consensus;
+
+type Hash = u64;
+
+/// A single working copy of the state that follows the client's best chain.
+///
+/// This lives outside the client because only undoable state machines can be followed this
+/// way. The client moves it to the current best block with `FullClient::roll_to_best`.
+pub struct BestState<SM: UndoableStateMachine> {
+    /// The block whose post-state this is.
+    best: Hash,
+    /// The post-state of the best block.
+    state: SM::State,
+    /// The undo tokens of every extrinsic in every block between the finalized block and the
+    /// best block, in the order the extrinsics were applied.
+    undo: HashMap<Hash, Vec<SM::Undo>>,
+}
+
+impl<SM: UndoableStateMachine> BestState<SM> {
+    /// The block that this state belongs to.
+    pub fn best_block(&self) -> Hash {
+        self.best
+    }
+
+    /// The post-state of the best block.
+    pub fn state(&self) -> &SM::State {
+        &self.state
+    }
+
+    /// The number of blocks for which undo data is being kept.
+    pub fn undoable_blocks(&self) -> usize {
+        self.undo.len()
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: UndoableStateMachine,
+    SM::State: Clone,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// A working copy of the state, positioned at the genesis block.
+    pub fn genesis_best_state(&self) -> BestState<SM> {
+        BestState {
+            best: self.genesis_hash,
+            state: self.states[&self.genesis_hash].clone(),
+            undo: HashMap::new(),
+        }
+    }
+
+    /// Move the given working copy of the state to the current best block. Blocks that have
+    /// left the best chain are undone, newest first, and then the blocks that have joined it
+    /// are applied, oldest first. Returns the number of blocks that were undone.
+    ///
+    /// Blocks at or below the finalized block can never be undone, so their undo data is
+    /// thrown away.
+    pub fn roll_to_best(&self, best_state: &mut BestState<SM>) -> usize {
+        let new_best = self.best_block();
+        let common_ancestor = self
+            .fork_tree
+            .common_ancestor(best_state.best, new_best)
+            .expect("the working copy only ever follows blocks in the fork tree");
+
+        let retracted = self
+            .fork_tree
+            .path_back_to(best_state.best, common_ancestor);
+        for hash in &retracted {
+            let undos = best_state
+                .undo
+                .remove(hash)
+                .expect("finalized blocks are never retracted");
+            for undo in undos.into_iter().rev() {
+                SM::undo(&mut best_state.state, undo);
+            }
+        }
+
+        let mut enacted = self.fork_tree.path_back_to(new_best, common_ancestor);
+        enacted.reverse();
+        for hash in enacted {
+            let block = self
+                .store
+                .block(hash)
+                .expect("every block in the fork tree is stored");
+            let undos = block
+                .body
+                .iter()
+                .map(|t| SM::apply(&mut best_state.state, t, &block.context))
+                .collect();
+            best_state.undo.insert(hash, undos);
+        }
+        best_state.best = new_best;
+
+        let finalized_height = self
+            .fork_tree
+            .get(self.fork_tree.finalized())
+            .map(|n| n.height);
+        best_state
+            .undo
+            .retain(|hash, _| self.fork_tree.get(*hash).map(|n| n.height) > finalized_height);
+
+        retracted.len()
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: Clone,
+    S: BlockStore<C, SM>,
+{
+    /// The post-state of the best block.
+    pub fn best_state(&self) -> &SM::State {
+        &self.best_state
+    }
+
+    /// Move the client's working copy of the best state from the old best block to the new
+    /// one. It is rolled back to the common ancestor, unless that is the old best block, and
+    /// then the extrinsics of the enacted blocks are applied, oldest first.
+    pub(crate) fn move_best_state(
+        &mut self,
+        old_best: Hash,
+        common_ancestor: Hash,
+        enacted: &[Hash],
+    ) {
+        if common_ancestor != old_best {
+            self.best_state = self.reexecute_state(common_ancestor);
+        }
+        for hash in enacted {
+            let block = self
+                .store
+                .block(*hash)
+                .expect("every block in the fork tree is stored");
+            self.best_state = execute::<SM>(&self.best_state, &block.body, &block.context);
+        }
+    }
+
+    /// The post-state of the given block, which must be in the fork tree. If it has been
+    /// pruned, it is recomputed from the nearest ancestor whose state is still kept. The
+    /// genesis state is never pruned, so there always is one.
+    fn reexecute_state(&self, block_hash: Hash) -> SM::State {
+        let mut missing = Vec::new();
+        let mut current = block_hash;
+        while !self.states.contains_key(&current) {
+            missing.push(current);
+            current = self
+                .fork_tree
+                .get(current)
+                .expect("only blocks in the fork tree are re-executed")
+                .parent;
+        }
+
+        missing
+            .into_iter()
+            .rev()
+            .fold(self.states[&current].clone(), |state, hash| {
+                let block = self
+                    .store
+                    .block(hash)
+                    .expect("every block in the fork tree is stored");
+                execute::<SM>(&state, &block.body, &block.context)
+            })
+    }
+}
+
+#[cfg(test)]
+use super::{Block, ImportBlock, LongestChain, Pruning, TieBreak};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, (), ()>;
+
+/// Build a branch of the given length on top of the given block, in which every block pays the
+/// given amount from Alice to Bob.
+#[cfg(test)]
+fn branch<FC: ForkChoice>(
+    client: &FullClient<(), Currency, FC, ()>,
+    parent: Hash,
+    length: usize,
+    amount: u64,
+) -> Vec<Block<(), Currency>> {
+    let mut blocks = Vec::new();
+    let mut parent = client.get_block(parent).unwrap();
+    let mut state = client.get_state(parent.hash()).unwrap();
+    for _ in 0..length {
+        let pay = CurrencyTransaction::Transfer {
+            from: Alice,
+            to: Bob,
+            amount,
+        };
+        let block = parent.child(&(), &state, vec![pay.clone()]).unwrap();
+        state = Currency::next_state(&state, &pay);
+        blocks.push(block.clone());
+        parent = block;
+    }
+    blocks
+}
+
+#[test]
+fn cl_8_best_state_follows_chain() {
+    let mut client = TestClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    let mut best_state = client.genesis_best_state();
+
+    for block in branch(&client, client.genesis_hash, 3, 10) {
+        assert!(client.import_block(block));
+    }
+    assert_eq!(client.roll_to_best(&mut best_state), 0);
+    assert_eq!(best_state.best_block(), client.best_block());
+    assert_eq!(
+        best_state.state(),
+        &Balances::from([(Alice, 70), (Bob, 30)])
+    );
+    assert_eq!(best_state.undoable_blocks(), 3);
+}
+
+#[test]
+fn cl_8_deep_reorg_rolls_back_to_common_ancestor() {
+    let mut client = TestClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    let mut best_state = client.genesis_best_state();
+
+    let trunk = branch(&client, client.genesis_hash, 2, 1);
+    for block in trunk.clone() {
+        assert!(client.import_block(block));
+    }
+    let fork_point = trunk[1].hash();
+    let short = branch(&client, fork_point, 5, 2);
+    for block in short {
+        assert!(client.import_block(block));
+    }
+    assert_eq!(client.roll_to_best(&mut best_state), 0);
+    assert_eq!(
+        best_state.state(),
+        &Balances::from([(Alice, 88), (Bob, 12)])
+    );
+
+    // A longer branch from the same fork point takes over.
+    let long = branch(&client, fork_point, 6, 3);
+    for block in long.clone() {
+        assert!(client.import_block(block));
+    }
+    assert_eq!(client.best_block(), long[5].hash());
+    assert_eq!(client.roll_to_best(&mut best_state), 5);
+    assert_eq!(best_state.best_block(), long[5].hash());
+    assert_eq!(
+        best_state.state(),
+        &Balances::from([(Alice, 80), (Bob, 20)])
+    );
+    assert_eq!(
+        Some(best_state.state().clone()),
+        client.get_state(long[5].hash())
+    );
+    assert_eq!(best_state.undoable_blocks(), 8);
+}
+
+#[test]
+fn cl_8_finalized_blocks_drop_undo_data() {
+    let mut client = TestClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    let mut best_state = client.genesis_best_state();
+
+    let chain = branch(&client, client.genesis_hash, 4, 5);
+    for block in chain.clone() {
+        assert!(client.import_block(block));
+    }
+    assert!(client.manually_finalize_block(chain[2].hash()));
+    client.roll_to_best(&mut best_state);
+    assert_eq!(best_state.undoable_blocks(), 1);
+    assert_eq!(
+        best_state.state(),
+        &Balances::from([(Alice, 80), (Bob, 20)])
+    );
+}
+
+#[test]
+fn cl_8_client_best_state_follows_deep_reorg() {
+    let mut client = TestClient::new((), Currency, (), (), Balances::from([(Alice, 100)]));
+    assert_eq!(client.best_state(), &Balances::from([(Alice, 100)]));
+
+    let trunk = branch(&client, client.genesis_hash, 2, 1);
+    for block in trunk.clone() {
+        assert!(client.import_block(block));
+    }
+    let short = branch(&client, trunk[1].hash(), 8, 2);
+    let long = branch(&client, trunk[1].hash(), 9, 3);
+    for block in short {
+        assert!(client.import_block(block));
+    }
+    assert_eq!(
+        client.best_state(),
+        &Balances::from([(Alice, 82), (Bob, 18)])
+    );
+
+    // The long branch only takes over with its last block, retracting eight blocks at once.
+    for block in long.clone() {
+        assert!(client.import_block(block));
+    }
+    assert_eq!(client.best_block(), long[8].hash());
+    assert_eq!(
+        client.best_state(),
+        &Balances::from([(Alice, 71), (Bob, 29)])
+    );
+    assert_eq!(
+        Some(client.best_state().clone()),
+        client.get_state(long[8].hash())
+    );
+}
+
+#[test]
+fn cl_8_client_best_state_reexecutes_pruned_ancestor() {
+    let first_seen = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis_state = Balances::from([(Alice, 100)]);
+    let mut client = FullClient::new((), Currency, first_seen, (), genesis_state)
+        .with_pruning(Pruning::KeepRecent(2));
+    let trunk = branch(&client, client.genesis_hash, 1, 1);
+    let fork_point = trunk[0].hash();
+    assert!(client.import_block(trunk[0].clone()));
+
+    // Both branches grow side by side, so each new block's parent is recent when it arrives.
+    // The main branch is always seen first, so it stays best until the fork pulls ahead.
+    let main = branch(&client, fork_point, 5, 2);
+    let fork = branch(&client, fork_point, 6, 3);
+    for (main_block, fork_block) in main.clone().into_iter().zip(fork.clone()) {
+        assert!(client.import_block(main_block));
+        assert!(client.import_block(fork_block));
+    }
+    assert_eq!(client.best_block(), main[4].hash());
+    assert!(client.get_state(fork_point).is_none());
+    assert!(client.import_block(fork[5].clone()));
+
+    assert_eq!(client.best_block(), fork[5].hash());
+    assert_eq!(
+        client.best_state(),
+        &Balances::from([(Alice, 81), (Bob, 19)])
+    );
+}
@@ -0,0 +1,174 @@
+//! Each of the previous sections gave the client one more ability. In this final section we
+//! use them together, the way a node does in practice. The client imports blocks from the
+//! network, follows the best chain that its fork choice rule picks, answers questions about
+//! the state at any block, and authors blocks of its own on top of the best one.
+//!
+//! Users rarely know the hash of the block they are interested in. More often they ask for
+//! "the state at block 100", meaning block 100 of the chain that the client currently
+//! considers best. So the client looks blocks up by `BlockId`, and resolves numbers along its
+//! best chain.
+
+use super::{BlockStore, Error, ForkChoice, FullClient, Header, ImportBlock};
+use crate::c1_state_machine::StateMachine;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    S: BlockStore<C, SM>,
+{
+    /// The header of the best block currently known to the client.
+    pub fn best_header(&self) -> Header<C::Digest> {
+        self.store
+            .header(BlockId::Hash(self.best_block()))
+            .expect("the best block is always stored")
+    }
+
+    /// Find the hash of the given block. Numbers are resolved along the best chain. Returns
+    /// None if the block is not known.
+    pub fn resolve(&self, id: BlockId) -> Option<Hash> {
+        self.store.header(id).map(|header| hash(&header))
+    }
+
+    /// The post-state of the given block.
+    pub fn state_at(&self, id: BlockId) -> Result<SM::State, Error> {
+        let block_hash = self.resolve(id).ok_or(Error::UnknownBlock)?;
+        self.get_state(block_hash).ok_or(Error::StatePruned)
+    }
+
+    /// Author a new block with the given extrinsics on top of the best block, and import it.
+    /// Every valid uncle that the client knows of is referenced, so that its author is
+    /// rewarded for its work.
+    ///
+    /// Returns the hash of the new block, or None if the consensus engine is unable to seal it.
+    pub fn author_block(&mut self, extrinsics: Vec<SM::Transition>) -> Option<Hash> {
+        let parent_hash = self.best_block();
+        let parent = self.store.block(parent_hash)?;
+        let pre_state = &self.states[&parent_hash];
+        let uncles = self.uncle_candidates(parent_hash);
+        let block =
+            parent.child_with_uncles(&self.consensus_engine, pre_state, extrinsics, uncles)?;
+        let block_hash = self.try_import_block(block).ok()?;
+        self.metrics.authored_blocks += 1;
+        Some(block_hash)
+    }
+}
+
+#[cfg(test)]
+use super::LongestChain;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User::*};
+#[cfg(test)]
+use crate::c3_consensus::{ConsensusAuthority, SimplePoa};
+
+#[cfg(test)]
+type TestClient = FullClient<SimplePoa, Currency, LongestChain, ()>;
+
+#[cfg(test)]
+fn client() -> TestClient {
+    let poa = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+    FullClient::new(
+        poa,
+        Currency,
+        LongestChain::default(),
+        (),
+        Balances::from([(Alice, 100)]),
+    )
+}
+
+#[cfg(test)]
+fn pay(amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: Alice,
+        to: Bob,
+        amount,
+    }
+}
+
+#[test]
+fn cl_9_authored_blocks_extend_best_chain() {
+    let mut client = client();
+    let genesis = client.genesis_hash();
+    assert_eq!(client.best_header().height, 0);
+
+    let b1 = client.author_block(vec![pay(10)]).unwrap();
+    let b2 = client.author_block(vec![pay(5), pay(5)]).unwrap();
+    assert_eq!(client.best_block(), b2);
+    assert_eq!(client.best_header().height, 2);
+    assert_eq!(client.best_header().parent, b1);
+
+    assert_eq!(client.resolve(BlockId::Number(0)), Some(genesis));
+    assert_eq!(
+        client.state_at(BlockId::Number(1)),
+        Ok(Balances::from([(Alice, 90), (Bob, 10)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Hash(b2)),
+        Ok(Balances::from([(Alice, 80), (Bob, 20)]))
+    );
+    assert_eq!(
+        client.state_at(BlockId::Number(3)),
+        Err(Error::UnknownBlock)
+    );
+    assert_eq!(client.state_at(BlockId::Hash(0)), Err(Error::UnknownBlock));
+}
+
+#[test]
+fn cl_9_numbers_follow_best_chain() {
+    let mut client = client();
+    let short = client.author_block(vec![pay(1)]).unwrap();
+
+    // Someone else builds a longer fork from genesis, which the client imports.
+    let genesis = client.get_block(client.genesis_hash()).unwrap();
+    let genesis_state = client.get_state(client.genesis_hash()).unwrap();
+    let poa = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+    let f1 = genesis.child(&poa, &genesis_state, vec![pay(50)]).unwrap();
+    let f1_state = Currency::next_state(&genesis_state, &pay(50));
+    let f2 = f1.child(&poa, &f1_state, vec![]).unwrap();
+    assert!(client.import_block(f1.clone()));
+    assert!(client.import_block(f2.clone()));
+
+    // Block number one now means the block on the new best chain, not the one we authored.
+    assert_eq!(client.best_block(), f2.hash());
+    assert_eq!(client.resolve(BlockId::Number(1)), Some(f1.hash()));
+    assert_eq!(client.state_at(BlockId::Number(1)), Ok(f1_state));
+    // The old block can still be looked up by its hash.
+    assert_eq!(client.resolve(BlockId::Hash(short)), Some(short));
+
+    // New blocks are authored on top of the new best chain, and reference the orphaned one
+    // as an uncle.
+    let b3 = client.author_block(vec![]).unwrap();
+    let header = client.best_header();
+    assert_eq!(header.parent, f2.hash());
+    assert_eq!(header.uncles, vec![short]);
+    assert_eq!(client.best_block(), b3);
+}
+
+#[test]
+fn cl_9_author_block_fails_without_seal() {
+    let no_authorities = SimplePoa {
+        authorities: vec![],
+    };
+    let mut client: TestClient = FullClient::new(
+        no_authorities,
+        Currency,
+        LongestChain::default(),
+        (),
+        Balances::new(),
+    );
+    assert_eq!(client.author_block(vec![]), None);
+    assert_eq!(client.best_block(), client.genesis_hash());
+}
@@ -0,0 +1,38 @@
+//! So far every client has lived on its own. Blocks reached it because a test handed them over.
+//! A real blockchain is a network of nodes, each running a client, that pass blocks and
+//! transactions to one another. Nobody sees the whole network, and messages take time to
+//! arrive, so nodes regularly disagree about the best block for a while. Fork choice is what
+//! brings them back into agreement.
+//!
+//! In this final chapter we wire several clients together into a simulated network. The network
+//! runs entirely in memory, on a virtual clock, in a fixed order. The same scenario always plays
+//! out the same way, which makes it possible to test claims like "all honest nodes end up on
+//! the same best block".
+
+mod p1_simulator;
+mod p2_gossip;
+mod p3_partition;
+#[cfg(feature = "p2p")]
+mod p4_libp2p;
+#[cfg(feature = "tcp")]
+mod p5_codec;
+#[cfg(feature = "tcp")]
+mod p6_tcp;
+mod p7_announce;
+mod p8_links;
+mod p9_reputation;
+mod p10_transactions;
+
+pub use p1_simulator::{Message, Network, Node, NodeId};
+pub use p2_gossip::{message_id, GossipStats, DEFAULT_HOP_LIMIT};
+#[cfg(feature = "p2p")]
+pub use p4_libp2p::{
+    Behaviour, P2pNode, SyncRequest, SyncResponse, BLOCKS_TOPIC, MAX_SYNC_BLOCKS, SYNC_PROTOCOL,
+};
+#[cfg(feature = "tcp")]
+pub use p5_codec::{encode, read_message, write_message, CodecError, WireMessage, MAX_FRAME_LEN};
+#[cfg(feature = "tcp")]
+pub use p6_tcp::{Peer, PeerError};
+pub use p7_announce::{Propagation, Request, REQUEST_TIMEOUT};
+pub use p8_links::{Latency, Link, DEFAULT_LATENCY, DEFAULT_SEED};
+pub use p9_reputation::{Misbehaviour, BAN_THRESHOLD, STALE_DEPTH};
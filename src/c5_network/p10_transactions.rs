@@ -0,0 +1,141 @@
+//! Blocks are not the only thing that travels. A user hands a transaction to whichever node
+//! they happen to be connected to, but the next block may well be authored by another. So
+//! nodes gossip transactions too, and every node's pool ends up with every transaction.
+//!
+//! Transactions are gossiped just like pushed blocks, with one difference. Anyone can send a
+//! transaction, and an invalid one costs nothing to make. So before a node passes a
+//! transaction on, it checks it, by putting it in its own pool first. A pool that checks
+//! transactions against the best state refuses those that can not be applied, and the node
+//! then keeps them to itself. Invalid transactions never travel further than the first honest
+//! node they reach. Duplicates are dropped as before, both because the node remembers what it
+//! has seen, and because pools refuse transactions they already hold.
+//!
+//! The check is only as strict as the pool. A `SimplePool` accepts anything, so a network of
+//! them gossips anything.
+
+use super::{Message, Network, NodeId};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::TransactionPool;
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: Clone + std::hash::Hash,
+    P: TransactionPool<SM>,
+{
+    /// A peer has sent the given node a transaction. Put it in the node's pool, and pass it on
+    /// if the pool accepts it. Transactions the node has seen before are dropped.
+    pub(super) fn on_transaction(
+        &mut self,
+        at: NodeId,
+        from: NodeId,
+        hops: u32,
+        t: SM::Transition,
+    ) {
+        let message = Message::Transaction(t.clone());
+        if !self.first_sighting(at, &message) {
+            return;
+        }
+        if self.nodes[at].client.submit_transaction(t) {
+            self.relay(at, from, hops, message);
+        } else {
+            self.stats.refused_transactions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+use super::Node;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{FullClient, ImportBlock, LongestChain, TieBreak, ValidatingPool};
+
+#[cfg(test)]
+type TestPool = ValidatingPool<Currency, fn(&CurrencyTransaction) -> u64>;
+
+#[cfg(test)]
+type TestNetwork = Network<(), Currency, LongestChain, TestPool>;
+
+/// A line of the given number of nodes, each with a pool that checks transactions against a
+/// chain where Alice starts with 100 coins.
+#[cfg(test)]
+fn network(count: usize) -> TestNetwork {
+    let genesis = Balances::from([(User::Alice, 100)]);
+    let nodes = (0..count).map(|_| {
+        let fork_choice = LongestChain {
+            tie_break: TieBreak::FirstSeen,
+        };
+        let pool: TestPool = ValidatingPool::new(genesis.clone(), |_| 0);
+        let client = FullClient::new((), Currency, fork_choice, pool, genesis.clone());
+        Node::new(client)
+    });
+    let links: Vec<_> = (1..count).map(|id| (id - 1, id)).collect();
+    Network::with_links(nodes, links)
+}
+
+#[cfg(test)]
+fn transfer(from: User, amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from,
+        to: User::Charlie,
+        amount,
+    }
+}
+
+#[test]
+fn nw_10_transactions_reach_other_authors() {
+    let mut network = network(3);
+    let t = transfer(User::Alice, 30);
+    assert!(network.submit_transaction(0, t.clone()));
+    network.run_until_idle();
+    assert!(network
+        .nodes()
+        .iter()
+        .all(|node| node.client().pool_contains(t.clone())));
+
+    // The far end of the line authors the block, and every pool forgets the transaction.
+    let block_hash = network.author(2).unwrap();
+    network.run_until_idle();
+    let block = network.node(0).client().get_block(block_hash).unwrap();
+    assert_eq!(block.body, vec![t.clone()]);
+    assert!(network
+        .nodes()
+        .iter()
+        .all(|node| node.client().pool_size() == 0));
+}
+
+#[test]
+fn nw_10_invalid_transactions_are_not_passed_on() {
+    let mut network = network(3);
+    // Bob has nothing to send, so the first node refuses his transfer outright.
+    assert!(!network.submit_transaction(0, transfer(User::Bob, 1)));
+    assert_eq!(network.stats().sent, 0);
+
+    // A node that skips the check gets no further than its first peer.
+    let overdraft = transfer(User::Alice, 101);
+    network.send(0, 1, 1, Message::Transaction(overdraft.clone()));
+    network.run_until_idle();
+    assert_eq!(network.stats().refused_transactions, 1);
+    assert_eq!(network.seen_at(2, crate::hash(&overdraft)), None);
+}
+
+#[test]
+fn nw_10_duplicate_transactions_are_dropped() {
+    let mut network = network(3);
+    let t = transfer(User::Alice, 30);
+    assert!(network.submit_transaction(0, t.clone()));
+    assert!(network.submit_transaction(2, t.clone()));
+    network.run_until_idle();
+
+    // Both ends send their copy to the middle node, which only keeps and passes on the first.
+    // The far end already has it.
+    assert_eq!(network.stats().duplicates, 2);
+    assert_eq!(network.stats().sent, 3);
+    assert!(network
+        .nodes()
+        .iter()
+        .all(|node| node.client().pool_size() == 1));
+}
@@ -0,0 +1,507 @@
+//! The simulator holds every node of the network, and every message that is on its way from
+//! one node to another. Each message takes a tick of the virtual clock to arrive. Messages are
+//! delivered one at a time, in the order they arrive, and messages that arrive at the same time
+//! in the order they were sent. Nothing is left to the operating system's thread scheduler, so
+//! a scenario plays out the same way every time it is run.
+//!
+//! Each node is a full client with an import queue in front of it, so that blocks whose parent
+//! has not arrived yet wait for it rather than being thrown away. Unless told otherwise, every
+//! node is connected to every other node. When a node authors a block, or is handed a
+//! transaction, it sends it to all of its peers.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use super::{
+    message_id, GossipStats, Link, Misbehaviour, Propagation, Request, DEFAULT_HOP_LIMIT,
+    DEFAULT_SEED,
+};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::{
+    Author, Block, ForkChoice, FullClient, ImportBlock, ImportError, ImportQueue, ImportResult,
+    TransactionPool,
+};
+
+type Hash = u64;
+
+/// Identifies a node by its position in the network.
+pub type NodeId = usize;
+
+/// What nodes send one another.
+pub enum Message<C: Consensus, SM: StateMachine> {
+    /// A complete block.
+    Block(Block<C, SM>),
+    /// A transaction, to be put in the pool.
+    Transaction(SM::Transition),
+    /// News that the sender has imported the block with the given hash and height.
+    Announce { hash: Hash, height: u64 },
+    /// A request for the complete block with the given hash.
+    GetBlock(Hash),
+    /// Bytes that do not decode into any message. Honest nodes never send these, but broken or
+    /// malicious ones might.
+    Malformed(Vec<u8>),
+}
+
+// Deriving these traits would require the consensus engine and state machine
+// themselves to implement them, so we write them by hand instead.
+impl<C: Consensus, SM: StateMachine> Clone for Message<C, SM>
+where
+    SM::Transition: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Message::Block(block) => Message::Block(block.clone()),
+            Message::Transaction(t) => Message::Transaction(t.clone()),
+            Message::Announce { hash, height } => Message::Announce {
+                hash: *hash,
+                height: *height,
+            },
+            Message::GetBlock(hash) => Message::GetBlock(*hash),
+            Message::Malformed(bytes) => Message::Malformed(bytes.clone()),
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> fmt::Debug for Message<C, SM>
+where
+    SM::Transition: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Block(block) => f.debug_tuple("Block").field(block).finish(),
+            Message::Transaction(t) => f.debug_tuple("Transaction").field(t).finish(),
+            Message::Announce { hash, height } => f
+                .debug_struct("Announce")
+                .field("hash", hash)
+                .field("height", height)
+                .finish(),
+            Message::GetBlock(hash) => f.debug_tuple("GetBlock").field(hash).finish(),
+            Message::Malformed(bytes) => f.debug_tuple("Malformed").field(bytes).finish(),
+        }
+    }
+}
+
+/// A single node of the network.
+pub struct Node<C: Consensus, SM: StateMachine, FC, P> {
+    /// The client that follows the chain for this node.
+    pub(super) client: FullClient<C, SM, FC, P>,
+    /// Authors this node's blocks from the client's transaction pool.
+    author: Author,
+    /// Holds on to blocks that arrive before their parents.
+    pub(super) queue: ImportQueue<C, SM>,
+    /// The nodes this node is connected to.
+    pub(super) peers: Vec<NodeId>,
+    /// Every message this node has seen, and the time it first saw it.
+    pub(super) seen: HashMap<Hash, u64>,
+    /// The blocks this node has asked a peer for, and is still waiting for.
+    pub(super) requested: HashMap<Hash, Request>,
+    /// How well each peer has behaved. Peers that have not misbehaved are not listed.
+    pub(super) reputation: HashMap<NodeId, i64>,
+    /// The peers this node has banned, and no longer listens to.
+    pub(super) banned: HashSet<NodeId>,
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Node<C, SM, FC, P> {
+    /// A node that follows the chain with the given client. It authors blocks without
+    /// declaring an author, and puts every transaction in its pool in them.
+    pub fn new(client: FullClient<C, SM, FC, P>) -> Self {
+        Self {
+            client,
+            author: Author::new(1, usize::MAX),
+            queue: ImportQueue::new(),
+            peers: Vec::new(),
+            seen: HashMap::new(),
+            requested: HashMap::new(),
+            reputation: HashMap::new(),
+            banned: HashSet::new(),
+        }
+    }
+
+    /// Author this node's blocks with the given author.
+    pub fn with_author(mut self, author: Author) -> Self {
+        self.author = author;
+        self
+    }
+
+    /// The client that follows the chain for this node.
+    pub fn client(&self) -> &FullClient<C, SM, FC, P> {
+        &self.client
+    }
+
+    /// The nodes this node is connected to.
+    pub fn peers(&self) -> &[NodeId] {
+        &self.peers
+    }
+}
+
+/// A message on its way from one node to another.
+struct Envelope<C: Consensus, SM: StateMachine> {
+    from: NodeId,
+    to: NodeId,
+    /// How many hops the message will have travelled when it arrives.
+    hops: u32,
+    message: Message<C, SM>,
+}
+
+/// A network of nodes that pass messages to one another on a virtual clock.
+pub struct Network<C: Consensus, SM: StateMachine, FC, P> {
+    pub(super) nodes: Vec<Node<C, SM, FC, P>>,
+    /// Every message on its way, keyed by the time it arrives and the order it was sent in.
+    in_flight: BTreeMap<(u64, u64), Envelope<C, SM>>,
+    /// The current time on the virtual clock.
+    pub(super) now: u64,
+    /// How many hops a message may travel before nodes stop forwarding it.
+    pub(super) hop_limit: u32,
+    /// How many messages have been sent, delivered, and dropped so far.
+    pub(super) stats: GossipStats,
+    /// The group each node is in while the network is partitioned, or None while it is whole.
+    pub(super) groups: Option<Vec<usize>>,
+    /// How blocks spread through the network.
+    pub(super) propagation: Propagation,
+    /// The links whose properties differ from the default, in each direction.
+    pub(super) links: HashMap<(NodeId, NodeId), Link>,
+    /// The properties of every other link.
+    pub(super) default_link: Link,
+    /// The time at which each link with limited bandwidth has finished sending what it has
+    /// been given so far.
+    pub(super) busy_until: HashMap<(NodeId, NodeId), u64>,
+    /// The state of the random number generator that latencies are drawn from.
+    pub(super) rng: u64,
+    /// Every block authored so far, in order.
+    pub(super) authored: Vec<Hash>,
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// A network of the given nodes, each connected to all of the others. The nodes are
+    /// identified by the order they are given in.
+    pub fn new(nodes: impl IntoIterator<Item = Node<C, SM, FC, P>>) -> Self {
+        let mut nodes: Vec<_> = nodes.into_iter().collect();
+        let ids: Vec<_> = (0..nodes.len()).collect();
+        for (id, node) in nodes.iter_mut().enumerate() {
+            node.peers = ids.iter().copied().filter(|&peer| peer != id).collect();
+        }
+        Self {
+            nodes,
+            in_flight: BTreeMap::new(),
+            now: 0,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            stats: GossipStats::default(),
+            groups: None,
+            propagation: Propagation::Push,
+            links: HashMap::new(),
+            default_link: Link::default(),
+            busy_until: HashMap::new(),
+            rng: DEFAULT_SEED,
+            authored: Vec::new(),
+        }
+    }
+
+    /// Every node in the network.
+    pub fn nodes(&self) -> &[Node<C, SM, FC, P>] {
+        &self.nodes
+    }
+
+    /// The node with the given id.
+    ///
+    /// Panics if there is no such node.
+    pub fn node(&self, id: NodeId) -> &Node<C, SM, FC, P> {
+        &self.nodes[id]
+    }
+
+    /// The current time on the virtual clock.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Whether no messages are on their way.
+    pub fn is_idle(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+
+    /// The time at which the next message arrives, or None if no messages are on their way.
+    pub fn next_arrival(&self) -> Option<u64> {
+        self.in_flight.keys().next().map(|&(arrival, _)| arrival)
+    }
+
+    /// Send the given message from one node to another, as its given hop.
+    /// The message is lost if a partition separates the two nodes.
+    pub(super) fn send(&mut self, from: NodeId, to: NodeId, hops: u32, message: Message<C, SM>) {
+        if self.is_cut(from, to) {
+            self.stats.cut_off += 1;
+            return;
+        }
+        if let Message::Block(_) = message {
+            self.stats.blocks_sent += 1;
+        }
+        let arrival = self.arrival_time(from, to, &message);
+        let envelope = Envelope {
+            from,
+            to,
+            hops,
+            message,
+        };
+        self.in_flight.insert((arrival, self.stats.sent), envelope);
+        self.stats.sent += 1;
+    }
+
+    /// Send the given message, as its given hop, from the given node to each of its peers
+    /// except the one it came from.
+    pub(super) fn forward(
+        &mut self,
+        from: NodeId,
+        came_from: Option<NodeId>,
+        hops: u32,
+        message: Message<C, SM>,
+    ) where
+        SM::Transition: Clone,
+    {
+        for to in self.nodes[from].peers.clone() {
+            if Some(to) != came_from {
+                self.send(from, to, hops, message.clone());
+            }
+        }
+    }
+}
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+{
+    /// The best block of every node, in order of node id.
+    pub fn best_blocks(&self) -> Vec<Hash> {
+        self.nodes
+            .iter()
+            .map(|node| node.client.best_block())
+            .collect()
+    }
+
+    /// Whether every node agrees on the best block.
+    pub fn converged(&self) -> bool {
+        self.best_blocks().windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    /// Have the given node author a block right now, and send it to its peers. Returns the
+    /// hash of the new block, or None if the node could not author one.
+    pub fn author(&mut self, id: NodeId) -> Option<Hash> {
+        let node = &mut self.nodes[id];
+        let block_hash = node.author.author(&mut node.client, self.now)?;
+        let block = node.client.get_block(block_hash)?;
+        node.seen.insert(block_hash, self.now);
+        self.authored.push(block_hash);
+        let message = match self.propagation {
+            Propagation::Push => Message::Block(block),
+            Propagation::Announce => Message::Announce {
+                hash: block_hash,
+                height: block.header.height,
+            },
+        };
+        self.forward(id, None, 1, message);
+        Some(block_hash)
+    }
+
+    /// Hand the given transaction to the given node, which puts it in its pool and sends it to
+    /// its peers. Returns false, and sends nothing, if the pool refuses the transaction.
+    pub fn submit_transaction(&mut self, id: NodeId, t: SM::Transition) -> bool {
+        let message = Message::Transaction(t.clone());
+        let node = &mut self.nodes[id];
+        if !node.client.submit_transaction(t) {
+            return false;
+        }
+        node.seen.insert(message_id(&message), self.now);
+        self.forward(id, None, 1, message);
+        true
+    }
+
+    /// Deliver the next message, moving the clock forward to the time it arrives. Returns
+    /// false if there was no message to deliver.
+    pub fn step(&mut self) -> bool {
+        let Some(((arrival, _), envelope)) = self.in_flight.pop_first() else {
+            return false;
+        };
+        self.now = arrival;
+        let Envelope {
+            from,
+            to,
+            hops,
+            message,
+        } = envelope;
+        if self.is_cut(from, to) {
+            self.stats.cut_off += 1;
+            return true;
+        }
+        if self.nodes[to].banned.contains(&from) {
+            self.stats.from_banned += 1;
+            return true;
+        }
+        match message {
+            Message::Announce { hash, height } => {
+                self.first_sighting(to, &message);
+                self.on_announce(to, from, hops, hash, height);
+            }
+            Message::GetBlock(hash) => {
+                self.on_get_block(to, from, hash);
+            }
+            Message::Block(block) if self.propagation == Propagation::Announce => {
+                self.on_fetched(to, from, block);
+            }
+            Message::Malformed(_) => {
+                self.penalize(to, from, Misbehaviour::MalformedMessage);
+            }
+            Message::Transaction(t) => {
+                self.on_transaction(to, from, hops, t);
+            }
+            Message::Block(block) => {
+                let relay = Message::Block(block.clone());
+                if self.first_sighting(to, &relay) {
+                    match self.receive(to, block) {
+                        None => self.relay(to, from, hops, relay),
+                        Some(error) if error.is_invalid() => {
+                            self.penalize(to, from, Misbehaviour::InvalidBlock);
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Deliver messages until none are left on their way.
+    pub fn run_until_idle(&mut self) {
+        while self.step() {}
+    }
+
+    /// Import the given block at the node it was sent to. Returns why the block was thrown away,
+    /// if it was. Blocks that were not thrown away are worth passing on.
+    fn receive(&mut self, to: NodeId, block: Block<C, SM>) -> Option<ImportError> {
+        let block_hash = block.hash();
+        self.import(to, block)
+            .into_iter()
+            .find_map(|(hash, result)| match result {
+                ImportResult::Bad(error) if hash == block_hash => Some(error),
+                _ => None,
+            })
+    }
+
+    /// Put the given block in the given node's import queue, import what can be imported,
+    /// and report what happened to each block.
+    pub(super) fn import(&mut self, to: NodeId, block: Block<C, SM>) -> Vec<(Hash, ImportResult)> {
+        let node = &mut self.nodes[to];
+        node.queue.push(block);
+        let results = node.queue.process(&mut node.client);
+        for &(hash, result) in &results {
+            if result == ImportResult::Imported {
+                node.client.prune_pool(hash);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{LongestChain, SimplePool, TieBreak};
+
+#[cfg(test)]
+type TestNetwork = Network<(), Currency, LongestChain, SimplePool<Currency>>;
+
+/// A network of the given number of nodes on a chain where Alice starts with 100 coins.
+#[cfg(test)]
+fn network(nodes: usize) -> TestNetwork {
+    Network::new((0..nodes).map(|_| {
+        let fork_choice = LongestChain {
+            tie_break: TieBreak::FirstSeen,
+        };
+        let genesis = Balances::from([(User::Alice, 100)]);
+        Node::new(FullClient::new(
+            (),
+            Currency,
+            fork_choice,
+            SimplePool::default(),
+            genesis,
+        ))
+    }))
+}
+
+#[cfg(test)]
+fn pay(to: User, amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: User::Alice,
+        to,
+        amount,
+    }
+}
+
+/// Play out a scenario with forks and transactions, and return every node's best block.
+#[cfg(test)]
+fn play_scenario() -> (TestNetwork, Vec<Hash>) {
+    let mut network = network(4);
+    network.author(0).unwrap();
+    network.run_until_idle();
+
+    // Two nodes author at the same time, so the network forks. The blocks differ, because
+    // only one of the nodes has heard of the transaction yet.
+    network.submit_transaction(1, pay(User::Charlie, 5));
+    let a = network.author(1).unwrap();
+    let b = network.author(2).unwrap();
+    assert_ne!(a, b);
+    network.run_until_idle();
+    assert!(!network.converged());
+
+    // The next block extends one side of the fork, and everyone follows it.
+    network.submit_transaction(3, pay(User::Bob, 30));
+    network.run_until_idle();
+    let c = network.author(3).unwrap();
+    network.run_until_idle();
+    assert!(network.best_blocks().iter().all(|&best| best == c));
+
+    let best = network.best_blocks();
+    (network, best)
+}
+
+#[test]
+fn nw_1_honest_nodes_converge() {
+    let (network, _) = play_scenario();
+    assert!(network.converged());
+    assert!(network.is_idle());
+    for node in network.nodes() {
+        let state = node.client().best_state();
+        assert_eq!(
+            state,
+            &Balances::from([(User::Alice, 65), (User::Bob, 30), (User::Charlie, 5)])
+        );
+        assert_eq!(node.client().pool_size(), 0);
+    }
+}
+
+#[test]
+fn nw_1_simulation_is_deterministic() {
+    let (first, first_best) = play_scenario();
+    let (second, second_best) = play_scenario();
+    assert_eq!(first_best, second_best);
+    assert_eq!(first.now(), second.now());
+}
+
+#[test]
+fn nw_1_blocks_wait_for_their_parents() {
+    let mut network = network(2);
+    let b1 = network.author(0).unwrap();
+    let b2 = network.author(0).unwrap();
+
+    // Deliver the child first, by sending it again ahead of its parent.
+    let child = network.node(0).client().get_block(b2).unwrap();
+    network.in_flight.clear();
+    network.send(0, 1, 1, Message::Block(child));
+    let parent = network.node(0).client().get_block(b1).unwrap();
+    network.send(0, 1, 1, Message::Block(parent));
+    network.run_until_idle();
+    assert_eq!(network.best_blocks(), vec![b2, b2]);
+}
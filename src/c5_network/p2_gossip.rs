@@ -0,0 +1,219 @@
+//! In a real network, no node is connected to every other node. Each has a handful of peers,
+//! and a message only reaches the far side of the network by being passed along. The simplest
+//! way to do that is flood gossip: whenever a node sees a message for the first time, it
+//! forwards it to all of its peers, except the one it came from.
+//!
+//! Two things keep a flood from going on forever. Each node remembers which messages it has
+//! already seen, and drops them when they come round again. And each message carries the
+//! number of hops it has travelled. Once that reaches the hop limit, nobody forwards it further.
+//!
+//! Flooding is wasteful. In a well-connected network, most copies of a message arrive at nodes
+//! that already have it. In exchange, messages travel along every path at once, so they arrive
+//! by the quickest one. The statistics here let you measure both sides of that trade.
+
+use super::{Message, Network, Node, NodeId};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+use crate::hash;
+
+type Hash = u64;
+
+/// How many hops a message may travel unless the network is told otherwise.
+pub const DEFAULT_HOP_LIMIT: u32 = 16;
+
+/// How many messages a network has sent, and what became of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GossipStats {
+    /// Copies of messages sent, one for each peer they were sent to.
+    pub sent: u64,
+    /// Copies that arrived at a node that had not seen the message before.
+    pub fresh: u64,
+    /// Copies that arrived at a node that had already seen the message, and were dropped.
+    pub duplicates: u64,
+    /// Copies that were not forwarded any further, because they had reached the hop limit.
+    pub hop_limited: u64,
+    /// Copies that were lost because a partition separated the sender from the receiver.
+    pub cut_off: u64,
+    /// Copies of complete blocks sent, which are by far the largest messages. These are
+    /// included in `sent`.
+    pub blocks_sent: u64,
+    /// Copies that were dropped because the receiver had banned the sender.
+    pub from_banned: u64,
+    /// Transactions that a node's pool refused, and so were not passed on.
+    pub refused_transactions: u64,
+}
+
+impl GossipStats {
+    /// How many duplicates were delivered for each fresh message. Zero means no copy was
+    /// wasted.
+    pub fn redundancy(&self) -> f64 {
+        if self.fresh == 0 {
+            return 0.0;
+        }
+        self.duplicates as f64 / self.fresh as f64
+    }
+}
+
+/// The identifier that nodes remember a message by. Blocks, and announcements of them, are
+/// known by the block's hash, and transactions by the hash of the transaction. Requests are
+/// never remembered, but get an identifier of their own all the same, as do malformed messages.
+pub fn message_id<C, SM>(message: &Message<C, SM>) -> Hash
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::Transition: std::hash::Hash,
+{
+    match message {
+        Message::Block(block) => block.hash(),
+        Message::Transaction(t) => hash(t),
+        Message::Announce { hash, .. } => *hash,
+        Message::GetBlock(block_hash) => hash(&("GetBlock", block_hash)),
+        Message::Malformed(bytes) => hash(bytes),
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// A network of the given nodes, connected only by the given links. Links go both ways.
+    pub fn with_links(
+        nodes: impl IntoIterator<Item = Node<C, SM, FC, P>>,
+        links: impl IntoIterator<Item = (NodeId, NodeId)>,
+    ) -> Self {
+        let mut network = Self::new(nodes);
+        for node in &mut network.nodes {
+            node.peers.clear();
+        }
+        for (a, b) in links {
+            network.nodes[a].peers.push(b);
+            network.nodes[b].peers.push(a);
+        }
+        network
+    }
+
+    /// Stop forwarding messages once they have travelled the given number of hops.
+    pub fn with_hop_limit(mut self, hop_limit: u32) -> Self {
+        self.hop_limit = hop_limit;
+        self
+    }
+
+    /// How many messages have been sent so far, and what became of them.
+    pub fn stats(&self) -> GossipStats {
+        self.stats
+    }
+
+    /// The time at which the given node first saw the message with the given id, or None if
+    /// it has not seen it.
+    pub fn seen_at(&self, id: NodeId, message: Hash) -> Option<u64> {
+        self.nodes[id].seen.get(&message).copied()
+    }
+
+    /// How long the message with the given id took to go from the first node that saw it to
+    /// the last. Returns None while some node has not seen it yet.
+    pub fn propagation_time(&self, message: Hash) -> Option<u64> {
+        let times = (0..self.nodes.len())
+            .map(|id| self.seen_at(id, message))
+            .collect::<Option<Vec<_>>>()?;
+        Some(times.iter().max()? - times.iter().min()?)
+    }
+
+    /// Record that the given node has received the given message. Returns whether this is the
+    /// first time it sees it. Copies it has seen before are counted as duplicates.
+    pub(super) fn first_sighting(&mut self, id: NodeId, message: &Message<C, SM>) -> bool
+    where
+        SM::Transition: std::hash::Hash,
+    {
+        let message_id = message_id(message);
+        let seen = &mut self.nodes[id].seen;
+        if seen.contains_key(&message_id) {
+            self.stats.duplicates += 1;
+            return false;
+        }
+        seen.insert(message_id, self.now);
+        self.stats.fresh += 1;
+        true
+    }
+
+    /// Pass on a message that the given node has just seen for the first time, unless it has
+    /// already travelled as far as it may.
+    pub(super) fn relay(
+        &mut self,
+        at: NodeId,
+        came_from: NodeId,
+        hops: u32,
+        message: Message<C, SM>,
+    ) where
+        SM::Transition: Clone,
+    {
+        if hops >= self.hop_limit {
+            self.stats.hop_limited += 1;
+            return;
+        }
+        self.forward(at, Some(came_from), hops + 1, message);
+    }
+}
+
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+#[cfg(test)]
+use crate::c4_client::{FullClient, SimplePool};
+
+#[cfg(test)]
+type TestNode = Node<(), LightSwitch, (), SimplePool<LightSwitch>>;
+#[cfg(test)]
+type TestNetwork = Network<(), LightSwitch, (), SimplePool<LightSwitch>>;
+
+#[cfg(test)]
+fn nodes(count: usize) -> impl Iterator<Item = TestNode> {
+    (0..count).map(|_| {
+        let pool = SimplePool::default();
+        Node::new(FullClient::new((), LightSwitch, (), pool, false))
+    })
+}
+
+/// A network of the given number of nodes, each linked only to the next.
+#[cfg(test)]
+fn line(count: usize) -> TestNetwork {
+    Network::with_links(nodes(count), (1..count).map(|id| (id - 1, id)))
+}
+
+#[test]
+fn nw_2_messages_cross_the_network_hop_by_hop() {
+    let mut network = line(6);
+    let block = network.author(0).unwrap();
+    network.run_until_idle();
+
+    assert!(network.converged());
+    assert_eq!(network.seen_at(3, block), Some(3));
+    assert_eq!(network.propagation_time(block), Some(5));
+    let stats = network.stats();
+    assert_eq!(stats.sent, 5);
+    assert_eq!(stats.fresh, 5);
+    assert_eq!(stats.redundancy(), 0.0);
+}
+
+#[test]
+fn nw_2_dense_networks_deliver_fast_but_redundantly() {
+    let mut network = Network::new(nodes(4));
+    let block = network.author(0).unwrap();
+    network.run_until_idle();
+
+    assert_eq!(network.propagation_time(block), Some(1));
+    // Every node that hears of the block forwards it to the two other nodes, which already
+    // have it.
+    let stats = network.stats();
+    assert_eq!(stats.sent, 9);
+    assert_eq!(stats.fresh, 3);
+    assert_eq!(stats.duplicates, 6);
+    assert_eq!(stats.redundancy(), 2.0);
+}
+
+#[test]
+fn nw_2_hop_limit_stops_the_flood() {
+    let mut network = line(6).with_hop_limit(2);
+    let block = network.author(0).unwrap();
+    network.run_until_idle();
+
+    assert_eq!(network.seen_at(2, block), Some(2));
+    assert_eq!(network.seen_at(3, block), None);
+    assert_eq!(network.propagation_time(block), None);
+    assert_eq!(network.stats().hop_limited, 1);
+}
@@ -0,0 +1,217 @@
+//! Networks split. A cable is cut, a data centre goes offline, or a country blocks traffic
+//! across its border. Each side of the split carries on without the other, and if both sides
+//! keep authoring blocks, each grows a chain of its own.
+//!
+//! When the split heals, the two sides have to agree again. Gossip alone won't do it, because
+//! the blocks that were lost crossing the split are never sent again. So, just as real nodes
+//! sync with a peer when they first connect to it, the nodes here send their best chains to the
+//! peers they were cut off from. Every node then knows both branches, and its fork choice rule
+//! picks one of them. If all nodes follow the same rule, they all pick the same one.
+
+use super::{Message, Network, NodeId, Propagation};
+use crate::c1_state_machine::StateMachine;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::{ForkChoice, ImportBlock, TransactionPool};
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// Split the network into the given groups. Messages between nodes of different groups
+    /// are lost, including those already on their way. A node that is not in any group is
+    /// cut off from every other node.
+    ///
+    /// Panics if a node is in more than one group.
+    pub fn partition<G>(&mut self, groups: impl IntoIterator<Item = G>)
+    where
+        G: IntoIterator<Item = NodeId>,
+    {
+        // Every node starts out in a group of its own, numbered after the given groups.
+        let mut group_of: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        for (group, members) in groups.into_iter().enumerate() {
+            for id in members {
+                assert!(group_of[id].is_none(), "node {id} is in two groups");
+                group_of[id] = Some(group);
+            }
+        }
+        let alone = group_of.iter().flatten().max().map_or(0, |group| group + 1);
+        let groups = group_of
+            .into_iter()
+            .enumerate()
+            .map(|(id, group)| group.unwrap_or(alone + id))
+            .collect();
+        self.groups = Some(groups);
+    }
+
+    /// Whether the network is currently split.
+    pub fn is_partitioned(&self) -> bool {
+        self.groups.is_some()
+    }
+
+    /// Whether a partition stops messages from passing between the given nodes.
+    pub fn is_cut(&self, a: NodeId, b: NodeId) -> bool {
+        self.groups
+            .as_ref()
+            .is_some_and(|groups| groups[a] != groups[b])
+    }
+}
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+{
+    /// Reconnect all the nodes, and have each of them send its best chain to the peers it was
+    /// cut off from. Nodes that announce blocks rather than push them only announce their best
+    /// block, and leave it to their peers to ask for its ancestors. Does nothing if the network
+    /// is not split.
+    pub fn heal(&mut self) {
+        let Some(groups) = self.groups.take() else {
+            return;
+        };
+        for from in 0..self.nodes.len() {
+            let client = self.nodes[from].client();
+            let best_chain = (1..)
+                .map_while(|height| client.resolve(BlockId::Number(height)))
+                .filter_map(|hash| client.get_block(hash));
+            let messages: Vec<_> = match self.propagation {
+                Propagation::Push => best_chain.map(Message::Block).collect(),
+                Propagation::Announce => best_chain
+                    .last()
+                    .map(|best| Message::Announce {
+                        hash: best.hash(),
+                        height: best.header.height,
+                    })
+                    .into_iter()
+                    .collect(),
+            };
+            for to in self.nodes[from].peers.clone() {
+                if groups[from] == groups[to] {
+                    continue;
+                }
+                for message in &messages {
+                    self.send(from, to, 1, message.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use super::Node;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{FullClient, LongestChain, SimplePool, TieBreak};
+
+#[cfg(test)]
+type TestNetwork = Network<(), Currency, LongestChain, SimplePool<Currency>>;
+
+#[cfg(test)]
+fn network(nodes: usize) -> TestNetwork {
+    Network::new((0..nodes).map(|_| {
+        let fork_choice = LongestChain {
+            tie_break: TieBreak::FirstSeen,
+        };
+        let genesis = Balances::from([(User::Alice, 100)]);
+        Node::new(FullClient::new(
+            (),
+            Currency,
+            fork_choice,
+            SimplePool::default(),
+            genesis,
+        ))
+    }))
+}
+
+#[cfg(test)]
+fn pay(to: User, amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: User::Alice,
+        to,
+        amount,
+    }
+}
+
+/// Have the given nodes author one block each, in turn, letting each block spread before the
+/// next is authored. Returns the last block.
+#[cfg(test)]
+fn grow(network: &mut TestNetwork, authors: &[NodeId]) -> u64 {
+    let mut last = 0;
+    for &id in authors {
+        last = network.author(id).unwrap();
+        network.run_until_idle();
+    }
+    last
+}
+
+#[test]
+fn nw_3_partitioned_sides_grow_their_own_chains() {
+    let mut network = network(5);
+    let shared = grow(&mut network, &[0]);
+    network.partition([vec![0, 1, 2], vec![3, 4]]);
+    assert!(network.is_cut(1, 3));
+    assert!(!network.is_cut(3, 4));
+
+    // Different transactions on each side make sure the two sides author different blocks.
+    network.submit_transaction(0, pay(User::Bob, 10));
+    network.submit_transaction(3, pay(User::Charlie, 10));
+    network.run_until_idle();
+    assert_eq!(network.node(1).client().pool_size(), 1);
+    assert_eq!(network.node(4).client().pool_size(), 1);
+
+    let left = grow(&mut network, &[0, 1]);
+    let right = grow(&mut network, &[3]);
+    assert_eq!(network.best_blocks(), vec![left, left, left, right, right]);
+    assert!(network.node(3).client().get_block(left).is_none());
+    assert!(network.stats().cut_off > 0);
+
+    // Blocks from before the split are on both sides.
+    assert!(network.node(4).client().get_block(shared).is_some());
+}
+
+#[test]
+fn nw_3_healed_network_reorgs_to_the_fork_choice() {
+    let mut network = network(5);
+    grow(&mut network, &[0]);
+    network.partition([vec![0, 1, 2], vec![3, 4]]);
+    network.submit_transaction(0, pay(User::Bob, 10));
+    network.submit_transaction(3, pay(User::Charlie, 10));
+    network.run_until_idle();
+
+    // The smaller side grows the longer chain, so the larger side gives up its own.
+    let left = grow(&mut network, &[0, 1]);
+    let right = grow(&mut network, &[3, 4, 3]);
+    network.heal();
+    assert!(!network.is_partitioned());
+    network.run_until_idle();
+
+    assert!(network.converged());
+    assert_eq!(network.best_blocks()[0], right);
+    assert!(network.node(0).client().get_block(left).is_some());
+    let reorgs: Vec<_> = network
+        .nodes()
+        .iter()
+        .map(|node| node.client().metrics().reorgs)
+        .collect();
+    assert_eq!(reorgs, vec![1, 1, 1, 0, 0]);
+}
+
+#[test]
+fn nw_3_nodes_left_out_of_every_group_are_isolated() {
+    let mut network = network(3);
+    network.partition([vec![0, 1]]);
+    assert!(network.is_cut(0, 2));
+    assert!(network.is_cut(2, 1));
+
+    let block = grow(&mut network, &[0]);
+    assert!(network.seen_at(1, block).is_some());
+    assert!(network.seen_at(2, block).is_none());
+
+    network.heal();
+    network.run_until_idle();
+    assert!(network.converged());
+}
@@ -0,0 +1,392 @@
+//! The simulator lets us reason about networks, but its messages never leave the process. To
+//! run a real network, nodes have to find one another, open connections, and agree on how to
+//! talk. libp2p is a library of ready-made pieces for all of that, used by Substrate, Ethereum
+//! clients, and IPFS among others.
+//!
+//! Our node uses four of those pieces. mDNS finds other nodes on the local network without
+//! any configuration. Connections run over TCP, encrypted with Noise. Blocks are announced
+//! with gossipsub, a smarter cousin of the flood gossip from part 2, on a single topic that
+//! every node subscribes to. And nodes that fall behind catch up by asking a peer directly,
+//! with request-response.
+//!
+//! Gossip only carries blocks that are authored while a node is listening. A node that joins
+//! late never hears of the blocks before. Announcing the whole chain again whenever a peer
+//! joins would send every block to every node, most of which already have it. Instead, when
+//! a peer subscribes to the topic, a node asks just that peer for the blocks above its own
+//! best block, a batch at a time. If the first block it gets back does not build on anything
+//! it knows, the two nodes are on different forks, so it asks again from further back, until
+//! it reaches a block they share. Blocks that arrive before their parents wait in an import
+//! queue, as in the simulator.
+//!
+//! All of this needs the `p2p` feature.
+
+use std::error::Error;
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::swarm::{NetworkBehaviour, SwarmEvent};
+use libp2p::{
+    gossipsub, mdns, noise, tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::c1_state_machine::StateMachine;
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::{
+    Block, BlockStore, ForkChoice, FullClient, ImportBlock, ImportQueue, ImportResult, MemoryStore,
+    TransactionPool,
+};
+
+type Hash = u64;
+
+/// The gossipsub topic on which nodes announce blocks.
+pub const BLOCKS_TOPIC: &str = "diy-blockchain/blocks";
+
+/// The request-response protocol with which nodes ask one another for blocks.
+pub const SYNC_PROTOCOL: &str = "/diy-blockchain/sync/1";
+
+/// The most blocks that a node sends in answer to a single sync request.
+pub const MAX_SYNC_BLOCKS: u64 = 128;
+
+/// Asks for the blocks of the peer's best chain, starting at the given height.
+pub type SyncRequest = u64;
+
+/// The requested blocks, oldest first, each encoded as JSON. Empty if the peer's best chain
+/// is not that high.
+pub type SyncResponse = Vec<serde_json::Value>;
+
+/// How long a connection may sit unused before it is closed.
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The libp2p protocols that a node speaks.
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    /// Announces blocks to, and hears of blocks from, the other nodes.
+    pub gossipsub: gossipsub::Behaviour,
+    /// Finds other nodes on the local network.
+    pub mdns: mdns::tokio::Behaviour,
+    /// Asks other nodes for the blocks that this node is missing, and answers them in turn.
+    pub sync: request_response::json::Behaviour<SyncRequest, SyncResponse>,
+}
+
+/// A node that follows the chain with its client, and exchanges blocks with other nodes over
+/// libp2p.
+pub struct P2pNode<C: Consensus, SM: StateMachine, FC, P, S = MemoryStore<C, SM>> {
+    client: FullClient<C, SM, FC, P, S>,
+    /// Holds on to blocks that arrive before their parents.
+    queue: ImportQueue<C, SM>,
+    swarm: Swarm<Behaviour>,
+    topic: gossipsub::IdentTopic,
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> P2pNode<C, SM, FC, P, S> {
+    /// A node with a fresh identity that follows the chain with the given client. It does not
+    /// accept connections until it is told to listen.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(client: FullClient<C, SM, FC, P, S>) -> Result<Self, Box<dyn Error>> {
+        let mut swarm = SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )?
+            .with_behaviour(|key| {
+                let gossipsub = gossipsub::Behaviour::new(
+                    gossipsub::MessageAuthenticity::Signed(key.clone()),
+                    gossipsub::Config::default(),
+                )?;
+                let mdns =
+                    mdns::tokio::Behaviour::new(mdns::Config::default(), key.public().into())?;
+                let sync = request_response::json::Behaviour::new(
+                    [(StreamProtocol::new(SYNC_PROTOCOL), ProtocolSupport::Full)],
+                    request_response::Config::default(),
+                );
+                Ok(Behaviour {
+                    gossipsub,
+                    mdns,
+                    sync,
+                })
+            })?
+            .with_swarm_config(|config| {
+                config.with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT)
+            })
+            .build();
+        let topic = gossipsub::IdentTopic::new(BLOCKS_TOPIC);
+        swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+        Ok(Self {
+            client,
+            queue: ImportQueue::new(),
+            swarm,
+            topic,
+        })
+    }
+
+    /// The client that follows the chain for this node.
+    pub fn client(&self) -> &FullClient<C, SM, FC, P, S> {
+        &self.client
+    }
+
+    /// The identity that other nodes know this node by.
+    pub fn peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Accept connections on the given address. Use port 0 to let the operating system pick
+    /// a free port.
+    pub fn listen(&mut self, address: Multiaddr) -> Result<(), Box<dyn Error>> {
+        self.swarm.listen_on(address)?;
+        Ok(())
+    }
+
+    /// Connect to the node at the given address, without waiting for mDNS to find it.
+    pub fn dial(&mut self, address: Multiaddr) -> Result<(), Box<dyn Error>> {
+        self.swarm.dial(address)?;
+        Ok(())
+    }
+
+    /// The addresses this node is listening on.
+    pub fn listeners(&self) -> Vec<Multiaddr> {
+        self.swarm.listeners().cloned().collect()
+    }
+}
+
+impl<C, SM, FC, P, S> P2pNode<C, SM, FC, P, S>
+where
+    C: Consensus,
+    C::Digest: Default + Serialize + DeserializeOwned,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash + Serialize + DeserializeOwned,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+    S: BlockStore<C, SM>,
+{
+    /// Author a block with the given extrinsics on top of the best block, and announce it.
+    /// Returns the hash of the new block, or None if the client could not author one.
+    pub fn author_block(&mut self, extrinsics: Vec<SM::Transition>) -> Option<Hash> {
+        let block_hash = self.client.author_block(extrinsics)?;
+        self.announce(block_hash);
+        Some(block_hash)
+    }
+
+    /// Announce the given block to the other nodes. Announcing fails quietly when no other
+    /// node is listening, since there is nobody to tell.
+    pub fn announce(&mut self, block_hash: Hash) {
+        let Some(block) = self.client.get_block(block_hash) else {
+            return;
+        };
+        let data = serde_json::to_vec(&block).expect("blocks can always be serialized");
+        let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+        let _ = gossipsub.publish(self.topic.clone(), data);
+    }
+
+    /// Ask the given peer for the blocks of its best chain, starting at the given height.
+    pub fn request_blocks(&mut self, peer: &PeerId, from: u64) {
+        self.swarm.behaviour_mut().sync.send_request(peer, from);
+    }
+
+    /// The blocks of our best chain from the given height on, at most `MAX_SYNC_BLOCKS` of
+    /// them.
+    fn blocks_from(&self, from: u64) -> SyncResponse {
+        (from..from.saturating_add(MAX_SYNC_BLOCKS))
+            .map_while(|height| self.client.resolve(BlockId::Number(height)))
+            .filter_map(|block_hash| self.client.get_block(block_hash))
+            .map(|block| serde_json::to_value(block).expect("blocks can always be serialized"))
+            .collect()
+    }
+
+    /// Wait for the next thing to happen on the network, and deal with it. Returns what
+    /// happened to each block that was imported, or could not be, as a result.
+    ///
+    /// Nothing is lost if the returned future is dropped before it completes, so it can be
+    /// raced against other futures in a `tokio::select!`.
+    pub async fn step(&mut self) -> Vec<(Hash, ImportResult)> {
+        let event = self.swarm.select_next_some().await;
+        let SwarmEvent::Behaviour(event) = event else {
+            return Vec::new();
+        };
+        match event {
+            BehaviourEvent::Mdns(mdns::Event::Discovered(peers)) => {
+                for (peer, _) in peers {
+                    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+                    gossipsub.add_explicit_peer(&peer);
+                }
+            }
+            BehaviourEvent::Mdns(mdns::Event::Expired(peers)) => {
+                for (peer, _) in peers {
+                    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+                    gossipsub.remove_explicit_peer(&peer);
+                }
+            }
+            BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed { peer_id, .. }) => {
+                let from = self.client.best_header().height + 1;
+                self.request_blocks(&peer_id, from);
+            }
+            BehaviourEvent::Gossipsub(gossipsub::Event::Message { message, .. }) => {
+                // Blocks that do not even decode are garbage, and are dropped.
+                if let Ok(block) = serde_json::from_slice(&message.data) {
+                    self.queue.push(block);
+                    return self.import_queued();
+                }
+            }
+            BehaviourEvent::Gossipsub(_) => {}
+            BehaviourEvent::Sync(request_response::Event::Message { peer, message }) => {
+                match message {
+                    request_response::Message::Request {
+                        request, channel, ..
+                    } => {
+                        let blocks = self.blocks_from(request);
+                        let _ = self
+                            .swarm
+                            .behaviour_mut()
+                            .sync
+                            .send_response(channel, blocks);
+                    }
+                    request_response::Message::Response { response, .. } => {
+                        return self.import_synced(&peer, response);
+                    }
+                }
+            }
+            // A peer that fails to answer is skipped. Gossip still brings its new blocks.
+            BehaviourEvent::Sync(_) => {}
+        }
+        Vec::new()
+    }
+
+    /// Import the blocks that a peer sent in answer to a sync request, and ask it for more if
+    /// it has them.
+    fn import_synced(
+        &mut self,
+        peer: &PeerId,
+        response: SyncResponse,
+    ) -> Vec<(Hash, ImportResult)> {
+        let full = response.len() as u64 == MAX_SYNC_BLOCKS;
+        // A peer that sends garbage is not asked again.
+        let Ok(blocks) = response
+            .into_iter()
+            .map(serde_json::from_value::<Block<C, SM>>)
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            return Vec::new();
+        };
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return Vec::new();
+        };
+        let (first_hash, first_height) = (first.hash(), first.header.height);
+        let next = last.header.height + 1;
+        for block in blocks {
+            self.queue.push(block);
+        }
+        let results = self.import_queued();
+        let forked = results.iter().any(|&(block_hash, result)| {
+            block_hash == first_hash && result == ImportResult::Orphan
+        });
+        if forked && first_height > 1 {
+            self.request_blocks(peer, first_height / 2);
+        } else if full {
+            self.request_blocks(peer, next);
+        }
+        results
+    }
+
+    /// Import every block in the queue.
+    fn import_queued(&mut self) -> Vec<(Hash, ImportResult)> {
+        let results = self.queue.process(&mut self.client);
+        for &(block_hash, result) in &results {
+            if result == ImportResult::Imported {
+                self.client.prune_pool(block_hash);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, User};
+#[cfg(test)]
+use crate::c4_client::{LongestChain, SimplePool, TieBreak};
+
+#[cfg(test)]
+type TestNode = P2pNode<(), Currency, LongestChain, SimplePool<Currency>>;
+
+#[cfg(test)]
+fn node() -> TestNode {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis = Balances::from([(User::Alice, 100)]);
+    let pool = SimplePool::default();
+    let client = FullClient::new((), Currency, fork_choice, pool, genesis);
+    let mut node = P2pNode::new(client).unwrap();
+    node.listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    node
+}
+
+/// Step both nodes until the second has the same best block as the first.
+#[cfg(test)]
+async fn sync(a: &mut TestNode, b: &mut TestNode) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(20);
+    while b.client().best_block() != a.client().best_block() {
+        tokio::select! {
+            _ = a.step() => {}
+            _ = b.step() => {}
+            _ = tokio::time::sleep_until(deadline) => panic!("the nodes did not sync"),
+        }
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn nw_4_nodes_sync_over_libp2p() {
+    let mut a = node();
+    let mut b = node();
+    // Wait for the first node to start listening, so we know its address.
+    while a.listeners().is_empty() {
+        a.step().await;
+    }
+
+    // The first node authors some blocks before the second one joins.
+    for _ in 0..3 {
+        a.author_block(vec![]).unwrap();
+    }
+    b.dial(a.listeners()[0].clone()).unwrap();
+    sync(&mut a, &mut b).await;
+    assert_eq!(b.client().best_header(), a.client().best_header());
+
+    // Once connected, new blocks are announced as they are authored.
+    a.author_block(vec![]).unwrap();
+    sync(&mut a, &mut b).await;
+    assert!(b.client().resolve(BlockId::Number(4)).is_some());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn nw_4_nodes_on_other_forks_sync_from_their_common_ancestor() {
+    let mut a = node();
+    let mut b = node();
+    while a.listeners().is_empty() {
+        a.step().await;
+    }
+
+    // The second node builds a fork of its own before it joins, so the first node's blocks
+    // above its best block do not build on anything it knows.
+    for _ in 0..3 {
+        a.author_block(vec![]).unwrap();
+    }
+    let transfer = crate::c1_state_machine::CurrencyTransaction::Transfer {
+        from: User::Alice,
+        to: User::Bob,
+        amount: 1,
+    };
+    let forked = b.author_block(vec![transfer]).unwrap();
+    b.dial(a.listeners()[0].clone()).unwrap();
+    sync(&mut a, &mut b).await;
+    assert_eq!(b.client().best_header(), a.client().best_header());
+    assert!(b.client().get_block(forked).is_some());
+}
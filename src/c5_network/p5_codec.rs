@@ -0,0 +1,184 @@
+//! libp2p does a lot for us, but it is a large dependency, and it hides how nodes actually talk.
+//! A peer protocol can be much smaller. Two nodes open a TCP connection and send each other
+//! messages over it, one after another.
+//!
+//! TCP delivers a stream of bytes, not separate messages, so each message is sent as a frame:
+//! four bytes giving the length of the message, in big-endian order, followed by the message
+//! itself. The reader first reads the length, then exactly that many bytes. Messages are
+//! encoded with bincode.
+//!
+//! A peer could claim that its next message is gigabytes long, and have us allocate that much
+//! memory just to read it. So frames longer than a fixed limit are refused.
+//!
+//! This and the next part need the `tcp` feature.
+
+use std::io::{self, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::c1_state_machine::BlockContext;
+use crate::c3_consensus::Header;
+
+type Hash = u64;
+
+/// The longest message, in bytes, that a node is willing to read.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// What nodes send one another over TCP.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireMessage<Digest, Transition> {
+    /// The first message in each direction. Nodes on different chains have nothing to say to
+    /// one another, so they hang up unless their genesis blocks match.
+    Hello {
+        genesis: Hash,
+        best: Hash,
+        best_height: u64,
+    },
+    /// A block that the sender has just imported.
+    Announce { hash: Hash, height: u64 },
+    /// The headers of up to `max` consecutive blocks on the receiver's best chain, starting
+    /// with the one at height `from`.
+    GetHeaders { from: u64, max: usize },
+    /// The answer to `GetHeaders`.
+    Headers(Vec<Header<Digest>>),
+    /// The body of the block with the given hash.
+    GetBody(Hash),
+    /// The answer to `GetBody`, along with the context the body is executed in. None if the
+    /// sender does not have the body.
+    Body(Option<(Vec<Transition>, BlockContext)>),
+}
+
+/// The reasons that a message can not be sent or received.
+#[derive(Debug)]
+pub enum CodecError {
+    /// The connection failed, or was closed.
+    Io(io::Error),
+    /// The peer announced a frame longer than `MAX_FRAME_LEN`.
+    FrameTooLong(usize),
+    /// The frame does not hold a valid message.
+    Malformed(bincode::Error),
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> Self {
+        CodecError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for CodecError {
+    fn from(error: bincode::Error) -> Self {
+        CodecError::Malformed(error)
+    }
+}
+
+/// The given message as a frame, ready to be sent.
+pub fn encode<Digest, Transition>(
+    message: &WireMessage<Digest, Transition>,
+) -> Result<Vec<u8>, CodecError>
+where
+    Digest: Serialize,
+    Transition: Serialize,
+{
+    let payload = bincode::serialize(message)?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(CodecError::FrameTooLong(payload.len()));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Send the given message as a single frame.
+pub fn write_message<Digest, Transition>(
+    writer: &mut impl Write,
+    message: &WireMessage<Digest, Transition>,
+) -> Result<(), CodecError>
+where
+    Digest: Serialize,
+    Transition: Serialize,
+{
+    writer.write_all(&encode(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Wait for the next frame, and decode the message in it.
+pub fn read_message<Digest, Transition>(
+    reader: &mut impl Read,
+) -> Result<WireMessage<Digest, Transition>, CodecError>
+where
+    Digest: DeserializeOwned,
+    Transition: DeserializeOwned,
+{
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_LEN {
+        return Err(CodecError::FrameTooLong(length));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(bincode::deserialize(&payload)?)
+}
+
+#[cfg(test)]
+type TestMessage = WireMessage<u64, u32>;
+
+#[test]
+fn nw_5_messages_survive_a_round_trip() {
+    let messages: Vec<TestMessage> = vec![
+        WireMessage::Hello {
+            genesis: 1,
+            best: 2,
+            best_height: 3,
+        },
+        WireMessage::Announce { hash: 4, height: 5 },
+        WireMessage::GetHeaders { from: 6, max: 7 },
+        WireMessage::Headers(vec![]),
+        WireMessage::GetBody(8),
+        WireMessage::Body(Some((vec![9, 10], BlockContext::default()))),
+        WireMessage::Body(None),
+    ];
+    let mut stream = Vec::new();
+    for message in &messages {
+        write_message(&mut stream, message).unwrap();
+    }
+
+    // The frames are read back one by one, and the stream ends after the last of them.
+    let mut reader = stream.as_slice();
+    for message in messages {
+        assert_eq!(read_message(&mut reader).unwrap(), message);
+    }
+    assert!(matches!(
+        read_message::<u64, u32>(&mut reader),
+        Err(CodecError::Io(_))
+    ));
+}
+
+#[test]
+fn nw_5_bad_frames_are_refused() {
+    let frame = encode(&TestMessage::GetBody(8)).unwrap();
+    assert_eq!(frame[..4], [0, 0, 0, frame.len() as u8 - 4]);
+
+    // A frame cut short does not pass for a complete one.
+    let mut truncated = &frame[..frame.len() - 1];
+    assert!(matches!(
+        read_message::<u64, u32>(&mut truncated),
+        Err(CodecError::Io(_))
+    ));
+
+    // Nothing is allocated for a frame that is too long.
+    let mut huge = &u32::MAX.to_be_bytes()[..];
+    assert!(matches!(
+        read_message::<u64, u32>(&mut huge),
+        Err(CodecError::FrameTooLong(_))
+    ));
+
+    let mut garbage = &[0, 0, 0, 2, 0xff, 0xff][..];
+    assert!(matches!(
+        read_message::<u64, u32>(&mut garbage),
+        Err(CodecError::Malformed(_))
+    ));
+}
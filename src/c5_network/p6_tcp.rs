@@ -0,0 +1,399 @@
+//! With the codec in place, the protocol itself is short. Each side of a new connection first
+//! says hello, giving its genesis block and its best block. Nodes on different chains hang up
+//! right away. Otherwise, a node that learns of a block it does not have, whether from the
+//! hello or from a later announcement, syncs from the peer that told it.
+//!
+//! Syncing works as in part 19 of the client chapter: headers first, then bodies. The
+//! `SyncWorker` from there decides what to ask for. The only difference is that this protocol
+//! asks for bodies one at a time, so the peer collects the bodies of a batch before handing
+//! them to the worker.
+//!
+//! Each `Peer` handles one connection, and blocks while it waits for the next message. A node
+//! with several peers would run each of them on a thread of its own.
+
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{read_message, write_message, CodecError, WireMessage};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::{
+    BlockStore, ForkChoice, FullClient, SyncError, SyncRequest, SyncResponse, SyncWorker,
+};
+
+type Hash = u64;
+
+/// How many headers a peer asks for at once while syncing.
+const SYNC_BATCH_SIZE: usize = 64;
+
+/// The reasons that a connection to a peer fails.
+#[derive(Debug)]
+pub enum PeerError {
+    /// A message could not be sent or received.
+    Codec(CodecError),
+    /// The peer follows a chain with a different genesis block.
+    DifferentGenesis,
+    /// The peer sent a message that makes no sense at this point in the conversation.
+    UnexpectedMessage,
+    /// Syncing from the peer failed.
+    Sync(SyncError),
+}
+
+impl From<CodecError> for PeerError {
+    fn from(error: CodecError) -> Self {
+        PeerError::Codec(error)
+    }
+}
+
+impl From<io::Error> for PeerError {
+    fn from(error: io::Error) -> Self {
+        PeerError::Codec(CodecError::Io(error))
+    }
+}
+
+impl From<SyncError> for PeerError {
+    fn from(error: SyncError) -> Self {
+        PeerError::Sync(error)
+    }
+}
+
+/// One end of a connection to another node.
+pub struct Peer<Digest, Transition> {
+    stream: TcpStream,
+    /// The sync in progress with this peer, if any.
+    sync: Option<SyncWorker<Digest>>,
+    /// How many bodies of the current batch have not arrived yet.
+    awaited_bodies: usize,
+    /// The bodies of the current batch that have arrived, in order.
+    bodies: Vec<(Vec<Transition>, BlockContext)>,
+    /// Whether the peer did not have one of the bodies of the current batch. Bodies after it
+    /// are not used, since their parents are missing.
+    body_missing: bool,
+}
+
+impl<Digest, Transition> Peer<Digest, Transition>
+where
+    Digest: Clone + std::hash::Hash + Serialize + DeserializeOwned,
+    Transition: Serialize + DeserializeOwned,
+{
+    /// Connect to the node at the given address, and say hello.
+    pub fn connect<C, SM, FC, P, S>(
+        address: impl ToSocketAddrs,
+        client: &FullClient<C, SM, FC, P, S>,
+    ) -> Result<Self, PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        Self::handshake(TcpStream::connect(address)?, client)
+    }
+
+    /// Wait for a node to connect on the given listener, and say hello.
+    pub fn accept<C, SM, FC, P, S>(
+        listener: &TcpListener,
+        client: &FullClient<C, SM, FC, P, S>,
+    ) -> Result<Self, PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let (stream, _) = listener.accept()?;
+        Self::handshake(stream, client)
+    }
+
+    /// Exchange hellos over a new connection, and start syncing if the peer has a block that
+    /// we do not.
+    fn handshake<C, SM, FC, P, S>(
+        stream: TcpStream,
+        client: &FullClient<C, SM, FC, P, S>,
+    ) -> Result<Self, PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let mut peer = Self {
+            stream,
+            sync: None,
+            awaited_bodies: 0,
+            bodies: Vec::new(),
+            body_missing: false,
+        };
+        let best = client.best_block();
+        peer.send(&WireMessage::Hello {
+            genesis: client.genesis_hash(),
+            best,
+            best_height: client.height_of(best),
+        })?;
+        match peer.receive()? {
+            WireMessage::Hello { genesis, .. } if genesis != client.genesis_hash() => {
+                Err(PeerError::DifferentGenesis)
+            }
+            WireMessage::Hello { best, .. } => {
+                peer.learn_of(client, best)?;
+                Ok(peer)
+            }
+            _ => Err(PeerError::UnexpectedMessage),
+        }
+    }
+
+    /// Whether we are syncing from this peer.
+    pub fn is_syncing(&self) -> bool {
+        self.sync.is_some()
+    }
+
+    /// Give up waiting for the next message after the given time. None waits forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// Tell the peer about a block that we have just imported or authored.
+    pub fn announce<C, SM, FC, P, S>(
+        &mut self,
+        client: &FullClient<C, SM, FC, P, S>,
+        block_hash: Hash,
+    ) -> Result<(), PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let Some(header) = client.store().header(BlockId::Hash(block_hash)) else {
+            return Ok(());
+        };
+        self.send(&WireMessage::Announce {
+            hash: block_hash,
+            height: header.height,
+        })
+    }
+
+    /// Wait for the next message from the peer, and handle it. Returns the number of blocks
+    /// imported as a result.
+    pub fn handle_next<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+    ) -> Result<usize, PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        match self.receive()? {
+            WireMessage::Hello { .. } => return Err(PeerError::UnexpectedMessage),
+            WireMessage::Announce { hash, .. } => self.learn_of(client, hash)?,
+            WireMessage::GetHeaders { from, max } => {
+                let request = SyncRequest::GetHeaders { from, max };
+                let SyncResponse::Headers(headers) = client.answer_sync_request(&request) else {
+                    unreachable!("headers are answered with headers")
+                };
+                self.send(&WireMessage::Headers(headers))?;
+            }
+            WireMessage::GetBody(hash) => {
+                let request = SyncRequest::GetBodies { hashes: vec![hash] };
+                let SyncResponse::Bodies(bodies) = client.answer_sync_request(&request) else {
+                    unreachable!("bodies are answered with bodies")
+                };
+                self.send(&WireMessage::Body(bodies.into_iter().next()))?;
+            }
+            WireMessage::Headers(headers) => {
+                let sync = self.sync.as_mut().ok_or(PeerError::UnexpectedMessage)?;
+                sync.on_response(client, SyncResponse::Headers(headers))?;
+                self.request_next()?;
+            }
+            WireMessage::Body(body) => {
+                if self.awaited_bodies == 0 {
+                    return Err(PeerError::UnexpectedMessage);
+                }
+                self.awaited_bodies -= 1;
+                match body {
+                    Some(body) if !self.body_missing => self.bodies.push(body),
+                    _ => self.body_missing = true,
+                }
+                if self.awaited_bodies == 0 {
+                    return self.on_batch(client);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// Hand the bodies of the current batch to the sync worker, which imports them.
+    fn on_batch<C, SM, FC, P, S>(
+        &mut self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+    ) -> Result<usize, PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        let bodies = std::mem::take(&mut self.bodies);
+        self.body_missing = false;
+        let sync = self.sync.as_mut().ok_or(PeerError::UnexpectedMessage)?;
+        let imported = sync.on_response(client, SyncResponse::Bodies(bodies))?;
+        self.request_next()?;
+        Ok(imported)
+    }
+
+    /// Start syncing from the peer if it has the given block and we do not.
+    fn learn_of<C, SM, FC, P, S>(
+        &mut self,
+        client: &FullClient<C, SM, FC, P, S>,
+        block_hash: Hash,
+    ) -> Result<(), PeerError>
+    where
+        C: Consensus<Digest = Digest>,
+        C::Digest: Default,
+        SM: StateMachine<Transition = Transition>,
+        SM::State: Clone + std::hash::Hash,
+        SM::Transition: Clone + std::hash::Hash,
+        FC: ForkChoice,
+        S: BlockStore<C, SM>,
+    {
+        if self.sync.is_some() || client.store().contains(block_hash) {
+            return Ok(());
+        }
+        self.sync = Some(SyncWorker::new(client, SYNC_BATCH_SIZE));
+        self.request_next()
+    }
+
+    /// Send the sync worker's next request, or finish syncing if it has none.
+    fn request_next(&mut self) -> Result<(), PeerError> {
+        let Some(sync) = &self.sync else {
+            return Ok(());
+        };
+        match sync.next_request() {
+            None => self.sync = None,
+            Some(SyncRequest::GetHeaders { from, max }) => {
+                self.send(&WireMessage::GetHeaders { from, max })?;
+            }
+            Some(SyncRequest::GetBodies { hashes }) => {
+                self.awaited_bodies = hashes.len();
+                for hash in hashes {
+                    self.send(&WireMessage::GetBody(hash))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, message: &WireMessage<Digest, Transition>) -> Result<(), PeerError> {
+        Ok(write_message(&mut self.stream, message)?)
+    }
+
+    fn receive(&mut self) -> Result<WireMessage<Digest, Transition>, PeerError> {
+        Ok(read_message(&mut self.stream)?)
+    }
+}
+
+#[cfg(test)]
+use std::thread::JoinHandle;
+
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{LongestChain, SimplePool, TieBreak};
+
+#[cfg(test)]
+type TestClient = FullClient<(), Currency, LongestChain, SimplePool<Currency>>;
+#[cfg(test)]
+type TestPeer = Peer<(), CurrencyTransaction>;
+
+#[cfg(test)]
+fn client(alice: u64) -> TestClient {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis = Balances::from([(User::Alice, alice)]);
+    FullClient::new((), Currency, fork_choice, SimplePool::default(), genesis)
+}
+
+/// Serve the given client to the next node that connects, until it hangs up. Returns the
+/// client once it has, or the reason the handshake failed.
+#[cfg(test)]
+fn serve(mut client: TestClient) -> (u16, JoinHandle<Result<TestClient, PeerError>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut peer = TestPeer::accept(&listener, &client)?;
+        while peer.handle_next(&mut client).is_ok() {}
+        Ok(client)
+    });
+    (port, server)
+}
+
+#[test]
+fn nw_6_nodes_sync_over_tcp() {
+    let mut server = client(100);
+    for _ in 0..100 {
+        server.author_block(vec![]).unwrap();
+    }
+    let server_best = server.best_block();
+    let (port, server) = serve(server);
+
+    // The hello tells the new node that it is behind, so it syncs straight away.
+    let mut node = client(100);
+    let mut peer = TestPeer::connect(("127.0.0.1", port), &node).unwrap();
+    let mut imported = 0;
+    while peer.is_syncing() {
+        imported += peer.handle_next(&mut node).unwrap();
+    }
+    assert_eq!(imported, 100);
+    assert_eq!(node.best_block(), server_best);
+
+    // When the node authors a block and announces it, the server syncs from the node in
+    // turn. It goes quiet once it has caught up.
+    let block = node.author_block(vec![]).unwrap();
+    peer.announce(&node, block).unwrap();
+    peer.set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    while peer.handle_next(&mut node).is_ok() {}
+    drop(peer);
+    let server = server.join().unwrap().unwrap();
+    assert_eq!(server.best_block(), block);
+}
+
+#[test]
+fn nw_6_nodes_on_different_chains_hang_up() {
+    let (port, server) = serve(client(100));
+    let node = client(50);
+    assert!(matches!(
+        TestPeer::connect(("127.0.0.1", port), &node),
+        Err(PeerError::DifferentGenesis)
+    ));
+    assert!(matches!(
+        server.join().unwrap(),
+        Err(PeerError::DifferentGenesis)
+    ));
+}
@@ -0,0 +1,255 @@
+//! Pushing complete blocks to every peer is wasteful. As part 2 showed, in a well-connected
+//! network most copies arrive at nodes that already have the block. Blocks are large, but
+//! their hashes are tiny. So real nodes do not push new blocks. They announce them, giving
+//! only the hash and height, and peers that do not have the block yet ask for it. Each node
+//! then downloads a block only once, however many of its peers announce it.
+//!
+//! When several peers announce the same block at about the same time, a node asks only the
+//! first of them, and ignores the other announcements while it waits for the answer. The peer
+//! may never answer, for example because the connection to it was lost. So after a while the
+//! node gives up waiting, and asks the next peer that announces the block.
+//!
+//! Nothing is free. Each hop now takes a round trip more than before, so blocks travel more
+//! slowly. A block that arrives before its parent is no longer waited out either: the node asks
+//! the same peer for the parent, since that peer must have it.
+
+use super::{Message, Misbehaviour, Network, NodeId};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::{Block, ForkChoice, ImportBlock, ImportResult, TransactionPool};
+
+type Hash = u64;
+
+/// How many ticks a node waits for a peer to answer a request before asking another peer.
+pub const REQUEST_TIMEOUT: u64 = 4;
+
+/// How blocks spread through the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Propagation {
+    /// Nodes send complete blocks to their peers.
+    #[default]
+    Push,
+    /// Nodes announce blocks to their peers, which ask for the blocks they do not have.
+    Announce,
+}
+
+/// A block that a node has asked a peer for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request {
+    /// The peer that was asked.
+    pub peer: NodeId,
+    /// When it was asked.
+    pub at: u64,
+    /// How many hops the announcement of the block had travelled.
+    pub hops: u32,
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// Spread blocks in the given way.
+    pub fn with_propagation(mut self, propagation: Propagation) -> Self {
+        self.propagation = propagation;
+        self
+    }
+
+    /// The peer that the given node is waiting for the given block from, if any.
+    pub fn requested_from(&self, id: NodeId, block: Hash) -> Option<NodeId> {
+        self.nodes[id]
+            .requested
+            .get(&block)
+            .map(|request| request.peer)
+    }
+
+    /// Whether the given node is waiting for an answer about the given block that is not too
+    /// late yet.
+    fn is_awaiting(&self, id: NodeId, block: Hash) -> bool {
+        self.nodes[id]
+            .requested
+            .get(&block)
+            .is_some_and(|request| self.now < request.at + REQUEST_TIMEOUT)
+    }
+
+    /// Have the given node ask a peer for a block.
+    fn request(&mut self, at: NodeId, peer: NodeId, hops: u32, block: Hash) {
+        let request = Request {
+            peer,
+            at: self.now,
+            hops,
+        };
+        self.nodes[at].requested.insert(block, request);
+        self.send(at, peer, 1, Message::GetBlock(block));
+    }
+}
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+{
+    /// A peer has announced a block at the given height to the given node. Ask for it, unless
+    /// the node has it already or is waiting for another peer to send it. Announcing a block
+    /// the node has long since built on is penalized.
+    pub(super) fn on_announce(
+        &mut self,
+        at: NodeId,
+        from: NodeId,
+        hops: u32,
+        block: Hash,
+        height: u64,
+    ) {
+        let node = &self.nodes[at];
+        let is_known =
+            node.client().get_block(block).is_some() || node.queue.orphan_pool().contains(block);
+        if is_known && self.is_stale(at, height) {
+            self.penalize(at, from, Misbehaviour::StaleAnnouncement);
+        }
+        if is_known || self.is_awaiting(at, block) {
+            return;
+        }
+        self.request(at, from, hops, block);
+    }
+
+    /// A peer has asked the given node for a block. Send it, if the node has it.
+    pub(super) fn on_get_block(&mut self, at: NodeId, from: NodeId, block: Hash) {
+        if let Some(block) = self.nodes[at].client().get_block(block) {
+            self.send(at, from, 1, Message::Block(block));
+        }
+    }
+
+    /// A peer has sent the given node a block. Import it, announce every recent block that
+    /// could be imported as a result, and ask for the block's parent if that is missing. A
+    /// peer that sends an invalid block is penalized.
+    pub(super) fn on_fetched(&mut self, at: NodeId, from: NodeId, block: Block<C, SM>) {
+        let block_hash = block.hash();
+        let parent = block.header.parent;
+        let hops = self.nodes[at]
+            .requested
+            .remove(&block_hash)
+            .map_or(1, |request| request.hops);
+        for (hash, result) in self.import(at, block) {
+            match result {
+                ImportResult::Imported => {
+                    let node = &mut self.nodes[at];
+                    node.seen.entry(hash).or_insert(self.now);
+                    let Some(imported) = node.client().get_block(hash) else {
+                        continue;
+                    };
+                    let height = imported.header.height;
+                    // Peers would hold it against the node if it announced old blocks.
+                    if !self.is_stale(at, height) {
+                        self.relay(at, from, hops, Message::Announce { hash, height });
+                    }
+                }
+                ImportResult::Orphan if hash == block_hash && !self.is_awaiting(at, parent) => {
+                    self.request(at, from, hops, parent);
+                }
+                ImportResult::Bad(error) if hash == block_hash && error.is_invalid() => {
+                    self.penalize(at, from, Misbehaviour::InvalidBlock);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+use super::Node;
+#[cfg(test)]
+use crate::c1_state_machine::LightSwitch;
+#[cfg(test)]
+use crate::c4_client::{FullClient, SimplePool};
+
+#[cfg(test)]
+type TestNetwork = Network<(), LightSwitch, (), SimplePool<LightSwitch>>;
+
+#[cfg(test)]
+fn network(count: usize, links: &[(NodeId, NodeId)]) -> TestNetwork {
+    let nodes = (0..count).map(|_| {
+        let pool = SimplePool::default();
+        Node::new(FullClient::new((), LightSwitch, (), pool, false))
+    });
+    Network::with_links(nodes, links.iter().copied()).with_propagation(Propagation::Announce)
+}
+
+/// Every pair of the given number of nodes.
+#[cfg(test)]
+fn full_mesh(count: usize) -> Vec<(NodeId, NodeId)> {
+    (0..count)
+        .flat_map(|a| (a + 1..count).map(move |b| (a, b)))
+        .collect()
+}
+
+#[test]
+fn nw_7_announcing_sends_each_block_once_per_node() {
+    let links = full_mesh(5);
+    let mut pushing = network(5, &links).with_propagation(Propagation::Push);
+    pushing.author(0).unwrap();
+    pushing.run_until_idle();
+    assert!(pushing.converged());
+    assert_eq!(pushing.stats().blocks_sent, 16);
+
+    let mut announcing = network(5, &links);
+    let block = announcing.author(0).unwrap();
+    announcing.run_until_idle();
+    assert!(announcing.converged());
+    assert_eq!(announcing.stats().blocks_sent, 4);
+    // Each node hears of the block after one tick, but only has it after three. Small
+    // messages make up for the larger blocks: announcements, requests, blocks, and the
+    // announcements of the nodes that fetched the block.
+    assert_eq!(announcing.seen_at(1, block), Some(1));
+    assert_eq!(announcing.stats().sent, 4 + 4 + 4 + 4 * 3);
+}
+
+#[test]
+fn nw_7_blocks_are_asked_for_only_once() {
+    // Node 3 hears of the block from nodes 1 and 2 at the same time.
+    let mut network = network(4, &[(0, 1), (0, 2), (1, 3), (2, 3)]);
+    let block = network.author(0).unwrap();
+    while network.requested_from(3, block).is_none() {
+        network.step();
+    }
+    assert_eq!(network.requested_from(3, block), Some(1));
+    network.run_until_idle();
+
+    assert!(network.converged());
+    assert_eq!(network.stats().blocks_sent, 3);
+    assert_eq!(network.requested_from(3, block), None);
+}
+
+#[test]
+fn nw_7_unanswered_requests_are_retried_with_another_peer() {
+    // Node 3 hears of the block from node 1 quickly, and from node 5 much later.
+    let links = [(0, 1), (1, 3), (0, 2), (2, 4), (4, 5), (5, 3)];
+    let mut network = network(6, &links);
+    let block = network.author(0).unwrap();
+    while network.requested_from(3, block).is_none() {
+        network.step();
+    }
+    // The request to node 1 is lost.
+    network.partition([vec![0, 2, 3, 4, 5], vec![1]]);
+    network.run_until_idle();
+
+    assert!(network.stats().cut_off > 0);
+    assert!(network.node(3).client().get_block(block).is_some());
+    assert_eq!(network.requested_from(3, block), None);
+}
+
+#[test]
+fn nw_7_missing_parents_are_asked_for() {
+    let mut network = network(2, &[(0, 1)]);
+    network.partition([vec![0], vec![1]]);
+    for _ in 0..3 {
+        network.author(0).unwrap();
+    }
+    network.run_until_idle();
+
+    // Once healed, node 0 only announces its best block. Node 1 walks back from there.
+    network.heal();
+    network.run_until_idle();
+    assert!(network.converged());
+    assert_eq!(network.stats().blocks_sent, 3);
+}
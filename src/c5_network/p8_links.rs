@@ -0,0 +1,305 @@
+//! So far every message has taken exactly one tick to arrive, however large it was. Real links
+//! are messier. A message to a node on the other side of the world takes longer than one to a
+//! node next door, and the time varies from one message to the next. A link also only carries
+//! so much data per second, so a large block takes a while to send, and whatever is sent after
+//! it has to wait.
+//!
+//! Each link here has a latency, which is either fixed or drawn at random from a range, and may
+//! have a limited bandwidth. The random numbers come from a generator with a fixed seed, so
+//! scenarios still play out the same way every time.
+//!
+//! Slow links matter because of forks. A node that authors a block before it has heard of the
+//! last one builds on a stale parent, and one of the two blocks ends up off the best chain. The
+//! closer the block time gets to the time it takes a block to cross the network, the more often
+//! that happens. This is why chains cannot simply make their block times as short as they like.
+
+use std::mem::size_of;
+
+use super::{Message, Network, NodeId};
+use crate::c1_state_machine::{BlockContext, StateMachine};
+use crate::c2_blockchain::BlockId;
+use crate::c3_consensus::{Consensus, Header};
+use crate::c4_client::{ForkChoice, ImportBlock, TransactionPool};
+
+type Hash = u64;
+
+/// How many ticks a message takes to cross a link unless the network is told otherwise.
+pub const DEFAULT_LATENCY: u64 = 1;
+
+/// The seed of the random number generator that latencies are drawn from, unless the network
+/// is given another.
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+/// How long messages take to cross a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Latency {
+    /// Every message takes the given number of ticks.
+    Fixed(u64),
+    /// Each message takes a number of ticks drawn at random between `min` and `max`, inclusive.
+    /// If `max` is less than `min`, every message takes `min` ticks.
+    Uniform { min: u64, max: u64 },
+}
+
+/// The properties of a link between two nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Link {
+    /// How long messages take to cross the link, once they have been sent.
+    pub latency: Latency,
+    /// How many bytes the link sends per tick, or None if it sends anything straight away.
+    pub bandwidth: Option<u64>,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            latency: Latency::Fixed(DEFAULT_LATENCY),
+            bandwidth: None,
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Message<C, SM> {
+    /// Roughly how many bytes the message takes up on the wire.
+    pub fn size(&self) -> u64 {
+        let size = match self {
+            Message::Block(block) => {
+                size_of::<Header<C::Digest>>()
+                    + size_of::<BlockContext>()
+                    + block.body.len() * size_of::<SM::Transition>()
+            }
+            Message::Transaction(_) => size_of::<SM::Transition>(),
+            Message::Announce { .. } => 2 * size_of::<u64>(),
+            Message::GetBlock(_) => size_of::<Hash>(),
+            Message::Malformed(bytes) => bytes.len(),
+        };
+        size as u64
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// Give every link the given properties, except those that are set one by one.
+    pub fn with_default_link(mut self, link: Link) -> Self {
+        self.default_link = link;
+        self
+    }
+
+    /// Draw random latencies from a generator with the given seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // The generator would only ever produce zeros from a zero seed.
+        self.rng = seed.max(1);
+        self
+    }
+
+    /// Give the link between the given nodes the given properties, in both directions.
+    pub fn set_link(&mut self, a: NodeId, b: NodeId, link: Link) {
+        self.links.insert((a, b), link);
+        self.links.insert((b, a), link);
+    }
+
+    /// The properties of the link from one node to another.
+    pub fn link(&self, from: NodeId, to: NodeId) -> Link {
+        self.links
+            .get(&(from, to))
+            .copied()
+            .unwrap_or(self.default_link)
+    }
+
+    /// The time at which the given message, sent now, arrives at the other end of the link.
+    /// Sending it keeps the link busy for as long as it takes to put the message on the wire.
+    pub(super) fn arrival_time(
+        &mut self,
+        from: NodeId,
+        to: NodeId,
+        message: &Message<C, SM>,
+    ) -> u64 {
+        let link = self.link(from, to);
+        let latency = match link.latency {
+            Latency::Fixed(ticks) => ticks,
+            Latency::Uniform { min, max } => match max.saturating_sub(min).checked_add(1) {
+                Some(span) => min + self.random() % span,
+                // The range covers every `u64`, so any number will do.
+                None => self.random(),
+            },
+        };
+        let sent = match link.bandwidth {
+            None => self.now,
+            Some(bandwidth) => {
+                let busy_until = self.busy_until.entry((from, to)).or_default();
+                let start = (*busy_until).max(self.now);
+                *busy_until = start + message.size().div_ceil(bandwidth.max(1));
+                *busy_until
+            }
+        };
+        sent.saturating_add(latency)
+    }
+
+    /// The next number from the xorshift generator.
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+    P: TransactionPool<SM>,
+{
+    /// Deliver every message that arrives up to the given time, and move the clock forward to
+    /// it.
+    pub fn run_until(&mut self, time: u64) {
+        while self.next_arrival().is_some_and(|arrival| arrival <= time) {
+            self.step();
+        }
+        self.now = self.now.max(time);
+    }
+
+    /// The share of the blocks authored so far that are not on the first node's best chain.
+    /// Every such block was wasted work. Zero if no block has been authored.
+    pub fn fork_rate(&self) -> f64 {
+        if self.authored.is_empty() {
+            return 0.0;
+        }
+        let client = self.nodes[0].client();
+        let on_best_chain = |hash: Hash| {
+            let height = client.get_block(hash).map(|block| block.header.height);
+            height.and_then(|height| client.resolve(BlockId::Number(height))) == Some(hash)
+        };
+        let stale = self
+            .authored
+            .iter()
+            .filter(|&&hash| !on_best_chain(hash))
+            .count();
+        stale as f64 / self.authored.len() as f64
+    }
+}
+
+#[cfg(test)]
+use super::Node;
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{FullClient, LongestChain, SimplePool, TieBreak};
+
+#[cfg(test)]
+type TestNetwork = Network<(), Currency, LongestChain, SimplePool<Currency>>;
+
+#[cfg(test)]
+fn network(nodes: usize) -> TestNetwork {
+    Network::new((0..nodes).map(|_| {
+        let fork_choice = LongestChain {
+            tie_break: TieBreak::FirstSeen,
+        };
+        let genesis = Balances::from([(User::Alice, 1_000_000)]);
+        let pool = SimplePool::default();
+        Node::new(FullClient::new((), Currency, fork_choice, pool, genesis))
+    }))
+}
+
+#[cfg(test)]
+fn pay(amount: u64) -> CurrencyTransaction {
+    CurrencyTransaction::Transfer {
+        from: User::Alice,
+        to: User::Bob,
+        amount,
+    }
+}
+
+#[test]
+fn nw_8_links_delay_messages() {
+    let mut triangle = network(3);
+    let slow = Link {
+        latency: Latency::Fixed(5),
+        bandwidth: None,
+    };
+    triangle.set_link(0, 2, slow);
+    let block = triangle.author(0).unwrap();
+    triangle.run_until_idle();
+    assert_eq!(triangle.seen_at(1, block), Some(1));
+    // The block reaches node 2 through node 1 before it arrives over the slow link.
+    assert_eq!(triangle.seen_at(2, block), Some(2));
+    assert_eq!(triangle.link(2, 0), slow);
+
+    let mut varied = network(2).with_default_link(Link {
+        latency: Latency::Uniform { min: 2, max: 8 },
+        bandwidth: None,
+    });
+    let block = varied.author(0).unwrap();
+    varied.run_until_idle();
+    let arrival = varied.seen_at(1, block).unwrap();
+    assert!((2..=8).contains(&arrival));
+
+    // Backwards and unbounded ranges do not break the network.
+    let backwards = Link {
+        latency: Latency::Uniform { min: 3, max: 1 },
+        bandwidth: None,
+    };
+    let mut backwards = network(2).with_default_link(backwards);
+    let block = backwards.author(0).unwrap();
+    backwards.run_until_idle();
+    assert_eq!(backwards.seen_at(1, block), Some(3));
+    let mut unbounded = network(2).with_default_link(Link {
+        latency: Latency::Uniform {
+            min: 0,
+            max: u64::MAX,
+        },
+        bandwidth: None,
+    });
+    unbounded.author(0).unwrap();
+    unbounded.run_until_idle();
+}
+
+#[test]
+fn nw_8_bandwidth_queues_messages() {
+    let size = size_of::<CurrencyTransaction>() as u64;
+    let mut network = network(2).with_default_link(Link {
+        latency: Latency::Fixed(1),
+        bandwidth: Some(size),
+    });
+    let transactions: Vec<_> = (1..=3).map(pay).collect();
+    for t in &transactions {
+        network.submit_transaction(0, t.clone());
+    }
+    network.run_until_idle();
+
+    // Each transaction takes a tick to send, and waits for those before it.
+    let arrivals: Vec<_> = transactions
+        .iter()
+        .map(|t| network.seen_at(1, crate::hash(t)))
+        .collect();
+    assert_eq!(arrivals, vec![Some(2), Some(3), Some(4)]);
+}
+
+/// Have the nodes take turns authoring a block every `block_time` ticks, over links that take
+/// between 2 and 6 ticks, and return the fork rate.
+#[cfg(test)]
+fn fork_rate(block_time: u64) -> f64 {
+    let mut network = network(4).with_default_link(Link {
+        latency: Latency::Uniform { min: 2, max: 6 },
+        bandwidth: None,
+    });
+    for slot in 0..40 {
+        network.run_until(slot * block_time);
+        let author = slot as usize % 4;
+        // A transaction of its own makes sure each author's block differs from the others'.
+        network.submit_transaction(author, pay(slot + 1));
+        network.author(author).unwrap();
+    }
+    network.run_until_idle();
+    network.fork_rate()
+}
+
+#[test]
+fn nw_8_fast_blocks_fork_more() {
+    assert_eq!(fork_rate(10), 0.0);
+    let fast = fork_rate(2);
+    assert!(fast > 0.0);
+    assert!(fork_rate(1) > fast);
+}
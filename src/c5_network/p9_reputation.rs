@@ -0,0 +1,218 @@
+//! So far every node has been honest. Real networks are open to anyone, and not everyone who
+//! joins means well. Some peers send blocks that do not import, some keep announcing blocks
+//! that everybody has long since built on, and some send bytes that are not a message at all.
+//! Each of those costs the receiver time and bandwidth, and a peer that does it on purpose
+//! could keep a node too busy to follow the chain.
+//!
+//! So each node keeps a reputation for each of its peers. Every peer starts at zero, and loses
+//! points each time it misbehaves, more for worse offences. Honest nodes can misbehave too, for
+//! example by announcing an old block while they are catching up, so one small offence is not
+//! enough to lose a peer. But a peer whose reputation falls below a threshold is disconnected
+//! and banned, and nothing it sends is listened to again.
+
+use super::{Network, NodeId};
+use crate::c1_state_machine::StateMachine;
+use crate::c3_consensus::Consensus;
+use crate::c4_client::ForkChoice;
+
+/// A peer whose reputation falls below this is banned.
+pub const BAN_THRESHOLD: i64 = -100;
+
+/// How far below a node's best block a block has to be for announcing it to count as stale.
+pub const STALE_DEPTH: u64 = 8;
+
+/// The ways in which a peer can misbehave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Misbehaviour {
+    /// The peer sent a block that is invalid.
+    InvalidBlock,
+    /// The peer announced a block far below the receiver's best block.
+    StaleAnnouncement,
+    /// The peer sent bytes that do not decode into any message.
+    MalformedMessage,
+}
+
+impl Misbehaviour {
+    /// How many points a peer loses for misbehaving in this way. Honest nodes never send
+    /// invalid blocks, so a single one is enough to get a peer banned.
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Misbehaviour::InvalidBlock => 200,
+            Misbehaviour::StaleAnnouncement => 10,
+            Misbehaviour::MalformedMessage => 50,
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P> Network<C, SM, FC, P> {
+    /// The reputation that the given node has given the given peer.
+    pub fn reputation(&self, id: NodeId, peer: NodeId) -> i64 {
+        self.nodes[id]
+            .reputation
+            .get(&peer)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether the given node has banned the given peer.
+    pub fn is_banned(&self, id: NodeId, peer: NodeId) -> bool {
+        self.nodes[id].banned.contains(&peer)
+    }
+
+    /// Have the given node penalize the given peer for misbehaving. If that leaves the peer's
+    /// reputation below the threshold, the node disconnects from the peer and bans it.
+    pub(super) fn penalize(&mut self, at: NodeId, peer: NodeId, misbehaviour: Misbehaviour) {
+        let node = &mut self.nodes[at];
+        if node.banned.contains(&peer) {
+            return;
+        }
+        let reputation = node.reputation.entry(peer).or_default();
+        *reputation -= misbehaviour.penalty();
+        node.client.metrics_mut().peer_penalties += 1;
+        if *reputation >= BAN_THRESHOLD {
+            return;
+        }
+        node.banned.insert(peer);
+        node.peers.retain(|&id| id != peer);
+        node.client.metrics_mut().banned_peers += 1;
+        self.nodes[peer].peers.retain(|&id| id != at);
+    }
+}
+
+impl<C, SM, FC, P> Network<C, SM, FC, P>
+where
+    C: Consensus,
+    C::Digest: Default,
+    SM: StateMachine,
+    SM::State: Clone + std::hash::Hash,
+    SM::Transition: Clone + std::hash::Hash,
+    FC: ForkChoice,
+{
+    /// Whether a block at the given height is far enough below the given node's best block
+    /// that announcing it is stale.
+    pub(super) fn is_stale(&self, id: NodeId, height: u64) -> bool {
+        let client = &self.nodes[id].client;
+        height + STALE_DEPTH < client.height_of(client.best_block())
+    }
+}
+
+#[cfg(test)]
+use super::{Message, Node, Propagation};
+#[cfg(test)]
+use crate::c1_state_machine::{Balances, Currency, CurrencyTransaction, User};
+#[cfg(test)]
+use crate::c4_client::{
+    FullClient, ImportBlock, ImportError, ImportResult, LongestChain, SimplePool, TieBreak,
+};
+
+#[cfg(test)]
+type TestNetwork = Network<(), Currency, LongestChain, SimplePool<Currency>>;
+
+#[cfg(test)]
+fn client() -> FullClient<(), Currency, LongestChain, SimplePool<Currency>> {
+    let fork_choice = LongestChain {
+        tie_break: TieBreak::FirstSeen,
+    };
+    let genesis = Balances::from([(User::Alice, 100)]);
+    FullClient::new((), Currency, fork_choice, SimplePool::default(), genesis)
+}
+
+#[cfg(test)]
+fn network(nodes: usize) -> TestNetwork {
+    Network::new((0..nodes).map(|_| Node::new(client())))
+}
+
+#[test]
+fn nw_9_invalid_blocks_get_peers_banned() {
+    for propagation in [Propagation::Push, Propagation::Announce] {
+        let mut network = network(3).with_propagation(propagation);
+        let mut forger = client();
+        let block_hash = forger.author_block(vec![]).unwrap();
+        let mut bad = forger.get_block(block_hash).unwrap();
+        bad.header.state_root = 0;
+
+        network.send(2, 0, 1, Message::Block(bad));
+        network.run_until_idle();
+        assert!(network.is_banned(0, 2));
+        assert!(!network.node(0).peers().contains(&2));
+        assert!(!network.node(2).peers().contains(&0));
+        assert_eq!(network.node(0).client().metrics().banned_peers, 1);
+
+        // Nothing the banned peer sends is listened to any more, but its blocks still arrive
+        // through the other peers.
+        network.send(2, 0, 1, Message::Malformed(vec![0]));
+        network.author(2).unwrap();
+        network.run_until_idle();
+        assert_eq!(network.stats().from_banned, 1);
+        assert_eq!(network.node(0).client().metrics().peer_penalties, 1);
+        assert_eq!(
+            network.node(0).client().best_block(),
+            network.best_blocks()[2]
+        );
+        assert!(!network.is_banned(0, 1));
+    }
+}
+
+#[test]
+fn nw_9_valid_blocks_that_can_not_be_imported_are_not_penalized() {
+    for propagation in [Propagation::Push, Propagation::Announce] {
+        let mut network = network(2).with_propagation(propagation);
+        let finalized = network.nodes[0].client.author_block(vec![]).unwrap();
+        assert!(network.nodes[0].client.manually_finalize_block(finalized));
+
+        // A perfectly valid block, on a fork that node 0 has finalized against.
+        let mut other = client();
+        let pay = CurrencyTransaction::Transfer {
+            from: User::Alice,
+            to: User::Bob,
+            amount: 10,
+        };
+        let fork_hash = other.author_block(vec![pay]).unwrap();
+        let fork = other.get_block(fork_hash).unwrap();
+        let refused = ImportResult::Bad(ImportError::ConflictsWithFinality);
+        assert_eq!(network.import(0, fork.clone()), vec![(fork_hash, refused)]);
+
+        network.send(1, 0, 1, Message::Block(fork));
+        network.run_until_idle();
+        assert_eq!(network.reputation(0, 1), 0);
+        assert_eq!(network.node(0).client().metrics().peer_penalties, 0);
+    }
+}
+
+#[test]
+fn nw_9_stale_announcements_are_penalized() {
+    let mut network = network(2).with_propagation(Propagation::Announce);
+    let old = network.author(0).unwrap();
+    for _ in 0..STALE_DEPTH + 1 {
+        network.author(0).unwrap();
+    }
+    network.run_until_idle();
+    assert!(network.converged());
+    assert_eq!(network.reputation(1, 0), 0);
+
+    let stale = Message::Announce {
+        hash: old,
+        height: 1,
+    };
+    network.send(0, 1, 1, stale);
+    network.run_until_idle();
+    let penalty = Misbehaviour::StaleAnnouncement.penalty();
+    assert_eq!(network.reputation(1, 0), -penalty);
+    assert!(!network.is_banned(1, 0));
+    assert_eq!(network.node(1).client().metrics().peer_penalties, 1);
+}
+
+#[test]
+fn nw_9_malformed_messages_add_up_to_a_ban() {
+    let mut network = network(2);
+    let penalty = Misbehaviour::MalformedMessage.penalty();
+    let mut sent = 0;
+    while !network.is_banned(1, 0) {
+        network.send(0, 1, 1, Message::Malformed(vec![0xff; sent + 1]));
+        network.run_until_idle();
+        sent += 1;
+        assert_eq!(network.reputation(1, 0), -penalty * sent as i64);
+    }
+    assert_eq!(sent as i64, -BAN_THRESHOLD / penalty + 1);
+    assert!(network.node(1).peers().is_empty());
+}
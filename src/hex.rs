@@ -0,0 +1,90 @@
+//! Hashes are plain `u64`s throughout the crate, which Rust prints in decimal. Nobody can read
+//! or compare twenty-digit decimal numbers at a glance, so wherever hashes are shown to people
+//! they are wrapped in `Hex`, which prints them the way block explorers do: as `0x` followed by
+//! sixteen hexadecimal digits. It also parses them back.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A hash, shown as `0x`-prefixed hexadecimal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hex(pub u64);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:016x}", self.0)
+    }
+}
+
+/// The reasons that a string is not a hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseHexError {
+    /// The string does not start with `0x`.
+    MissingPrefix,
+    /// Nothing follows the prefix, or something that is not a hexadecimal digit does.
+    InvalidDigits,
+    /// There are more than sixteen digits, which is more than a hash has.
+    TooLong,
+}
+
+impl fmt::Display for ParseHexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHexError::MissingPrefix => write!(f, "hashes start with 0x"),
+            ParseHexError::InvalidDigits => write!(f, "hashes are written in hexadecimal digits"),
+            ParseHexError::TooLong => write!(f, "hashes have at most 16 digits"),
+        }
+    }
+}
+
+impl std::error::Error for ParseHexError {}
+
+impl FromStr for Hex {
+    type Err = ParseHexError;
+
+    /// Leading zeros may be left out, so `0x2a` is the same hash as `0x000000000000002a`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0x").ok_or(ParseHexError::MissingPrefix)?;
+        // Checked by hand, because `from_str_radix` would also accept a leading `+`.
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseHexError::InvalidDigits);
+        }
+        if digits.len() > 16 {
+            return Err(ParseHexError::TooLong);
+        }
+        u64::from_str_radix(digits, 16)
+            .map(Hex)
+            .map_err(|_| ParseHexError::InvalidDigits)
+    }
+}
+
+impl From<u64> for Hex {
+    fn from(hash: u64) -> Self {
+        Hex(hash)
+    }
+}
+
+impl From<Hex> for u64 {
+    fn from(hex: Hex) -> Self {
+        hex.0
+    }
+}
+
+#[test]
+fn hex_round_trip() {
+    let hash = Hex(0x2a);
+    assert_eq!(hash.to_string(), "0x000000000000002a");
+    assert_eq!("0x000000000000002a".parse(), Ok(hash));
+    assert_eq!("0x2a".parse(), Ok(hash));
+    assert_eq!(Hex(u64::MAX).to_string().parse(), Ok(Hex(u64::MAX)));
+}
+
+#[test]
+fn hex_refuses_what_is_not_a_hash() {
+    assert_eq!("2a".parse::<Hex>(), Err(ParseHexError::MissingPrefix));
+    for bad in ["0x", "0xg", "0x+2a", "0x-2a", "0x 2a"] {
+        assert_eq!(bad.parse::<Hex>(), Err(ParseHexError::InvalidDigits));
+    }
+    let too_long = "0x10000000000000000";
+    assert_eq!(too_long.parse::<Hex>(), Err(ParseHexError::TooLong));
+}
@@ -1,10 +1,14 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
-mod c1_state_machine;
-mod c2_blockchain;
-mod c3_consensus;
-mod c4_client;
+pub mod c1_state_machine;
+pub mod c2_blockchain;
+pub mod c3_consensus;
+pub mod c4_client;
+pub mod c5_network;
+pub mod hex;
+#[cfg(feature = "serde")]
+mod serde_helpers;
 
 // Simple helper to do some hashing.
 fn hash<T: Hash>(t: &T) -> u64 {